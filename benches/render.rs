@@ -0,0 +1,167 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use lumen::{
+    config::Config,
+    http::{HttpRequest, ResponseBody, process_http_request, write_head},
+    state::ServerState,
+    theme::reload_theme,
+};
+use std::{fs, hint::black_box, net::Ipv4Addr, sync::Arc};
+
+const PAGE: &str = r#"---
+title: "Benchmark Page"
+date: "2026-03-01"
+---
+# Rendering throughput
+
+Lumen renders **markdown** through a _minijinja_ theme. This page mixes the
+constructs a typical post uses so the numbers reflect real content.
+
+| Column | Value |
+|--------|-------|
+| alpha  | 1     |
+| beta   | 2     |
+
+- [x] tables
+- [ ] footnotes
+- ~~strikethrough~~
+
+```rust
+fn main() {
+    println!("hello");
+}
+```
+"#;
+
+fn workspace() -> Config {
+    let root = std::env::temp_dir().join(format!("lumen-bench-{}", std::process::id()));
+    let content = root.join("content");
+    let theme = root.join("theme");
+    fs::create_dir_all(&content).unwrap();
+    fs::create_dir_all(&theme).unwrap();
+
+    let mut body = String::from(PAGE);
+    for i in 0..20 {
+        body.push_str(&format!(
+            "\n## Section {}\n\nParagraph with a [link](/posts/{}).\n",
+            i, i
+        ));
+    }
+    fs::write(content.join("page.md"), body).unwrap();
+    fs::write(
+        theme.join("index.html"),
+        "<!DOCTYPE html>\n<html><head><title>{{ title }}</title></head><body>\n<main>\n<h1>{{ title }}</h1>\n{{ content|safe }}\n</main>\n</body></html>",
+    )
+    .unwrap();
+
+    let mut config = Config::default();
+    config.paths.content_dir = content.to_string_lossy().into_owned();
+    config.paths.theme_dir = theme.to_string_lossy().into_owned();
+    config
+}
+
+fn request() -> HttpRequest {
+    HttpRequest {
+        method: "GET".into(),
+        path: "/page".into(),
+        accept_encoding: "".into(),
+        accept: String::new(),
+        host: String::new(),
+        authorization: None,
+        range: None,
+        if_none_match: None,
+        if_modified_since: None,
+        if_range: None,
+        user_agent: String::new(),
+        cookie: String::new(),
+        keep_alive: true,
+        peer_ip: Ipv4Addr::LOCALHOST.into(),
+        request_id: "bench".into(),
+        http_minor: 1,
+        stream_notify: None,
+    }
+}
+
+fn render_to_vec(state: &Arc<ServerState>, out: &mut Vec<u8>) {
+    out.clear();
+    let res = process_http_request(request(), Arc::clone(state));
+    write_head(
+        out,
+        &res,
+        1,
+        &state.http_date(),
+        &state.precomputed_headers.load(),
+    )
+    .unwrap();
+    if let Some(ResponseBody::Bytes(b)) = &res.body {
+        out.extend_from_slice(b);
+    }
+}
+
+fn bench_render(c: &mut Criterion) {
+    let mut config = workspace();
+    let mut out = Vec::with_capacity(64 * 1024);
+
+    config.performance.enable_caching = false;
+    let uncached = Arc::new(ServerState::new(config.clone()));
+    let _ = reload_theme(&uncached);
+    c.bench_function("render_markdown_uncached", |b| {
+        b.iter(|| render_to_vec(black_box(&uncached), &mut out))
+    });
+
+    config.performance.enable_caching = true;
+    let cached = Arc::new(ServerState::new(config));
+    let _ = reload_theme(&cached);
+    c.bench_function("render_markdown_cached", |b| {
+        b.iter(|| render_to_vec(black_box(&cached), &mut out))
+    });
+}
+
+fn bench_write_head(c: &mut Criterion) {
+    let state = ServerState::new(workspace());
+    let res = lumen::http::build_response(
+        true,
+        200,
+        "text/html; charset=utf-8",
+        None,
+        18_432,
+        vec![
+            ("ETag".into(), "W/\"65f1a2b3-4800\"".into()),
+            (
+                "Last-Modified".into(),
+                "Fri, 01 Mar 2026 00:00:00 GMT".into(),
+            ),
+        ],
+    );
+    let mut out = Vec::with_capacity(1024);
+    c.bench_function("write_head", |b| {
+        b.iter(|| {
+            out.clear();
+            write_head(
+                &mut out,
+                black_box(&res),
+                1,
+                &state.http_date(),
+                &state.precomputed_headers.load(),
+            )
+            .unwrap();
+        })
+    });
+}
+
+fn bench_date_header(c: &mut Criterion) {
+    let mut config = workspace();
+    config.performance.cache_date_header = false;
+    let fresh = ServerState::new(config.clone());
+    c.bench_function("date_header_formatted", |b| {
+        b.iter(|| black_box(fresh.http_date()))
+    });
+
+    config.performance.cache_date_header = true;
+    let cached = ServerState::new(config);
+    c.bench_function("date_header_cached", |b| {
+        b.iter(|| black_box(cached.http_date()))
+    });
+}
+
+criterion_group!(benches, bench_render, bench_write_head, bench_date_header);
+criterion_main!(benches);
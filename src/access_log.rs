@@ -0,0 +1,136 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{config::AccessLogFormat, utils::format_rfc3339};
+
+/// One line per response appended to `server.access_log`. Lines are buffered and written out
+/// by `flush`, which the server calls every second and on shutdown.
+pub struct AccessLog {
+    out: Mutex<BufWriter<File>>,
+    format: AccessLogFormat,
+}
+
+pub struct AccessRecord<'a> {
+    pub ip: IpAddr,
+    pub request_id: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub http_minor: u8,
+    pub status: u16,
+    /// Body bytes, or `None` for a streamed body of unknown length.
+    pub bytes: Option<u64>,
+    pub duration: Duration,
+    /// The response `Date`, which Common Log Format reuses rather than formatting its own.
+    pub date: &'a str,
+}
+
+impl AccessLog {
+    pub fn open(path: &Path, format: AccessLogFormat) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            out: Mutex::new(BufWriter::with_capacity(64 * 1024, file)),
+            format,
+        })
+    }
+
+    pub fn log(&self, rec: &AccessRecord) {
+        let line = match self.format {
+            AccessLogFormat::Common => common_line(rec),
+            AccessLogFormat::Json => json_line(rec),
+        };
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = out.write_all(line.as_bytes());
+    }
+
+    pub fn flush(&self) {
+        let _ = self.out.lock().unwrap_or_else(|e| e.into_inner()).flush();
+    }
+}
+
+/// Common Log Format with the duration in microseconds appended, as Apache's `%D` does.
+fn common_line(rec: &AccessRecord) -> String {
+    // `Fri, 16 Oct 2026 10:49:37 GMT` to `16/Oct/2026:10:49:37 +0000`
+    let parts: Vec<&str> = rec.date.split(' ').collect();
+    let time = match parts[..] {
+        [_, day, month, year, clock, _] => format!("{}/{}/{}:{} +0000", day, month, year, clock),
+        _ => rec.date.to_string(),
+    };
+    let path = rec.path.replace('\\', "\\\\").replace('"', "\\\"");
+    let bytes = rec.bytes.map_or_else(|| "-".to_string(), |b| b.to_string());
+    format!(
+        "{} - - [{}] \"{} {} HTTP/1.{}\" {} {} {}\n",
+        rec.ip,
+        time,
+        rec.method,
+        path,
+        rec.http_minor,
+        rec.status,
+        bytes,
+        rec.duration.as_micros()
+    )
+}
+
+fn json_line(rec: &AccessRecord) -> String {
+    let mut line = serde_json::json!({
+        "time": format_rfc3339(SystemTime::now()),
+        "ip": rec.ip.to_string(),
+        "request_id": rec.request_id,
+        "method": rec.method,
+        "path": rec.path,
+        "protocol": format!("HTTP/1.{}", rec.http_minor),
+        "status": rec.status,
+        "bytes": rec.bytes,
+        "duration_us": rec.duration.as_micros() as u64,
+    })
+    .to_string();
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(bytes: Option<u64>) -> AccessRecord<'static> {
+        AccessRecord {
+            ip: "203.0.113.7".parse().unwrap(),
+            request_id: "r-1",
+            method: "GET",
+            path: "/a \"quoted\" \\path",
+            http_minor: 1,
+            status: 200,
+            bytes,
+            duration: Duration::from_micros(1500),
+            date: "Fri, 16 Oct 2026 10:49:37 GMT",
+        }
+    }
+
+    #[test]
+    fn common_line_escapes_the_path_and_appends_the_duration() {
+        assert_eq!(
+            common_line(&record(Some(42))),
+            "203.0.113.7 - - [16/Oct/2026:10:49:37 +0000] \"GET /a \\\"quoted\\\" \\\\path HTTP/1.1\" 200 42 1500\n"
+        );
+        assert!(common_line(&record(None)).ends_with("\" 200 - 1500\n"));
+    }
+
+    #[test]
+    fn json_line_is_one_object_per_line() {
+        let line = json_line(&record(None));
+        assert!(line.ends_with('\n') && !line.trim_end().contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["ip"], "203.0.113.7");
+        assert_eq!(value["request_id"], "r-1");
+        assert_eq!(value["path"], "/a \"quoted\" \\path");
+        assert_eq!(value["protocol"], "HTTP/1.1");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["bytes"], serde_json::Value::Null);
+        assert_eq!(value["duration_us"], 1500);
+    }
+}
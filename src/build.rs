@@ -0,0 +1,107 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{config::Config, render::render_markdown, state::ServerState, utils::get_all_files};
+
+/// What a static build wrote, and the pages it could not render.
+#[derive(Default)]
+pub struct BuildReport {
+    pub pages: usize,
+    pub assets: usize,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Renders every page under the content roots into `out_dir`, laid out by URL so a static host
+/// serves the same paths the server would: `index.md` becomes `index.html` and
+/// `posts/foo.md` becomes `posts/foo/index.html`. Other files are copied verbatim. As when
+/// serving, the first root holding a path wins, a page wins over a file it would overwrite, and
+/// hidden files other than `.well-known` are left out.
+pub fn build_site(mut config: Config, out_dir: &Path) -> io::Result<BuildReport> {
+    // there is no server behind a static build to reload from
+    config.server.live_reload = false;
+    let state = Arc::new(ServerState::new(config));
+    let failed_templates = crate::theme::reload_theme(&state);
+    if !failed_templates.is_empty() {
+        return Err(io::Error::other(format!(
+            "broken templates: {}",
+            failed_templates.join(", ")
+        )));
+    }
+
+    fs::create_dir_all(out_dir)?;
+    // the output may sit inside a content root; never read back what this build writes
+    let out_canon = fs::canonicalize(out_dir)?;
+    let mut report = BuildReport::default();
+    let mut sources = Vec::new();
+    let mut seen = HashSet::new();
+    for root in &state.content_roots {
+        let mut files = get_all_files(&root.canon, 0);
+        files.sort();
+        for path in files {
+            let Ok(rel) = path.strip_prefix(&root.canon) else {
+                continue;
+            };
+            let is_hidden = rel.components().any(|c| {
+                let part = c.as_os_str().to_string_lossy();
+                part.starts_with('.') && part != ".well-known"
+            });
+            if !is_hidden && !path.starts_with(&out_canon) && seen.insert(rel.to_path_buf()) {
+                let rel = rel.to_path_buf();
+                sources.push((path, rel));
+            }
+        }
+    }
+    // pages first, so they claim their output paths before any copied file
+    sources.sort_by_key(|(path, _)| path.extension().is_none_or(|ext| ext != "md"));
+
+    let mut written = HashSet::new();
+    for (path, rel) in sources {
+        if path.extension().is_some_and(|ext| ext == "md") {
+            let dest = page_output(&rel);
+            if !written.insert(dest.clone()) {
+                continue;
+            }
+            let rendered = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    render_markdown(&content, &path, &state).map_err(|e| e.to_string())
+                });
+            match rendered {
+                Ok(page) => {
+                    write_file(&out_dir.join(dest), page.body.as_bytes())?;
+                    report.pages += 1;
+                }
+                Err(e) => report.failed.push((path, e)),
+            }
+        } else if written.insert(rel.clone()) {
+            let dest = out_dir.join(rel);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&path, &dest)?;
+            report.assets += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Where a page's HTML goes, relative to the output directory.
+fn page_output(rel: &Path) -> PathBuf {
+    let stem = rel.with_extension("");
+    if stem.file_name().is_some_and(|name| name == "index") {
+        stem.with_extension("html")
+    } else {
+        stem.join("index.html")
+    }
+}
+
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)
+}
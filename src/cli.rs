@@ -1,6 +1,12 @@
-use crate::{config::load_config, server::start_server};
-use clap::{Parser, Subcommand};
+#[cfg(feature = "markdown")]
+use crate::build::build_site;
+use crate::{
+    config::{Config, load_config, upgrade_config},
+    server::start_server,
+};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::{fs, path::Path};
+use tracing_subscriber::{fmt::MakeWriter, util::SubscriberInitExt};
 
 #[derive(Parser)]
 #[command(
@@ -26,9 +32,45 @@ enum Commands {
         config: String,
         #[arg(long)]
         dev: bool,
+        #[arg(long)]
+        strict: bool,
+        #[arg(long)]
+        single_thread: bool,
+        #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+        log_format: LogFormat,
+        /// Print the effective configuration (file, defaults and flags merged) and exit.
+        #[arg(long)]
+        config_check: bool,
+    },
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Render the whole site to static files, for hosting without the server.
+    #[cfg(feature = "markdown")]
+    Build {
+        #[arg(short, long, default_value = "lumen.toml")]
+        config: String,
+        #[arg(short, long, default_value = "dist")]
+        out: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Add any fields missing from an existing config, keeping current values.
+    Upgrade {
+        #[arg(short, long, default_value = "lumen.toml")]
+        config: String,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
 pub fn execute() {
     let cli = Cli::parse();
     match cli.command {
@@ -37,36 +79,170 @@ pub fn execute() {
                 eprintln!("ERROR: Failed to initialize workspace at '{}': {}", path, e);
                 std::process::exit(1);
             }
+            println!(
+                "Lumen workspace initialized at '{}'. Run `lumen start --dev` to begin.",
+                path
+            );
         }
-        Commands::Start { port, config, dev } => {
-            let mut cfg = match load_config(&config) {
+        Commands::Start {
+            port,
+            config,
+            dev,
+            strict,
+            single_thread,
+            log_format,
+            config_check,
+        } => {
+            let flags = StartFlags {
+                port,
+                dev,
+                strict,
+                single_thread,
+            };
+            // re-run on SIGHUP, so the flags keep overriding the reloaded file
+            let load = move || flags.load(&config);
+            let cfg = match load() {
                 Ok(c) => c,
                 Err(e) => {
                     eprintln!("CRITICAL: {}", e);
                     std::process::exit(1);
                 }
             };
-
-            if let Some(p) = port {
-                cfg.server.port = p;
+            if config_check {
+                match effective_config(&cfg) {
+                    Ok(out) => print!("{}", out),
+                    Err(e) => {
+                        eprintln!("ERROR: Failed to serialize config: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                return;
             }
-            if dev {
-                cfg.performance.enable_caching = false;
-                println!("DEBUG: Developer mode enabled (Caching Disabled).");
-                tracing_subscriber::fmt()
-                    .with_max_level(tracing::Level::DEBUG)
-                    .init();
+
+            let level = if dev {
+                println!("DEBUG: Developer mode enabled (Caching Disabled, Live Reload On).");
+                tracing::Level::DEBUG
             } else {
-                tracing_subscriber::fmt()
-                    .with_max_level(tracing::Level::INFO)
-                    .init();
+                tracing::Level::INFO
+            };
+            log_subscriber(log_format, level, std::io::stdout).init();
+            start_server(cfg, Box::new(load));
+        }
+        Commands::Config {
+            action: ConfigAction::Upgrade { config },
+        } => match upgrade_config(&config) {
+            Ok(added) if added.is_empty() => println!("{} is already up to date.", config),
+            Ok(added) => {
+                println!("Added {} field(s) to {}:", added.len(), config);
+                for name in added {
+                    println!("  {}", name);
+                }
             }
-            start_server(cfg);
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        #[cfg(feature = "markdown")]
+        Commands::Build { config, out } => {
+            let cfg = match load_config(&config) {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("CRITICAL: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            match build_site(cfg, Path::new(&out)) {
+                Ok(report) => {
+                    for (path, e) in &report.failed {
+                        eprintln!("ERROR: {}: {}", path.display(), e);
+                    }
+                    println!(
+                        "Wrote {} page(s) and {} asset(s) to {}.",
+                        report.pages, report.assets, out
+                    );
+                    if !report.failed.is_empty() {
+                        eprintln!("{} page(s) failed to render.", report.failed.len());
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("ERROR: Failed to build into '{}': {}", out, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// The `start` flags that override the config file.
+#[derive(Clone, Copy)]
+struct StartFlags {
+    port: Option<u16>,
+    dev: bool,
+    strict: bool,
+    single_thread: bool,
+}
+
+impl StartFlags {
+    /// Loads the config at `path` with the flags applied on top.
+    fn load(&self, path: &str) -> Result<Config, String> {
+        let mut cfg = load_config(path)?;
+        if let Some(p) = self.port {
+            cfg.server.port = p;
+        }
+        if self.strict {
+            cfg.server.strict = true;
+        }
+        if self.single_thread {
+            cfg.server.threads = 0;
+        }
+        if self.dev {
+            cfg.performance.enable_caching = false;
+            cfg.server.live_reload = true;
         }
+        Ok(cfg)
     }
 }
 
-fn scaffold_workspace(base_path: &str) -> std::io::Result<()> {
+/// The `--config-check` dump: `cfg` as TOML, with credential passwords replaced so the
+/// output can be pasted into a bug report.
+fn effective_config(cfg: &Config) -> Result<String, String> {
+    let redact = |entry: &mut String| {
+        if let Some((user, _)) = entry.split_once(':') {
+            *entry = format!("{}:<redacted>", user);
+        }
+    };
+    let mut cfg = cfg.clone();
+    cfg.server.admin_credentials.iter_mut().for_each(redact);
+    cfg.security
+        .basic_auth
+        .values_mut()
+        .flatten()
+        .for_each(redact);
+    toml::to_string_pretty(&cfg).map_err(|e| e.to_string())
+}
+
+/// The subscriber `--log-format` selects, writing events at `level` and above to `writer`.
+fn log_subscriber<W>(
+    format: LogFormat,
+    level: tracing::Level,
+    writer: W,
+) -> Box<dyn tracing::Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    }
+}
+
+/// Writes a starter config, theme and content under `base_path`, leaving existing files alone.
+pub fn scaffold_workspace(base_path: &str) -> std::io::Result<()> {
     let base = Path::new(base_path);
     fs::create_dir_all(base.join("content/posts"))?;
     fs::create_dir_all(base.join("themes/default"))?;
@@ -80,25 +256,130 @@ host = "127.0.0.1"
 port = 8080
 name = "Lumen"
 threads = 16
+min_threads = 0
+thread_idle_timeout_secs = 30
 queue_size = 10000
 timeout_secs = 15
+keep_alive_timeout_secs = 5
+max_connection_lifetime_secs = 120
+max_keepalive_requests = 1000
+base_url = "http://127.0.0.1:8080"
+base_path = ""
+strict = false
+trust_request_id = false
+metrics_interval_secs = 0
+metrics_path = ""
+metrics_allow_ips = ["127.0.0.1", "::1"]
+admin_path = ""
+admin_credentials = []
+verify_content_length = true
+client_error_log_level = "off"
+server_error_log_level = "off"
+access_log = ""
+access_log_format = "common"
+alt_svc = ""
+trailing_slash = "keep"
+autoindex = false
+live_reload = false
 
 [paths]
 content_dir = "content"
+content_dirs = []
 theme_dir = "themes/default"
 fallback_404 = "<h1>404 - File Not Found</h1>"
+dir_precedence = "file"
+dir_redirect = "redirect"
+index_files = ["index.md", "index.html"]
+extensionless_html = false
+error_pages = {}
 
 [security]
 x_frame_options = "DENY"
 x_content_type_options = "nosniff"
 content_security_policy = "default-src 'self'; style-src 'self' 'unsafe-inline'; media-src 'self'"
 cors_allow_origin = ""
+cors_allow_methods = "GET, HEAD, OPTIONS"
+cors_allow_headers = ""
+strict_path_decoding = true
+basic_auth = {}
+allow_ips = []
+deny_ips = []
 
 [performance]
 enable_caching = true
 enable_compression = true
 max_cache_memory_mb = 256
+max_dir_cache_memory_mb = 0
 max_markdown_size_mb = 5
+max_rendered_bytes = 67108864
+max_memory_mb = 0
+cache_date_header = true
+generated_max_age = 300
+non_blocking_cache = false
+max_concurrent_renders = 0
+cache_key_cookies = []
+cache_key_query = ["page"]
+disk_cache_dir = ""
+disk_cache_gzip = true
+stream_threshold_bytes = 0
+
+[performance.compression]
+algorithms = ["br", "gzip", "deflate"]
+brotli_level = 4
+gzip_level = 6
+deflate_level = 6
+min_size = 256
+precompressed = true
+
+[seo]
+canonical_link_header = false
+redirect_index = true
+plaintext_alternates = false
+sitemap_url = ""
+disallow = []
+user_agent = {}
+
+[defaults]
+language = ""
+charset = "utf-8"
+
+[markdown]
+tables = true
+footnotes = false
+strikethrough = true
+tasklists = true
+smart_punctuation = true
+heading_attributes = false
+math = false
+gfm = false
+definition_list = false
+superscript = false
+subscript = false
+wikilinks = false
+heading_ids = "off"
+toc_max_depth = 3
+json_frontmatter = false
+
+[redirects]
+canonical_host = ""
+
+# Content-Type by file extension, checked before the built-in guesses.
+[mime]
+# webmanifest = "application/manifest+json"
+# txt = "text/plain; charset=utf-8"
+
+# Redirects checked before the filesystem; a trailing `*` in `from` appends the rest of the path.
+# [[redirect]]
+# from = "/old-blog/*"
+# to = "/blog/"
+# status = 301
+
+# Alternate templates per request; a page's `variants` frontmatter map can override them.
+# [[variants]]
+# name = "print"
+# query = "print=1"
+# user_agent = ""
+# template = "print.html"
 "#,
         )?;
     }
@@ -157,9 +438,132 @@ Server is running successfully!
         )?;
     }
 
-    println!(
-        "Lumen workspace initialized at '{}'. Run `lumen start --dev` to begin.",
-        base_path
-    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn start_log_format(args: &[&str]) -> LogFormat {
+        let cli = Cli::try_parse_from(["lumen", "start"].iter().chain(args)).unwrap();
+        match cli.command {
+            Commands::Start { log_format, .. } => log_format,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn log_format_defaults_to_text() {
+        assert!(matches!(start_log_format(&[]), LogFormat::Text));
+    }
+
+    #[test]
+    fn log_format_flag_parses() {
+        assert!(matches!(
+            start_log_format(&["--log-format", "json"]),
+            LogFormat::Json
+        ));
+        assert!(Cli::try_parse_from(["lumen", "start", "--log-format", "xml"]).is_err());
+    }
+
+    /// Collects everything a subscriber writes.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn log_line(format: LogFormat) -> String {
+        let out = Captured::default();
+        let subscriber = log_subscriber(format, tracing::Level::INFO, out.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(method = "GET", status = 404, "request");
+            tracing::debug!("below the level");
+        });
+        let bytes = out.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn json_format_writes_one_object_per_event() {
+        let out = log_line(LogFormat::Json);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 1, "{}", out);
+        let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(event["level"], "INFO");
+        assert!(event["timestamp"].is_string());
+        assert_eq!(event["target"], "lumen::cli::tests");
+        assert_eq!(event["fields"]["message"], "request");
+        assert_eq!(event["fields"]["method"], "GET");
+        assert_eq!(event["fields"]["status"], 404);
+    }
+
+    #[test]
+    fn text_format_writes_plain_lines() {
+        let out = log_line(LogFormat::Text);
+        assert_eq!(out.lines().count(), 1, "{}", out);
+        assert!(serde_json::from_str::<serde_json::Value>(&out).is_err());
+        assert!(out.contains("INFO"), "{}", out);
+        assert!(out.contains(" request "), "{}", out);
+        assert!(out.contains("404"), "{}", out);
+    }
+
+    #[test]
+    fn config_check_reflects_flags_over_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lumen.toml");
+        fs::write(&path, "[server]\nport = 8080\nthreads = 8\n").unwrap();
+        let flags = StartFlags {
+            port: Some(9000),
+            dev: true,
+            strict: false,
+            single_thread: true,
+        };
+        let out = effective_config(&flags.load(path.to_str().unwrap()).unwrap()).unwrap();
+        let dumped: Config = toml::from_str(&out).unwrap();
+        assert_eq!(dumped.server.port, 9000);
+        assert_eq!(dumped.server.threads, 0);
+        assert!(dumped.server.live_reload);
+        assert!(!dumped.performance.enable_caching);
+        assert!(!dumped.server.strict);
+        // untouched fields come through with their defaults
+        assert_eq!(dumped.server.name, Config::default().server.name);
+    }
+
+    #[test]
+    fn config_check_redacts_credentials() {
+        let mut cfg = Config::default();
+        cfg.server.admin_credentials = vec!["ops:sha256:abc123".into()];
+        cfg.security
+            .basic_auth
+            .insert("/private".into(), vec!["alice:hunter2".into()]);
+        let out = effective_config(&cfg).unwrap();
+        assert!(
+            !out.contains("hunter2") && !out.contains("abc123"),
+            "{}",
+            out
+        );
+        assert!(out.contains("\"alice:<redacted>\""), "{}", out);
+        assert!(out.contains("\"ops:<redacted>\""), "{}", out);
+        // the original is left alone
+        assert_eq!(cfg.security.basic_auth["/private"], ["alice:hunter2"]);
+    }
+}
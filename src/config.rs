@@ -1,23 +1,117 @@
-use serde::Deserialize;
-use std::{fs, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+use toml_edit::{DocumentMut, Item, Table, TableLike};
 
-#[derive(Deserialize, Clone, Default)]
+use crate::utils::parse_cidr;
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 #[serde(default)]
 pub struct Config {
+    /// Listening, connections, logging and the built-in endpoints.
     pub server: ServerConfig,
+    /// Where content and the theme live and how URLs map onto them.
     pub paths: PathConfig,
+    /// Response security headers, CORS, access control and path decoding.
     pub security: SecurityConfig,
+    /// Caching, compression, rendering and memory limits.
     pub performance: PerformanceConfig,
+    /// Canonical URLs, index redirects and the generated `robots.txt`.
+    pub seo: SeoConfig,
+    /// Page settings used when frontmatter doesn't give its own.
+    pub defaults: DefaultsConfig,
+    /// Markdown extensions and heading ids.
+    pub markdown: MarkdownConfig,
+    /// Site-wide redirects; per-path rules go in `[[redirect]]`.
+    pub redirects: RedirectsConfig,
+    /// Redirect rules checked in order before the filesystem, first match wins.
+    pub redirect: Vec<RedirectRule>,
+    /// `Content-Type` by file extension for static files, consulted before the built-in
+    /// guesses, e.g. `webmanifest = "application/manifest+json"`. The value is sent as is, so
+    /// a text type can carry its charset: `txt = "text/plain; charset=utf-8"`.
+    pub mime: BTreeMap<String, String>,
+    /// Alternate templates chosen per request, first match wins.
+    pub variants: Vec<TemplateVariant>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct ServerConfig {
+    /// Address to listen on.
     pub host: String,
+    /// Port to listen on.
     pub port: u16,
+    /// Sent as the `Server` header, and the site title in feeds.
     pub name: String,
+    /// Worker threads handling requests; 0 handles them on the event loop itself.
     pub threads: usize,
+    /// Workers kept running when idle; the pool grows from here to `threads` while requests
+    /// queue up and shrinks back once workers sit idle for `thread_idle_timeout_secs`. 0 keeps
+    /// all `threads` workers running.
+    pub min_threads: usize,
+    /// Seconds a worker above `min_threads` may sit idle before it exits.
+    pub thread_idle_timeout_secs: u64,
+    /// Requests waiting for a worker before the connections of new ones are closed unanswered.
     pub queue_size: usize,
+    /// How long a connection may take to deliver a complete request.
+    #[serde(alias = "read_timeout_secs")]
     pub timeout_secs: u64,
+    /// How long an idle connection is kept open waiting for its next request.
+    pub keep_alive_timeout_secs: u64,
+    /// Wall-clock cap on a connection, however active it is.
+    pub max_connection_lifetime_secs: u64,
+    /// Requests served on one connection before it is closed, pipelined or not; 0 is no limit.
+    pub max_keepalive_requests: u64,
+    /// Origin the site is published at, for absolute links in feeds, sitemaps and
+    /// redirects.
+    pub base_url: String,
+    /// Path prefix the site is published under, e.g. `/docs`. It is prefixed to generated
+    /// links and redirects and stripped from request paths that carry it; `base_url` stays
+    /// the bare origin.
+    pub base_path: String,
+    /// Refuse to start when any theme template fails to compile.
+    pub strict: bool,
+    /// Echo a client-supplied `X-Request-Id` instead of generating one (enable behind a proxy).
+    pub trust_request_id: bool,
+    /// Log a `metrics` event with traffic and cache counters this often; 0 disables it.
+    pub metrics_interval_secs: u64,
+    /// Path the counters are served at in Prometheus text format, e.g. `/metrics`; empty
+    /// disables the endpoint.
+    pub metrics_path: String,
+    /// CIDR blocks allowed to read `metrics_path`; everyone else gets a 403.
+    pub metrics_allow_ips: Vec<String>,
+    /// Path of the cache admin endpoint, e.g. `/admin/cache`: GET reports cache statistics
+    /// and POST with `?action=purge` empties the caches. Empty disables it.
+    pub admin_path: String,
+    /// `user:password` or `user:sha256:<hex>` entries, as in `security.basic_auth`, allowed
+    /// to use `admin_path`.
+    pub admin_credentials: Vec<String>,
+    /// Close the connection when a body falls short of its declared `Content-Length`, rather
+    /// than let the next response on it be read as the missing bytes. A file that shrinks
+    /// while it is sent always closes it.
+    pub verify_content_length: bool,
+    /// Level 4xx responses are logged at.
+    pub client_error_log_level: LogLevel,
+    /// Level 5xx responses are logged at.
+    pub server_error_log_level: LogLevel,
+    /// File every response is appended to, one line each; empty disables it.
+    pub access_log: String,
+    /// `common` (Common Log Format) or `json` lines in `access_log`.
+    pub access_log_format: AccessLogFormat,
+    /// `Alt-Svc` value sent on every response to advertise other endpoints, e.g.
+    /// `h3=":443"; ma=86400` when a proxy in front offers HTTP/3; empty sends none.
+    pub alt_svc: String,
+    /// Whether pages and directories are canonical with or without a trailing slash.
+    pub trailing_slash: TrailingSlash,
+    /// List a directory's contents when it has no index file, through the theme's
+    /// `autoindex.html` when there is one.
+    pub autoindex: bool,
+    /// Reload open pages in the browser when content or theme files change, over a
+    /// WebSocket the page script holds open. `--dev` turns it on.
+    pub live_reload: bool,
 }
 impl Default for ServerConfig {
     fn default() -> Self {
@@ -26,34 +120,158 @@ impl Default for ServerConfig {
             port: 8080,
             name: "Lumen".into(),
             threads: 32,
+            min_threads: 0,
+            thread_idle_timeout_secs: 30,
             queue_size: 10_000,
             timeout_secs: 15,
+            keep_alive_timeout_secs: 5,
+            max_connection_lifetime_secs: 120,
+            max_keepalive_requests: 1000,
+            base_url: "http://127.0.0.1:8080".into(),
+            base_path: "".into(),
+            strict: false,
+            trust_request_id: false,
+            metrics_interval_secs: 0,
+            metrics_path: "".into(),
+            metrics_allow_ips: vec!["127.0.0.1".into(), "::1".into()],
+            admin_path: "".into(),
+            admin_credentials: Vec::new(),
+            verify_content_length: true,
+            client_error_log_level: LogLevel::Off,
+            server_error_log_level: LogLevel::Off,
+            access_log: "".into(),
+            access_log_format: AccessLogFormat::Common,
+            alt_svc: "".into(),
+            trailing_slash: TrailingSlash::Keep,
+            autoindex: false,
+            live_reload: false,
         }
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format, with the duration in microseconds appended.
+    Common,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Outside `keep`, each page and directory has one URL and the other spelling 301s to it.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    /// `/posts/foo` is `foo.md` and `/posts/foo/` is `foo/index.md`; `paths.dir_redirect`
+    /// decides slashless directories.
+    Keep,
+    /// Pages and directories live at `/posts/foo/`; `/posts/foo/` falls back to `foo.md`
+    /// when there is no `foo/` index.
+    AlwaysRedirect,
+    /// Pages and directories live at `/posts/foo`, with a directory's index served there as
+    /// `dir_redirect = "index"` does.
+    NeverRedirect,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct PathConfig {
+    /// Directory pages and static files are served from.
     pub content_dir: String,
+    /// Ordered overlay roots; when set, the first root holding a path wins over `content_dir`.
+    pub content_dirs: Vec<String>,
+    /// Directory holding the theme's templates.
     pub theme_dir: String,
+    /// Body of the built-in 404 page, when `error_pages` has none.
     pub fallback_404: String,
+    /// What `/posts` resolves to when both `posts.md` and a `posts/` directory exist.
+    pub dir_precedence: DirPrecedence,
+    /// What a slashless request for a directory gets when it isn't answered by a file.
+    pub dir_redirect: DirRedirect,
+    /// Files tried in order for a directory URL; `.md` entries are rendered, others sent as is.
+    pub index_files: Vec<String>,
+    /// Serve `about.html` at `/about` when there is no `about.md` or literal `about`.
+    pub extensionless_html: bool,
+    /// Files sent for 403, 404, 500 and 503 responses, keyed by status. Markdown files are
+    /// rendered through the theme; a file that fails to load falls back to the built-in page
+    /// (`fallback_404` for 404s).
+    pub error_pages: BTreeMap<String, String>,
 }
 impl Default for PathConfig {
     fn default() -> Self {
         Self {
             content_dir: "content".into(),
+            content_dirs: Vec::new(),
             theme_dir: "themes/default".into(),
             fallback_404: "404".into(),
+            dir_precedence: DirPrecedence::File,
+            dir_redirect: DirRedirect::Redirect,
+            index_files: vec!["index.md".into(), "index.html".into()],
+            extensionless_html: false,
+            error_pages: BTreeMap::new(),
         }
     }
 }
 
-#[derive(Deserialize, Clone)]
+/// A trailing-slash request always prefers the directory's `index.md` over a sibling file;
+/// this only decides the slashless case.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DirPrecedence {
+    /// Serve `posts.md` at `/posts`.
+    File,
+    /// Redirect `/posts` to `/posts/`.
+    Directory,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DirRedirect {
+    /// 301 `/posts` to `/posts/`.
+    Redirect,
+    /// Answer 404, for setups that route slashless URLs elsewhere.
+    NotFound,
+    /// Serve `/posts/`'s index at `/posts` itself; relative links in it resolve one level up.
+    Index,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct SecurityConfig {
+    /// `X-Frame-Options` sent on every response.
     pub x_frame_options: String,
+    /// `X-Content-Type-Options` sent on every response.
     pub x_content_type_options: String,
+    /// `Content-Security-Policy` sent on every response.
     pub content_security_policy: String,
+    /// `Access-Control-Allow-Origin` sent on every response; empty disables CORS.
     pub cors_allow_origin: String,
+    /// `Access-Control-Allow-Methods` sent on CORS preflights.
+    pub cors_allow_methods: String,
+    /// `Access-Control-Allow-Headers` sent on CORS preflights; empty omits it.
+    pub cors_allow_headers: String,
+    /// Answer 400 to paths with malformed `%` escapes or non-UTF-8 bytes instead of
+    /// resolving the still-encoded text.
+    pub strict_path_decoding: bool,
+    /// Path prefixes that require HTTP Basic credentials, each listing `user:password` or
+    /// `user:sha256:<hex digest of the password>` entries. The longest matching prefix applies.
+    /// The part after `user:` may be `env:NAME` or `file:/path` to read it from there instead.
+    pub basic_auth: BTreeMap<String, Vec<String>>,
+    /// CIDR blocks (or single addresses) allowed to connect; empty allows everyone.
+    pub allow_ips: Vec<String>,
+    /// CIDR blocks refused even when also allowed. Refused clients get a 403 and are
+    /// disconnected before their request is read.
+    pub deny_ips: Vec<String>,
 }
 impl Default for SecurityConfig {
     fn default() -> Self {
@@ -63,16 +281,57 @@ impl Default for SecurityConfig {
             content_security_policy:
                 "default-src 'self'; style-src 'self' 'unsafe-inline'; media-src 'self'".into(),
             cors_allow_origin: "".into(),
+            cors_allow_methods: "GET, HEAD, OPTIONS".into(),
+            cors_allow_headers: "".into(),
+            strict_path_decoding: true,
+            basic_auth: BTreeMap::new(),
+            allow_ips: vec![],
+            deny_ips: vec![],
         }
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct PerformanceConfig {
+    /// Keep rendered pages in memory until their source or the theme changes.
     pub enable_caching: bool,
+    /// Compress responses for clients that accept it.
     pub enable_compression: bool,
+    /// Byte budget for the in-memory page cache.
     pub max_cache_memory_mb: usize,
+    /// Byte budget for each of the caches behind `list_dir`, the tag functions and `toc()`,
+    /// on top of their 10,000-entry cap; 0 leaves only the entry cap.
+    pub max_dir_cache_memory_mb: usize,
+    /// Larger markdown files are refused with 413 rather than rendered.
     pub max_markdown_size_mb: usize,
+    /// Abort a render with 500 once its output passes this many bytes; 0 disables the cap.
+    pub max_rendered_bytes: usize,
+    /// Shed new requests with 503 above this much memory; 0 disables the check.
+    pub max_memory_mb: usize,
+    /// Format the `Date` header once per second on a ticker thread instead of per response.
+    pub cache_date_header: bool,
+    /// `Cache-Control: max-age` for synthesized responses such as feeds and sitemaps.
+    pub generated_max_age: u64,
+    /// Algorithms and levels used when `enable_compression` is on.
+    pub compression: CompressionConfig,
+    /// Never wait on a locked page-cache shard: lookups miss and inserts are dropped instead.
+    pub non_blocking_cache: bool,
+    /// Cap on markdown pages rendered at once; 0 leaves it to the worker count.
+    pub max_concurrent_renders: usize,
+    /// Cookies exposed to cached pages' templates; their values become part of the cache key.
+    pub cache_key_cookies: Vec<String>,
+    /// Query parameters exposed to cached pages' templates as `query`; like
+    /// `cache_key_cookies`, their values become part of the cache key.
+    pub cache_key_query: Vec<String>,
+    /// Persist rendered pages here so they survive restarts; empty disables the disk cache.
+    pub disk_cache_dir: String,
+    /// Store disk cache entries gzipped, which also serves them to gzip clients as-is.
+    pub disk_cache_gzip: bool,
+    /// With the page cache off, send renders larger than this as they are produced using
+    /// chunked encoding instead of buffering them; 0 always buffers. At most `server.threads`
+    /// pages stream at once, the rest render buffered.
+    pub stream_threshold_bytes: usize,
 }
 impl Default for PerformanceConfig {
     fn default() -> Self {
@@ -80,16 +339,448 @@ impl Default for PerformanceConfig {
             enable_caching: true,
             enable_compression: true,
             max_cache_memory_mb: 256,
+            max_dir_cache_memory_mb: 0,
             max_markdown_size_mb: 5,
+            max_rendered_bytes: 64 * 1024 * 1024,
+            max_memory_mb: 0,
+            cache_date_header: true,
+            generated_max_age: 300,
+            compression: CompressionConfig::default(),
+            non_blocking_cache: false,
+            max_concurrent_renders: 0,
+            cache_key_cookies: Vec::new(),
+            cache_key_query: vec!["page".into()],
+            disk_cache_dir: "".into(),
+            disk_cache_gzip: true,
+            stream_threshold_bytes: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+    #[serde(rename = "br")]
+    Brotli,
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "deflate")]
+    Deflate,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Server preference; the first one the client accepts is used.
+    pub algorithms: Vec<Algorithm>,
+    /// Brotli quality, 0-11.
+    pub brotli_level: u32,
+    /// Gzip level, 0-9.
+    pub gzip_level: u32,
+    /// Deflate level, 0-9.
+    pub deflate_level: u32,
+    /// Bodies smaller than this many bytes are always sent uncompressed.
+    pub min_size: usize,
+    /// Serve a static file's up-to-date `.br`/`.gz` sibling instead of compressing it.
+    pub precompressed: bool,
+}
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: vec![Algorithm::Brotli, Algorithm::Gzip, Algorithm::Deflate],
+            brotli_level: 4,
+            gzip_level: 6,
+            deflate_level: 6,
+            min_size: 256,
+            precompressed: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SeoConfig {
+    /// Send a `Link: <url>; rel="canonical"` header with each rendered page.
+    pub canonical_link_header: bool,
+    /// 301 `/index` and `/index.html` (at any depth) to the directory URL.
+    pub redirect_index: bool,
+    /// Serve a page's markdown source (minus frontmatter) as text/plain at `/page.txt` and to
+    /// requests preferring `text/plain` over `text/html`.
+    pub plaintext_alternates: bool,
+    /// Listed as `Sitemap:` in the generated `/robots.txt`; a path is made absolute against
+    /// `base_url`.
+    pub sitemap_url: String,
+    /// Paths disallowed for every crawler in the generated `/robots.txt`.
+    pub disallow: Vec<String>,
+    /// Paths disallowed per crawler, by user agent. A `robots.txt` in the content directory
+    /// replaces the generated one, which is only served when one of these is set.
+    pub user_agent: BTreeMap<String, Vec<String>>,
+}
+impl Default for SeoConfig {
+    fn default() -> Self {
+        Self {
+            canonical_link_header: false,
+            redirect_index: true,
+            plaintext_alternates: false,
+            sitemap_url: "".into(),
+            disallow: vec![],
+            user_agent: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DefaultsConfig {
+    /// `Content-Language` of pages without a `language`; empty sends none.
+    pub language: String,
+    /// Charset declared for pages and generated text without a `charset` of their own.
+    pub charset: String,
+}
+impl Default for DefaultsConfig {
+    fn default() -> Self {
+        Self {
+            language: "".into(),
+            charset: "utf-8".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RedirectsConfig {
+    /// Preferred `Host`, e.g. `example.com`. GET and HEAD requests naming another host are
+    /// 301'd to the same path on this one, keeping the `base_url` scheme. Without a port it
+    /// matches the request host on any port. Empty disables the redirect.
+    pub canonical_host: String,
+}
+
+/// pulldown-cmark extensions applied to page bodies and feed content.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MarkdownConfig {
+    /// Pipe tables.
+    pub tables: bool,
+    /// `[^1]` footnotes.
+    pub footnotes: bool,
+    /// `~~struck~~` text.
+    pub strikethrough: bool,
+    /// `- [ ]` task list items.
+    pub tasklists: bool,
+    /// Curly quotes, dashes and ellipses from their ASCII spellings.
+    pub smart_punctuation: bool,
+    /// `# Heading {#id .class}`
+    pub heading_attributes: bool,
+    /// `$inline$` and `$$display$$` math, emitted for a client-side renderer.
+    pub math: bool,
+    /// GitHub-style blockquote alerts such as `> [!NOTE]`.
+    pub gfm: bool,
+    /// Definition lists: a term line followed by `: definition`.
+    pub definition_list: bool,
+    /// `^superscript^` text.
+    pub superscript: bool,
+    /// `~subscript~` text.
+    pub subscript: bool,
+    /// `[[Page]]` links.
+    pub wikilinks: bool,
+    /// Give headings without an explicit id a slug of their text, optionally with a `#`
+    /// permalink inside.
+    pub heading_ids: HeadingIds,
+    /// Deepest heading level `toc()` lists unless the template passes its own.
+    pub toc_max_depth: u8,
+    /// Also read frontmatter from a leading JSON object or a `;;;`-fenced JSON block. Off by
+    /// default so a page that merely starts with `{` keeps it as text.
+    pub json_frontmatter: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HeadingIds {
+    Off,
+    Ids,
+    /// Ids plus an `<a class="anchor">` link to each heading.
+    Permalinks,
+}
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            tables: true,
+            footnotes: false,
+            strikethrough: true,
+            tasklists: true,
+            smart_punctuation: true,
+            heading_attributes: false,
+            math: false,
+            gfm: false,
+            definition_list: false,
+            superscript: false,
+            subscript: false,
+            wikilinks: false,
+            heading_ids: HeadingIds::Off,
+            toc_max_depth: 3,
+            json_frontmatter: false,
+        }
+    }
+}
+
+/// Renders pages with `template` when the request carries `query` (`key` or `key=value`)
+/// or its `User-Agent` contains `user_agent` (case-insensitive). Empty matchers never match.
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct TemplateVariant {
+    pub name: String,
+    pub query: String,
+    pub user_agent: String,
+    pub template: String,
+}
+
+impl TemplateVariant {
+    /// Identifies the variant in frontmatter `variants` maps and in page cache keys.
+    pub fn id(&self) -> &str {
+        if self.name.is_empty() {
+            &self.template
+        } else {
+            &self.name
+        }
+    }
+
+    pub fn matches(&self, query: Option<&str>, user_agent: &str) -> bool {
+        let query_hit = !self.query.is_empty()
+            && query.is_some_and(|q| {
+                q.split('&').any(|pair| {
+                    pair == self.query
+                        || (!self.query.contains('=')
+                            && pair.split('=').next() == Some(self.query.as_str()))
+                })
+            });
+        let ua_hit = !self.user_agent.is_empty()
+            && user_agent
+                .to_ascii_lowercase()
+                .contains(&self.user_agent.to_ascii_lowercase());
+        query_hit || ua_hit
+    }
+}
+
+/// Sends requests for `from` to `to` with `status`. `from` is a site path matched exactly,
+/// or as a prefix when it ends in `*`, in which case the rest of the path is appended to
+/// `to`. `to` is an absolute URL or a site path, which gets `server.base_path` prepended.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    pub status: u16,
+}
+impl Default for RedirectRule {
+    fn default() -> Self {
+        Self {
+            from: String::new(),
+            to: String::new(),
+            status: 301,
+        }
+    }
+}
+
+impl RedirectRule {
+    /// The target for `path` (decoded, without query or `base_path`) if the rule applies.
+    pub fn target(&self, path: &str) -> Option<String> {
+        match self.from.strip_suffix('*') {
+            Some(prefix) => path
+                .strip_prefix(prefix)
+                .map(|rest| format!("{}{}", self.to, rest)),
+            None => (path == self.from).then(|| self.to.clone()),
+        }
+    }
+
+    /// Whether `to` leaves the site rather than naming a path on it.
+    pub fn is_external(&self) -> bool {
+        self.to.contains("://") || self.to.starts_with("//")
+    }
+}
+
+impl Config {
+    /// Rejects values that parse but leave the server unable to serve: a zero timeout drops
+    /// every connection before its first read, and a zero queue refuses every request.
+    pub fn validate(&self) -> Result<(), String> {
+        let server = &self.server;
+        for (name, value) in [
+            ("server.timeout_secs", server.timeout_secs),
+            (
+                "server.keep_alive_timeout_secs",
+                server.keep_alive_timeout_secs,
+            ),
+            (
+                "server.max_connection_lifetime_secs",
+                server.max_connection_lifetime_secs,
+            ),
+        ] {
+            if value == 0 {
+                return Err(format!("Invalid config: {} must be at least 1", name));
+            }
+        }
+        if server.threads > 0 && server.queue_size == 0 {
+            return Err(
+                "Invalid config: server.queue_size must be at least 1 when server.threads is set"
+                    .into(),
+            );
+        }
+        if server.threads > 0 && server.min_threads > server.threads {
+            return Err("Invalid config: server.min_threads must not exceed server.threads".into());
+        }
+        if server.min_threads > 0 && server.thread_idle_timeout_secs == 0 {
+            return Err(
+                "Invalid config: server.thread_idle_timeout_secs must be at least 1 when server.min_threads is set"
+                    .into(),
+            );
+        }
+        if !server.admin_path.is_empty() && server.admin_credentials.is_empty() {
+            return Err(
+                "Invalid config: server.admin_path needs server.admin_credentials to log in with"
+                    .into(),
+            );
+        }
+        if let Some(status) = self
+            .paths
+            .error_pages
+            .keys()
+            .find(|s| !["403", "404", "500", "503"].contains(&s.as_str()))
+        {
+            return Err(format!(
+                "Invalid config: paths.error_pages has '{}', but only 403, 404, 500 and 503 pages can be set",
+                status
+            ));
+        }
+        if !server.alt_svc.is_empty() && !is_valid_alt_svc(&server.alt_svc) {
+            return Err(format!(
+                "Invalid config: server.alt_svc '{}' is not `clear` or a list of `protocol=\"host:port\"; ma=seconds` entries",
+                server.alt_svc
+            ));
+        }
+        let canonical_host = &self.redirects.canonical_host;
+        if !canonical_host.is_empty() && !is_valid_host_port(canonical_host) {
+            return Err(format!(
+                "Invalid config: redirects.canonical_host {:?} is not a `host` or `host:port`",
+                canonical_host
+            ));
+        }
+        for (ext, mime) in &self.mime {
+            if ext.trim_start_matches('.').is_empty() || ext.contains('/') {
+                return Err(format!(
+                    "Invalid config: mime key '{}' is not a file extension",
+                    ext
+                ));
+            }
+            let (essence, _) = mime.split_once(';').unwrap_or((mime, ""));
+            if essence.trim().split('/').filter(|p| !p.is_empty()).count() != 2
+                || mime.chars().any(|c| c.is_control())
+            {
+                return Err(format!(
+                    "Invalid config: mime type '{}' for '{}' is not `type/subtype`",
+                    mime, ext
+                ));
+            }
+        }
+        for rule in &self.redirect {
+            if !rule.from.starts_with('/')
+                || rule
+                    .from
+                    .strip_suffix('*')
+                    .unwrap_or(&rule.from)
+                    .contains('*')
+            {
+                return Err(format!(
+                    "Invalid config: redirect from '{}' must be a path starting with '/', with `*` only at the end",
+                    rule.from
+                ));
+            }
+            if rule.to.is_empty() || !(rule.to.starts_with('/') || rule.is_external()) {
+                return Err(format!(
+                    "Invalid config: redirect to '{}' for '{}' must be an absolute URL or a path starting with '/'",
+                    rule.to, rule.from
+                ));
+            }
+            if ![301, 302, 303, 307, 308].contains(&rule.status) {
+                return Err(format!(
+                    "Invalid config: redirect status {} for '{}' must be 301, 302, 303, 307 or 308",
+                    rule.status, rule.from
+                ));
+            }
+        }
+        for (name, list) in [
+            ("security.allow_ips", &self.security.allow_ips),
+            ("security.deny_ips", &self.security.deny_ips),
+            ("server.metrics_allow_ips", &self.server.metrics_allow_ips),
+        ] {
+            if let Some(bad) = list.iter().find(|s| parse_cidr(s).is_none()) {
+                return Err(format!(
+                    "Invalid config: {} entry '{}' is not an address or CIDR block",
+                    name, bad
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces `env:` and `file:` passwords in credential entries with the secret they name,
+    /// so a config that loads has every credential in hand.
+    pub fn resolve_secrets(&mut self) -> Result<(), String> {
+        for (prefix, entries) in &mut self.security.basic_auth {
+            for entry in entries {
+                resolve_credential(entry).map_err(|e| {
+                    format!("Invalid config: security.basic_auth '{}': {}", prefix, e)
+                })?;
+            }
+        }
+        for entry in &mut self.server.admin_credentials {
+            resolve_credential(entry)
+                .map_err(|e| format!("Invalid config: server.admin_credentials: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Copies over from `running` the settings that only take effect at startup, returning
+    /// the dotted names of those that differed.
+    pub fn keep_startup_settings(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut kept = Vec::new();
+        macro_rules! keep {
+            ($($section:ident . $field:ident),* $(,)?) => {$(
+                if self.$section.$field != running.$section.$field {
+                    self.$section.$field = running.$section.$field.clone();
+                    kept.push(concat!(stringify!($section), ".", stringify!($field)));
+                }
+            )*};
         }
+        keep!(
+            server.host,
+            server.port,
+            server.threads,
+            server.min_threads,
+            server.thread_idle_timeout_secs,
+            server.queue_size,
+            server.metrics_interval_secs,
+            server.access_log,
+            server.access_log_format,
+            server.live_reload,
+            paths.content_dir,
+            paths.content_dirs,
+            performance.max_memory_mb,
+            performance.cache_date_header,
+            performance.max_concurrent_renders,
+            performance.disk_cache_dir,
+            performance.disk_cache_gzip,
+        );
+        kept
     }
 }
 
 pub fn load_config(path: &str) -> Result<Config, String> {
     if Path::new(path).exists() {
         match fs::read_to_string(path) {
-            Ok(content) => match toml::from_str(&content) {
-                Ok(c) => Ok(c),
+            Ok(content) => match toml::from_str::<Config>(&content) {
+                Ok(mut c) => {
+                    c.validate()?;
+                    c.resolve_secrets()?;
+                    Ok(c)
+                }
                 Err(e) => Err(format!("Failed to parse config file: {}", e)),
             },
             Err(e) => Err(format!("Failed to read config file '{}': {}", path, e)),
@@ -98,3 +789,699 @@ pub fn load_config(path: &str) -> Result<Config, String> {
         Ok(Config::default())
     }
 }
+
+/// Adds every field missing from the config at `path`, set to its default and commented
+/// with its documentation. Existing values, comments, formatting and unknown keys are left
+/// as they are. The original is saved as `.bak`. Returns the dotted names of the fields that
+/// were added.
+pub fn upgrade_config(path: &str) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file '{}': {}", path, e))?;
+    toml::from_str::<Config>(&content)
+        .map_err(|e| format!("Failed to parse config file: {}", e))?;
+    let mut doc: DocumentMut = content
+        .parse()
+        .map_err(|e| format!("Failed to parse config file: {}", e))?;
+    let defaults: DocumentMut = toml::to_string(&Config::default())
+        .map_err(|e| format!("Failed to serialize defaults: {}", e))?
+        .parse()
+        .map_err(|e| format!("Failed to serialize defaults: {}", e))?;
+
+    // Spell serde aliases the canonical way so they aren't "added" a second time.
+    for (section, alias, key) in [("server", "read_timeout_secs", "timeout_secs")] {
+        if let Some(t) = doc.get_mut(section).and_then(Item::as_table_like_mut)
+            && let Some(decor) = t.key(alias).map(|k| k.leaf_decor().clone())
+            && let Some(v) = t.remove(alias)
+        {
+            t.insert(key, v);
+            if let Some(mut k) = t.key_mut(key) {
+                *k.leaf_decor_mut() = decor;
+            }
+        }
+    }
+
+    let first_table = first_header(doc.as_table());
+    let mut added = Vec::new();
+    fill_missing(
+        doc.as_table_mut(),
+        true,
+        defaults.as_table(),
+        "",
+        &field_docs(),
+        &mut added,
+    );
+    if added.is_empty() {
+        return Ok(added);
+    }
+    // Whatever now prints above what used to be the first table is set apart from it.
+    let mut body = doc.to_string();
+    if let Some((_, path)) = first_table
+        && let Some(table) = path
+            .iter()
+            .try_fold(doc.as_table_mut(), |t, key| t.get_mut(key)?.as_table_mut())
+    {
+        let prefix = table
+            .decor()
+            .prefix()
+            .and_then(|p| p.as_str())
+            .unwrap_or("")
+            .to_string();
+        if !prefix.starts_with('\n') && !body.starts_with(&format!("{}[", prefix)) {
+            table.decor_mut().set_prefix(format!("\n{}", prefix));
+            body = doc.to_string();
+        }
+    }
+    toml::from_str::<Config>(&body).map_err(|e| {
+        format!(
+            "Upgraded config would not load, leaving it untouched: {}",
+            e
+        )
+    })?;
+    fs::copy(path, format!("{}.bak", path))
+        .map_err(|e| format!("Failed to back up '{}': {}", path, e))?;
+    fs::write(path, body).map_err(|e| format!("Failed to write config file '{}': {}", path, e))?;
+    Ok(added)
+}
+
+/// Checks for a bare `host` or `host:port`, the host a name, an IPv4 address or a bracketed
+/// IPv6 address, so it can go into a `Location` header as is.
+fn is_valid_host_port(value: &str) -> bool {
+    let (host, port) = match value.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') && !value.ends_with(']') => (host, Some(port)),
+        _ => (value, None),
+    };
+    let host_ok = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(v6) => v6.parse::<std::net::Ipv6Addr>().is_ok(),
+        None => {
+            !host.is_empty()
+                && host
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b))
+        }
+    };
+    host_ok
+        && port.is_none_or(|p| p.bytes().all(|b| b.is_ascii_digit()) && p.parse::<u16>().is_ok())
+}
+
+/// Checks an `Alt-Svc` field value against RFC 7838: `clear`, or comma-separated
+/// `protocol-id="[host]:port"` alternatives, each optionally followed by `; ma=<seconds>`,
+/// `; persist=1` or other `; name=value` parameters.
+fn is_valid_alt_svc(value: &str) -> bool {
+    fn is_token(s: &str) -> bool {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+    }
+    if value.trim() == "clear" {
+        return true;
+    }
+    value.split(',').all(|alt| {
+        let mut parts = alt.split(';').map(str::trim);
+        let Some((protocol, authority)) = parts.next().and_then(|p| p.split_once('=')) else {
+            return false;
+        };
+        let Some(authority) = authority
+            .strip_prefix('"')
+            .and_then(|a| a.strip_suffix('"'))
+        else {
+            return false;
+        };
+        let port_ok = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+        is_token(protocol)
+            && port_ok
+            && parts.all(|param| match param.split_once('=') {
+                Some(("ma", secs)) => !secs.is_empty() && secs.bytes().all(|b| b.is_ascii_digit()),
+                Some(("persist", flag)) => flag == "1",
+                Some((name, value)) => is_token(name) && !value.is_empty(),
+                None => false,
+            })
+    })
+}
+
+/// Inserts the entries of `defaults` missing from `table`, recursing into tables present in
+/// both. Each added key gets its doc comment when `commented`, which inline tables can't be.
+fn fill_missing(
+    table: &mut dyn TableLike,
+    commented: bool,
+    defaults: &Table,
+    prefix: &str,
+    docs: &HashMap<String, String>,
+    added: &mut Vec<String>,
+) {
+    for (key, default) in defaults {
+        let name = format!("{}{}", prefix, key);
+        let nested_prefix = format!("{}.", name);
+        match (table.get_mut(key), default) {
+            (None, _) => {
+                let item = match default {
+                    Item::Table(nested) => {
+                        let mut new = Table::new();
+                        fill_missing(
+                            &mut new,
+                            true,
+                            nested,
+                            &nested_prefix,
+                            docs,
+                            &mut Vec::new(),
+                        );
+                        Item::Table(new)
+                    }
+                    other => other.clone(),
+                };
+                table.insert(key, item);
+                if let Some(doc) = docs.get(&name).filter(|_| commented) {
+                    let comment: String = doc.lines().map(|l| format!("# {}\n", l)).collect();
+                    match table.get_mut(key) {
+                        Some(Item::Table(new)) => {
+                            new.decor_mut().set_prefix(format!("\n{}", comment))
+                        }
+                        _ => {
+                            if let Some(mut k) = table.key_mut(key) {
+                                k.leaf_decor_mut().set_prefix(comment);
+                            }
+                        }
+                    }
+                }
+                added.push(name);
+            }
+            (Some(existing), Item::Table(nested)) => {
+                let commented = existing.is_table();
+                if let Some(existing) = existing.as_table_like_mut() {
+                    fill_missing(existing, commented, nested, &nested_prefix, docs, added);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The position and key path of the table whose `[header]` prints first, if any.
+fn first_header(table: &Table) -> Option<(isize, Vec<String>)> {
+    table
+        .iter()
+        .filter_map(|(key, item)| Some((key, item.as_table()?)))
+        .flat_map(|(key, nested)| {
+            let prints = !nested.is_implicit() || nested.iter().any(|(_, i)| i.is_value());
+            let own = nested
+                .position()
+                .filter(|_| prints)
+                .map(|pos| (pos, vec![key.to_string()]));
+            let inner = first_header(nested).map(|(pos, mut path)| {
+                path.insert(0, key.to_string());
+                (pos, path)
+            });
+            own.into_iter().chain(inner)
+        })
+        .min_by_key(|(pos, _)| *pos)
+}
+
+/// Doc comments of the config fields by dotted name, read out of this file so the keys
+/// `upgrade_config` adds are explained just as the code explains them.
+fn field_docs() -> HashMap<String, String> {
+    // struct name -> (field, type, doc) in declaration order
+    let mut structs: HashMap<&str, Vec<(&str, &str, String)>> = HashMap::new();
+    let mut current = None;
+    let mut doc: Vec<&str> = Vec::new();
+    for line in include_str!("config.rs").lines() {
+        if let Some(rest) = line.strip_prefix("pub struct ") {
+            current = rest.split_whitespace().next();
+        } else if line == "}" {
+            current = None;
+        } else if let Some(text) = line.strip_prefix("    ///") {
+            doc.push(text.trim_start());
+            continue;
+        } else if line.trim_start().starts_with("#[") {
+            continue;
+        } else if let Some(name) = current
+            && let Some((field, ty)) = line
+                .strip_prefix("    pub ")
+                .and_then(|f| f.split_once(": "))
+        {
+            structs.entry(name).or_default().push((
+                field,
+                ty.trim_end_matches(','),
+                doc.join("\n"),
+            ));
+        }
+        doc.clear();
+    }
+
+    fn walk(
+        structs: &HashMap<&str, Vec<(&str, &str, String)>>,
+        name: &str,
+        prefix: &str,
+        docs: &mut HashMap<String, String>,
+    ) {
+        for (field, ty, doc) in structs.get(name).into_iter().flatten() {
+            let dotted = format!("{}{}", prefix, field);
+            walk(structs, ty, &format!("{}.", dotted), docs);
+            if !doc.is_empty() {
+                docs.insert(dotted, doc.clone());
+            }
+        }
+    }
+    let mut docs = HashMap::new();
+    walk(&structs, "Config", "", &mut docs);
+    docs
+}
+
+/// Resolves the password part of a `user:password` entry in place.
+fn resolve_credential(entry: &mut String) -> std::io::Result<()> {
+    if let Some((user, secret)) = entry.split_once(':')
+        && (secret.starts_with("env:") || secret.starts_with("file:"))
+    {
+        *entry = format!("{}:{}", user, resolve_secret(secret)?);
+    }
+    Ok(())
+}
+
+/// Resolves a secret-holding config value: `env:NAME` reads an environment variable,
+/// `file:/path` reads a file (trailing newline trimmed), anything else is used literally.
+pub fn resolve_secret(value: &str) -> std::io::Result<String> {
+    if let Some(name) = value.strip_prefix("env:") {
+        std::env::var(name).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("secret env var '{}': {}", name, e),
+            )
+        })
+    } else if let Some(path) = value.strip_prefix("file:") {
+        fs::read_to_string(path)
+            .map(|s| s.trim_end_matches(['\r', '\n']).to_string())
+            .map_err(|e| std::io::Error::new(e.kind(), format!("secret file '{}': {}", path, e)))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_config_round_trips_with_defaults_filled_in() {
+        let minimal: Config = toml::from_str("[server]\nport = 9000\n").unwrap();
+        let text = toml::to_string_pretty(&minimal).unwrap();
+        let back: Config = toml::from_str(&text).unwrap();
+
+        let mut expected = Config::default();
+        expected.server.port = 9000;
+        assert_eq!(
+            toml::Table::try_from(back).unwrap(),
+            toml::Table::try_from(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn upgrade_adds_missing_fields_and_keeps_existing_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lumen.toml");
+        let path = path.to_str().unwrap();
+        let original = "[server]\nport = 9000\nread_timeout_secs = 3\n\n[custom]\nkept = true\n";
+        fs::write(path, original).unwrap();
+
+        let added = upgrade_config(path).unwrap();
+        assert!(added.contains(&"server.name".to_string()), "{:?}", added);
+        assert!(added.contains(&"performance".to_string()), "{:?}", added);
+        assert!(
+            !added
+                .iter()
+                .any(|f| f == "server.port" || f == "server.timeout_secs")
+        );
+
+        let upgraded = fs::read_to_string(path).unwrap();
+        let config: Config = toml::from_str(&upgraded).unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.timeout_secs, 3);
+        assert!(upgraded.contains("[custom]"));
+        assert_eq!(
+            fs::read_to_string(format!("{}.bak", path)).unwrap(),
+            original
+        );
+
+        assert!(upgrade_config(path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn upgrade_keeps_comments_and_documents_what_it_adds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lumen.toml");
+        let path = path.to_str().unwrap();
+        let original = "# My site\n[server]\n# behind the proxy\nport = 9000 # not 80\n\n[markdown]\nmath = true\n";
+        fs::write(path, original).unwrap();
+        upgrade_config(path).unwrap();
+
+        let upgraded = fs::read_to_string(path).unwrap();
+        assert!(
+            upgraded.contains("# My site\n[server]\n# behind the proxy\nport = 9000 # not 80\n"),
+            "{}",
+            upgraded
+        );
+        assert!(
+            upgraded.contains("[markdown]\nmath = true\n"),
+            "{}",
+            upgraded
+        );
+        assert!(
+            upgraded.contains("# Address to listen on.\nhost = \"127.0.0.1\"\n"),
+            "{}",
+            upgraded
+        );
+        assert!(
+            upgraded.contains("[markdown]\nmath = true\n# Pipe tables.\ntables = true\n"),
+            "{}",
+            upgraded
+        );
+        assert!(
+            upgraded.contains(
+                "\n# Caching, compression, rendering and memory limits.\n[performance]\n"
+            ),
+            "{}",
+            upgraded
+        );
+        let config: Config = toml::from_str(&upgraded).unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert!(config.markdown.math);
+    }
+
+    #[test]
+    fn every_config_field_has_a_doc_comment() {
+        let docs = field_docs();
+        let defaults = toml::Table::try_from(Config::default()).unwrap();
+        fn check(table: &toml::Table, prefix: &str, docs: &HashMap<String, String>) {
+            for (key, value) in table {
+                let name = format!("{}{}", prefix, key);
+                assert!(docs.contains_key(&name), "{} has no doc comment", name);
+                if let toml::Value::Table(nested) = value {
+                    check(nested, &format!("{}.", name), docs);
+                }
+            }
+        }
+        check(&defaults, "", &docs);
+    }
+
+    #[test]
+    fn upgrade_refuses_a_config_that_does_not_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lumen.toml");
+        fs::write(&path, "[server]\nport = \"eighty\"\n").unwrap();
+        assert!(upgrade_config(path.to_str().unwrap()).is_err());
+        assert!(!dir.path().join("lumen.toml.bak").exists());
+    }
+
+    #[test]
+    fn resolve_secret_reads_env_vars() {
+        // setting a variable races the other tests' reads, so use one that is always set
+        assert_eq!(
+            resolve_secret("env:PATH").unwrap(),
+            std::env::var("PATH").unwrap()
+        );
+    }
+
+    #[test]
+    fn resolve_secret_reads_files_without_the_trailing_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pw");
+        fs::write(&path, "from a file\r\n").unwrap();
+        let value = format!("file:{}", path.display());
+        assert_eq!(resolve_secret(&value).unwrap(), "from a file");
+    }
+
+    #[test]
+    fn resolve_secret_uses_anything_else_literally() {
+        for literal in ["hunter2", "", "sha256:abc", "ENV:NOT_A_PREFIX", "envy:x"] {
+            assert_eq!(resolve_secret(literal).unwrap(), literal);
+        }
+    }
+
+    #[test]
+    fn resolve_secret_reports_missing_sources() {
+        let err = resolve_secret("env:LUMEN_TEST_SECRET_UNSET").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(
+            err.to_string().contains("LUMEN_TEST_SECRET_UNSET"),
+            "{}",
+            err
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("absent");
+        let err = resolve_secret(&format!("file:{}", missing.display())).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("absent"), "{}", err);
+    }
+
+    fn load(dir: &Path, toml: &str) -> Result<Config, String> {
+        let path = dir.join("lumen.toml");
+        fs::write(&path, toml).unwrap();
+        load_config(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn basic_auth_passwords_resolve_at_load() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pw"), "sha256:abcd\n").unwrap();
+        let config = load(
+            dir.path(),
+            &format!(
+                "[security.basic_auth]\n\"/admin\" = [\"ops:env:PATH\", \"web:file:{}\", \"me:plain\", \"you:sha256:ff\"]\n",
+                dir.path().join("pw").display()
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            config.security.basic_auth["/admin"],
+            [
+                format!("ops:{}", std::env::var("PATH").unwrap()),
+                "web:sha256:abcd".into(),
+                "me:plain".into(),
+                "you:sha256:ff".into()
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolvable_basic_auth_secrets_fail_the_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let Err(err) = load(
+            dir.path(),
+            "[security.basic_auth]\n\"/admin\" = [\"ops:env:LUMEN_TEST_AUTH_UNSET\"]\n",
+        ) else {
+            panic!("loaded with an unset env var");
+        };
+        assert!(err.contains("security.basic_auth '/admin'"), "{}", err);
+        assert!(err.contains("LUMEN_TEST_AUTH_UNSET"), "{}", err);
+
+        let Err(err) = load(
+            dir.path(),
+            "[security.basic_auth]\n\"/x\" = [\"ops:file:/nonexistent/lumen-pw\"]\n",
+        ) else {
+            panic!("loaded with a missing secret file");
+        };
+        assert!(err.contains("/nonexistent/lumen-pw"), "{}", err);
+    }
+
+    #[test]
+    fn admin_credentials_resolve_at_load() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pw"), "hunter2\n").unwrap();
+        let config = load(
+            dir.path(),
+            &format!(
+                "[server]\nadmin_path = \"/admin/cache\"\nadmin_credentials = [\"ops:file:{}\", \"me:plain\"]\n",
+                dir.path().join("pw").display()
+            ),
+        )
+        .unwrap();
+        assert_eq!(config.server.admin_credentials, ["ops:hunter2", "me:plain"]);
+
+        let Err(err) = load(
+            dir.path(),
+            "[server]\nadmin_path = \"/admin/cache\"\nadmin_credentials = [\"ops:env:LUMEN_TEST_ADMIN_UNSET\"]\n",
+        ) else {
+            panic!("loaded with an unset env var");
+        };
+        assert!(err.contains("server.admin_credentials"), "{}", err);
+        assert!(err.contains("LUMEN_TEST_ADMIN_UNSET"), "{}", err);
+    }
+
+    #[test]
+    fn zero_timeouts_are_rejected() {
+        for key in [
+            "timeout_secs",
+            "read_timeout_secs",
+            "keep_alive_timeout_secs",
+            "max_connection_lifetime_secs",
+        ] {
+            let config: Config = toml::from_str(&format!("[server]\n{} = 0\n", key)).unwrap();
+            let err = config.validate().unwrap_err();
+            assert!(err.contains("must be at least 1"), "{}: {}", key, err);
+            assert!(
+                err.contains(key.replace("read_", "").as_str()),
+                "{}: {}",
+                key,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn zero_pool_settings_are_rejected_only_when_they_apply() {
+        let config: Config = toml::from_str("[server]\nthreads = 4\nqueue_size = 0\n").unwrap();
+        assert!(config.validate().unwrap_err().contains("server.queue_size"));
+        let config: Config = toml::from_str("[server]\nthreads = 0\nqueue_size = 0\n").unwrap();
+        assert_eq!(config.validate(), Ok(()));
+
+        let config: Config = toml::from_str(
+            "[server]\nthreads = 4\nmin_threads = 2\nthread_idle_timeout_secs = 0\n",
+        )
+        .unwrap();
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("server.thread_idle_timeout_secs")
+        );
+    }
+
+    #[test]
+    fn a_degenerate_config_fails_the_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let Err(err) = load(dir.path(), "[server]\nread_timeout_secs = 0\n") else {
+            panic!("loaded with a zero timeout");
+        };
+        assert!(err.contains("server.timeout_secs"), "{}", err);
+        assert!(load(dir.path(), "[server]\nread_timeout_secs = 1\n").is_ok());
+    }
+
+    #[test]
+    fn alt_svc_values_are_checked_for_syntax() {
+        for valid in [
+            "clear",
+            "h3=\":443\"",
+            "h3=\":443\"; ma=86400, h3-29=\"alt.example:8443\"; ma=3600; persist=1",
+        ] {
+            assert!(is_valid_alt_svc(valid), "{:?}", valid);
+        }
+        for invalid in [
+            "h3",
+            "h3=:443",
+            "h3=\"alt.example\"",
+            "h3=\":443\"; ma=soon",
+            "h3=\":443\"; persist=yes",
+            "h 3=\":443\"",
+            "h3=\":443\",",
+        ] {
+            assert!(!is_valid_alt_svc(invalid), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn canonical_hosts_are_checked_for_syntax() {
+        for valid in [
+            "example.com",
+            "example.com.",
+            "www.example.com:8443",
+            "127.0.0.1:8080",
+            "[::1]",
+            "[2001:db8::1]:443",
+        ] {
+            assert!(is_valid_host_port(valid), "{:?}", valid);
+        }
+        for invalid in [
+            " example.com",
+            "example.com/path",
+            "example.com\r\nSet-Cookie: x=1",
+            "exa mple.com",
+            "example.com:",
+            "example.com:http",
+            "example.com:70000",
+            "user@example.com",
+            "::1",
+            "[::1",
+            ":8080",
+        ] {
+            assert!(!is_valid_host_port(invalid), "{:?}", invalid);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let Err(err) = load(
+            dir.path(),
+            "[redirects]\ncanonical_host = \"example.com\\r\\nX-Injected: 1\"\n",
+        ) else {
+            panic!("loaded a canonical_host with a line break");
+        };
+        assert!(err.contains("redirects.canonical_host"), "{}", err);
+        assert!(
+            load(
+                dir.path(),
+                "[redirects]\ncanonical_host = \"example.com:8080\"\n"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn error_pages_only_take_the_statuses_lumen_sends() {
+        let config: Config =
+            toml::from_str("[paths.error_pages]\n404 = \"404.html\"\n503 = \"busy.html\"\n")
+                .unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        let config: Config =
+            toml::from_str("[paths.error_pages]\n418 = \"teapot.html\"\n").unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("paths.error_pages has '418'"), "{}", err);
+    }
+
+    #[test]
+    fn redirect_rules_are_validated() {
+        let check = |from: &str, to: &str, status: u16| {
+            Config {
+                redirect: vec![RedirectRule {
+                    from: from.into(),
+                    to: to.into(),
+                    status,
+                }],
+                ..Config::default()
+            }
+            .validate()
+        };
+        assert_eq!(check("/old", "/new", 301), Ok(()));
+        assert_eq!(check("/a/*", "https://b.example/", 308), Ok(()));
+        assert_eq!(check("/a", "//cdn.example/a", 302), Ok(()));
+        for (from, to, status, complaint) in [
+            ("old", "/new", 301, "redirect from 'old'"),
+            ("/a*/b", "/new", 301, "with `*` only at the end"),
+            ("/old", "new", 301, "redirect to 'new'"),
+            ("/old", "", 301, "redirect to ''"),
+            ("/old", "/new", 200, "redirect status 200"),
+        ] {
+            let err = check(from, to, status).unwrap_err();
+            assert!(err.contains(complaint), "{}", err);
+        }
+    }
+
+    #[test]
+    fn mime_overrides_are_validated() {
+        let check = |ext: &str, mime: &str| {
+            Config {
+                mime: [(ext.to_string(), mime.to_string())].into_iter().collect(),
+                ..Config::default()
+            }
+            .validate()
+        };
+        assert_eq!(check("webmanifest", "application/manifest+json"), Ok(()));
+        assert_eq!(check(".txt", "text/plain; charset=utf-8"), Ok(()));
+        for (ext, mime) in [
+            (".", "text/plain"),
+            ("a/b", "text/plain"),
+            ("txt", "text"),
+            ("txt", "text/"),
+            ("txt", "text/plain\r\nX-Evil: 1"),
+        ] {
+            assert!(check(ext, mime).is_err(), "{:?} = {:?}", ext, mime);
+        }
+    }
+}
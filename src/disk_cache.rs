@@ -0,0 +1,244 @@
+use bytes::Bytes;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use crate::{state::CacheEntry, utils::content_hash};
+
+/// Rendered pages persisted under a directory so they survive restarts. Each cache key maps
+/// to one file holding the version it was rendered at; a version mismatch is a miss and the
+/// next store overwrites it.
+pub struct DiskCache {
+    dir: PathBuf,
+    gzip: bool,
+    /// Bumped by `purge` and kept in a `generation` file next to the pages, so a purge
+    /// outlives a restart.
+    generation: AtomicU64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredHead {
+    version: String,
+    gzip: bool,
+    content_type: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+impl DiskCache {
+    pub fn new(dir: &Path, gzip: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let generation = fs::read_to_string(dir.join("generation"))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0);
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            gzip,
+            generation: AtomicU64::new(generation),
+        })
+    }
+
+    /// Identifies one rendering of a page: its source mtime, the theme and configuration it
+    /// went through, and the cache `generation` it was stored in.
+    pub fn version(
+        mtime: SystemTime,
+        theme_hash: u64,
+        config_hash: u64,
+        generation: u64,
+    ) -> String {
+        let nanos = mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        format!(
+            "{:x}-{:x}-{:x}-{:x}",
+            nanos, theme_hash, config_hash, generation
+        )
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Moves to a new generation, so every page stored so far reads as outdated.
+    pub fn purge(&self) -> io::Result<()> {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = self.dir.join("generation");
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, generation.to_string())?;
+        fs::rename(&tmp, &path)
+    }
+
+    fn file_for(&self, cache_key: &Path) -> PathBuf {
+        let hash = content_hash(cache_key.as_os_str().as_encoded_bytes());
+        self.dir.join(format!("{:016x}.page", hash))
+    }
+
+    pub fn load(&self, cache_key: &Path, version: &str, mtime: SystemTime) -> Option<CacheEntry> {
+        let data = fs::read(self.file_for(cache_key)).ok()?;
+        let split = data.iter().position(|&b| b == b'\n')?;
+        let head: StoredHead = serde_json::from_slice(&data[..split]).ok()?;
+        if head.version != version {
+            return None;
+        }
+
+        let stored = Bytes::from(data).slice(split + 1..);
+        let gz = Arc::new(OnceLock::new());
+        let raw = if head.gzip {
+            let mut raw = Vec::new();
+            GzDecoder::new(&stored[..]).read_to_end(&mut raw).ok()?;
+            let _ = gz.set(stored);
+            Bytes::from(raw)
+        } else {
+            stored
+        };
+        Some(CacheEntry {
+            raw,
+            br: Arc::new(OnceLock::new()),
+            gz,
+            df: Arc::new(OnceLock::new()),
+            content_type: head.content_type,
+            mtime,
+            status: head.status,
+            headers: head.headers,
+            expires_at: None,
+        })
+    }
+
+    /// Writes through a temporary file and renames it, so a crash never leaves a torn entry.
+    pub fn store(&self, cache_key: &Path, version: &str, entry: &CacheEntry) -> io::Result<()> {
+        let head = StoredHead {
+            version: version.to_string(),
+            gzip: self.gzip,
+            content_type: entry.content_type.clone(),
+            status: entry.status,
+            headers: entry.headers.clone(),
+        };
+        let mut out = serde_json::to_vec(&head)?;
+        out.push(b'\n');
+        if self.gzip {
+            let mut encoder = GzEncoder::new(out, Compression::default());
+            encoder.write_all(&entry.raw)?;
+            out = encoder.finish()?;
+        } else {
+            out.extend_from_slice(&entry.raw);
+        }
+
+        let path = self.file_for(cache_key);
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn entry(body: &'static str) -> CacheEntry {
+        CacheEntry {
+            raw: Bytes::from_static(body.as_bytes()),
+            br: Arc::default(),
+            gz: Arc::default(),
+            df: Arc::default(),
+            content_type: "text/html; charset=utf-8".into(),
+            mtime: SystemTime::UNIX_EPOCH,
+            status: 410,
+            headers: vec![("X-Robots-Tag".into(), "noindex".into())],
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn stored_entries_load_back_with_or_without_gzip() {
+        for gzip in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = DiskCache::new(&dir.path().join("nested/cache"), gzip).unwrap();
+            let key = Path::new("/site/content/page.md");
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+            cache.store(key, "v1", &entry("<p>hi</p>")).unwrap();
+
+            let loaded = cache.load(key, "v1", mtime).unwrap();
+            assert_eq!(loaded.raw, "<p>hi</p>");
+            assert_eq!(loaded.status, 410);
+            assert_eq!(loaded.content_type, "text/html; charset=utf-8");
+            assert_eq!(loaded.headers, entry("").headers);
+            assert_eq!(loaded.mtime, mtime);
+            // the stored gzip bytes are reused rather than compressed again
+            assert_eq!(loaded.gz.get().is_some(), gzip);
+        }
+    }
+
+    #[test]
+    fn other_versions_and_keys_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path(), true).unwrap();
+        let key = Path::new("page.md");
+        cache.store(key, "v1", &entry("old")).unwrap();
+        assert!(cache.load(key, "v2", SystemTime::UNIX_EPOCH).is_none());
+        assert!(
+            cache
+                .load(Path::new("other.md"), "v1", SystemTime::UNIX_EPOCH)
+                .is_none()
+        );
+
+        cache.store(key, "v2", &entry("new")).unwrap();
+        assert_eq!(
+            cache.load(key, "v2", SystemTime::UNIX_EPOCH).unwrap().raw,
+            "new"
+        );
+        assert!(cache.load(key, "v1", SystemTime::UNIX_EPOCH).is_none());
+    }
+
+    #[test]
+    fn corrupt_files_are_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path(), true).unwrap();
+        let key = Path::new("page.md");
+        cache.store(key, "v1", &entry("body")).unwrap();
+        let file = cache.file_for(key);
+        let mut data = fs::read(&file).unwrap();
+        data.truncate(data.len() - 4);
+        fs::write(&file, &data).unwrap();
+        assert!(cache.load(key, "v1", SystemTime::UNIX_EPOCH).is_none());
+
+        fs::write(&file, "not json\nbody").unwrap();
+        assert!(cache.load(key, "v1", SystemTime::UNIX_EPOCH).is_none());
+    }
+
+    #[test]
+    fn version_changes_with_each_input() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(5);
+        let base = DiskCache::version(t, 1, 2, 0);
+        assert_eq!(base, DiskCache::version(t, 1, 2, 0));
+        assert_ne!(
+            base,
+            DiskCache::version(t + Duration::from_nanos(1), 1, 2, 0)
+        );
+        assert_ne!(base, DiskCache::version(t, 3, 2, 0));
+        assert_ne!(base, DiskCache::version(t, 1, 3, 0));
+        assert_ne!(base, DiskCache::version(t, 1, 2, 1));
+    }
+
+    #[test]
+    fn purges_bump_a_generation_that_survives_a_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path(), false).unwrap();
+        assert_eq!(cache.generation(), 0);
+        cache.purge().unwrap();
+        cache.purge().unwrap();
+        assert_eq!(cache.generation(), 2);
+        assert_eq!(DiskCache::new(dir.path(), false).unwrap().generation(), 2);
+    }
+}
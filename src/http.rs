@@ -1,12 +1,40 @@
 use bytes::Bytes;
 use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
-use std::{fs, sync::Arc, sync::OnceLock, time::SystemTime};
+use serde::Serialize;
+use serde_json::json;
+#[cfg(feature = "markdown")]
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use std::{
+    fs,
+    io::{self, Write},
+    net::IpAddr,
+    sync::Arc,
+    sync::OnceLock,
+    sync::mpsc,
+    time::SystemTime,
+};
+#[cfg(feature = "markdown")]
+use tracing::error;
+use tracing::{info, warn};
 
+#[cfg(feature = "markdown")]
+use crate::{
+    config::TemplateVariant,
+    disk_cache::DiskCache,
+    render::{RenderError, RenderedPage, RequestParams, prepare_page, render_page},
+    state::OwnedRenderPermit,
+    utils::{parse_cookies, prefers_plain_text, split_frontmatter, try_split_frontmatter},
+};
 use crate::{
+    config::{Algorithm, CompressionConfig, DirPrecedence, DirRedirect, TrailingSlash},
     state::{CacheEntry, ServerState},
     utils::{
-        escape_html, get_mime_type, is_compressible, markdown_to_html, secure_join,
-        split_frontmatter,
+        absolute_url, base64_decode, constant_time_eq, content_hash, encoding_quality, escape_html,
+        format_rfc3339, get_mime_type, ip_bits, is_compressible, parse_accept_encoding, parse_cidr,
+        parse_query, sha256,
     },
 };
 
@@ -25,22 +53,67 @@ pub const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
 pub enum Encoding {
     Brotli,
     Gzip,
+    Deflate,
     None,
 }
 
+impl Encoding {
+    fn content_coding(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::None => "identity",
+        }
+    }
+
+    /// Extension of a pre-built sibling file holding this coding, e.g. `app.js.br`.
+    fn sidecar_extension(self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gz"),
+            Encoding::Deflate | Encoding::None => None,
+        }
+    }
+
+    fn etag_suffix(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "-br",
+            Encoding::Gzip => "-gz",
+            Encoding::Deflate => "-df",
+            Encoding::None => "",
+        }
+    }
+}
+
 pub struct HttpRequest {
     pub method: String,
     pub path: String,
     pub accept_encoding: String,
+    pub accept: String,
+    pub host: String,
+    pub authorization: Option<String>,
     pub range: Option<String>,
     pub if_none_match: Option<String>,
     pub if_modified_since: Option<String>,
+    pub if_range: Option<String>,
+    pub user_agent: String,
+    pub cookie: String,
     pub keep_alive: bool,
+    pub peer_ip: IpAddr,
+    pub request_id: String,
+    pub http_minor: u8,
+    /// Wakes the connection when a streamed body has more to send; `None` disables streaming.
+    pub stream_notify: Option<StreamNotify>,
 }
 
+pub type StreamNotify = Arc<dyn Fn() + Send + Sync>;
+
 pub enum ResponseBody {
     Bytes(Bytes),
     Stream(std::fs::File, u64, u64),
+    /// Sent with chunked framing as it arrives; `None` means the producer failed mid-body.
+    Chunked(mpsc::Receiver<Option<Bytes>>),
 }
 
 pub struct HttpResponse {
@@ -50,32 +123,236 @@ pub struct HttpResponse {
     pub clen: usize,
     pub extra_headers: Vec<(String, String)>,
     pub keep_alive: bool,
+    /// Replaces the standard reason phrase, e.g. to pass on one from an upstream response.
+    /// Ignored if it contains control characters.
+    pub reason: Option<String>,
+}
+
+/// The IANA-registered reason phrase for `status`, or a generic one for its class.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        102 => "Processing",
+        103 => "Early Hints",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        208 => "Already Reported",
+        226 => "IM Used",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        305 => "Use Proxy",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        418 => "I'm a teapot",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        423 => "Locked",
+        424 => "Failed Dependency",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        506 => "Variant Also Negotiates",
+        507 => "Insufficient Storage",
+        508 => "Loop Detected",
+        510 => "Not Extended",
+        511 => "Network Authentication Required",
+        _ => match status / 100 {
+            1 => "Informational",
+            2 => "Success",
+            3 => "Redirection",
+            4 => "Client Error",
+            _ => "Server Error",
+        },
+    }
+}
+
+const CONNECTION_KEEP_ALIVE: &[u8] = b"Connection: keep-alive\r\n";
+const CONNECTION_CLOSE: &[u8] = b"Connection: close\r\n";
+
+/// Prebuilt status lines for the codes the hot path produces; others are formatted.
+fn static_status_line(status: u16) -> Option<&'static [u8]> {
+    Some(match status {
+        200 => b"HTTP/1.1 200 OK\r\n",
+        206 => b"HTTP/1.1 206 Partial Content\r\n",
+        301 => b"HTTP/1.1 301 Moved Permanently\r\n",
+        304 => b"HTTP/1.1 304 Not Modified\r\n",
+        404 => b"HTTP/1.1 404 Not Found\r\n",
+        _ => return None,
+    })
+}
+
+/// Serializes the status line and headers of `res`, leaving the body to the caller. The
+/// status line answers in the request's HTTP/1.x minor version.
+pub fn write_head<W: Write>(
+    out: &mut W,
+    res: &HttpResponse,
+    http_minor: u8,
+    date: &str,
+    precomputed_headers: &[u8],
+) -> io::Result<()> {
+    let custom_reason = res
+        .reason
+        .as_deref()
+        .filter(|r| !r.chars().any(char::is_control));
+    match static_status_line(res.status).filter(|_| custom_reason.is_none()) {
+        Some(line) if http_minor == 1 => out.write_all(line)?,
+        Some(line) => {
+            out.write_all(b"HTTP/1.0")?;
+            out.write_all(&line[8..])?;
+        }
+        None => write!(
+            out,
+            "HTTP/1.{} {} {}\r\n",
+            http_minor.min(1),
+            res.status,
+            custom_reason.unwrap_or_else(|| reason_phrase(res.status))
+        )?,
+    }
+    out.write_all(b"Date: ")?;
+    out.write_all(date.as_bytes())?;
+    out.write_all(b"\r\n")?;
+    // a 204 has no content to describe, and must not carry Content-Length
+    if res.status != 204 {
+        out.write_all(b"Content-Type: ")?;
+        out.write_all(res.content_type.as_bytes())?;
+        if matches!(res.body, Some(ResponseBody::Chunked(_))) {
+            out.write_all(b"\r\nTransfer-Encoding: chunked")?;
+        } else {
+            out.write_all(b"\r\nContent-Length: ")?;
+            out.write_all(itoa::Buffer::new().format(res.clen).as_bytes())?;
+        }
+        out.write_all(b"\r\n")?;
+    }
+    out.write_all(if res.keep_alive {
+        CONNECTION_KEEP_ALIVE
+    } else {
+        CONNECTION_CLOSE
+    })?;
+
+    for (k, v) in &res.extra_headers {
+        out.write_all(k.as_bytes())?;
+        out.write_all(b": ")?;
+        out.write_all(v.as_bytes())?;
+        out.write_all(b"\r\n")?;
+    }
+    out.write_all(precomputed_headers)?;
+    out.write_all(b"\r\n")
+}
+
+/// Picks the configured algorithm with the highest client q-value, breaking ties by the
+/// server's preference order.
+pub fn determine_encoding(accept_enc: &str, config: &CompressionConfig) -> Encoding {
+    let accepted = parse_accept_encoding(accept_enc);
+    let mut best = (Encoding::None, 0.0);
+    for alg in &config.algorithms {
+        let (encoding, name) = match alg {
+            Algorithm::Brotli => (Encoding::Brotli, "br"),
+            Algorithm::Gzip => (Encoding::Gzip, "gzip"),
+            Algorithm::Deflate => (Encoding::Deflate, "deflate"),
+        };
+        let q = encoding_quality(&accepted, name);
+        if q > best.1 {
+            best = (encoding, q);
+        }
+    }
+    best.0
+}
+
+/// Whether the client will take an uncompressed body; false after `identity;q=0` or `*;q=0`.
+fn accepts_identity(accept_enc: &str) -> bool {
+    encoding_quality(&parse_accept_encoding(accept_enc), "identity") > 0.0
 }
 
-pub fn determine_encoding(accept_enc: &str) -> Encoding {
-    if accept_enc.contains("br") {
-        Encoding::Brotli
-    } else if accept_enc.contains("gzip") {
-        Encoding::Gzip
+fn not_acceptable(keep_alive: bool, is_head: bool) -> HttpResponse {
+    let msg = Bytes::from("Not Acceptable");
+    build_response(
+        keep_alive,
+        406,
+        "text/plain",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(msg.clone()))
+        },
+        msg.len(),
+        vec![("Vary".into(), "Accept-Encoding".into())],
+    )
+}
+
+/// The encoding actually applied to a body of `len` bytes, honoring `min_size`.
+fn effective_encoding(
+    state: &ServerState,
+    encoding: Encoding,
+    compressible: bool,
+    len: usize,
+) -> Encoding {
+    if compressible && len >= state.config().performance.compression.min_size {
+        encoding
     } else {
         Encoding::None
     }
 }
 
-fn compress_brotli(data: &[u8]) -> Bytes {
+fn compress_brotli(data: &[u8], level: u32) -> Bytes {
     let mut output = Vec::with_capacity(data.len() / 2);
-    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 4, 20);
-    let _ = std::io::Write::write_all(&mut writer, data);
+    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, level.min(11), 20);
+    let _ = writer.write_all(data);
     drop(writer);
     Bytes::from(output)
 }
 
-fn compress_gzip(data: &[u8]) -> Bytes {
+fn compress_gzip(data: &[u8], level: u32) -> Bytes {
     let mut encoder = flate2::write::GzEncoder::new(
         Vec::with_capacity(data.len() / 2),
-        flate2::Compression::default(),
+        flate2::Compression::new(level.min(9)),
+    );
+    let _ = encoder.write_all(data);
+    Bytes::from(encoder.finish().unwrap_or_default())
+}
+
+fn compress_deflate(data: &[u8], level: u32) -> Bytes {
+    let mut encoder = flate2::write::ZlibEncoder::new(
+        Vec::with_capacity(data.len() / 2),
+        flate2::Compression::new(level.min(9)),
     );
-    let _ = std::io::Write::write_all(&mut encoder, data);
+    let _ = encoder.write_all(data);
     Bytes::from(encoder.finish().unwrap_or_default())
 }
 
@@ -94,133 +371,527 @@ pub fn build_response(
         clen,
         extra_headers: extra,
         keep_alive,
+        reason: None,
     }
 }
 
-fn check_conditional(req: &HttpRequest, etag: &str, last_mod: &str) -> bool {
-    if let Some(inm) = &req.if_none_match
-        && inm == etag
+pub fn service_unavailable(is_head: bool) -> HttpResponse {
+    let msg = Bytes::from("Service Unavailable");
+    build_response(
+        false,
+        503,
+        "text/plain",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(msg.clone()))
+        },
+        msg.len(),
+        vec![("Retry-After".into(), "1".into())],
+    )
+}
+
+/// A 403, 404, 500 or 503 response, with the `paths.error_pages` file for the status when one
+/// is loaded and the built-in body otherwise.
+pub fn send_error(
+    state: &ServerState,
+    status: u16,
+    keep_alive: bool,
+    is_head: bool,
+) -> HttpResponse {
+    if status == 503 && !state.error_pages.load().contains_key(&503) {
+        return service_unavailable(is_head);
+    }
+    let (content_type, body) = match state.error_pages.load().get(&status) {
+        Some(page) => (page.content_type.clone(), page.body.clone()),
+        None => match status {
+            404 => (
+                format!("text/html; charset={}", state.config().defaults.charset),
+                Bytes::from(state.config().paths.fallback_404.clone()),
+            ),
+            500 => ("text/plain".into(), Bytes::from("Internal Server Error")),
+            _ => (
+                "text/plain".into(),
+                Bytes::from(format!("{} {}", status, reason_phrase(status))),
+            ),
+        },
+    };
+    // an overloaded server sheds the connection as well as the request
+    let (keep_alive, hdrs) = if status == 503 {
+        (false, vec![("Retry-After".into(), "1".into())])
+    } else {
+        (keep_alive, vec![])
+    };
+    let clen = body.len();
+    build_response(
+        keep_alive,
+        status,
+        &content_type,
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(body))
+        },
+        clen,
+        hdrs,
+    )
+}
+
+/// Rejects a malformed request; the connection is closed after it.
+pub fn bad_request(is_head: bool) -> HttpResponse {
+    let msg = Bytes::from("400 Bad Request");
+    build_response(
+        false,
+        400,
+        "text/plain",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(msg.clone()))
+        },
+        msg.len(),
+        vec![],
+    )
+}
+
+/// Sent before closing a connection whose request line isn't HTTP/1.0 or HTTP/1.1, which
+/// includes HTTP/0.9 simple requests and an HTTP/2 preface over cleartext.
+pub fn version_not_supported(is_head: bool) -> HttpResponse {
+    let msg = Bytes::from("HTTP Version Not Supported");
+    build_response(
+        false,
+        505,
+        "text/plain",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(msg.clone()))
+        },
+        msg.len(),
+        vec![],
+    )
+}
+
+/// Responds with a body the server synthesized rather than read from disk, validated by a
+/// strong ETag over its bytes so repeat fetches can be answered with 304.
+pub fn generated_response(
+    state: &ServerState,
+    req: &HttpRequest,
+    keep_alive: bool,
+    content_type: &str,
+    body: Bytes,
+) -> HttpResponse {
+    let etag = format!("\"{:016x}\"", content_hash(&body));
+    let hdrs = vec![
+        ("ETag".into(), etag.clone()),
+        (
+            "Cache-Control".into(),
+            format!("max-age={}", state.config().performance.generated_max_age),
+        ),
+    ];
+    if req
+        .if_none_match
+        .as_deref()
+        .is_some_and(|inm| etag_matches_weak(inm, &etag))
     {
-        return true;
+        return build_response(keep_alive, 304, content_type, None, 0, hdrs);
+    }
+    let clen = body.len();
+    let body = (req.method != "HEAD").then_some(ResponseBody::Bytes(body));
+    build_response(keep_alive, 200, content_type, body, clen, hdrs)
+}
+
+/// Whether an `If-None-Match` list names `etag` under weak comparison, where `W/"x"` and
+/// `"x"` are the same validator.
+fn etag_matches_weak(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    header
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// True when the client's copy is current and a 304 should be sent. `If-Modified-Since` is
+/// only consulted when there is no `If-None-Match`.
+fn check_conditional(req: &HttpRequest, etag: &str, last_mod: &str) -> bool {
+    if let Some(inm) = &req.if_none_match {
+        return etag_matches_weak(inm, etag);
     }
     if let Some(ims) = &req.if_modified_since
-        && ims == last_mod
+        && let (Ok(since), Ok(modified)) = (
+            httpdate::parse_http_date(ims),
+            httpdate::parse_http_date(last_mod),
+        )
     {
-        return true;
+        return modified <= since;
     }
     false
 }
 
+/// Whether a `Range` may be honored: an `If-Range` must name the current representation by
+/// strong ETag or exact `Last-Modified`, otherwise the full body is sent.
+fn if_range_matches(req: &HttpRequest, etag: &str, last_mod: &str) -> bool {
+    match req.if_range.as_deref().map(str::trim) {
+        None => true,
+        Some(tag) if tag.starts_with('"') => !etag.starts_with("W/") && tag == etag,
+        Some(tag) if tag.starts_with("W/") => false,
+        Some(date) => date == last_mod,
+    }
+}
+
+/// Returns the body for `encoding` (already passed through `effective_encoding`), compressing
+/// into the cache entry on first use.
 fn extract_encoded_body(
     state: &ServerState,
     cache_key: &std::path::PathBuf,
     entry: &CacheEntry,
     encoding: Encoding,
-    use_compression: bool,
+    compressible: bool,
     hdrs: &mut Vec<(String, String)>,
 ) -> Option<Bytes> {
-    if use_compression {
+    if compressible {
         hdrs.push(("Vary".into(), "Accept-Encoding".into()));
-        match encoding {
-            Encoding::Brotli => {
-                let mut added_size = 0;
-                let br_bytes = entry
-                    .br
-                    .get_or_init(|| {
-                        let c = compress_brotli(&entry.raw);
-                        added_size = c.len();
-                        c
-                    })
-                    .clone();
-                if added_size > 0 {
-                    state.add_cache_size(cache_key, added_size);
-                }
-                hdrs.push(("Content-Encoding".into(), "br".into()));
-                return Some(br_bytes);
-            }
-            Encoding::Gzip => {
-                let mut added_size = 0;
-                let gz_bytes = entry
-                    .gz
-                    .get_or_init(|| {
-                        let c = compress_gzip(&entry.raw);
-                        added_size = c.len();
-                        c
-                    })
-                    .clone();
-                if added_size > 0 {
-                    state.add_cache_size(cache_key, added_size);
-                }
-                hdrs.push(("Content-Encoding".into(), "gzip".into()));
-                return Some(gz_bytes);
-            }
-            _ => {}
-        }
     }
-    Some(entry.raw.clone())
+    let levels = &state.config().performance.compression;
+    let slot = match encoding {
+        Encoding::Brotli => &entry.br,
+        Encoding::Gzip => &entry.gz,
+        Encoding::Deflate => &entry.df,
+        Encoding::None => return Some(entry.raw.clone()),
+    };
+
+    let mut added_size = 0;
+    let bytes = slot
+        .get_or_init(|| {
+            let c = match encoding {
+                Encoding::Brotli => compress_brotli(&entry.raw, levels.brotli_level),
+                Encoding::Gzip => compress_gzip(&entry.raw, levels.gzip_level),
+                _ => compress_deflate(&entry.raw, levels.deflate_level),
+            };
+            added_size = c.len();
+            c
+        })
+        .clone();
+    if added_size > 0 {
+        state.add_cache_size(cache_key, added_size);
+    }
+    hdrs.push(("Content-Encoding".into(), encoding.content_coding().into()));
+    Some(bytes)
 }
 
-pub fn serve_markdown(
-    state: &ServerState,
-    md_path: &std::path::Path,
+/// Headers for a 206, carrying the identity validators so a client can resume with `If-Range`
+/// (which is also what a HEAD with `Range` is usually probing for).
+fn partial_headers(
+    start: usize,
+    end: usize,
+    len: usize,
+    identity_etag: &str,
+    last_mod: &str,
+) -> Vec<(String, String)> {
+    vec![
+        (
+            "Content-Range".into(),
+            format!("bytes {}-{}/{}", start, end, len),
+        ),
+        ("Accept-Ranges".into(), "bytes".into()),
+        ("ETag".into(), identity_etag.into()),
+        ("Last-Modified".into(), last_mod.into()),
+    ]
+}
+
+/// Opens the pre-compressed sibling of `path` for `encoding`, ignoring one older than the
+/// original so a stale build artifact is never served.
+fn precompressed_sidecar(
+    path: &std::path::Path,
+    encoding: Encoding,
     mtime: SystemTime,
+) -> Option<(fs::File, u64)> {
+    let ext = encoding.sidecar_extension()?;
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(ext);
+    let file = fs::File::open(&sidecar).ok()?;
+    let meta = file.metadata().ok()?;
+    if !meta.is_file() || meta.len() == 0 || meta.modified().ok()? < mtime {
+        return None;
+    }
+    Some((file, meta.len()))
+}
+
+/// Every `%` must start a two-digit hex escape and the decoded bytes must be UTF-8.
+fn is_valid_encoded_path(raw: &str) -> bool {
+    let bytes = raw.as_bytes();
+    let escapes_ok = bytes.iter().enumerate().all(|(i, &b)| {
+        b != b'%'
+            || bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    });
+    escapes_ok && percent_decode_str(raw).decode_utf8().is_ok()
+}
+
+fn moved_permanently(
+    state: &ServerState,
+    keep_alive: bool,
+    is_head: bool,
+    location: &str,
+    query: Option<&str>,
+) -> HttpResponse {
+    let location = &state.public_path(location);
+    let mut encoded_location = utf8_percent_encode(location, PATH_ENCODE_SET).to_string();
+    if let Some(q) = query {
+        encoded_location.push('?');
+        encoded_location.push_str(&utf8_percent_encode(q, PATH_ENCODE_SET).to_string());
+    }
+    redirect_response(state, 301, keep_alive, is_head, encoded_location, location)
+}
+
+/// Answers with the first `[[redirect]]` rule matching `path`, carrying the request's query
+/// over unless the target has its own.
+fn rule_redirect(
+    state: &ServerState,
+    path: &str,
+    query: Option<&str>,
+    keep_alive: bool,
+    is_head: bool,
+) -> Option<HttpResponse> {
+    let config = state.config();
+    let (rule, target) = config
+        .redirect
+        .iter()
+        .find_map(|rule| rule.target(path).map(|target| (rule, target)))?;
+    let location = if rule.is_external() {
+        target
+    } else {
+        state.public_path(&target)
+    };
+    let (target_path, target_query) = match location.split_once('?') {
+        Some((p, q)) => (p, Some(q)),
+        None => (location.as_str(), query),
+    };
+    let mut encoded_location = utf8_percent_encode(target_path, PATH_ENCODE_SET).to_string();
+    if let Some(q) = target_query {
+        encoded_location.push('?');
+        encoded_location.push_str(&utf8_percent_encode(q, PATH_ENCODE_SET).to_string());
+    }
+    Some(redirect_response(
+        state,
+        rule.status,
+        keep_alive,
+        is_head,
+        encoded_location,
+        &location,
+    ))
+}
+
+/// Sends the client to `config.redirects.canonical_host` when it asked for another host,
+/// keeping the raw request target as it arrived.
+fn canonical_host_redirect(
+    state: &ServerState,
+    req: &HttpRequest,
+    keep_alive: bool,
+    is_head: bool,
+) -> Option<HttpResponse> {
+    let config = state.config();
+    let canonical = config.redirects.canonical_host.trim().trim_end_matches('.');
+    let host = req.host.trim().trim_end_matches('.');
+    if canonical.is_empty() || host.is_empty() {
+        return None;
+    }
+    // a port-less canonical host matches the request host on any port
+    let compared = if canonical.contains(':') {
+        host
+    } else {
+        host.rsplit_once(':')
+            .filter(|(_, port)| port.bytes().all(|b| b.is_ascii_digit()))
+            .map_or(host, |(name, _)| name)
+    };
+    if compared.eq_ignore_ascii_case(canonical) {
+        return None;
+    }
+    let scheme = if config.server.base_url.starts_with("https://") {
+        "https"
+    } else {
+        "http"
+    };
+    let location = format!("{}://{}{}", scheme, canonical, req.path);
+    Some(redirect_response(
+        state,
+        301,
+        keep_alive,
+        is_head,
+        location.clone(),
+        &location,
+    ))
+}
+
+fn redirect_response(
+    state: &ServerState,
+    status: u16,
+    keep_alive: bool,
+    is_head: bool,
+    encoded_location: String,
+    location: &str,
+) -> HttpResponse {
+    let redirect_html = Bytes::from(format!(
+        "{} {}: <a href=\"{}\">{}</a>",
+        status,
+        reason_phrase(status),
+        escape_html(&encoded_location),
+        escape_html(location)
+    ));
+    build_response(
+        keep_alive,
+        status,
+        &format!("text/html; charset={}", state.config().defaults.charset),
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(redirect_html.clone()))
+        },
+        redirect_html.len(),
+        vec![("Location".into(), encoded_location)],
+    )
+}
+
+/// Serves a rendered page from its cache entry, with the entry's own mtime as the validator.
+#[cfg(feature = "markdown")]
+fn respond_with_page(
+    state: &ServerState,
+    cache_key: &std::path::PathBuf,
+    entry: &CacheEntry,
     encoding: Encoding,
     keep_alive: bool,
     is_head: bool,
     req: &HttpRequest,
 ) -> HttpResponse {
-    let cache_key = md_path.to_path_buf();
-    let use_compression = state.config.performance.enable_compression;
-    let enc_suffix = match encoding {
-        Encoding::Brotli => "-br",
-        Encoding::Gzip => "-gz",
-        Encoding::None => "",
-    };
+    let use_compression = state.config().performance.enable_compression;
+    let encoding = effective_encoding(state, encoding, use_compression, entry.raw.len());
+    if encoding == Encoding::None && !accepts_identity(&req.accept_encoding) {
+        return not_acceptable(keep_alive, is_head);
+    }
+    let mut hdrs = Vec::new();
+    let body = extract_encoded_body(
+        state,
+        cache_key,
+        entry,
+        encoding,
+        use_compression,
+        &mut hdrs,
+    );
 
-    if state.config.performance.enable_caching
-        && let Some(entry) = state.page_cache.get(&cache_key)
-        && entry.mtime == mtime
+    let mtime_sec = entry
+        .mtime
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let etag = format!(
+        "W/\"{:x}-{:x}{}\"",
+        mtime_sec,
+        entry.raw.len(),
+        encoding.etag_suffix()
+    );
+    let last_mod = httpdate::fmt_http_date(entry.mtime);
+
+    hdrs.push(("ETag".into(), etag.clone()));
+    hdrs.push(("Last-Modified".into(), last_mod.clone()));
+    if state
+        .config()
+        .variants
+        .iter()
+        .any(|v| !v.user_agent.is_empty())
     {
-        let mut hdrs = Vec::new();
-        let body = extract_encoded_body(
-            state,
-            &cache_key,
-            &entry,
-            encoding,
-            use_compression,
-            &mut hdrs,
-        );
+        hdrs.push(("Vary".into(), "User-Agent".into()));
+    }
+    if !state.config().performance.cache_key_cookies.is_empty() {
+        hdrs.push(("Vary".into(), "Cookie".into()));
+    }
+    if state.config().seo.plaintext_alternates {
+        hdrs.push(("Vary".into(), "Accept".into()));
+    }
+    hdrs.extend(entry.headers.iter().cloned());
 
-        let mtime_sec = mtime
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let etag = format!("W/\"{:x}-{:x}{}\"", mtime_sec, entry.raw.len(), enc_suffix);
-        let last_mod = httpdate::fmt_http_date(mtime);
+    if entry.status == 200 && check_conditional(req, &etag, &last_mod) {
+        return build_response(keep_alive, 304, &entry.content_type, None, 0, hdrs);
+    }
+
+    let clen = body.as_ref().map(|b| b.len()).unwrap_or(0);
+    build_response(
+        keep_alive,
+        entry.status,
+        &entry.content_type,
+        if is_head {
+            None
+        } else {
+            body.map(ResponseBody::Bytes)
+        },
+        clen,
+        hdrs,
+    )
+}
 
-        hdrs.push(("ETag".into(), etag.clone()));
-        hdrs.push(("Last-Modified".into(), last_mod.clone()));
+/// How long a cache miss waits for a render slot before falling back to a stale entry.
+#[cfg(feature = "markdown")]
+const RENDER_SLOT_WAIT: Duration = Duration::from_millis(50);
 
-        if check_conditional(req, &etag, &last_mod) {
-            return build_response(keep_alive, 304, &entry.content_type, None, 0, hdrs);
+#[cfg(feature = "markdown")]
+pub fn serve_markdown(
+    state: &Arc<ServerState>,
+    md_path: &std::path::Path,
+    mtime: SystemTime,
+    encoding: Encoding,
+    keep_alive: bool,
+    is_head: bool,
+    req: &HttpRequest,
+) -> HttpResponse {
+    let config = state.config();
+    let query = req.path.split_once('?').map(|(_, q)| q);
+    let variant = config
+        .variants
+        .iter()
+        .find(|v| v.matches(query, &req.user_agent));
+    let params = RequestParams {
+        cookies: parse_cookies(&req.cookie),
+        query: query.map(parse_query).unwrap_or_default(),
+    };
+    // each variant and keyed cookie or query value renders to different bytes, so gets its
+    // own cache slot
+    let mut key = md_path.as_os_str().to_owned();
+    if let Some(v) = variant {
+        key.push("#");
+        key.push(v.id());
+    }
+    for name in &config.performance.cache_key_cookies {
+        if let Some(value) = params.cookies.get(name) {
+            key.push(format!(";{}={}", name, value));
+        }
+    }
+    for name in &config.performance.cache_key_query {
+        if let Some(value) = params.query.get(name) {
+            key.push(format!("?{}={}", name, value));
         }
+    }
+    let cache_key = std::path::PathBuf::from(key);
 
-        let clen = body.as_ref().map(|b| b.len()).unwrap_or(0);
-        return build_response(
-            keep_alive,
-            200,
-            &entry.content_type,
-            if is_head {
-                None
-            } else {
-                body.map(ResponseBody::Bytes)
-            },
-            clen,
-            hdrs,
+    let (fresh, stale) = match config
+        .performance
+        .enable_caching
+        .then(|| state.cache_get(&cache_key))
+        .flatten()
+    {
+        Some(entry) if entry.mtime == mtime && !entry.is_expired() => (Some(entry), None),
+        other => (None, other),
+    };
+    if config.performance.enable_caching {
+        state.metrics.record_cache(fresh.is_some());
+    }
+    if let Some(entry) = fresh {
+        return respond_with_page(
+            state, &cache_key, &entry, encoding, keep_alive, is_head, req,
         );
     }
 
-    let max_mb = state.config.performance.max_markdown_size_mb as u64;
+    let max_mb = config.performance.max_markdown_size_mb as u64;
     let max_bytes = if max_mb == 0 {
         5 * 1024 * 1024
     } else {
@@ -245,115 +916,865 @@ pub fn serve_markdown(
         );
     }
 
-    if let Ok(content) = fs::read_to_string(md_path) {
-        let (mut meta, raw_body) = split_frontmatter(&content);
-        let fm_use_cache = meta
-            .get("cache")
-            .map(|v| {
-                if let Ok(b) = bool::try_from(v.clone()) {
-                    b
-                } else if let Some(s) = v.as_str() {
-                    s == "true"
-                } else {
-                    true
+    // a streamed body would go out before the live reload script could be added
+    let streamable = config.performance.stream_threshold_bytes > 0
+        && !config.performance.enable_caching
+        && !config.server.live_reload
+        && !is_head
+        && req.http_minor == 1;
+    // With every stream or render slot taken, render buffered on this worker instead: a
+    // stream holds its render slot until the client has read most of the page, so waiting
+    // for one here could wait on a reader that never comes.
+    let stream = req
+        .stream_notify
+        .as_ref()
+        .filter(|_| streamable)
+        .and_then(|notify| {
+            let slot = state.stream_limiter.try_acquire_owned()?;
+            Some((notify, slot, state.render_limiter.try_acquire_owned()?))
+        });
+    let rendered = match stream {
+        Some((notify, slot, permit)) => {
+            match start_render_stream(
+                state,
+                md_path,
+                variant.cloned(),
+                params,
+                Arc::clone(notify),
+                slot,
+                permit,
+            ) {
+                RenderStart::Streaming {
+                    status,
+                    content_type,
+                    headers,
+                    body,
+                } => {
+                    return build_response(
+                        keep_alive,
+                        status,
+                        &content_type,
+                        Some(ResponseBody::Chunked(body)),
+                        0,
+                        headers,
+                    );
                 }
-            })
-            .unwrap_or(true);
-        let use_cache = state.config.performance.enable_caching && fm_use_cache;
-        let template_name = meta
-            .get("template")
-            .and_then(|v| v.as_str())
-            .unwrap_or("index")
-            .to_string();
-        let content_type = meta
-            .get("content_type")
-            .and_then(|v| v.as_str())
-            .unwrap_or("text/html; charset=utf-8")
-            .to_string();
-
-        let env = Arc::clone(
-            &state
+                RenderStart::Buffered(page) => Ok(page_entry(page, mtime)),
+                RenderStart::Failed(status) => Err(status),
+            }
+        }
+        None => render_coalesced(state, md_path, &cache_key, variant, &params, mtime, stale),
+    };
+
+    match rendered {
+        Ok(entry) => respond_with_page(
+            state, &cache_key, &entry, encoding, keep_alive, is_head, req,
+        ),
+        Err(500) => send_error(state, 500, keep_alive, is_head),
+        Err(_) => send_error(state, 404, keep_alive, is_head),
+    }
+}
+
+/// Renders `md_path` once for every concurrent miss on it: the first caller renders while
+/// the rest block on the same flight and share its result.
+#[cfg(feature = "markdown")]
+fn render_coalesced(
+    state: &ServerState,
+    md_path: &std::path::Path,
+    cache_key: &std::path::Path,
+    variant: Option<&TemplateVariant>,
+    params: &RequestParams,
+    mtime: SystemTime,
+    stale: Option<CacheEntry>,
+) -> Result<CacheEntry, u16> {
+    // an uncacheable page sees every cookie and query parameter, so only requests sending
+    // the same ones may share
+    let mut flight_key = cache_key.as_os_str().to_owned();
+    for (name, value) in &params.cookies {
+        flight_key.push(format!("|{}={}", name, value));
+    }
+    for (name, value) in &params.query {
+        flight_key.push(format!("&{}={}", name, value));
+    }
+    let flight_key = std::path::PathBuf::from(flight_key);
+    let flight = Arc::clone(
+        state
+            .inflight_renders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(flight_key.clone())
+            .or_default(),
+    );
+
+    let mut led = false;
+    let result = flight
+        .get_or_init(|| {
+            led = true;
+            render_entry(state, md_path, cache_key, variant, params, mtime, stale)
+        })
+        .clone();
+
+    if led {
+        let mut inflight = state
+            .inflight_renders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if inflight
+            .get(&flight_key)
+            .is_some_and(|f| Arc::ptr_eq(f, &flight))
+        {
+            inflight.remove(&flight_key);
+        }
+    }
+    result
+}
+
+/// Renders and caches a page, failing with the status to answer: 500 for a broken render,
+/// 404 when the file can't be read.
+#[cfg(feature = "markdown")]
+fn render_entry(
+    state: &ServerState,
+    md_path: &std::path::Path,
+    cache_key: &std::path::Path,
+    variant: Option<&TemplateVariant>,
+    params: &RequestParams,
+    mtime: SystemTime,
+    stale: Option<CacheEntry>,
+) -> Result<CacheEntry, u16> {
+    let disk = state
+        .disk_cache
+        .as_ref()
+        .filter(|_| state.config().performance.enable_caching)
+        .map(|disk| {
+            let theme_hash = state
                 .theme_state
                 .read()
                 .unwrap_or_else(|e| e.into_inner())
-                .1,
-        );
+                .0;
+            let config_hash = state.config_hash.load(std::sync::atomic::Ordering::Relaxed);
+            let version = DiskCache::version(mtime, theme_hash, config_hash, disk.generation());
+            (disk, version)
+        });
+    if let Some((disk, version)) = &disk
+        && let Some(entry) = disk.load(cache_key, version, mtime)
+    {
+        state.cache_put(cache_key.to_path_buf(), entry.clone());
+        return Ok(entry);
+    }
 
-        let html_body = markdown_to_html(raw_body);
-        meta.insert("content".to_string(), minijinja::Value::from(html_body));
+    // Under a burst of misses, an outdated copy of the page beats queueing for a render slot.
+    let _permit = match state.render_limiter.try_acquire_for(RENDER_SLOT_WAIT) {
+        Some(permit) => Some(permit),
+        None => match stale {
+            Some(entry) => return Ok(entry),
+            // Without workers this is the event loop, and the slots may be held by streams
+            // only it can drain, so render over the cap rather than wait on them.
+            None if state.config().server.threads == 0 => None,
+            None => Some(state.render_limiter.acquire()),
+        },
+    };
 
-        if let Ok(template) = env.get_template(&template_name)
-            && let Ok(rendered) = template.render(minijinja::Value::from(meta))
-        {
-            let raw_bytes = Bytes::from(rendered.into_bytes());
+    let content = fs::read_to_string(md_path).map_err(|_| 404u16)?;
+    let (meta, raw_body) = page_frontmatter(state, md_path, &content);
 
-            let entry = CacheEntry {
-                raw: raw_bytes.clone(),
-                br: Arc::new(OnceLock::new()),
-                gz: Arc::new(OnceLock::new()),
-                content_type: content_type.clone(),
-                mtime,
-            };
-            if use_cache {
-                state.cache_put(cache_key.clone(), entry.clone());
-            }
+    let page = render_page(state, md_path, meta, raw_body, variant, params).map_err(|e| {
+        error!("Failed to render {}: {}", md_path.display(), e);
+        500u16
+    })?;
 
-            let mut hdrs = Vec::new();
-            let body = extract_encoded_body(
-                state,
-                &cache_key,
-                &entry,
-                encoding,
-                use_compression,
-                &mut hdrs,
+    let cacheable = page.cacheable;
+    let entry = page_entry(page, mtime);
+    if state.config().performance.enable_caching && cacheable {
+        state.cache_put(cache_key.to_path_buf(), entry.clone());
+        // the disk cache only knows mtimes, so it would keep serving a page past its TTL
+        if let Some((disk, version)) = &disk
+            && entry.expires_at.is_none()
+            && let Err(e) = disk.store(cache_key, version, &entry)
+        {
+            warn!(
+                "Failed to persist {} to disk cache: {}",
+                md_path.display(),
+                e
             );
+        }
+    }
+    Ok(entry)
+}
 
-            let mtime_sec = mtime
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-            let etag = format!("W/\"{:x}-{:x}{}\"", mtime_sec, entry.raw.len(), enc_suffix);
-            let last_mod = httpdate::fmt_http_date(mtime);
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
 
-            hdrs.push(("ETag".into(), etag.clone()));
-            hdrs.push(("Last-Modified".into(), last_mod.clone()));
+/// The `server.metrics_path` endpoint: GET only, and only for `server.metrics_allow_ips`.
+fn metrics_response(state: &ServerState, req: &HttpRequest, keep_alive: bool) -> HttpResponse {
+    let is_head = req.method == "HEAD";
+    let peer = ip_bits(req.peer_ip);
+    let allowed = state
+        .config()
+        .server
+        .metrics_allow_ips
+        .iter()
+        .filter_map(|s| parse_cidr(s))
+        .any(|(net, mask)| peer & mask == net);
+    let (status, msg, hdrs) = if !allowed {
+        (403, "403 Forbidden", vec![])
+    } else if req.method != "GET" {
+        (
+            405,
+            "Method Not Allowed",
+            vec![("Allow".into(), "GET".into())],
+        )
+    } else {
+        let body = Bytes::from(state.metrics.prometheus());
+        let clen = body.len();
+        return build_response(
+            keep_alive,
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            Some(ResponseBody::Bytes(body)),
+            clen,
+            vec![("Cache-Control".into(), "no-store".into())],
+        );
+    };
+    let msg = Bytes::from(msg);
+    build_response(
+        keep_alive,
+        status,
+        "text/plain",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(msg.clone()))
+        },
+        msg.len(),
+        hdrs,
+    )
+}
 
-            if check_conditional(req, &etag, &last_mod) {
-                return build_response(keep_alive, 304, &content_type, None, 0, hdrs);
+/// The `server.admin_path` endpoint, for `server.admin_credentials` only: GET reports each
+/// cache's statistics as JSON, and POST with `action=purge` empties the page and template
+/// caches, outdates the disk cache and rebuilds the theme first, so the next requests
+/// render from scratch.
+fn admin_response(state: &Arc<ServerState>, req: &HttpRequest, keep_alive: bool) -> HttpResponse {
+    let is_head = req.method == "HEAD";
+    if !is_authorized(req, &state.config().server.admin_credentials) {
+        return unauthorized(state, keep_alive, is_head);
+    }
+    let action = req
+        .path
+        .split_once('?')
+        .and_then(|(_, q)| parse_query(q).remove("action"));
+    let purged = match (req.method.as_str(), action.as_deref()) {
+        ("GET" | "HEAD", _) => false,
+        ("POST", Some("purge")) => {
+            #[cfg(feature = "markdown")]
+            {
+                // the rebuild clears the page and directory caches itself
+                let failed_templates = crate::theme::rebuild_theme(state);
+                if !failed_templates.is_empty() {
+                    warn!("Broken templates: {}", failed_templates.join(", "));
+                }
+                state.toc_cache.clear();
             }
-
-            let clen = body.as_ref().map(|b| b.len()).unwrap_or(0);
+            state.page_cache.clear();
+            if let Some(disk) = &state.disk_cache
+                && let Err(e) = disk.purge()
+            {
+                warn!("Failed to record the disk cache purge: {}", e);
+            }
+            info!("Caches purged through {}", state.config().server.admin_path);
+            true
+        }
+        (method, _) => {
+            let (status, msg) = if method == "POST" {
+                (400, "Unknown action; use ?action=purge")
+            } else {
+                (405, "Method Not Allowed")
+            };
+            let msg = Bytes::from(msg);
             return build_response(
                 keep_alive,
-                200,
-                &content_type,
+                status,
+                "text/plain",
                 if is_head {
                     None
                 } else {
-                    body.map(ResponseBody::Bytes)
+                    Some(ResponseBody::Bytes(msg.clone()))
                 },
-                clen,
-                hdrs,
+                msg.len(),
+                vec![("Allow".into(), "GET, HEAD, POST".into())],
+            );
+        }
+    };
+
+    let mut stats = serde_json::Map::new();
+    stats.insert("purged".into(), purged.into());
+    stats.insert("page_cache".into(), json!(state.page_cache.stats()));
+    #[cfg(feature = "markdown")]
+    {
+        stats.insert("dir_cache".into(), json!(state.dir_cache.stats()));
+        stats.insert("toc_cache".into(), json!(state.toc_cache.stats()));
+    }
+    let body = Bytes::from(serde_json::Value::Object(stats).to_string());
+    let clen = body.len();
+    build_response(
+        keep_alive,
+        200,
+        "application/json",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(body))
+        },
+        clen,
+        vec![("Cache-Control".into(), "no-store".into())],
+    )
+}
+
+/// Answers `OPTIONS`, including CORS preflights, without touching the filesystem. The
+/// `Access-Control-Allow-Origin` header itself is part of every response already.
+fn options_response(state: &ServerState, keep_alive: bool) -> HttpResponse {
+    let security = &state.config().security;
+    let mut hdrs = vec![("Allow".into(), ALLOWED_METHODS.into())];
+    if !security.cors_allow_origin.is_empty() {
+        hdrs.push((
+            "Access-Control-Allow-Methods".into(),
+            security.cors_allow_methods.clone(),
+        ));
+        if !security.cors_allow_headers.is_empty() {
+            hdrs.push((
+                "Access-Control-Allow-Headers".into(),
+                security.cors_allow_headers.clone(),
+            ));
+        }
+    }
+    build_response(keep_alive, 204, "text/plain", None, 0, hdrs)
+}
+
+/// Splits a page's frontmatter off, falling back to the lenient parse (with a warning) when
+/// the frontmatter is malformed.
+#[cfg(feature = "markdown")]
+fn page_frontmatter<'a>(
+    state: &ServerState,
+    md_path: &std::path::Path,
+    content: &'a str,
+) -> (BTreeMap<String, minijinja::Value>, &'a str) {
+    let config = state.config();
+    match try_split_frontmatter(content, &config.markdown) {
+        Ok(parts) => parts,
+        Err(e) => {
+            warn!(
+                "Ignoring frontmatter of {}: {}",
+                md_path.display(),
+                RenderError::Frontmatter(e)
             );
+            split_frontmatter(content, &config.markdown)
+        }
+    }
+}
+
+#[cfg(feature = "markdown")]
+fn page_entry(page: RenderedPage, mtime: SystemTime) -> CacheEntry {
+    CacheEntry {
+        raw: Bytes::from(page.body.into_bytes()),
+        br: Arc::new(OnceLock::new()),
+        gz: Arc::new(OnceLock::new()),
+        df: Arc::new(OnceLock::new()),
+        content_type: page.content_type,
+        mtime,
+        status: page.status,
+        headers: page.headers,
+        expires_at: page.cache_ttl.map(|ttl| Instant::now() + ttl),
+    }
+}
+
+/// Streamed renders forward their output in pieces of about this size.
+#[cfg(feature = "markdown")]
+const STREAM_CHUNK: usize = 16 * 1024;
+
+/// How a streaming render began, reported back to the request that started it.
+#[cfg(feature = "markdown")]
+enum RenderStart {
+    /// The whole render fit under the threshold and is answered like any other page.
+    Buffered(RenderedPage),
+    /// The render outgrew the threshold; the rest of the body arrives on `body`.
+    Streaming {
+        status: u16,
+        content_type: String,
+        headers: Vec<(String, String)>,
+        body: mpsc::Receiver<Option<Bytes>>,
+    },
+    Failed(u16),
+}
+
+/// What a streaming render still owes its caller until it either finishes or starts streaming.
+#[cfg(feature = "markdown")]
+struct StreamHead {
+    start: mpsc::Sender<RenderStart>,
+    status: u16,
+    content_type: String,
+    headers: Vec<(String, String)>,
+}
+
+/// Buffers a render until it outgrows `threshold`, then announces a streamed response and
+/// forwards the output through a bounded channel, so a slow client stalls the render rather
+/// than letting it pile up in memory.
+#[cfg(feature = "markdown")]
+struct StreamingWriter {
+    buf: Vec<u8>,
+    threshold: usize,
+    head: Option<StreamHead>,
+    tx: Option<mpsc::SyncSender<Option<Bytes>>>,
+    notify: StreamNotify,
+}
+
+#[cfg(feature = "markdown")]
+impl StreamingWriter {
+    fn send(&self, chunk: Option<Bytes>) -> io::Result<()> {
+        if let Some(tx) = &self.tx {
+            tx.send(chunk)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            (self.notify)();
+        }
+        Ok(())
+    }
+
+    fn send_buffered(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buf));
+        self.send(Some(chunk))
+    }
+}
+
+#[cfg(feature = "markdown")]
+impl Write for StreamingWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.tx.is_none()
+            && self.buf.len() > self.threshold
+            && let Some(head) = self.head.take()
+        {
+            let (tx, body) = mpsc::sync_channel(8);
+            head.start
+                .send(RenderStart::Streaming {
+                    status: head.status,
+                    content_type: head.content_type,
+                    headers: head.headers,
+                    body,
+                })
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            self.tx = Some(tx);
+        }
+        if self.tx.is_some() && self.buf.len() >= STREAM_CHUNK {
+            self.send_buffered()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Renders `md_path` on its own thread, returning once the output is complete or has
+/// outgrown `stream_threshold_bytes`, in which case the render carries on feeding the
+/// response body. Headers a template sets after streaming begins are dropped.
+#[cfg(feature = "markdown")]
+fn start_render_stream(
+    state: &Arc<ServerState>,
+    md_path: &std::path::Path,
+    variant: Option<TemplateVariant>,
+    params: RequestParams,
+    notify: StreamNotify,
+    slot: OwnedRenderPermit,
+    permit: OwnedRenderPermit,
+) -> RenderStart {
+    let (start_tx, start_rx) = mpsc::channel();
+    let state = Arc::clone(state);
+    let md_path = md_path.to_path_buf();
+    let span = tracing::Span::current();
+    std::thread::spawn(move || {
+        let _enter = span.enter();
+        let _slot = slot;
+        let _permit = permit;
+        let Ok(content) = fs::read_to_string(&md_path) else {
+            let _ = start_tx.send(RenderStart::Failed(404));
+            return;
+        };
+        let (meta, raw_body) = page_frontmatter(&state, &md_path, &content);
+        let page = prepare_page(&state, &md_path, meta, raw_body, variant.as_ref(), &params);
+        let mut writer = StreamingWriter {
+            buf: Vec::new(),
+            threshold: state.config().performance.stream_threshold_bytes,
+            head: Some(StreamHead {
+                start: start_tx,
+                status: page.status,
+                content_type: page.content_type.clone(),
+                headers: page.headers.clone(),
+            }),
+            tx: None,
+            notify,
+        };
+
+        let result = page.render_to(&mut writer);
+        match (result, writer.head.take()) {
+            (Ok(set_headers), Some(head)) => {
+                let mut headers = page.headers;
+                headers.extend(set_headers);
+                let _ = head.start.send(RenderStart::Buffered(RenderedPage {
+                    body: String::from_utf8_lossy(&writer.buf).into_owned(),
+                    content_type: page.content_type,
+                    status: page.status,
+                    headers,
+                    cacheable: false,
+                    cache_ttl: None,
+                }));
+            }
+            (Ok(_), None) => {
+                let _ = writer.send_buffered();
+            }
+            (Err(e), head) => {
+                error!("Failed to render {}: {}", md_path.display(), e);
+                match head {
+                    Some(head) => {
+                        let _ = head.start.send(RenderStart::Failed(500));
+                    }
+                    None => {
+                        let _ = writer.send(None);
+                    }
+                }
+            }
+        }
+        // closing the channel is what ends the chunked body
+        if writer.tx.take().is_some() {
+            (writer.notify)();
+        }
+    });
+    start_rx.recv().unwrap_or(RenderStart::Failed(500))
+}
+
+/// Answers 401 to requests under a `security.basic_auth` prefix without matching credentials.
+/// Prefixes match whole segments of the decoded, normalized path, so neither escapes nor
+/// doubled slashes reach a protected file unchallenged.
+fn basic_auth_challenge(
+    state: &ServerState,
+    req: &HttpRequest,
+    path: &str,
+    keep_alive: bool,
+    is_head: bool,
+) -> Option<HttpResponse> {
+    let config = state.config();
+    let segments = |p: &str| -> Vec<String> {
+        p.split('/')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let path = segments(path);
+    let users = config
+        .security
+        .basic_auth
+        .iter()
+        .map(|(prefix, users)| (segments(prefix), users))
+        .filter(|(prefix, _)| path.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())?
+        .1;
+    (!is_authorized(req, users)).then(|| unauthorized(state, keep_alive, is_head))
+}
+
+/// Whether the request's Basic credentials match one of `users`.
+fn is_authorized(req: &HttpRequest, users: &[String]) -> bool {
+    let credentials = req
+        .authorization
+        .as_deref()
+        .and_then(|auth| auth.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+        .and_then(|(_, encoded)| base64_decode(encoded.trim()))
+        .and_then(|raw| String::from_utf8(raw).ok());
+    credentials
+        .as_deref()
+        .and_then(|c| c.split_once(':'))
+        .is_some_and(|(user, password)| {
+            users
+                .iter()
+                .any(|entry| credential_matches(entry, user, password))
+        })
+}
+
+/// A 401 asking for Basic credentials, with the server name as the realm.
+fn unauthorized(state: &ServerState, keep_alive: bool, is_head: bool) -> HttpResponse {
+    let realm: String = state
+        .config()
+        .server
+        .name
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"' && *c != '\\')
+        .collect();
+    let msg = Bytes::from("401 Unauthorized");
+    build_response(
+        keep_alive,
+        401,
+        "text/plain",
+        if is_head {
+            None
+        } else {
+            Some(ResponseBody::Bytes(msg.clone()))
+        },
+        msg.len(),
+        vec![(
+            "WWW-Authenticate".into(),
+            format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm),
+        )],
+    )
+}
+
+/// Checks one `user:password` or `user:sha256:<hex>` entry. Passwords are compared as
+/// digests, so the comparison takes as long however much of the password matches.
+fn credential_matches(entry: &str, user: &str, password: &str) -> bool {
+    let Some((name, secret)) = entry.split_once(':') else {
+        return false;
+    };
+    let expected = match secret.strip_prefix("sha256:") {
+        Some(hex) => (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>(),
+        None => Some(sha256(secret.as_bytes()).to_vec()),
+    };
+    let Some(expected) = expected else {
+        return false;
+    };
+    let name_ok = constant_time_eq(name.as_bytes(), user.as_bytes());
+    let password_ok = constant_time_eq(&expected, &sha256(password.as_bytes()));
+    name_ok & password_ok
+}
+
+/// The `/robots.txt` described by the `seo` settings, or `None` when none are set.
+fn robots_txt(state: &ServerState) -> Option<String> {
+    let config = state.config();
+    let seo = &config.seo;
+    if seo.sitemap_url.is_empty() && seo.disallow.is_empty() && seo.user_agent.is_empty() {
+        return None;
+    }
+    let clean = |s: &String| !s.is_empty() && !s.chars().any(char::is_control);
+    let mut out = String::new();
+    let groups = std::iter::once(("*", &seo.disallow)).chain(
+        seo.user_agent
+            .iter()
+            .map(|(ua, paths)| (ua.as_str(), paths)),
+    );
+    for (agent, paths) in groups {
+        if agent.is_empty() || agent.chars().any(char::is_control) {
+            continue;
+        }
+        out.push_str(&format!("User-agent: {}\n", agent));
+        let mut any = false;
+        for path in paths.iter().filter(|p| clean(p)) {
+            out.push_str(&format!("Disallow: {}\n", state.public_path(path)));
+            any = true;
+        }
+        // an empty `Disallow` allows everything, and every group needs a rule
+        if !any {
+            out.push_str("Disallow:\n");
+        }
+        out.push('\n');
+    }
+    if clean(&seo.sitemap_url) {
+        let url = if seo.sitemap_url.contains("://") {
+            seo.sitemap_url.clone()
+        } else {
+            absolute_url(
+                &config.server.base_url,
+                &utf8_percent_encode(&state.public_path(&seo.sitemap_url), PATH_ENCODE_SET)
+                    .to_string(),
+            )
+        };
+        out.push_str(&format!("Sitemap: {}\n", url));
+    }
+    Some(out)
+}
+
+/// A page's markdown source without its frontmatter, the `seo.plaintext_alternates` view.
+/// `negotiated` marks an answer chosen by `Accept` rather than by URL, which caches must
+/// keep apart from the HTML.
+#[cfg(feature = "markdown")]
+fn plaintext_response(
+    state: &ServerState,
+    req: &HttpRequest,
+    keep_alive: bool,
+    md_path: &std::path::Path,
+    negotiated: bool,
+) -> Option<HttpResponse> {
+    let content = fs::read_to_string(md_path).ok()?;
+    let (_, body) = split_frontmatter(&content, &state.config().markdown);
+    let content_type = format!("text/plain; charset={}", state.config().defaults.charset);
+    let mut res = generated_response(
+        state,
+        req,
+        keep_alive,
+        &content_type,
+        Bytes::from(body.to_string()),
+    );
+    if negotiated {
+        res.extra_headers.push(("Vary".into(), "Accept".into()));
+    }
+    Some(res)
+}
+
+/// Hrefs in listings are built from file names, where a literal `%` must not read as an escape.
+const LISTING_ENCODE_SET: &AsciiSet = &PATH_ENCODE_SET.add(b'%');
+
+/// A row of an autoindex listing, as the `autoindex.html` template sees it.
+#[derive(Serialize)]
+struct ListingRow {
+    name: String,
+    href: String,
+    is_dir: bool,
+    size: u64,
+    modified: String,
+}
+
+/// Lists content directory `dir` for `server.autoindex`. Pages are linked by their URL, and
+/// without the markdown feature left out, as they couldn't be served.
+fn autoindex_response(
+    state: &ServerState,
+    req: &HttpRequest,
+    keep_alive: bool,
+    dir: &str,
+) -> Option<HttpResponse> {
+    let mut entries = state.list_content_dir(dir)?;
+    entries.sort_by_key(|entry| !entry.is_dir);
+    let href =
+        |rel: &str| utf8_percent_encode(&state.public_path(rel), LISTING_ENCODE_SET).to_string();
+    let rows: Vec<ListingRow> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (name, link) = if entry.is_dir {
+                (
+                    format!("{}/", entry.name),
+                    format!("/{}{}/", dir, entry.name),
+                )
+            } else if let Some(stem) = entry.name.strip_suffix(".md") {
+                if !cfg!(feature = "markdown") {
+                    return None;
+                }
+                (stem.to_string(), format!("/{}{}", dir, stem))
+            } else {
+                (entry.name.clone(), format!("/{}{}", dir, entry.name))
+            };
+            Some(ListingRow {
+                name,
+                href: href(&link),
+                is_dir: entry.is_dir,
+                size: entry.size,
+                modified: format_rfc3339(entry.mtime),
+            })
+        })
+        .collect();
+    let path = state.public_path(&format!("/{}", dir));
+    let parent = dir.strip_suffix('/').map(|d| {
+        let up = d.rfind('/').map_or("", |i| &d[..=i]);
+        href(&format!("/{}", up))
+    });
+
+    let themed = autoindex_template(state, &path, parent.as_deref(), &rows);
+    let html = themed.unwrap_or_else(|| {
+        let title = escape_html(&path);
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"{}\"><title>Index of {}</title></head>\n<body><h1>Index of {}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+            escape_html(&state.config().defaults.charset),
+            title,
+            title
+        );
+        if let Some(parent) = &parent {
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n",
+                escape_html(parent)
+            ));
+        }
+        for row in &rows {
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(&row.href),
+                escape_html(&row.name),
+                if row.is_dir {
+                    "-".to_string()
+                } else {
+                    row.size.to_string()
+                },
+                row.modified
+            ));
+        }
+        html.push_str("</table>\n</body></html>\n");
+        html
+    });
+    let content_type = format!("text/html; charset={}", state.config().defaults.charset);
+    Some(generated_response(
+        state,
+        req,
+        keep_alive,
+        &content_type,
+        Bytes::from(html),
+    ))
+}
+
+/// Renders the theme's `autoindex.html`, if it has one, with `path`, `parent` (absent at the
+/// root) and `entries`.
+#[cfg(feature = "markdown")]
+fn autoindex_template(
+    state: &ServerState,
+    path: &str,
+    parent: Option<&str>,
+    rows: &[ListingRow],
+) -> Option<String> {
+    let env = Arc::clone(
+        &state
+            .theme_state
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .1,
+    );
+    let template = env.get_template("autoindex.html").ok()?;
+    template
+        .render(minijinja::context! { path => path, parent => parent, entries => rows })
+        .map_err(|e| error!("Failed to render autoindex.html: {}", e))
+        .ok()
+}
+
+#[cfg(not(feature = "markdown"))]
+fn autoindex_template(
+    _state: &ServerState,
+    _path: &str,
+    _parent: Option<&str>,
+    _rows: &[ListingRow],
+) -> Option<String> {
+    None
+}
+
+/// A directory's index: the first of `paths.index_files` that exists, as either a markdown
+/// page to render or a static file to send.
+fn resolve_index(state: &ServerState, dir: &str) -> (Option<ResolvedFile>, Option<ResolvedFile>) {
+    for name in &state.config().paths.index_files {
+        // without the markdown feature a page is never served, so it can't be an index either
+        if name.ends_with(".md") && !cfg!(feature = "markdown") {
+            continue;
+        }
+        if let Some(file) = state.resolve_file(&format!("{}{}", dir, name)) {
+            return if name.ends_with(".md") {
+                (Some(file), None)
+            } else {
+                (None, Some(file))
+            };
         }
     }
-
-    let not_found = Bytes::from(state.config.paths.fallback_404.as_bytes().to_vec());
-    let clen = not_found.len();
-    build_response(
-        keep_alive,
-        404,
-        "text/html",
-        if is_head {
-            None
-        } else {
-            Some(ResponseBody::Bytes(not_found))
-        },
-        clen,
-        vec![],
-    )
+    (None, None)
 }
 
+type ResolvedFile = (std::path::PathBuf, fs::Metadata);
+
 pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpResponse {
     let method = req.method.as_str();
     let path = req.path.as_str();
@@ -364,6 +1785,27 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
         false
     };
 
+    // asterisk-form asks about the server as a whole, which only OPTIONS can do; it is no
+    // resource, so there are no CORS headers to add
+    if path == "*" {
+        return if method == "OPTIONS" {
+            let hdrs = vec![("Allow".into(), ALLOWED_METHODS.into())];
+            build_response(keep_alive, 204, "text/plain", None, 0, hdrs)
+        } else {
+            bad_request(is_head)
+        };
+    }
+    let metrics_path = &state.config().server.metrics_path;
+    if !metrics_path.is_empty() && path.split('?').next() == Some(metrics_path.as_str()) {
+        return metrics_response(&state, &req, keep_alive);
+    }
+    let admin_path = &state.config().server.admin_path;
+    if !admin_path.is_empty() && path.split('?').next() == Some(admin_path.as_str()) {
+        return admin_response(&state, &req, keep_alive);
+    }
+    if method == "OPTIONS" {
+        return options_response(&state, keep_alive);
+    }
     if method != "GET" && method != "HEAD" {
         let msg = Bytes::from("Method Not Allowed");
         return build_response(
@@ -376,53 +1818,148 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
                 Some(ResponseBody::Bytes(msg.clone()))
             },
             msg.len(),
-            vec![("Allow".into(), "GET, HEAD".into())],
+            vec![("Allow".into(), ALLOWED_METHODS.into())],
         );
     }
+    if let Some(res) = canonical_host_redirect(&state, &req, keep_alive, is_head) {
+        return res;
+    }
+
+    let raw_path = path.split('?').next().unwrap_or("/");
+    if state.config().security.strict_path_decoding && !is_valid_encoded_path(raw_path) {
+        return bad_request(is_head);
+    }
 
     let decoded_path = percent_decode_str(path)
         .decode_utf8()
         .unwrap_or_else(|_| path.into());
-    let normalized = decoded_path
+    let mut normalized = decoded_path
         .split('?')
         .next()
         .unwrap_or("/")
         .replace('\\', "/");
+    let query = decoded_path.split_once('?').map(|(_, q)| q);
+
+    // a proxy may or may not strip `base_path`, so paths without it resolve as they are
+    let base_path = format!("/{}", state.config().server.base_path.trim_matches('/'));
+    if base_path != "/" {
+        if normalized == base_path {
+            return moved_permanently(&state, keep_alive, is_head, "/", query);
+        }
+        if let Some(rest) = normalized.strip_prefix(&base_path)
+            && rest.starts_with('/')
+        {
+            normalized = rest.to_string();
+        }
+    }
 
     let has_hidden = normalized
         .split('/')
         .any(|part| part.starts_with('.') && part != ".well-known");
     if normalized.contains("..") || has_hidden {
-        let msg = Bytes::from("403 Forbidden");
-        return build_response(
-            keep_alive,
-            403,
-            "text/plain",
-            if is_head {
-                None
-            } else {
-                Some(ResponseBody::Bytes(msg.clone()))
-            },
-            msg.len(),
-            vec![],
-        );
+        return send_error(&state, 403, keep_alive, is_head);
+    }
+    if let Some(res) = rule_redirect(&state, &normalized, query, keep_alive, is_head) {
+        return res;
+    }
+    if let Some(res) = basic_auth_challenge(&state, &req, &normalized, keep_alive, is_head) {
+        return res;
+    }
+
+    let trailing_slash = state.config().server.trailing_slash;
+    if state.config().seo.redirect_index
+        && let Some(dir) = normalized
+            .strip_suffix("/index")
+            .or_else(|| normalized.strip_suffix("/index.html"))
+    {
+        let location = if trailing_slash == TrailingSlash::NeverRedirect && !dir.is_empty() {
+            dir.to_string()
+        } else {
+            format!("{}/", dir)
+        };
+        return moved_permanently(&state, keep_alive, is_head, &location, query);
     }
 
-    let target = normalized.trim_start_matches('/');
-    let is_dir = normalized.ends_with('/') || normalized == "/";
-    let encoding = determine_encoding(&req.accept_encoding);
-    let md_target = if is_dir {
-        format!("{}index.md", target)
+    let mut target = normalized.trim_start_matches('/').to_string();
+    let mut is_dir = normalized.ends_with('/') || normalized == "/";
+    let encoding = determine_encoding(
+        &req.accept_encoding,
+        &state.config().performance.compression,
+    );
+    let page_file = |target: &str| {
+        cfg!(feature = "markdown")
+            .then(|| state.resolve_file(&format!("{}.md", target)))
+            .flatten()
+    };
+    let bare = target.trim_end_matches('/');
+    if trailing_slash == TrailingSlash::NeverRedirect
+        && is_dir
+        && !bare.is_empty()
+        && (state.is_content_dir(bare) || page_file(bare).is_some())
+    {
+        let location = normalized.trim_end_matches('/');
+        return moved_permanently(&state, keep_alive, is_head, location, query);
+    }
+    let (mut md_file, mut static_index) = if is_dir {
+        resolve_index(&state, &target)
     } else {
-        format!("{}.md", target)
+        (page_file(&target), None)
     };
+    if trailing_slash == TrailingSlash::AlwaysRedirect {
+        if !is_dir && (md_file.is_some() || state.is_content_dir(&target)) {
+            return moved_permanently(
+                &state,
+                keep_alive,
+                is_head,
+                &format!("{}/", normalized),
+                query,
+            );
+        }
+        if is_dir && !bare.is_empty() && md_file.is_none() && static_index.is_none() {
+            md_file = page_file(bare);
+        }
+    }
 
-    if let Some(md_path) = secure_join(&state.base_dir, &md_target)
-        && let Ok(canon) = md_path.canonicalize()
-        && canon.starts_with(&state.base_canon)
-        && let Ok(metadata) = std::fs::metadata(&canon)
-        && metadata.is_file()
-    {
+    // `posts/index.md` only answers `/posts/`; whether `/posts` is `posts.md` or a redirect
+    // to the directory is decided by `dir_precedence`, and what the redirect becomes by
+    // `dir_redirect`.
+    let redirect_to_dir = !is_dir
+        && state.is_content_dir(&target)
+        && (state.config().paths.dir_precedence == DirPrecedence::Directory || md_file.is_none());
+    if redirect_to_dir {
+        let dir_redirect = match trailing_slash {
+            TrailingSlash::NeverRedirect => DirRedirect::Index,
+            _ => state.config().paths.dir_redirect,
+        };
+        match dir_redirect {
+            DirRedirect::Redirect => {
+                return moved_permanently(
+                    &state,
+                    keep_alive,
+                    is_head,
+                    &format!("{}/", normalized),
+                    query,
+                );
+            }
+            DirRedirect::NotFound => {}
+            DirRedirect::Index => {
+                target.push('/');
+                is_dir = true;
+                (md_file, static_index) = resolve_index(&state, &target);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "markdown"))]
+    let _ = md_file;
+    #[cfg(feature = "markdown")]
+    if let Some((canon, metadata)) = md_file {
+        if state.config().seo.plaintext_alternates
+            && prefers_plain_text(&req.accept)
+            && let Some(res) = plaintext_response(&state, &req, keep_alive, &canon, true)
+        {
+            return res;
+        }
         return serve_markdown(
             &state,
             &canon,
@@ -434,66 +1971,30 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
         );
     }
 
-    if !is_dir
-        && let Some(target_path) = secure_join(&state.base_dir, target)
-        && let Ok(metadata) = std::fs::metadata(&target_path)
-        && metadata.is_dir()
-    {
-        let encoded_location =
-            utf8_percent_encode(&format!("{}/", normalized), PATH_ENCODE_SET).to_string();
-        let escaped_html = escape_html(&normalized);
-        let redirect_html = Bytes::from(format!(
-            "301 Moved Permanently: <a href=\"{}\">{}/</a>",
-            encoded_location, escaped_html
-        ));
-        return build_response(
-            keep_alive,
-            301,
-            "text/html",
-            if is_head {
-                None
-            } else {
-                Some(ResponseBody::Bytes(redirect_html.clone()))
-            },
-            redirect_html.len(),
-            vec![("Location".into(), encoded_location)],
-        );
-    }
-
-    let static_target = if is_dir {
-        format!("{}index.html", target)
+    let static_file = if is_dir {
+        static_index
     } else {
-        target.to_string()
+        state.resolve_file(&target).or_else(|| {
+            state
+                .config()
+                .paths
+                .extensionless_html
+                .then(|| state.resolve_file(&format!("{}.html", target)))
+                .flatten()
+        })
     };
 
-    if let Some(static_path) = secure_join(&state.base_dir, &static_target)
-        && let Ok(canon) = static_path.canonicalize()
-        && canon.starts_with(&state.base_canon)
-        && let Ok(metadata) = std::fs::metadata(&canon)
-        && metadata.is_file()
-    {
+    if let Some((canon, metadata)) = static_file {
         if canon
             .extension()
             .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case("md"))
         {
-            let msg = Bytes::from("403 Forbidden");
-            return build_response(
-                keep_alive,
-                403,
-                "text/plain",
-                if is_head {
-                    None
-                } else {
-                    Some(ResponseBody::Bytes(msg.clone()))
-                },
-                msg.len(),
-                vec![],
-            );
+            return send_error(&state, 403, keep_alive, is_head);
         }
 
         let file_len = metadata.len() as usize;
-        let mime = get_mime_type(&canon);
-        let compressible = state.config.performance.enable_compression && is_compressible(&mime);
+        let mime = get_mime_type(&canon, &state.mime_types.load());
+        let compressible = state.config().performance.enable_compression && is_compressible(&mime);
         let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
         let mtime_sec = mtime
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -501,12 +2002,23 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
             .as_secs();
         let cache_key = canon.clone();
 
-        let enc_suffix = match encoding {
-            Encoding::Brotli => "-br",
-            Encoding::Gzip => "-gz",
-            Encoding::None => "",
+        let sidecar = (state.config().performance.enable_compression
+            && state.config().performance.compression.precompressed)
+            .then(|| precompressed_sidecar(&canon, encoding, mtime))
+            .flatten();
+        let encoding = match sidecar {
+            Some(_) => encoding,
+            None => effective_encoding(&state, encoding, compressible, file_len),
         };
-        let etag = format!("W/\"{:x}-{:x}{}\"", mtime_sec, file_len, enc_suffix);
+        // strong, since the bytes on disk for a given size and mtime are fixed
+        let etag = format!(
+            "\"{:x}-{:x}{}\"",
+            mtime_sec,
+            file_len,
+            encoding.etag_suffix()
+        );
+        // ranges are always served from the identity bytes, so `If-Range` is checked against those
+        let identity_etag = format!("\"{:x}-{:x}\"", mtime_sec, file_len);
         let last_mod = httpdate::fmt_http_date(mtime);
 
         let mut range_start = 0;
@@ -514,6 +2026,7 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
         let mut is_partial = false;
 
         if let Some(range_val) = &req.range
+            && if_range_matches(&req, &identity_etag, &last_mod)
             && let Some(stripped) = range_val.strip_prefix("bytes=")
             && !stripped.contains(',')
         {
@@ -540,6 +2053,10 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
             }
         }
 
+        if !is_partial && encoding == Encoding::None && !accepts_identity(&req.accept_encoding) {
+            return not_acceptable(keep_alive, is_head);
+        }
+
         if is_partial && (range_start > range_end || range_start >= file_len) {
             let msg = Bytes::from("Range Not Satisfiable");
             return build_response(
@@ -567,12 +2084,40 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
             );
         }
 
+        if !is_partial && let Some((file, len)) = sidecar {
+            return build_response(
+                keep_alive,
+                200,
+                &mime,
+                if is_head {
+                    None
+                } else {
+                    Some(ResponseBody::Stream(file, 0, len - 1))
+                },
+                len as usize,
+                vec![
+                    ("Vary".into(), "Accept-Encoding".into()),
+                    ("Content-Encoding".into(), encoding.content_coding().into()),
+                    ("Cache-Control".into(), "public, max-age=86400".into()),
+                    ("ETag".into(), etag),
+                    ("Last-Modified".into(), last_mod),
+                ],
+            );
+        }
+
         const STREAM_THRESHOLD: usize = 10 * 1024 * 1024; // 10MB bypass memory cache natively
 
-        if state.config.performance.enable_caching
-            && let Some(entry) = state.page_cache.get(&cache_key)
-            && entry.mtime == mtime
-        {
+        let cached = state
+            .config()
+            .performance
+            .enable_caching
+            .then(|| state.cache_get(&cache_key))
+            .flatten()
+            .filter(|entry| entry.mtime == mtime && entry.raw.len() == file_len);
+        if state.config().performance.enable_caching {
+            state.metrics.record_cache(cached.is_some());
+        }
+        if let Some(entry) = cached {
             if is_partial {
                 let sliced_body = entry.raw.slice(range_start..range_end + 1);
                 return build_response(
@@ -585,13 +2130,7 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
                         Some(ResponseBody::Bytes(sliced_body))
                     },
                     range_end - range_start + 1,
-                    vec![
-                        (
-                            "Content-Range".into(),
-                            format!("bytes {}-{}/{}", range_start, range_end, file_len),
-                        ),
-                        ("Accept-Ranges".into(), "bytes".into()),
-                    ],
+                    partial_headers(range_start, range_end, file_len, &identity_etag, &last_mod),
                 );
             } else {
                 let mut hdrs = Vec::new();
@@ -623,67 +2162,25 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
             }
         }
 
-        if file_len > STREAM_THRESHOLD
-            && let Ok(file) = std::fs::File::open(&canon)
+        // a file that changed between the stat and the read no longer matches the length and
+        // validators computed above, so it is streamed at the declared length and not cached
+        if file_len <= STREAM_THRESHOLD
+            && let Ok(buf) = fs::read(&canon)
+            && buf.len() == file_len
         {
-            if is_partial {
-                let clen = range_end - range_start + 1;
-                return build_response(
-                    keep_alive,
-                    206,
-                    &mime,
-                    if is_head {
-                        None
-                    } else {
-                        Some(ResponseBody::Stream(
-                            file,
-                            range_start as u64,
-                            range_end as u64,
-                        ))
-                    },
-                    clen,
-                    vec![
-                        (
-                            "Content-Range".into(),
-                            format!("bytes {}-{}/{}", range_start, range_end, file_len),
-                        ),
-                        ("Accept-Ranges".into(), "bytes".into()),
-                    ],
-                );
-            } else {
-                return build_response(
-                    keep_alive,
-                    200,
-                    &mime,
-                    if is_head {
-                        None
-                    } else {
-                        Some(ResponseBody::Stream(
-                            file,
-                            0,
-                            file_len.saturating_sub(1) as u64,
-                        ))
-                    },
-                    file_len,
-                    vec![
-                        ("Accept-Ranges".into(), "bytes".into()),
-                        ("ETag".into(), etag),
-                        ("Last-Modified".into(), last_mod),
-                    ],
-                );
-            }
-        }
-
-        if let Ok(buf) = fs::read(&canon) {
             let raw_bytes = Bytes::from(buf);
             let entry = CacheEntry {
                 raw: raw_bytes.clone(),
                 br: Arc::new(OnceLock::new()),
                 gz: Arc::new(OnceLock::new()),
+                df: Arc::new(OnceLock::new()),
                 content_type: mime.clone(),
                 mtime,
+                status: 200,
+                headers: Vec::new(),
+                expires_at: None,
             };
-            if state.config.performance.enable_caching {
+            if state.config().performance.enable_caching {
                 state.cache_put(cache_key.clone(), entry.clone());
             }
 
@@ -699,13 +2196,7 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
                         Some(ResponseBody::Bytes(sliced_body))
                     },
                     range_end - range_start + 1,
-                    vec![
-                        (
-                            "Content-Range".into(),
-                            format!("bytes {}-{}/{}", range_start, range_end, file_len),
-                        ),
-                        ("Accept-Ranges".into(), "bytes".into()),
-                    ],
+                    partial_headers(range_start, range_end, file_len, &identity_etag, &last_mod),
                 );
             } else {
                 let mut hdrs = Vec::new();
@@ -736,20 +2227,219 @@ pub fn process_http_request(req: HttpRequest, state: Arc<ServerState>) -> HttpRe
                 );
             }
         }
+
+        if let Ok(file) = std::fs::File::open(&canon) {
+            if is_partial {
+                let clen = range_end - range_start + 1;
+                return build_response(
+                    keep_alive,
+                    206,
+                    &mime,
+                    if is_head {
+                        None
+                    } else {
+                        Some(ResponseBody::Stream(
+                            file,
+                            range_start as u64,
+                            range_end as u64,
+                        ))
+                    },
+                    clen,
+                    partial_headers(range_start, range_end, file_len, &identity_etag, &last_mod),
+                );
+            } else {
+                return build_response(
+                    keep_alive,
+                    200,
+                    &mime,
+                    if is_head {
+                        None
+                    } else if file_len == 0 {
+                        Some(ResponseBody::Bytes(Bytes::new()))
+                    } else {
+                        Some(ResponseBody::Stream(
+                            file,
+                            0,
+                            file_len.saturating_sub(1) as u64,
+                        ))
+                    },
+                    file_len,
+                    vec![
+                        ("Accept-Ranges".into(), "bytes".into()),
+                        ("ETag".into(), etag),
+                        ("Last-Modified".into(), last_mod),
+                    ],
+                );
+            }
+        }
     }
 
-    let not_found = Bytes::from(state.config.paths.fallback_404.as_bytes().to_vec());
-    let clen = not_found.len();
-    build_response(
-        keep_alive,
-        404,
-        "text/html; charset=utf-8",
-        if is_head {
-            None
-        } else {
-            Some(ResponseBody::Bytes(not_found))
-        },
-        clen,
-        vec![],
-    )
+    if is_dir
+        && state.config().server.autoindex
+        && let Some(res) = autoindex_response(&state, &req, keep_alive, &target)
+    {
+        return res;
+    }
+
+    if target == "robots.txt"
+        && let Some(robots) = robots_txt(&state)
+    {
+        let content_type = format!("text/plain; charset={}", state.config().defaults.charset);
+        return generated_response(&state, &req, keep_alive, &content_type, Bytes::from(robots));
+    }
+
+    #[cfg(feature = "markdown")]
+    if state.config().seo.plaintext_alternates
+        && !is_dir
+        && let Some(stem) = target.strip_suffix(".txt")
+        && let Some((canon, _)) = state.resolve_file(&format!("{}.md", stem))
+        && let Some(res) = plaintext_response(&state, &req, keep_alive, &canon, false)
+    {
+        return res;
+    }
+
+    send_error(&state, 404, keep_alive, is_head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The head `write_head` must produce, assembled the plain way with `format!`.
+    fn formatted_head(res: &HttpResponse, http_minor: u8, date: &str, precomputed: &str) -> String {
+        let reason = res
+            .reason
+            .clone()
+            .unwrap_or_else(|| reason_phrase(res.status).to_string());
+        let mut head = format!(
+            "HTTP/1.{} {} {}\r\nDate: {}\r\n",
+            http_minor, res.status, reason, date
+        );
+        if res.status != 204 {
+            head += &format!("Content-Type: {}\r\n", res.content_type);
+            if matches!(res.body, Some(ResponseBody::Chunked(_))) {
+                head += "Transfer-Encoding: chunked\r\n";
+            } else {
+                head += &format!("Content-Length: {}\r\n", res.clen);
+            }
+        }
+        head += &format!(
+            "Connection: {}\r\n",
+            if res.keep_alive {
+                "keep-alive"
+            } else {
+                "close"
+            }
+        );
+        for (k, v) in &res.extra_headers {
+            head += &format!("{}: {}\r\n", k, v);
+        }
+        head + precomputed + "\r\n"
+    }
+
+    fn written_head(res: &HttpResponse, http_minor: u8, date: &str, precomputed: &str) -> String {
+        let mut out = Vec::new();
+        write_head(&mut out, res, http_minor, date, precomputed.as_bytes()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_head_matches_formatted_output() {
+        let date = "Fri, 01 Mar 2026 00:00:00 GMT";
+        let precomputed = "Server: Lumen\r\nX-Frame-Options: DENY\r\n";
+        for status in [200, 204, 206, 301, 304, 404, 410, 418, 503, 299] {
+            for keep_alive in [true, false] {
+                for http_minor in [0, 1] {
+                    let res = build_response(
+                        keep_alive,
+                        status,
+                        "text/html; charset=utf-8",
+                        None,
+                        18_432,
+                        vec![("ETag".into(), "\"abc\"".into())],
+                    );
+                    assert_eq!(
+                        written_head(&res, http_minor, date, precomputed),
+                        formatted_head(&res, http_minor, date, precomputed),
+                        "status {} keep_alive {} minor {}",
+                        status,
+                        keep_alive,
+                        http_minor
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reason_phrases_cover_registered_codes_and_fall_back_by_class() {
+        for (status, reason) in [
+            (103, "Early Hints"),
+            (301, "Moved Permanently"),
+            (418, "I'm a teapot"),
+            (422, "Unprocessable Content"),
+            (425, "Too Early"),
+            (451, "Unavailable For Legal Reasons"),
+            (511, "Network Authentication Required"),
+            (199, "Informational"),
+            (299, "Success"),
+            (399, "Redirection"),
+            (499, "Client Error"),
+            (599, "Server Error"),
+        ] {
+            assert_eq!(reason_phrase(status), reason, "{}", status);
+        }
+    }
+
+    #[test]
+    fn uncommon_statuses_go_out_with_their_registered_reasons() {
+        for (status, line) in [
+            (207, "207 Multi-Status"),
+            (308, "308 Permanent Redirect"),
+            (418, "418 I'm a teapot"),
+            (421, "421 Misdirected Request"),
+            (422, "422 Unprocessable Content"),
+            (428, "428 Precondition Required"),
+            (451, "451 Unavailable For Legal Reasons"),
+            (507, "507 Insufficient Storage"),
+            (511, "511 Network Authentication Required"),
+            (432, "432 Client Error"),
+        ] {
+            let res = build_response(false, status, "text/plain", None, 0, vec![]);
+            assert!(
+                written_head(&res, 1, "d", "").starts_with(&format!("HTTP/1.1 {}\r\n", line)),
+                "{}",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn write_head_matches_formatted_output_for_chunked_and_custom_reasons() {
+        let (_tx, rx) = std::sync::mpsc::channel();
+        let chunked = build_response(
+            true,
+            200,
+            "text/html",
+            Some(ResponseBody::Chunked(rx)),
+            0,
+            vec![],
+        );
+        assert_eq!(
+            written_head(&chunked, 1, "d", ""),
+            formatted_head(&chunked, 1, "d", "")
+        );
+
+        let mut custom = build_response(false, 200, "text/plain", None, 2, vec![]);
+        custom.reason = Some("Fine".into());
+        assert_eq!(
+            written_head(&custom, 1, "d", ""),
+            formatted_head(&custom, 1, "d", "")
+        );
+        assert!(written_head(&custom, 1, "d", "").starts_with("HTTP/1.1 200 Fine\r\n"));
+
+        // a reason with control characters falls back to the standard phrase
+        custom.reason = Some("Fine\r\nX-Injected: 1".into());
+        assert!(written_head(&custom, 1, "d", "").starts_with("HTTP/1.1 200 OK\r\nDate"));
+    }
 }
@@ -0,0 +1,16 @@
+pub mod access_log;
+#[cfg(feature = "markdown")]
+pub mod build;
+pub mod cli;
+pub mod config;
+pub mod disk_cache;
+pub mod http;
+pub mod live_reload;
+#[cfg(feature = "markdown")]
+pub mod render;
+pub mod server;
+pub mod state;
+#[cfg(feature = "markdown")]
+pub mod theme;
+pub mod thread_pool;
+pub mod utils;
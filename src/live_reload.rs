@@ -0,0 +1,181 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::Ordering,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tracing::warn;
+
+use crate::{
+    state::ServerState,
+    utils::{base64_encode, sha1},
+};
+
+/// Where the page script opens its WebSocket, under `server.base_path` like any other URL.
+pub const LIVE_RELOAD_PATH: &str = "/__lumen/live-reload";
+
+/// How long file events must go quiet before a reload is sent, so saving several files at
+/// once, or an editor's write-and-rename, reloads the page once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Appended to the client's key before hashing, fixed by RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Reloads the page on a `reload` message and reconnects, without reloading, whenever the
+/// socket drops, so a restarted server or a connection past its lifetime picks up again.
+#[cfg(feature = "markdown")]
+const SCRIPT: &str = r#"<script>(function(){var u=(location.protocol==="https:"?"wss://":"ws://")+location.host+URL;function c(){var s=new WebSocket(u);s.onmessage=function(e){if(e.data==="reload")location.reload();};s.onclose=function(){setTimeout(c,1000);};}c();})();</script>"#;
+
+/// Puts the reload script, connecting to `url`, before the page's last `</body>`, or at the
+/// end when there is none.
+#[cfg(feature = "markdown")]
+pub fn inject_script(mut html: String, url: &str) -> String {
+    let url = serde_json::to_string(url)
+        .unwrap_or_default()
+        .replace("</", "<\\/");
+    let script = SCRIPT.replace("URL", &url);
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(at) => html.insert_str(at, &script),
+        None => html.push_str(&script),
+    }
+    html
+}
+
+/// The `101 Switching Protocols` head accepting a handshake that sent `key`.
+pub fn handshake_response(key: &str) -> Bytes {
+    let accept = base64_encode(&sha1(
+        format!("{}{}", key.trim(), WEBSOCKET_GUID).as_bytes(),
+    ));
+    Bytes::from(format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    ))
+}
+
+/// A single unmasked text frame, as a server sends them.
+pub fn text_frame(text: &str) -> Bytes {
+    let mut frame = vec![0x81];
+    match text.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(text.as_bytes());
+    Bytes::from(frame)
+}
+
+/// Whether client data starts with a close frame. Clients only ever send control frames on
+/// this socket, so nothing else is parsed.
+pub fn is_close_frame(data: &[u8]) -> bool {
+    data.first().is_some_and(|b| b & 0x0f == 0x8)
+}
+
+/// Watches the content roots and theme directory, calling `on_change` once changes have gone
+/// quiet for `DEBOUNCE`. A changed theme is rebuilt first, so the reload already renders
+/// with it. Without a working watcher live reload is left off with a warning.
+pub fn start_watcher(state: Arc<ServerState>, on_change: impl Fn() + Send + 'static) {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Live reload is off, watching files failed: {}", e);
+            return;
+        }
+    };
+    let theme_dir = PathBuf::from(&state.config().paths.theme_dir);
+    let dirs = state
+        .content_roots
+        .iter()
+        .map(|root| root.canon.clone())
+        .chain([theme_dir]);
+    for dir in dirs {
+        if let Err(e) = watcher.watch(&dir, RecursiveMode::Recursive) {
+            warn!("Not watching {} for live reload: {}", dir.display(), e);
+        }
+    }
+
+    thread::spawn(move || {
+        // dropping the watcher stops the watch
+        let _watcher = watcher;
+        while state.is_running.load(Ordering::Relaxed) {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Ok(event)) if is_change(&event) => {
+                    if !settle(&rx) {
+                        break;
+                    }
+                    #[cfg(feature = "markdown")]
+                    let _ = crate::theme::reload_theme(&state);
+                    on_change();
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(e)) => warn!("Live reload watcher error: {}", e),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+}
+
+/// Opening or reading a file is reported too, and serving the reloaded page does both.
+fn is_change(event: &notify::Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+}
+
+/// Waits until no change has arrived for `DEBOUNCE`; false once the watcher has gone away.
+fn settle(rx: &mpsc::Receiver<notify::Result<notify::Event>>) -> bool {
+    let mut quiet_at = Instant::now() + DEBOUNCE;
+    loop {
+        match rx.recv_timeout(quiet_at.saturating_duration_since(Instant::now())) {
+            Ok(Ok(event)) if is_change(&event) => quiet_at = Instant::now() + DEBOUNCE,
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_frames_use_the_shortest_length_encoding() {
+        assert_eq!(&text_frame("reload")[..], b"\x81\x06reload");
+        let medium = text_frame(&"a".repeat(300));
+        assert_eq!(&medium[..4], [0x81, 126, 0x01, 0x2c]);
+        assert_eq!(medium.len(), 4 + 300);
+        let long = text_frame(&"a".repeat(70_000));
+        assert_eq!(&long[..10], [0x81, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]);
+    }
+
+    #[test]
+    fn close_frames_are_recognized_by_opcode() {
+        assert!(is_close_frame(&[0x88, 0x80, 1, 2, 3, 4]));
+        assert!(!is_close_frame(&[0x89, 0x80]));
+        assert!(!is_close_frame(&[]));
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn the_script_goes_before_the_last_closing_body_tag() {
+        let html = inject_script("<BODY>a</BODY></html>".into(), "/x");
+        assert!(html.starts_with("<BODY>a<script>"), "{}", html);
+        assert!(html.ends_with("</script></BODY></html>"), "{}", html);
+        assert!(html.contains("+\"/x\";"), "{}", html);
+        let html = inject_script("no body".into(), "</script>");
+        assert!(html.starts_with("no body<script>"), "{}", html);
+        assert!(html.contains("\"<\\/script>\""), "{}", html);
+    }
+}
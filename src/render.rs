@@ -0,0 +1,797 @@
+use percent_encoding::utf8_percent_encode;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::{
+    config::TemplateVariant,
+    http::PATH_ENCODE_SET,
+    live_reload::{LIVE_RELOAD_PATH, inject_script},
+    state::ServerState,
+    utils::{absolute_url, markdown_to_html, try_split_frontmatter},
+};
+
+#[derive(Debug)]
+pub enum RenderError {
+    Frontmatter(String),
+    TemplateMissing(String),
+    TemplateRender {
+        template: String,
+        source: minijinja::Error,
+    },
+    TooLarge {
+        template: String,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Frontmatter(e) => write!(f, "invalid frontmatter: {}", e),
+            RenderError::TemplateMissing(name) => write!(f, "template '{}' not found", name),
+            RenderError::TemplateRender { template, source } => {
+                write!(f, "template '{}' failed to render: {:#}", template, source)
+            }
+            RenderError::TooLarge { template, limit } => {
+                write!(
+                    f,
+                    "template '{}' produced more than {} bytes",
+                    template, limit
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+pub struct RenderedPage {
+    pub body: String,
+    pub content_type: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub cacheable: bool,
+    /// From `cache_ttl`: how long a cached copy may be served before it is rendered again,
+    /// however unchanged the file is.
+    pub cache_ttl: Option<Duration>,
+}
+
+pub fn frontmatter_bool(v: &minijinja::Value) -> bool {
+    if let Ok(b) = bool::try_from(v.clone()) {
+        b
+    } else if let Some(s) = v.as_str() {
+        s == "true"
+    } else {
+        true
+    }
+}
+
+pub fn frontmatter_u64(v: &minijinja::Value) -> Option<u64> {
+    u64::try_from(v.clone())
+        .ok()
+        .or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()))
+}
+
+/// Headers that frame the response or the connection stay under the server's control.
+const RESERVED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "content-type",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Checks a page-supplied header: the name must be an RFC 9110 token and not reserved, the
+/// value free of control characters so it cannot smuggle in CR/LF.
+pub fn validate_page_header(name: &str, value: &str) -> Result<(), String> {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_tchar) {
+        return Err(format!("invalid header name {:?}", name));
+    }
+    if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(format!("header '{}' cannot be set by a page", name));
+    }
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("invalid value for header '{}'", name));
+    }
+    Ok(())
+}
+
+/// Headers collected from `set_header` and `set_cookie` calls during a single render.
+#[derive(Debug, Default)]
+struct SetHeaders(Mutex<Vec<(String, String)>>);
+
+impl minijinja::value::Object for SetHeaders {}
+
+const SET_HEADERS_TEMP: &str = "__set_headers";
+
+/// The `set_header(name, value)` template function, emitting a header with the page.
+pub fn set_header(
+    state: &minijinja::State,
+    name: String,
+    value: String,
+) -> Result<String, minijinja::Error> {
+    validate_page_header(&name, &value).map_err(invalid_operation)?;
+    push_header(state, name, value);
+    Ok(String::new())
+}
+
+/// The `set_cookie(name, value, attrs)` template function. `attrs` may set `path`, `max_age`,
+/// `http_only`, `secure` and `same_site`; unset ones default to `Path=/`, `HttpOnly`,
+/// `SameSite=Lax`, and `Secure` when `secure_default` (an https `base_url`).
+pub fn set_cookie(
+    state: &minijinja::State,
+    secure_default: bool,
+    name: String,
+    value: String,
+    attrs: Option<minijinja::Value>,
+) -> Result<String, minijinja::Error> {
+    let cookie = format_set_cookie(&name, &value, attrs.as_ref(), secure_default)
+        .map_err(invalid_operation)?;
+    push_header(state, "Set-Cookie".into(), cookie);
+    Ok(String::new())
+}
+
+/// Serializes a `Set-Cookie` value, rejecting anything that could break out of the header.
+pub fn format_set_cookie(
+    name: &str,
+    value: &str,
+    attrs: Option<&minijinja::Value>,
+    secure_default: bool,
+) -> Result<String, String> {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_tchar) {
+        return Err(format!("invalid cookie name {:?}", name));
+    }
+    // RFC 6265 cookie-octets: printable ASCII minus whitespace, `"`, `,`, `;` and `\`
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_graphic() && !"\",;\\".contains(c))
+    {
+        return Err(format!("invalid value for cookie '{}'", name));
+    }
+
+    let (mut path, mut max_age, mut http_only, mut secure, mut same_site) = (
+        "/".to_string(),
+        None,
+        true,
+        secure_default,
+        "Lax".to_string(),
+    );
+    if let Some(attrs) = attrs.filter(|a| !a.is_undefined() && !a.is_none()) {
+        let keys = attrs
+            .try_iter()
+            .map_err(|_| "cookie attributes must be a map".to_string())?;
+        for key in keys {
+            let key = key.as_str().unwrap_or_default().to_string();
+            let val = attrs.get_attr(&key).unwrap_or_default();
+            match key.as_str() {
+                "path" => path = val.to_string(),
+                "max_age" => {
+                    max_age = Some(
+                        i64::try_from(val).map_err(|_| "max_age must be an integer".to_string())?,
+                    )
+                }
+                "http_only" => http_only = val.is_true(),
+                "secure" => secure = val.is_true(),
+                "same_site" => same_site = val.to_string(),
+                other => return Err(format!("unknown cookie attribute '{}'", other)),
+            }
+        }
+    }
+    if path.is_empty() || path.chars().any(|c| c.is_control() || c == ';') {
+        return Err(format!("invalid path for cookie '{}'", name));
+    }
+    let same_site = match same_site.to_ascii_lowercase().as_str() {
+        "strict" => "Strict",
+        "lax" => "Lax",
+        // browsers drop `SameSite=None` cookies that aren't `Secure`
+        "none" => {
+            secure = true;
+            "None"
+        }
+        _ => return Err(format!("invalid same_site for cookie '{}'", name)),
+    };
+
+    let mut cookie = format!("{}={}; Path={}", name, value, path);
+    if let Some(secs) = max_age {
+        cookie.push_str(&format!("; Max-Age={}", secs));
+    }
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    cookie.push_str("; SameSite=");
+    cookie.push_str(same_site);
+    Ok(cookie)
+}
+
+fn invalid_operation(msg: String) -> minijinja::Error {
+    minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, msg)
+}
+
+fn push_header(state: &minijinja::State, name: String, value: String) {
+    state
+        .get_or_set_temp_object(SET_HEADERS_TEMP, SetHeaders::default)
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((name, value));
+}
+
+/// Maps a markdown file back to the URL it is served at, e.g. `posts/index.md` to `/posts/`.
+pub fn page_path(state: &ServerState, md_path: &Path) -> String {
+    let rel = state
+        .content_roots
+        .iter()
+        .find_map(|root| md_path.strip_prefix(&root.canon).ok())
+        .unwrap_or(md_path)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let stem = rel.strip_suffix(".md").unwrap_or(&rel);
+    let path = if stem == "index" {
+        "/".to_string()
+    } else if let Some(dir) = stem.strip_suffix("/index") {
+        format!("/{}/", dir)
+    } else {
+        format!("/{}", stem)
+    };
+    state.public_path(&path)
+}
+
+/// A page's frontmatter `variants` map (variant id to template) overrides the variant's own
+/// template; a variant template missing from the theme falls back to the page's template.
+fn select_template(
+    env: &minijinja::Environment<'_>,
+    meta: &BTreeMap<String, minijinja::Value>,
+    variant: Option<&TemplateVariant>,
+) -> String {
+    let page_template = meta
+        .get("template")
+        .and_then(|v| v.as_str())
+        .unwrap_or("index");
+    let Some(variant) = variant else {
+        return page_template.to_string();
+    };
+    let overridden = meta
+        .get("variants")
+        .and_then(|m| m.get_attr(variant.id()).ok())
+        .and_then(|v| v.as_str().map(str::to_string));
+    match overridden {
+        Some(name) => name,
+        None if env.get_template(&variant.template).is_ok() => variant.template.clone(),
+        None => page_template.to_string(),
+    }
+}
+
+/// Parses and renders a markdown source through the theme, rejecting malformed frontmatter.
+pub fn render_markdown(
+    content: &str,
+    md_path: &Path,
+    state: &ServerState,
+) -> Result<RenderedPage, RenderError> {
+    let (meta, body) = try_split_frontmatter(content, &state.config().markdown)
+        .map_err(RenderError::Frontmatter)?;
+    render_page(state, md_path, meta, body, None, &RequestParams::default())
+}
+
+/// The parts of a request a page's template may see.
+#[derive(Default)]
+pub struct RequestParams {
+    pub cookies: BTreeMap<String, String>,
+    pub query: BTreeMap<String, String>,
+}
+
+/// Renders already-parsed frontmatter and markdown body through the page's theme template,
+/// or through `variant`'s template when the theme has it.
+pub fn render_page(
+    state: &ServerState,
+    md_path: &Path,
+    meta: BTreeMap<String, minijinja::Value>,
+    raw_body: &str,
+    variant: Option<&TemplateVariant>,
+    params: &RequestParams,
+) -> Result<RenderedPage, RenderError> {
+    prepare_page(state, md_path, meta, raw_body, variant, params).render()
+}
+
+/// A page with its status and headers settled and its template context built, ready to run
+/// the template either into a string or out to a writer.
+pub struct PreparedPage {
+    pub content_type: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub cacheable: bool,
+    pub cache_ttl: Option<Duration>,
+    env: Arc<minijinja::Environment<'static>>,
+    template_name: String,
+    context: minijinja::Value,
+    max_bytes: usize,
+    /// Where the live reload script connects, when `server.live_reload` is on.
+    live_reload_url: Option<String>,
+}
+
+/// Settles everything about a page that doesn't depend on running its template.
+///
+/// Templates see request cookies as `cookies` and query parameters as `query`. A cached page is
+/// shared by every visitor, so it only gets those listed in `performance.cache_key_cookies` and
+/// `performance.cache_key_query` (which key the cache); a page with `cache: false` gets all.
+pub fn prepare_page(
+    state: &ServerState,
+    md_path: &Path,
+    mut meta: BTreeMap<String, minijinja::Value>,
+    raw_body: &str,
+    variant: Option<&TemplateVariant>,
+    params: &RequestParams,
+) -> PreparedPage {
+    let config = state.config();
+    state
+        .metrics
+        .renders
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let cacheable = meta.get("cache").map(frontmatter_bool).unwrap_or(true);
+    let cache_ttl = meta
+        .get("cache_ttl")
+        .and_then(frontmatter_u64)
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
+    for (key, values, keyed) in [
+        (
+            "cookies",
+            &params.cookies,
+            &config.performance.cache_key_cookies,
+        ),
+        ("query", &params.query, &config.performance.cache_key_query),
+    ] {
+        let visible: BTreeMap<&str, &str> = values
+            .iter()
+            .filter(|(name, _)| !cacheable || keyed.contains(name))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        meta.insert(key.to_string(), minijinja::Value::from_serialize(&visible));
+    }
+    // 204 and 304 cannot carry the rendered body, so only body-bearing codes are honored
+    let status = meta
+        .get("status")
+        .and_then(frontmatter_u64)
+        .and_then(|s| u16::try_from(s).ok())
+        .filter(|s| (200..600).contains(s) && *s != 204 && *s != 304)
+        .unwrap_or(200);
+    let charset = meta
+        .get("charset")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&config.defaults.charset);
+    let content_type = meta
+        .get("content_type")
+        .and_then(|v| v.as_str())
+        .map(|ct| ct.to_string())
+        .unwrap_or_else(|| format!("text/html; charset={}", charset));
+    let language = meta
+        .get("language")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&config.defaults.language)
+        .to_string();
+
+    let env = Arc::clone(
+        &state
+            .theme_state
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .1,
+    );
+    let template_name = select_template(&env, &meta, variant);
+
+    let html_body = markdown_to_html(
+        raw_body,
+        state.markdown_options(),
+        config.markdown.heading_ids,
+    );
+    meta.insert("content".to_string(), minijinja::Value::from(html_body));
+    let page_url = page_path(state, md_path);
+    let canonical_url = match meta
+        .get("canonical")
+        .and_then(|v| v.as_str())
+        .filter(|c| !c.chars().any(char::is_control))
+    {
+        Some(c) if c.starts_with("http://") || c.starts_with("https://") => c.to_string(),
+        Some(c) => absolute_url(
+            &config.server.base_url,
+            &utf8_percent_encode(&state.public_path(c), PATH_ENCODE_SET).to_string(),
+        ),
+        None => absolute_url(
+            &config.server.base_url,
+            &utf8_percent_encode(&page_url, PATH_ENCODE_SET).to_string(),
+        ),
+    };
+    let mut headers = Vec::new();
+    if !language.is_empty() && !language.chars().any(char::is_control) {
+        headers.push(("Content-Language".into(), language));
+    }
+    if config.seo.canonical_link_header {
+        headers.push((
+            "Link".into(),
+            format!("<{}>; rel=\"canonical\"", canonical_url),
+        ));
+    }
+    match meta.get("max_age").and_then(frontmatter_u64) {
+        Some(0) => headers.push(("Cache-Control".into(), "no-store".into())),
+        Some(secs) => headers.push(("Cache-Control".into(), format!("max-age={}", secs))),
+        None => {}
+    }
+    let robots: Vec<&str> = [("noindex", "noindex"), ("nofollow", "nofollow")]
+        .into_iter()
+        .filter(|(key, _)| meta.get(*key).is_some_and(frontmatter_bool))
+        .map(|(_, directive)| directive)
+        .collect();
+    if !robots.is_empty() {
+        headers.push(("X-Robots-Tag".into(), robots.join(", ")));
+    }
+    if let Some(extra) = meta.get("headers")
+        && let Ok(names) = extra.try_iter()
+    {
+        for name in names {
+            let Some(name) = name.as_str() else { continue };
+            let value = extra.get_attr(name).unwrap_or_default().to_string();
+            match validate_page_header(name, &value) {
+                Ok(()) => headers.push((name.to_string(), value)),
+                Err(e) => warn!("Skipping header of {}: {}", md_path.display(), e),
+            }
+        }
+    }
+    meta.insert(
+        "canonical_url".to_string(),
+        minijinja::Value::from(canonical_url),
+    );
+    meta.entry("url".to_string())
+        .or_insert_with(|| minijinja::Value::from(page_url));
+
+    PreparedPage {
+        content_type,
+        status,
+        headers,
+        cacheable,
+        cache_ttl,
+        env,
+        template_name,
+        context: minijinja::Value::from(meta),
+        max_bytes: config.performance.max_rendered_bytes,
+        live_reload_url: config
+            .server
+            .live_reload
+            .then(|| state.public_path(LIVE_RELOAD_PATH)),
+    }
+}
+
+impl PreparedPage {
+    pub fn render(self) -> Result<RenderedPage, RenderError> {
+        let mut out = Vec::new();
+        let set = self.render_to(&mut out)?;
+        let mut headers = self.headers.clone();
+        headers.extend(set);
+        // the template engine only ever writes whole strings
+        let mut body = String::from_utf8(out)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+        if let Some(url) = &self.live_reload_url
+            && self.content_type.starts_with("text/html")
+        {
+            body = inject_script(body, url);
+        }
+        Ok(RenderedPage {
+            body,
+            content_type: self.content_type,
+            status: self.status,
+            cacheable: self.cacheable && !sets_cookie(&headers),
+            cache_ttl: self.cache_ttl,
+            headers,
+        })
+    }
+
+    /// Renders into `out`, returning the headers the template added with `set_header` and
+    /// `set_cookie`. Those that a caller already sent can no longer take effect. Output past
+    /// `max_rendered_bytes` fails the render instead of being written.
+    pub fn render_to<W: Write>(&self, out: W) -> Result<Vec<(String, String)>, RenderError> {
+        let template = self.template()?;
+        let mut out = CappedWriter {
+            inner: out,
+            written: 0,
+            limit: self.max_bytes,
+            exceeded: false,
+        };
+        let captured = template
+            .render_captured_to(&self.context, &mut out)
+            .map_err(|source| {
+                if out.exceeded {
+                    RenderError::TooLarge {
+                        template: self.template_name.clone(),
+                        limit: self.max_bytes,
+                    }
+                } else {
+                    self.render_error(source)
+                }
+            })?;
+        let mut set = Vec::new();
+        collect_set_headers(captured.state(), &mut set);
+        Ok(set)
+    }
+
+    fn template(&self) -> Result<minijinja::Template<'_, '_>, RenderError> {
+        self.env
+            .get_template(&self.template_name)
+            .map_err(|_| RenderError::TemplateMissing(self.template_name.clone()))
+    }
+
+    fn render_error(&self, source: minijinja::Error) -> RenderError {
+        RenderError::TemplateRender {
+            template: self.template_name.clone(),
+            source,
+        }
+    }
+}
+
+/// Refuses writes that would take the output past `limit`, so a runaway template stops at
+/// the cap rather than growing its buffer until the process runs out of memory.
+struct CappedWriter<W> {
+    inner: W,
+    written: usize,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl<W: Write> Write for CappedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.limit > 0 && self.written + buf.len() > self.limit {
+            self.exceeded = true;
+            return Err(std::io::Error::other(
+                "rendered output exceeds the size limit",
+            ));
+        }
+        let n = self.inner.write(buf)?;
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn collect_set_headers(state: &minijinja::State, headers: &mut Vec<(String, String)>) {
+    if let Some(set) = state.get_temp(SET_HEADERS_TEMP)
+        && let Some(set) = set.downcast_object_ref::<SetHeaders>()
+    {
+        headers.extend(set.0.lock().unwrap_or_else(|e| e.into_inner()).drain(..));
+    }
+}
+
+/// A cached Set-Cookie would hand one visitor's cookie to everyone after them.
+fn sets_cookie(headers: &[(String, String)]) -> bool {
+    headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("set-cookie"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::fs;
+
+    /// A state whose theme holds the given `(name, source)` templates.
+    fn state_with_theme(
+        templates: &[(&str, &str)],
+        configure: impl FnOnce(&mut Config),
+    ) -> (tempfile::TempDir, Arc<ServerState>) {
+        let dir = tempfile::tempdir().unwrap();
+        let theme = dir.path().join("theme");
+        fs::create_dir_all(&theme).unwrap();
+        for (name, source) in templates {
+            fs::write(theme.join(name), source).unwrap();
+        }
+        let mut config = Config::default();
+        config.paths.content_dir = dir.path().to_string_lossy().into_owned();
+        config.paths.theme_dir = theme.to_string_lossy().into_owned();
+        configure(&mut config);
+        let state = Arc::new(ServerState::new(config));
+        crate::theme::reload_theme(&state);
+        (dir, state)
+    }
+
+    fn render(state: &ServerState, source: &str) -> Result<RenderedPage, RenderError> {
+        render_markdown(source, Path::new("page.md"), state)
+    }
+
+    #[test]
+    fn renders_a_valid_page() {
+        let (_dir, state) = state_with_theme(&[("index.html", "<h1>{{ title }}</h1>")], |_| {});
+        let page = render(&state, "---\ntitle: Hi\n---\nbody").unwrap();
+        assert_eq!(page.body, "<h1>Hi</h1>");
+        assert_eq!(page.status, 200);
+    }
+
+    #[test]
+    fn malformed_frontmatter_is_a_frontmatter_error() {
+        let (_dir, state) = state_with_theme(&[("index.html", "{{ title }}")], |_| {});
+        let err = render(&state, "---\ntitle: [unclosed\n---\nbody")
+            .err()
+            .unwrap();
+        assert!(matches!(err, RenderError::Frontmatter(_)), "{:?}", err);
+        assert!(
+            err.to_string().starts_with("invalid frontmatter: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn unknown_template_is_template_missing() {
+        let (_dir, state) = state_with_theme(&[("index.html", "{{ title }}")], |_| {});
+        let err = render(&state, "---\ntemplate: nope.html\n---\n")
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, RenderError::TemplateMissing(name) if name == "nope.html"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn failing_template_is_template_render() {
+        let (_dir, state) = state_with_theme(
+            &[
+                ("index.html", "{{ title }}"),
+                ("bad.html", "{{ no_such_function() }}"),
+            ],
+            |_| {},
+        );
+        let err = render(&state, "---\ntemplate: bad.html\n---\n")
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, RenderError::TemplateRender { template, .. } if template == "bad.html"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn output_past_the_limit_is_too_large() {
+        let (_dir, state) = state_with_theme(
+            &[(
+                "index.html",
+                "{% for i in range(100) %}0123456789{% endfor %}",
+            )],
+            |config| config.performance.max_rendered_bytes = 500,
+        );
+        let err = render(&state, "").err().unwrap();
+        assert!(
+            matches!(&err, RenderError::TooLarge { template, limit: 500 } if template == "index"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn page_headers_must_be_tokens_without_control_characters() {
+        for (name, value) in [
+            ("X-Custom", "value"),
+            ("Refresh", "5; url=/next"),
+            ("x-tabbed", "a\tb"),
+            ("Cache-Control", "no-store"),
+        ] {
+            assert!(
+                validate_page_header(name, value).is_ok(),
+                "{}: {}",
+                name,
+                value
+            );
+        }
+        for (name, value) in [
+            ("X-Custom", "a\r\nX-Injected: 1"),
+            ("X-Custom", "a\nb"),
+            ("X-Custom", "nul\0"),
+            ("X-Custom\r\nX-Injected", "1"),
+            ("X Custom", "1"),
+            ("X-Custom:", "1"),
+            ("", "1"),
+            ("Content-Length", "0"),
+            ("transfer-encoding", "chunked"),
+            ("Connection", "close"),
+        ] {
+            assert!(
+                validate_page_header(name, value).is_err(),
+                "{:?}: {:?}",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn set_cookie_serializes_attributes_with_secure_defaults() {
+        let attrs = |pairs: &[(&str, minijinja::Value)]| {
+            minijinja::Value::from_iter(pairs.iter().map(|(k, v)| (k.to_string(), v.clone())))
+        };
+        let cases = [
+            (None, false, "theme=dark; Path=/; HttpOnly; SameSite=Lax"),
+            (
+                None,
+                true,
+                "theme=dark; Path=/; HttpOnly; Secure; SameSite=Lax",
+            ),
+            (
+                Some(attrs(&[
+                    ("path", "/docs".into()),
+                    ("max_age", 3600.into()),
+                    ("http_only", false.into()),
+                    ("same_site", "strict".into()),
+                ])),
+                false,
+                "theme=dark; Path=/docs; Max-Age=3600; SameSite=Strict",
+            ),
+            (
+                Some(attrs(&[("secure", false.into())])),
+                true,
+                "theme=dark; Path=/; HttpOnly; SameSite=Lax",
+            ),
+            (
+                Some(attrs(&[("same_site", "None".into())])),
+                false,
+                "theme=dark; Path=/; HttpOnly; Secure; SameSite=None",
+            ),
+        ];
+        for (attrs, secure_default, expected) in cases {
+            assert_eq!(
+                format_set_cookie("theme", "dark", attrs.as_ref(), secure_default).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn set_cookie_rejects_injection_and_bad_attributes() {
+        let attr = |k: &str, v: minijinja::Value| minijinja::Value::from_iter([(k.to_string(), v)]);
+        for (name, value) in [
+            ("theme", "dark\r\nX-Injected: 1"),
+            ("theme", "dark; Domain=evil.example"),
+            ("theme", "has space"),
+            ("theme", "quote\"d"),
+            ("the me", "dark"),
+            ("theme\r\n", "dark"),
+            ("", "dark"),
+        ] {
+            assert!(
+                format_set_cookie(name, value, None, false).is_err(),
+                "{:?}={:?}",
+                name,
+                value
+            );
+        }
+        for attrs in [
+            attr("path", "/\r\nX-Injected: 1".into()),
+            attr("path", "/; Domain=evil.example".into()),
+            attr("path", "".into()),
+            attr("same_site", "sometimes".into()),
+            attr("max_age", "soon".into()),
+            attr("domain", "example.com".into()),
+        ] {
+            assert!(
+                format_set_cookie("theme", "dark", Some(&attrs), false).is_err(),
+                "{}",
+                attrs
+            );
+        }
+    }
+}
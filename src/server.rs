@@ -3,19 +3,27 @@ use mio::net::{TcpListener, TcpStream};
 use mio::{Events, Interest, Poll, Token, Waker};
 use std::{
     collections::{HashMap, VecDeque},
-    fs,
     io::{self, Read, Seek, SeekFrom, Write},
     net::IpAddr,
-    sync::{Arc, RwLock, atomic::AtomicBool, mpsc},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
     thread,
     time::{Duration, Instant, SystemTime},
 };
-use tracing::{error, info};
+use tracing::{debug, error, info, info_span, warn};
 
 use crate::{
-    config::Config,
-    http::{HttpRequest, HttpResponse, ResponseBody, process_http_request},
-    state::{ServerState, ShardedLruCache},
+    access_log::AccessRecord,
+    config::{Config, LogLevel},
+    http::{
+        HttpRequest, HttpResponse, ResponseBody, StreamNotify, bad_request, process_http_request,
+        send_error, version_not_supported, write_head,
+    },
+    live_reload::{self, LIVE_RELOAD_PATH},
+    state::{Metrics, ServerState},
     thread_pool::ThreadPool,
 };
 
@@ -26,11 +34,16 @@ const MAX_CONNECTIONS: usize = 10_000;
 pub enum MainMessage {
     HttpResponse(usize, HttpResponse),
     FileChunk(usize, std::fs::File, u64, u64, Bytes),
+    /// A streaming render has produced a chunk (or finished) for this connection.
+    StreamReady(usize),
+    /// Watched files changed; every live reload socket is told to reload its page.
+    LiveReload,
 }
 
 pub enum WriteChunk {
     Raw(Bytes),
     Stream(std::fs::File, u64, u64),
+    Chunked(mpsc::Receiver<Option<Bytes>>),
 }
 
 struct Connection {
@@ -42,251 +55,539 @@ struct Connection {
     state: ConnState,
     created_at: Instant,
     last_active: Instant,
+    requests: u64,
+    /// Request body bytes still to be read off the socket and thrown away.
+    discard: usize,
+    /// `handle_read` stopped at the buffer limit with data still pending on the socket.
+    read_paused: bool,
+    /// HTTP/1.x minor version of the request being answered, echoed in the status line.
+    http_minor: u8,
+    /// Upgraded to a live reload WebSocket: no more requests are read, and only the
+    /// connection lifetime limits it.
+    websocket: bool,
+    /// The client shut down its sending side. Requests already read are still answered, then
+    /// the connection closes.
+    read_closed: bool,
 }
 
 #[derive(PartialEq)]
 enum ConnState {
     Idle,
     Writing,
+    /// The final response is out; reading the rest of the request body before closing so
+    /// the client isn't reset mid-upload.
+    Draining,
 }
 
-pub fn start_server(config: Config) {
-    let base_dir = std::env::current_dir()
-        .unwrap_or_default()
-        .join(&config.paths.content_dir);
-    let base_canon = base_dir.canonicalize().unwrap_or_else(|_| base_dir.clone());
-
-    let mut precomp = format!(
-        "Server: {}\r\nX-Content-Type-Options: {}\r\nX-Frame-Options: {}\r\nContent-Security-Policy: {}\r\n",
-        config.server.name,
-        config.security.x_content_type_options,
-        config.security.x_frame_options,
-        config.security.content_security_policy
-    );
-    if !config.security.cors_allow_origin.is_empty() {
-        precomp.push_str(&format!(
-            "Access-Control-Allow-Origin: {}\r\n",
-            config.security.cors_allow_origin
-        ));
-    }
-    let precomputed_headers: Arc<[u8]> = precomp.into_bytes().into();
-
-    let cache_mem_bytes = config.performance.max_cache_memory_mb * 1024 * 1024;
-    let state = Arc::new(ServerState {
-        base_dir,
-        base_canon,
-        page_cache: ShardedLruCache::new(cache_mem_bytes, usize::MAX),
-        dir_cache: ShardedLruCache::new(usize::MAX, 10_000),
-        theme_state: RwLock::new((0, Arc::new(minijinja::Environment::new()))),
-        config: config.clone(),
-        precomputed_headers,
-        is_running: Arc::new(AtomicBool::new(true)),
-    });
+/// Larger unread bodies close the connection right after the response instead of draining.
+const MAX_DRAIN_BYTES: usize = 1024 * 1024;
+
+/// Sent to clients refused by `security.allow_ips`/`deny_ips` before the connection is dropped.
+const FORBIDDEN: &[u8] =
+    b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// Reports the process's memory use in bytes for `performance.max_memory_mb`, or `None` when
+/// the platform can't tell, in which case the caches' size stands in for it.
+pub type MemorySampler = fn() -> Option<usize>;
+
+/// Re-reads the configuration the server was started with, command-line overrides included.
+pub type ConfigLoader = Box<dyn Fn() -> Result<Config, String> + Send>;
+
+/// Set from the SIGHUP handler and picked up by the event loop.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn install_reload_handler() {
+    extern "C" fn on_sighup(_: libc::c_int) {
+        RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+    }
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as *const () as libc::sighandler_t);
+    }
+}
 
-    start_theme_watcher(Arc::clone(&state));
+#[cfg(not(unix))]
+fn install_reload_handler() {}
 
-    let host_port = format!("{}:{}", config.server.host, config.server.port);
-    let address = match host_port.parse() {
-        Ok(addr) => addr,
+/// Swaps in the re-read configuration for requests that start from now on; a config that
+/// fails to load leaves the running one in place.
+fn reload_config(state: &Arc<ServerState>, load: &ConfigLoader) {
+    let config = match load() {
+        Ok(c) => c,
         Err(e) => {
-            error!("Invalid bind address {}: {}", host_port, e);
-            std::process::exit(1);
+            error!("Keeping the running configuration: {}", e);
+            return;
         }
     };
+    for name in state.reload_config(config) {
+        warn!("{} changed but only takes effect after a restart", name);
+    }
+    // templates capture config values, and cached pages were rendered under the old config
+    #[cfg(feature = "markdown")]
+    {
+        let failed_templates = crate::theme::rebuild_theme(state);
+        if !failed_templates.is_empty() {
+            warn!("Broken templates: {}", failed_templates.join(", "));
+        }
+    }
+    // with a theme, rebuilding it reloads the error pages too
+    #[cfg(not(feature = "markdown"))]
+    state.load_error_pages();
+    info!("Configuration reloaded");
+}
 
-    let mut listener = match TcpListener::bind(address) {
-        Ok(l) => l,
-        Err(e) => {
-            error!("Failed to bind to {}: {}", host_port, e);
-            std::process::exit(1);
+/// A bound server that has not started serving yet. `start_server` is the command-line entry
+/// point; embedders (and tests) bind to port 0, read `local_addr`, and stop the server through
+/// a `ShutdownHandle`.
+pub struct Server {
+    state: Arc<ServerState>,
+    listener: TcpListener,
+    address: std::net::SocketAddr,
+    poll: Poll,
+    waker: Arc<Waker>,
+    pool: ThreadPool,
+    load_config: ConfigLoader,
+    memory_sampler: MemorySampler,
+}
+
+/// Asks a running server to stop accepting and exit once its open connections finish.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    is_running: Arc<AtomicBool>,
+    waker: Arc<Waker>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.is_running.store(false, Ordering::SeqCst);
+        let _ = self.waker.wake();
+    }
+}
+
+impl Server {
+    /// Loads the theme, binds the listener and starts the worker pool. Nothing is served
+    /// until `run`.
+    pub fn bind(config: Config, load_config: ConfigLoader) -> Result<Self, String> {
+        let state = Arc::new(ServerState::new(config.clone()));
+
+        #[cfg(feature = "markdown")]
+        {
+            let failed_templates = crate::theme::reload_theme(&state);
+            if config.server.strict && !failed_templates.is_empty() {
+                return Err(format!(
+                    "Refusing to start in strict mode, broken templates: {}",
+                    failed_templates.join(", ")
+                ));
+            }
         }
-    };
+        #[cfg(not(feature = "markdown"))]
+        state.load_error_pages();
 
-    let mut poll = Poll::new().expect("Failed to create Poll instance");
-    poll.registry()
-        .register(&mut listener, SERVER_TOKEN, Interest::READABLE)
-        .expect("Failed to register server listener");
+        let host_port = format!("{}:{}", config.server.host, config.server.port);
+        let address = host_port
+            .parse()
+            .map_err(|e| format!("Invalid bind address {}: {}", host_port, e))?;
+        let mut listener = TcpListener::bind(address)
+            .map_err(|e| format!("Failed to bind to {}: {}", host_port, e))?;
+        let address = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to bind to {}: {}", host_port, e))?;
 
-    let waker = Arc::new(Waker::new(poll.registry(), WAKER_TOKEN).expect("Failed to create waker"));
-    let pool = ThreadPool::new(config.server.threads, config.server.queue_size);
-    let (tx_main, rx_main) = mpsc::channel::<MainMessage>();
+        let poll = Poll::new().map_err(|e| format!("Failed to create Poll instance: {}", e))?;
+        poll.registry()
+            .register(&mut listener, SERVER_TOKEN, Interest::READABLE)
+            .map_err(|e| format!("Failed to register server listener: {}", e))?;
+        let waker = Waker::new(poll.registry(), WAKER_TOKEN)
+            .map_err(|e| format!("Failed to create waker: {}", e))?;
 
-    let is_running_clone = Arc::clone(&state.is_running);
-    let waker_clone = Arc::clone(&waker);
-    ctrlc::set_handler(move || {
-        info!("Received Ctrl-C, shutting down gracefully...");
-        is_running_clone.store(false, std::sync::atomic::Ordering::SeqCst);
-        let _ = waker_clone.wake();
-    })
-    .expect("Error setting Ctrl-C handler");
+        let min_threads = match config.server.min_threads {
+            0 => config.server.threads,
+            n => n,
+        };
+        let pool = ThreadPool::new(
+            min_threads,
+            config.server.threads,
+            config.server.queue_size,
+            Duration::from_secs(config.server.thread_idle_timeout_secs),
+        )
+        .map_err(|e| format!("Failed to start worker threads: {}", e))?;
 
-    let mut connections: HashMap<usize, Connection> = HashMap::new();
-    let mut ip_counts: HashMap<IpAddr, usize> = HashMap::new();
-    let mut next_token: usize = 0;
-    let mut events = Events::with_capacity(2048);
-    let mut last_sweep = Instant::now();
+        Ok(Self {
+            state,
+            listener,
+            address,
+            poll,
+            waker: Arc::new(waker),
+            pool,
+            load_config,
+            memory_sampler: crate::utils::resident_memory_bytes,
+        })
+    }
 
-    let idle_timeout = Duration::from_secs(config.server.timeout_secs);
-    let max_connection_life = Duration::from_secs(120);
+    /// Replaces how memory use is measured for `performance.max_memory_mb`, which defaults to
+    /// the process's resident set size.
+    pub fn with_memory_sampler(mut self, sampler: MemorySampler) -> Self {
+        self.memory_sampler = sampler;
+        self
+    }
 
-    info!("Lumen HTTP server running bound to {}", host_port);
+    /// Re-reads the configuration through the server's loader whenever the process gets a
+    /// SIGHUP. The handler is process-wide, so only one server per process should ask for it.
+    pub fn reload_on_sighup(self) -> Self {
+        install_reload_handler();
+        self
+    }
 
-    loop {
-        if !state.is_running.load(std::sync::atomic::Ordering::SeqCst) && connections.is_empty() {
-            break;
+    /// The address actually bound, with the real port when the config asked for port 0.
+    pub fn local_addr(&self) -> std::net::SocketAddr {
+        self.address
+    }
+
+    /// The server's counters, which keep updating once it runs.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.state.metrics)
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            is_running: Arc::clone(&self.state.is_running),
+            waker: Arc::clone(&self.waker),
         }
+    }
 
-        if let Err(e) = poll.poll(&mut events, Some(Duration::from_millis(500))) {
-            if e.kind() == io::ErrorKind::Interrupted {
-                continue;
-            }
-            break;
+    /// Serves until shut down, then returns once every open connection has finished.
+    pub fn run(self) {
+        let Self {
+            state,
+            listener,
+            address,
+            mut poll,
+            waker,
+            pool,
+            load_config,
+            memory_sampler,
+        } = self;
+        #[cfg(feature = "markdown")]
+        crate::theme::start_theme_watcher(Arc::clone(&state));
+        start_memory_monitor(Arc::clone(&state), memory_sampler);
+        start_date_ticker(Arc::clone(&state));
+        start_access_log_flusher(Arc::clone(&state));
+
+        let (tx_main, rx_main) = mpsc::channel::<MainMessage>();
+        start_metrics_logger(Arc::clone(&state), pool.pending_jobs());
+        if state.config().server.live_reload {
+            let (tx, w) = (tx_main.clone(), Arc::clone(&waker));
+            live_reload::start_watcher(Arc::clone(&state), move || {
+                let _ = tx.send(MainMessage::LiveReload);
+                let _ = w.wake();
+            });
         }
 
-        let now = Instant::now();
-        if now.duration_since(last_sweep) > Duration::from_secs(5) {
-            last_sweep = now;
-            let mut timed_out = Vec::new();
-            for (&token, conn) in &connections {
-                if now.duration_since(conn.last_active) > idle_timeout
-                    || now.duration_since(conn.created_at) > max_connection_life
-                {
-                    timed_out.push(token);
-                }
+        let mut connections: HashMap<usize, Connection> = HashMap::new();
+        let mut ip_counts: HashMap<IpAddr, usize> = HashMap::new();
+        let mut next_token: usize = 0;
+        let mut events = Events::with_capacity(2048);
+        let mut last_sweep = Instant::now();
+
+        if pool.max_workers() == 0 {
+            info!(
+                "Lumen HTTP server running bound to {} in single-threaded mode",
+                address
+            );
+        } else if pool.workers() < pool.max_workers() {
+            info!(
+                "Lumen HTTP server running bound to {} with {} workers, growing to {}",
+                address,
+                pool.workers(),
+                pool.max_workers()
+            );
+        } else {
+            info!(
+                "Lumen HTTP server running bound to {} with {} workers",
+                address,
+                pool.workers()
+            );
+        }
+
+        loop {
+            if !state.is_running.load(std::sync::atomic::Ordering::SeqCst) && connections.is_empty()
+            {
+                break;
             }
-            for token in timed_out {
-                if let Some(mut c) = connections.remove(&token) {
-                    cleanup_connection(&mut c, &poll, &mut ip_counts);
+            if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+                reload_config(&state, &load_config);
+            }
+
+            if let Err(e) = poll.poll(&mut events, Some(Duration::from_millis(500))) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
                 }
+                break;
             }
-        }
 
-        for event in events.iter() {
-            match event.token() {
-                SERVER_TOKEN => {
-                    if !state.is_running.load(std::sync::atomic::Ordering::SeqCst) {
-                        continue;
+            let now = Instant::now();
+            if now.duration_since(last_sweep) >= Duration::from_secs(1) {
+                last_sweep = now;
+                let (read_timeout, keep_alive_timeout, max_connection_life) = {
+                    let server = &state.config().server;
+                    (
+                        Duration::from_secs(server.timeout_secs),
+                        Duration::from_secs(server.keep_alive_timeout_secs),
+                        Duration::from_secs(server.max_connection_lifetime_secs),
+                    )
+                };
+                let mut timed_out = Vec::new();
+                for (&token, conn) in &connections {
+                    // Between keep-alive requests the shorter idle timeout applies; a client that
+                    // has started (or not yet sent) a request gets the full read timeout.
+                    let between_requests = conn.requests > 0
+                        && conn.state == ConnState::Idle
+                        && conn.read_buf.is_empty()
+                        && conn.write_queue.is_empty();
+                    let timeout = if between_requests {
+                        keep_alive_timeout
+                    } else {
+                        read_timeout
+                    };
+                    // a live reload socket is idle by design, but mustn't hold up a shutdown
+                    let idle = if conn.websocket {
+                        !state.is_running.load(Ordering::Relaxed)
+                    } else {
+                        now.duration_since(conn.last_active) > timeout
+                    };
+                    if idle || now.duration_since(conn.created_at) > max_connection_life {
+                        timed_out.push(token);
                     }
-                    loop {
-                        if connections.len() >= MAX_CONNECTIONS {
-                            break;
+                }
+                for token in timed_out {
+                    if let Some(mut c) = connections.remove(&token) {
+                        cleanup_connection(&mut c, &poll, &mut ip_counts);
+                    }
+                }
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    SERVER_TOKEN => {
+                        if !state.is_running.load(std::sync::atomic::Ordering::SeqCst) {
+                            continue;
                         }
-                        match listener.accept() {
-                            Ok((mut stream, peer)) => {
-                                let ip = peer.ip();
-                                let count = ip_counts.entry(ip).or_insert(0);
-                                if *count > 200 {
-                                    continue;
-                                }
-                                *count += 1;
+                        loop {
+                            if connections.len() >= MAX_CONNECTIONS {
+                                break;
+                            }
+                            match listener.accept() {
+                                Ok((mut stream, peer)) => {
+                                    let ip = peer.ip();
+                                    if !state.ip_filter.load().permits(ip) {
+                                        // best effort; a fresh socket's send buffer has room
+                                        let _ = stream.write(FORBIDDEN);
+                                        continue;
+                                    }
+                                    let count = ip_counts.entry(ip).or_insert(0);
+                                    if *count > 200 {
+                                        continue;
+                                    }
+                                    *count += 1;
 
-                                let _ = stream.set_nodelay(true);
-                                while connections.contains_key(&next_token) {
+                                    let _ = stream.set_nodelay(true);
+                                    while connections.contains_key(&next_token) {
+                                        next_token = next_token.wrapping_add(1);
+                                    }
+                                    let token_id = next_token;
                                     next_token = next_token.wrapping_add(1);
+
+                                    if poll
+                                        .registry()
+                                        .register(
+                                            &mut stream,
+                                            Token(token_id),
+                                            Interest::READABLE | Interest::WRITABLE,
+                                        )
+                                        .is_ok()
+                                    {
+                                        let now = Instant::now();
+                                        connections.insert(
+                                            token_id,
+                                            Connection {
+                                                stream,
+                                                ip,
+                                                read_buf: Vec::with_capacity(4096),
+                                                write_queue: VecDeque::with_capacity(16),
+                                                keep_alive: true,
+                                                state: ConnState::Idle,
+                                                created_at: now,
+                                                last_active: now,
+                                                requests: 0,
+                                                discard: 0,
+                                                read_paused: false,
+                                                http_minor: 1,
+                                                websocket: false,
+                                                read_closed: false,
+                                            },
+                                        );
+                                    }
                                 }
-                                let token_id = next_token;
-                                next_token = next_token.wrapping_add(1);
-
-                                if poll
-                                    .registry()
-                                    .register(
-                                        &mut stream,
-                                        Token(token_id),
-                                        Interest::READABLE | Interest::WRITABLE,
-                                    )
-                                    .is_ok()
-                                {
-                                    let now = Instant::now();
-                                    connections.insert(
-                                        token_id,
-                                        Connection {
-                                            stream,
-                                            ip,
-                                            read_buf: Vec::with_capacity(4096),
-                                            write_queue: VecDeque::with_capacity(16),
-                                            keep_alive: true,
-                                            state: ConnState::Idle,
-                                            created_at: now,
-                                            last_active: now,
-                                        },
-                                    );
-                                }
+                                Err(_) => break,
                             }
-                            Err(_) => break,
                         }
                     }
-                }
-                WAKER_TOKEN => {
-                    while let Ok(msg) = rx_main.try_recv() {
-                        match msg {
-                            MainMessage::HttpResponse(token_id, res) => {
-                                let is_done = if let Some(conn) = connections.get_mut(&token_id) {
-                                    format_response(conn, &res, &state);
-                                    conn.keep_alive = res.keep_alive;
-                                    pump_connection(
-                                        conn, token_id, true, &pool, &tx_main, &waker, &state,
-                                    )
-                                } else {
-                                    false
-                                };
-                                if is_done && let Some(mut c) = connections.remove(&token_id) {
-                                    cleanup_connection(&mut c, &poll, &mut ip_counts);
+                    WAKER_TOKEN => {
+                        while let Ok(msg) = rx_main.try_recv() {
+                            match msg {
+                                MainMessage::HttpResponse(token_id, res) => {
+                                    state.metrics.record_response(res.status);
+                                    let is_done = if let Some(conn) = connections.get_mut(&token_id)
+                                    {
+                                        let keep_alive = res.keep_alive;
+                                        let framed = format_response(conn, res, &state);
+                                        conn.keep_alive = keep_alive && framed;
+                                        pump_connection(
+                                            conn, token_id, true, &pool, &tx_main, &waker, &state,
+                                        )
+                                    } else {
+                                        false
+                                    };
+                                    if is_done && let Some(mut c) = connections.remove(&token_id) {
+                                        cleanup_connection(&mut c, &poll, &mut ip_counts);
+                                    }
                                 }
-                            }
-                            MainMessage::FileChunk(token_id, file, new_offset, end, bytes) => {
-                                let is_done = if let Some(conn) = connections.get_mut(&token_id) {
-                                    conn.last_active = Instant::now();
-                                    if !bytes.is_empty() {
-                                        if new_offset <= end {
-                                            conn.write_queue.push_front(WriteChunk::Stream(
-                                                file, new_offset, end,
-                                            ));
+                                MainMessage::FileChunk(token_id, file, new_offset, end, bytes) => {
+                                    let is_done = if let Some(conn) = connections.get_mut(&token_id)
+                                    {
+                                        conn.last_active = Instant::now();
+                                        if bytes.is_empty() && new_offset <= end {
+                                            // the file shrank after its length was sent; the client
+                                            // is owed bytes we can't produce, so the stream is unusable
+                                            warn!(
+                                                "File ended {} bytes short of its Content-Length, closing connection",
+                                                end - new_offset + 1
+                                            );
+                                            true
+                                        } else {
+                                            if !bytes.is_empty() {
+                                                if new_offset <= end {
+                                                    conn.write_queue.push_front(
+                                                        WriteChunk::Stream(file, new_offset, end),
+                                                    );
+                                                }
+                                                conn.write_queue.push_front(WriteChunk::Raw(bytes));
+                                            }
+                                            pump_connection(
+                                                conn, token_id, true, &pool, &tx_main, &waker,
+                                                &state,
+                                            )
+                                        }
+                                    } else {
+                                        false
+                                    };
+                                    if is_done && let Some(mut c) = connections.remove(&token_id) {
+                                        cleanup_connection(&mut c, &poll, &mut ip_counts);
+                                    }
+                                }
+                                MainMessage::LiveReload => {
+                                    let frame = live_reload::text_frame("reload");
+                                    let mut closed = Vec::new();
+                                    for (&token_id, conn) in &mut connections {
+                                        if !conn.websocket {
+                                            continue;
+                                        }
+                                        conn.write_queue.push_back(WriteChunk::Raw(frame.clone()));
+                                        if pump_connection(
+                                            conn, token_id, true, &pool, &tx_main, &waker, &state,
+                                        ) {
+                                            closed.push(token_id);
+                                        }
+                                    }
+                                    for token_id in closed {
+                                        if let Some(mut c) = connections.remove(&token_id) {
+                                            cleanup_connection(&mut c, &poll, &mut ip_counts);
                                         }
-                                        conn.write_queue.push_front(WriteChunk::Raw(bytes));
                                     }
-                                    pump_connection(
-                                        conn, token_id, true, &pool, &tx_main, &waker, &state,
-                                    )
-                                } else {
-                                    false
-                                };
-                                if is_done && let Some(mut c) = connections.remove(&token_id) {
-                                    cleanup_connection(&mut c, &poll, &mut ip_counts);
+                                }
+                                MainMessage::StreamReady(token_id) => {
+                                    let is_done = if let Some(conn) = connections.get_mut(&token_id)
+                                    {
+                                        conn.last_active = Instant::now();
+                                        pump_connection(
+                                            conn, token_id, true, &pool, &tx_main, &waker, &state,
+                                        )
+                                    } else {
+                                        false
+                                    };
+                                    if is_done && let Some(mut c) = connections.remove(&token_id) {
+                                        cleanup_connection(&mut c, &poll, &mut ip_counts);
+                                    }
                                 }
                             }
                         }
                     }
-                }
-                Token(token_id) => {
-                    let mut done = false;
-                    if let Some(conn) = connections.get_mut(&token_id) {
-                        conn.last_active = Instant::now();
+                    Token(token_id) => {
+                        let mut done = false;
+                        if let Some(conn) = connections.get_mut(&token_id) {
+                            conn.last_active = Instant::now();
 
-                        if event.is_readable() {
-                            done = handle_read(conn);
-                        }
+                            if event.is_readable() {
+                                done = handle_read(conn);
+                            }
 
-                        if !done {
-                            done = pump_connection(
-                                conn,
-                                token_id,
-                                event.is_writable(),
-                                &pool,
-                                &tx_main,
-                                &waker,
-                                &state,
-                            );
+                            if !done {
+                                done = pump_connection(
+                                    conn,
+                                    token_id,
+                                    event.is_writable(),
+                                    &pool,
+                                    &tx_main,
+                                    &waker,
+                                    &state,
+                                );
+                            }
+
+                            while !done && conn.read_paused && conn.state != ConnState::Idle {
+                                conn.read_paused = false;
+                                done = handle_read(conn)
+                                    || pump_connection(
+                                        conn, token_id, true, &pool, &tx_main, &waker, &state,
+                                    );
+                            }
+                        }
+                        if done && let Some(mut c) = connections.remove(&token_id) {
+                            cleanup_connection(&mut c, &poll, &mut ip_counts);
                         }
-                    }
-                    if done && let Some(mut c) = connections.remove(&token_id) {
-                        cleanup_connection(&mut c, &poll, &mut ip_counts);
                     }
                 }
             }
+            state
+                .metrics
+                .active_connections
+                .store(connections.len(), Ordering::Relaxed);
+        }
+        if let Some(log) = &state.access_log {
+            log.flush();
         }
     }
 }
 
+pub fn start_server(config: Config, load_config: ConfigLoader) {
+    let server = match Server::bind(config, load_config) {
+        Ok(server) => server.reload_on_sighup(),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || {
+        info!("Received Ctrl-C, shutting down gracefully...");
+        shutdown.shutdown();
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    server.run();
+}
+
 #[inline(always)]
 fn cleanup_connection(conn: &mut Connection, poll: &Poll, ip_counts: &mut HashMap<IpAddr, usize>) {
     let _ = poll.registry().deregister(&mut conn.stream);
@@ -311,14 +612,14 @@ fn pump_connection(
     let mut done = false;
     loop {
         if !conn.write_queue.is_empty() && is_writable {
-            done = handle_write(conn, token_id, pool, tx_main, waker);
+            done = handle_write(conn, token_id, pool, tx_main, waker, &state.metrics);
             if done {
                 break;
             }
         }
 
         let mut parsed_something = false;
-        if !conn.read_buf.is_empty() && conn.state != ConnState::Writing {
+        if !conn.read_buf.is_empty() && conn.state == ConnState::Idle && !conn.websocket {
             let (d, p) = try_parse_h1(conn, token_id, pool, tx_main, waker, state);
             done = d;
             parsed_something = p;
@@ -328,17 +629,46 @@ fn pump_connection(
             break;
         }
     }
-    done
+    // whatever is left unparsed after a half-close is a request that will never complete
+    done || (conn.read_closed && conn.state == ConnState::Idle)
 }
 
 fn handle_read(conn: &mut Connection) -> bool {
     let mut buf = [0u8; 8192];
     loop {
         match conn.stream.read(&mut buf) {
-            Ok(0) => return true,
+            Ok(0) => {
+                let pending = conn.state == ConnState::Writing || !conn.read_buf.is_empty();
+                if conn.websocket || !pending {
+                    return true;
+                }
+                conn.read_closed = true;
+                return false;
+            }
+            Ok(n) if conn.websocket => {
+                if live_reload::is_close_frame(&buf[..n]) {
+                    return true;
+                }
+            }
             Ok(n) => {
-                conn.read_buf.extend_from_slice(&buf[..n]);
+                let skip = conn.discard.min(n);
+                conn.discard -= skip;
+                if conn.state == ConnState::Draining {
+                    if conn.discard == 0 {
+                        return true;
+                    }
+                    continue;
+                }
+                conn.read_buf.extend_from_slice(&buf[skip..n]);
                 if conn.read_buf.len() > 64 * 1024 {
+                    // A complete head followed by body bytes is fine: stop here and let the
+                    // caller parse it, after which the rest of the body is discarded.
+                    if conn.state == ConnState::Idle
+                        && conn.read_buf.windows(4).any(|w| w == b"\r\n\r\n")
+                    {
+                        conn.read_paused = true;
+                        return false;
+                    }
                     return true;
                 }
             }
@@ -354,6 +684,7 @@ fn handle_write(
     pool: &ThreadPool,
     tx: &mpsc::Sender<MainMessage>,
     waker: &Arc<Waker>,
+    metrics: &Metrics,
 ) -> bool {
     while let Some(chunk) = conn.write_queue.pop_front() {
         match chunk {
@@ -363,6 +694,7 @@ fn handle_write(
                     return true;
                 }
                 Ok(n) => {
+                    metrics.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
                     if n < bytes.len() {
                         bytes = bytes.slice(n..);
                         conn.write_queue.push_front(WriteChunk::Raw(bytes));
@@ -375,6 +707,28 @@ fn handle_write(
                 }
                 Err(_) => return true,
             },
+            WriteChunk::Chunked(rx) => match rx.try_recv() {
+                Ok(Some(data)) => {
+                    let mut frame = Vec::with_capacity(data.len() + 12);
+                    frame.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+                    frame.extend_from_slice(&data);
+                    frame.extend_from_slice(b"\r\n");
+                    conn.write_queue.push_front(WriteChunk::Chunked(rx));
+                    conn.write_queue
+                        .push_front(WriteChunk::Raw(Bytes::from(frame)));
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    conn.write_queue.push_front(WriteChunk::Chunked(rx));
+                    return false;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    conn.write_queue
+                        .push_front(WriteChunk::Raw(Bytes::from_static(b"0\r\n\r\n")));
+                }
+                // the render failed after the head went out; without the terminating chunk
+                // the client can tell the body is incomplete
+                Ok(None) => return true,
+            },
             WriteChunk::Stream(mut file, offset, end) => {
                 let to_read = std::cmp::min(32768, end - offset + 1) as usize;
                 if to_read == 0 {
@@ -418,6 +772,8 @@ fn handle_write(
     if conn.write_queue.is_empty() {
         if conn.keep_alive {
             conn.state = ConnState::Idle;
+        } else if conn.discard > 0 && conn.discard <= MAX_DRAIN_BYTES {
+            conn.state = ConnState::Draining;
         } else {
             return true;
         }
@@ -425,47 +781,18 @@ fn handle_write(
     false
 }
 
-fn format_response(conn: &mut Connection, res: &HttpResponse, state: &ServerState) {
+/// Queues the response, returning false when its body disagrees with the declared
+/// `Content-Length` so the caller closes the connection after it instead of reusing it.
+fn format_response(conn: &mut Connection, res: HttpResponse, state: &ServerState) -> bool {
     let mut head = Vec::with_capacity(1024);
-    let reason = match res.status {
-        200 => "OK",
-        206 => "Partial Content",
-        301 => "Moved",
-        304 => "Not Modified",
-        400 => "Bad Request",
-        403 => "Forbidden",
-        404 => "Not Found",
-        405 => "Method Not Allowed",
-        413 => "Payload Too Large",
-        416 => "Range Not Satisfiable",
-        500 => "Internal Server Error",
-        _ => "Error",
-    };
-
-    let _ = write!(
+    let _ = write_head(
         &mut head,
-        "HTTP/1.1 {} {}\r\nDate: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n",
-        res.status,
-        reason,
-        httpdate::fmt_http_date(SystemTime::now()),
-        res.content_type,
-        res.clen,
-        if res.keep_alive {
-            "keep-alive"
-        } else {
-            "close"
-        }
+        &res,
+        conn.http_minor,
+        &state.http_date(),
+        &state.precomputed_headers.load(),
     );
 
-    for (k, v) in &res.extra_headers {
-        head.extend_from_slice(k.as_bytes());
-        head.extend_from_slice(b": ");
-        head.extend_from_slice(v.as_bytes());
-        head.extend_from_slice(b"\r\n");
-    }
-    head.extend_from_slice(&state.precomputed_headers);
-    head.extend_from_slice(b"\r\n");
-
     conn.write_queue
         .push_back(WriteChunk::Raw(Bytes::from(head)));
     match &res.body {
@@ -476,8 +803,40 @@ fn format_response(conn: &mut Connection, res: &HttpResponse, state: &ServerStat
                     .push_back(WriteChunk::Stream(dup, *start, *end));
             }
         }
-        None => {}
+        Some(ResponseBody::Chunked(_)) | None => {}
+    }
+
+    let body_len = match res.body {
+        Some(ResponseBody::Bytes(b)) => b.len() as u64,
+        Some(ResponseBody::Stream(_, start, end)) => end - start + 1,
+        Some(ResponseBody::Chunked(rx)) => {
+            conn.write_queue.push_back(WriteChunk::Chunked(rx));
+            return true;
+        }
+        None => return true,
+    };
+    if state.config().server.verify_content_length && body_len != res.clen as u64 {
+        error!(
+            "Response body is {} bytes but Content-Length is {}, closing connection",
+            body_len, res.clen
+        );
+        return false;
     }
+    true
+}
+
+/// Lets a render streaming into this connection's response wake the event loop per chunk.
+fn stream_notifier(
+    token_id: usize,
+    tx_main: &mpsc::Sender<MainMessage>,
+    waker: &Arc<Waker>,
+) -> StreamNotify {
+    let tx = tx_main.clone();
+    let waker = Arc::clone(waker);
+    Arc::new(move || {
+        let _ = tx.send(MainMessage::StreamReady(token_id));
+        let _ = waker.wake();
+    })
 }
 
 fn try_parse_h1(
@@ -492,77 +851,133 @@ fn try_parse_h1(
     let mut req = httparse::Request::new(&mut headers);
     match req.parse(&conn.read_buf) {
         Ok(httparse::Status::Complete(header_len)) => {
+            // HTTP/1.0 defaults to close; only an explicit `Connection: keep-alive` below
+            // keeps it open
+            conn.http_minor = req.version.unwrap_or(1);
             let mut req_struct = HttpRequest {
                 method: req.method.unwrap_or("GET").into(),
                 path: req.path.unwrap_or("/").into(),
                 accept_encoding: "".into(),
+                accept: String::new(),
+                host: String::new(),
+                authorization: None,
                 range: None,
                 if_none_match: None,
                 if_modified_since: None,
+                if_range: None,
+                user_agent: String::new(),
+                cookie: String::new(),
                 keep_alive: req.version.unwrap_or(0) == 1,
+                peer_ip: conn.ip,
+                request_id: String::new(),
+                http_minor: conn.http_minor,
+                stream_notify: Some(stream_notifier(token_id, tx_main, waker)),
             };
 
-            let (mut clen, mut cl_count, mut has_te) = (0, 0, false);
-            for h in req.headers.iter() {
-                if h.name.eq_ignore_ascii_case("content-length") {
-                    cl_count += 1;
-                    if let Ok(s) = std::str::from_utf8(h.value) {
-                        clen = s.trim().parse().unwrap_or(0);
-                    }
-                } else if h.name.eq_ignore_ascii_case("transfer-encoding") {
-                    has_te = true;
-                } else if h.name.eq_ignore_ascii_case("accept-encoding") {
-                    if let Ok(s) = std::str::from_utf8(h.value) {
-                        req_struct.accept_encoding = s.into();
-                    }
-                } else if h.name.eq_ignore_ascii_case("range") {
-                    if let Ok(s) = std::str::from_utf8(h.value) {
-                        req_struct.range = Some(s.into());
-                    }
-                } else if h.name.eq_ignore_ascii_case("if-none-match") {
-                    if let Ok(s) = std::str::from_utf8(h.value) {
-                        req_struct.if_none_match = Some(s.into());
-                    }
-                } else if h.name.eq_ignore_ascii_case("if-modified-since") {
-                    if let Ok(s) = std::str::from_utf8(h.value) {
-                        req_struct.if_modified_since = Some(s.into());
-                    }
-                } else if h.name.eq_ignore_ascii_case("connection")
-                    && let Ok(val) = std::str::from_utf8(h.value)
-                {
-                    if val.eq_ignore_ascii_case("keep-alive") {
-                        req_struct.keep_alive = true;
-                    } else if val.eq_ignore_ascii_case("close") {
-                        req_struct.keep_alive = false;
-                    }
+            let clen = match apply_headers(&mut req_struct, req.headers, state) {
+                Ok(clen) => clen,
+                Err(()) => {
+                    conn.read_buf.clear();
+                    conn.state = ConnState::Writing;
+                    let is_head = req_struct.method == "HEAD";
+                    let _ = tx_main.send(MainMessage::HttpResponse(token_id, bad_request(is_head)));
+                    let _ = waker.wake();
+                    return (false, true);
                 }
+            };
+
+            if let Some(key) = live_reload_key(&req_struct, req.headers, state) {
+                conn.read_buf.drain(..header_len);
+                conn.websocket = true;
+                conn.keep_alive = true;
+                conn.state = ConnState::Writing;
+                conn.write_queue
+                    .push_back(WriteChunk::Raw(live_reload::handshake_response(&key)));
+                debug!("Live reload client connected from {}", conn.ip);
+                // the socket may not report writable again by itself; have the loop flush it
+                let _ = tx_main.send(MainMessage::StreamReady(token_id));
+                let _ = waker.wake();
+                return (false, true);
             }
-            if cl_count > 1 || (cl_count > 0 && has_te) {
-                return (true, false);
+
+            // Bodies are never used, so don't wait for them: answer as soon as the head is in
+            // and drop the remaining body bytes as they arrive.
+            let buffered_body = clen.min(conn.read_buf.len() - header_len);
+            conn.read_buf.drain(..header_len + buffered_body);
+            conn.discard = clen - buffered_body;
+            if conn.discard > MAX_DRAIN_BYTES {
+                req_struct.keep_alive = false;
+            }
+            conn.state = ConnState::Writing;
+            conn.requests += 1;
+            // Requests are answered one at a time, so a pipelining client gets no more than
+            // anyone else per turn of the loop; this bounds how many turns it gets in total.
+            let max_requests = state.config().server.max_keepalive_requests;
+            if max_requests > 0 && conn.requests >= max_requests {
+                req_struct.keep_alive = false;
+            }
+            if req_struct.request_id.is_empty() {
+                req_struct.request_id = state.next_request_id();
             }
 
-            let total_len = header_len.saturating_add(clen);
-            if conn.read_buf.len() >= total_len {
-                conn.read_buf.drain(..total_len);
-                conn.state = ConnState::Writing;
+            if state.memory_pressure.load(Ordering::Relaxed) {
+                let mut res = send_error(state, 503, false, req_struct.method == "HEAD");
+                res.extra_headers
+                    .push(("X-Request-Id".into(), req_struct.request_id));
+                let _ = tx_main.send(MainMessage::HttpResponse(token_id, res));
+                let _ = waker.wake();
+                return (false, true);
+            }
 
-                let st = Arc::clone(state);
-                let tx = tx_main.clone();
-                let w = waker.clone();
-                if pool
-                    .execute(move || {
-                        let res = process_http_request(req_struct, st);
-                        let _ = tx.send(MainMessage::HttpResponse(token_id, res));
-                        let _ = w.wake();
-                    })
-                    .is_err()
-                {
-                    return (true, true);
-                }
-                (false, true)
-            } else {
-                (false, false)
+            let started = Instant::now();
+            let st = Arc::clone(state);
+            let tx = tx_main.clone();
+            let w = waker.clone();
+            if pool
+                .execute(move || {
+                    let span = info_span!(
+                        "request",
+                        id = %req_struct.request_id,
+                        ip = %req_struct.peer_ip
+                    );
+                    let _enter = span.enter();
+                    let request_id = req_struct.request_id.clone();
+                    let (method, path) = (req_struct.method.clone(), req_struct.path.clone());
+                    let (peer_ip, http_minor) = (req_struct.peer_ip, req_struct.http_minor);
+                    let log_levels = (
+                        st.config().server.client_error_log_level,
+                        st.config().server.server_error_log_level,
+                    );
+                    let mut res = process_http_request(req_struct, Arc::clone(&st));
+                    log_error_response(log_levels, &method, &path, res.status);
+                    st.metrics.record_duration(started.elapsed());
+                    if let Some(log) = &st.access_log {
+                        log.log(&AccessRecord {
+                            ip: peer_ip,
+                            request_id: &request_id,
+                            method: &method,
+                            path: &path,
+                            http_minor,
+                            status: res.status,
+                            bytes: match &res.body {
+                                Some(ResponseBody::Bytes(b)) => Some(b.len() as u64),
+                                Some(ResponseBody::Stream(_, start, end)) => Some(end - start + 1),
+                                Some(ResponseBody::Chunked(_)) => None,
+                                None => Some(0),
+                            },
+                            duration: started.elapsed(),
+                            date: &st.http_date(),
+                        });
+                    }
+                    res.extra_headers.push(("X-Request-Id".into(), request_id));
+                    let _ = tx.send(MainMessage::HttpResponse(token_id, res));
+                    let _ = w.wake();
+                })
+                .is_err()
+            {
+                return (true, true);
             }
+            (false, true)
         }
         Ok(httparse::Status::Partial) => {
             if conn.read_buf.len() > 65536 {
@@ -570,132 +985,306 @@ fn try_parse_h1(
             }
             (false, false)
         }
+        Err(e) if e == httparse::Error::Version || is_http09_request(&conn.read_buf) => {
+            let is_head = conn.read_buf.starts_with(b"HEAD ");
+            conn.read_buf.clear();
+            conn.state = ConnState::Writing;
+            let _ = tx_main.send(MainMessage::HttpResponse(
+                token_id,
+                version_not_supported(is_head),
+            ));
+            let _ = waker.wake();
+            (false, true)
+        }
         Err(_) => (true, false),
     }
 }
 
-fn start_theme_watcher(state: Arc<ServerState>) {
-    thread::spawn(move || {
-        loop {
-            thread::sleep(Duration::from_secs(2));
-            let mut max_mtime = SystemTime::UNIX_EPOCH;
-            let mut file_count = 0usize;
-
-            let theme_files =
-                crate::utils::get_all_files(std::path::Path::new(&state.config.paths.theme_dir), 0);
-            for path in &theme_files {
-                if let Ok(meta) = fs::metadata(path) {
-                    file_count += 1;
-                    if let Ok(mt) = meta.modified()
-                        && mt > max_mtime
-                    {
-                        max_mtime = mt;
-                    }
-                }
-            }
+/// The `Sec-WebSocket-Key` of a handshake for the live reload endpoint, when
+/// `server.live_reload` is on; any other request is served as usual.
+fn live_reload_key(
+    req: &HttpRequest,
+    headers: &[httparse::Header],
+    state: &ServerState,
+) -> Option<String> {
+    if !state.config().server.live_reload || req.method != "GET" {
+        return None;
+    }
+    let path = req.path.split('?').next().unwrap_or("/");
+    if path != LIVE_RELOAD_PATH && path != state.public_path(LIVE_RELOAD_PATH) {
+        return None;
+    }
+    let upgrade = single_header(headers, "upgrade").ok()??;
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return None;
+    }
+    single_header(headers, "sec-websocket-key")
+        .ok()?
+        .filter(|key| !key.trim().is_empty())
+        .map(Into::into)
+}
 
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            use std::hash::Hasher;
-            hasher.write(
-                &(max_mtime
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs())
-                .to_ne_bytes(),
-            );
-            hasher.write(&file_count.to_ne_bytes());
-            let current_hash = hasher.finish();
-
-            let current_cache_hash = state
-                .theme_state
-                .read()
-                .unwrap_or_else(|e| e.into_inner())
-                .0;
-
-            if current_cache_hash != current_hash {
-                let mut env = minijinja::Environment::new();
-                let env_state = Arc::clone(&state);
-
-                env.add_function("list_dir", move |dir_path: String| -> minijinja::Value {
-                    let target_dir = crate::utils::secure_join(&env_state.base_dir, &dir_path)
-                        .unwrap_or_default();
-                    if target_dir.as_os_str().is_empty() {
-                        return minijinja::Value::from(Vec::<minijinja::Value>::new());
-                    }
+/// Logs 4xx and 5xx responses at their configured levels; anything else is not logged.
+fn log_error_response(levels: (LogLevel, LogLevel), method: &str, path: &str, status: u16) {
+    let level = match status {
+        400..=499 => levels.0,
+        500.. => levels.1,
+        _ => return,
+    };
+    match level {
+        LogLevel::Off => {}
+        LogLevel::Debug => debug!("{} {} -> {}", method, path, status),
+        LogLevel::Info => info!("{} {} -> {}", method, path, status),
+        LogLevel::Warn => warn!("{} {} -> {}", method, path, status),
+        LogLevel::Error => error!("{} {} -> {}", method, path, status),
+    }
+}
 
-                    let mut dir_hash = 0u64;
-                    let mut file_entries = Vec::new();
-
-                    if let Ok(read_dir) = fs::read_dir(&target_dir) {
-                        for entry in read_dir.flatten() {
-                            if entry.path().extension().is_some_and(|ext| ext == "md")
-                                && let Ok(meta) = entry.metadata()
-                            {
-                                let mtime = meta
-                                    .modified()
-                                    .unwrap_or(SystemTime::UNIX_EPOCH)
-                                    .duration_since(SystemTime::UNIX_EPOCH)
-                                    .unwrap_or_default()
-                                    .as_secs();
-                                dir_hash =
-                                    (dir_hash.rotate_left(3) ^ mtime).wrapping_add(meta.len());
-                                file_entries.push(entry);
-                            }
-                        }
-                    }
+/// An HTTP/0.9 simple request is a bare `GET /path` line, which httparse rejects as a token error.
+fn is_http09_request(buf: &[u8]) -> bool {
+    let Some(line_end) = buf.iter().position(|&b| b == b'\n') else {
+        return false;
+    };
+    let line = buf[..line_end]
+        .strip_suffix(b"\r")
+        .unwrap_or(&buf[..line_end]);
+    let mut parts = line.split(|&b| b == b' ');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some(method), Some(path), None) if !method.is_empty() && path.starts_with(b"/")
+    )
+}
 
-                    if let Some((cached_hash, cached_val)) = env_state.dir_cache.get(&target_dir)
-                        && cached_hash == dir_hash
-                    {
-                        return cached_val;
-                    }
+/// Fills `req` from the request headers and returns the declared body length. Headers that
+/// must be single-valued fail the request (answered with 400) when repeated with differing
+/// values; `Host` may not repeat at all, and `Transfer-Encoding` may not appear. List-valued headers (`Accept`,
+/// `Accept-Encoding`, `If-None-Match`, `Cookie`, `Connection`) are joined in order as if
+/// sent once.
+fn apply_headers(
+    req: &mut HttpRequest,
+    headers: &[httparse::Header],
+    state: &ServerState,
+) -> Result<usize, ()> {
+    if headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("host"))
+        .count()
+        > 1
+    {
+        return Err(());
+    }
+    // request bodies are only ever framed by length, so a coded one would be read as the
+    // next pipelined request
+    if headers
+        .iter()
+        .any(|h| h.name.eq_ignore_ascii_case("transfer-encoding"))
+    {
+        return Err(());
+    }
+    let clen = match single_header(headers, "content-length")? {
+        Some(v) => v.trim().parse().map_err(|_| ())?,
+        None => 0,
+    };
 
-                    let mut entries = Vec::new();
-                    for entry in file_entries {
-                        let file_stem = entry
-                            .path()
-                            .file_stem()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .into_owned();
-                        let content = fs::read_to_string(entry.path()).unwrap_or_default();
-                        let (mut meta, _) = crate::utils::split_frontmatter(&content);
-                        let url = if file_stem == "index" {
-                            format!("/{}/", dir_path)
-                        } else {
-                            format!("/{}/{}", dir_path, file_stem)
-                        };
-                        meta.insert("url".to_string(), minijinja::Value::from(url));
-                        entries.push(minijinja::Value::from(meta));
-                    }
+    req.accept_encoding = list_header(headers, "accept-encoding", ", ").unwrap_or_default();
+    req.accept = list_header(headers, "accept", ", ").unwrap_or_default();
+    req.host = single_header(headers, "host")?.unwrap_or_default().into();
+    req.range = single_header(headers, "range")?.map(Into::into);
+    req.authorization = single_header(headers, "authorization")?.map(Into::into);
+    req.if_none_match = list_header(headers, "if-none-match", ", ");
+    req.if_modified_since = single_header(headers, "if-modified-since")?.map(Into::into);
+    req.if_range = single_header(headers, "if-range")?.map(Into::into);
+    req.user_agent = single_header(headers, "user-agent")?
+        .unwrap_or_default()
+        .into();
+    req.cookie = list_header(headers, "cookie", "; ").unwrap_or_default();
+    if state.config().server.trust_request_id
+        && let Some(id) = single_header(headers, "x-request-id")?
+        && is_valid_request_id(id)
+    {
+        req.request_id = id.into();
+    }
+    // `close` anywhere wins over `keep-alive`
+    if let Some(connection) = list_header(headers, "connection", ",") {
+        let mut options = connection.split(',').map(str::trim);
+        if options.clone().any(|o| o.eq_ignore_ascii_case("close")) {
+            req.keep_alive = false;
+        } else if options.any(|o| o.eq_ignore_ascii_case("keep-alive")) {
+            req.keep_alive = true;
+        }
+    }
+    Ok(clen)
+}
 
-                    entries.sort_by(|a, b| {
-                        let d1 = a.get_attr("date").unwrap_or_default().to_string();
-                        let d2 = b.get_attr("date").unwrap_or_default().to_string();
-                        d2.cmp(&d1)
-                    });
-
-                    let val = minijinja::Value::from(entries);
-                    env_state.dir_cache_put(target_dir, dir_hash, val.clone());
-                    val
-                });
-
-                let theme_dir_path = std::path::Path::new(&state.config.paths.theme_dir);
-                for path in theme_files {
-                    let rel_path = path.strip_prefix(theme_dir_path).unwrap_or(&path);
-                    let name = rel_path.to_string_lossy().replace('\\', "/");
-                    let content = fs::read_to_string(&path).unwrap_or_default();
-                    let _ = env.add_template_owned(name.clone(), content.clone());
-                    if name == "index.html" {
-                        let _ = env.add_template_owned("index", content);
-                    }
-                }
+/// The value of a header that may appear more than once only if every copy is identical.
+/// Values that aren't UTF-8 are ignored.
+fn single_header<'h>(headers: &[httparse::Header<'h>], name: &str) -> Result<Option<&'h str>, ()> {
+    let mut found: Option<&'h str> = None;
+    for h in headers.iter().filter(|h| h.name.eq_ignore_ascii_case(name)) {
+        let Ok(value) = std::str::from_utf8(h.value) else {
+            continue;
+        };
+        match found {
+            Some(prev) if prev.trim() != value.trim() => return Err(()),
+            Some(_) => {}
+            None => found = Some(value),
+        }
+    }
+    Ok(found)
+}
+
+/// Every value of a list-valued header, joined with `sep` in the order received.
+fn list_header(headers: &[httparse::Header], name: &str, sep: &str) -> Option<String> {
+    let values: Vec<&str> = headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case(name))
+        .filter_map(|h| std::str::from_utf8(h.value).ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join(sep))
+}
+
+/// Incoming IDs are echoed into a response header, so only allow a conservative charset.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// Periodically logs a structured summary; request and cache counts cover the interval since
+/// the previous event.
+fn start_metrics_logger(state: Arc<ServerState>, pending_jobs: Arc<AtomicUsize>) {
+    let interval = state.config().server.metrics_interval_secs;
+    if interval == 0 {
+        return;
+    }
+    thread::spawn(move || {
+        let m = &state.metrics;
+        let mut last = (0, 0, 0, 0, 0, 0, 0, 0);
+        while state.is_running.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(interval));
+            let now = (
+                m.requests.load(Ordering::Relaxed),
+                m.server_errors.load(Ordering::Relaxed),
+                m.cache_hits.load(Ordering::Relaxed),
+                m.cache_misses.load(Ordering::Relaxed),
+                m.client_errors.load(Ordering::Relaxed),
+                m.not_found.load(Ordering::Relaxed),
+                m.renders.load(Ordering::Relaxed),
+                m.bytes_sent.load(Ordering::Relaxed),
+            );
+            let (hits, misses) = (now.2 - last.2, now.3 - last.3);
+            let hit_ratio = if hits + misses == 0 {
+                0.0
+            } else {
+                hits as f64 / (hits + misses) as f64
+            };
+            info!(
+                target: "lumen::metrics",
+                active_connections = m.active_connections.load(Ordering::Relaxed),
+                queue_depth = pending_jobs.load(Ordering::Relaxed),
+                requests = now.0 - last.0,
+                server_errors = now.1 - last.1,
+                client_errors = now.4 - last.4,
+                not_found = now.5 - last.5,
+                renders = now.6 - last.6,
+                bytes_sent = now.7 - last.7,
+                cache_hit_ratio = hit_ratio,
+                cache_bytes = state.cache_bytes(),
+                "metrics"
+            );
+            last = now;
+        }
+    });
+}
 
-                let mut cache = state.theme_state.write().unwrap_or_else(|e| e.into_inner());
-                *cache = (current_hash, Arc::new(env));
-                state.page_cache.clear();
-                state.dir_cache.clear();
+/// Writes out buffered access log lines once a second.
+fn start_access_log_flusher(state: Arc<ServerState>) {
+    if state.access_log.is_none() {
+        return;
+    }
+    thread::spawn(move || {
+        while state.is_running.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(1));
+            if let Some(log) = &state.access_log {
+                log.flush();
             }
         }
     });
 }
+
+/// Refreshes the cached `Date` header just after each second boundary.
+fn start_date_ticker(state: Arc<ServerState>) {
+    if !state.config().performance.cache_date_header {
+        return;
+    }
+    thread::spawn(move || {
+        while state.is_running.load(Ordering::Relaxed) {
+            let subsec = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos();
+            thread::sleep(Duration::from_nanos(1_000_000_000 - u64::from(subsec)));
+            state.refresh_date_header();
+        }
+    });
+}
+
+/// Samples memory use once a second, shedding new requests with 503 while it is over
+/// `performance.max_memory_mb`.
+fn start_memory_monitor(state: Arc<ServerState>, sample: MemorySampler) {
+    let limit = state.config().performance.max_memory_mb * 1024 * 1024;
+    if limit == 0 {
+        return;
+    }
+    thread::spawn(move || {
+        while state.is_running.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(1));
+            let used = sample().unwrap_or_else(|| state.cache_bytes());
+            let over = used > limit;
+            if state.memory_pressure.swap(over, Ordering::Relaxed) != over {
+                if over {
+                    warn!(
+                        "Memory usage {} MB exceeds limit, shedding new requests",
+                        used / (1024 * 1024)
+                    );
+                } else {
+                    info!("Memory usage back under limit, accepting requests");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(all(test, feature = "markdown"))]
+mod tests {
+    use super::*;
+
+    fn config_for(theme_dir: &std::path::Path) -> Config {
+        let mut config = Config::default();
+        config.server.port = 0;
+        config.server.threads = 1;
+        config.paths.theme_dir = theme_dir.to_string_lossy().into_owned();
+        config
+    }
+
+    #[test]
+    fn bind_compiles_the_theme_before_serving() {
+        let theme = tempfile::tempdir().unwrap();
+        std::fs::write(theme.path().join("index.html"), "{{ content }}").unwrap();
+        std::fs::write(
+            theme.path().join("post.html"),
+            "<article>{{ content }}</article>",
+        )
+        .unwrap();
+
+        let config = config_for(theme.path());
+        let server = Server::bind(config.clone(), Box::new(move || Ok(config.clone()))).unwrap();
+        let (hash, env) = &*server.state.theme_state.read().unwrap();
+        assert_ne!(*hash, 0, "theme not marked as built");
+        for name in ["index", "index.html", "post.html"] {
+            assert!(env.get_template(name).is_ok(), "{} not compiled", name);
+        }
+    }
+}
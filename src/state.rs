@@ -1,29 +1,96 @@
-use crate::config::Config;
+#[cfg(feature = "markdown")]
+use crate::utils::markdown_options;
+use crate::{
+    access_log::AccessLog,
+    config::{Config, SecurityConfig},
+    disk_cache::DiskCache,
+    utils::{content_hash, get_mime_type, ip_bits, parse_cidr, secure_join},
+};
+use arc_swap::{ArcSwap, Guard};
 use bytes::Bytes;
 use lru::LruCache;
+#[cfg(feature = "markdown")]
 use minijinja::Environment;
+#[cfg(feature = "markdown")]
+use pulldown_cmark::Options;
+use serde::Serialize;
+#[cfg(feature = "markdown")]
+use std::sync::{RwLock, atomic::AtomicU32};
 use std::{
-    collections::hash_map::RandomState,
+    collections::{BTreeMap, HashMap, hash_map::RandomState},
+    fs,
     hash::BuildHasher,
-    path::PathBuf,
-    sync::{Arc, Mutex, OnceLock, RwLock, atomic::AtomicBool},
-    time::SystemTime,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard, OnceLock, TryLockError,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
+use tracing::{error, warn};
 
 #[derive(Clone)]
 pub struct CacheEntry {
     pub raw: Bytes,
     pub br: Arc<OnceLock<Bytes>>,
     pub gz: Arc<OnceLock<Bytes>>,
+    pub df: Arc<OnceLock<Bytes>>,
     pub content_type: String,
     pub mtime: SystemTime,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Set for pages with a `cache_ttl`, which are rendered again after it even when the file
+    /// hasn't changed.
+    pub expires_at: Option<Instant>,
 }
 
 impl CacheEntry {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| Instant::now() >= at)
+    }
+
     pub fn size_bytes(&self) -> usize {
         self.raw.len()
             + self.br.get().map(|v| v.len()).unwrap_or(0)
             + self.gz.get().map(|v| v.len()).unwrap_or(0)
+            + self.df.get().map(|v| v.len()).unwrap_or(0)
+    }
+}
+
+/// What a cached value counts against a cache's byte budget.
+pub trait CacheWeight {
+    fn weight(&self) -> usize;
+}
+
+impl CacheWeight for CacheEntry {
+    fn weight(&self) -> usize {
+        self.size_bytes()
+    }
+}
+
+#[cfg(feature = "markdown")]
+impl CacheWeight for minijinja::Value {
+    /// Text held by the value plus a rough per-item overhead; exact sizes aren't exposed.
+    fn weight(&self) -> usize {
+        use minijinja::value::ValueKind;
+        const ITEM: usize = 32;
+        let items = self.try_iter().into_iter().flatten();
+        ITEM + match self.kind() {
+            ValueKind::String => self.as_str().map_or(0, str::len),
+            ValueKind::Seq => items.map(|item| item.weight()).sum(),
+            ValueKind::Map => items
+                .map(|key| key.weight() + self.get_item(&key).map_or(0, |v| v.weight()))
+                .sum(),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(feature = "markdown")]
+impl CacheWeight for (u64, minijinja::Value) {
+    fn weight(&self) -> usize {
+        8 + self.1.weight()
     }
 }
 
@@ -36,9 +103,46 @@ pub struct CacheShard<K, V> {
     pub max_entries: usize,
 }
 
+impl<K: std::hash::Hash + Eq, V: CacheWeight> CacheShard<K, V> {
+    /// Inserts `v`, then evicts from the cold end until the shard is back within both its
+    /// byte and entry limits, whichever binds first. Returns how many entries were evicted.
+    pub fn insert(&mut self, k: K, v: V) -> u64 {
+        let weight = v.weight();
+        if let Some(old) = self.cache.put(k, v) {
+            self.current_bytes = self.current_bytes.saturating_sub(old.weight());
+        }
+        self.current_bytes += weight;
+        self.evict_over_budget()
+    }
+
+    pub fn evict_over_budget(&mut self) -> u64 {
+        let mut evicted = 0;
+        while (self.current_bytes > self.max_bytes || self.cache.len() > self.max_entries)
+            && let Some((_, value)) = self.cache.pop_lru()
+        {
+            self.current_bytes = self.current_bytes.saturating_sub(value.weight());
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
 pub struct ShardedLruCache<K, V> {
     pub shards: Vec<Mutex<CacheShard<K, V>>>,
     pub builder: RandomState,
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub evictions: AtomicU64,
+}
+
+/// A snapshot of one cache's size and counters since startup.
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
 }
 
 impl<K: std::hash::Hash + Eq, V: Clone> ShardedLruCache<K, V> {
@@ -56,7 +160,13 @@ impl<K: std::hash::Hash + Eq, V: Clone> ShardedLruCache<K, V> {
                 max_entries: shard_max_entries,
             }));
         }
-        Self { shards, builder }
+        Self {
+            shards,
+            builder,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
     }
 
     #[inline(always)]
@@ -69,7 +179,55 @@ impl<K: std::hash::Hash + Eq, V: Clone> ShardedLruCache<K, V> {
         let mut shard = self.shards[shard_idx]
             .lock()
             .unwrap_or_else(|e| e.into_inner());
-        shard.cache.get(k).cloned()
+        self.count_lookup(shard.cache.get(k).cloned())
+    }
+
+    /// Like `get`, but treats a contended shard as a miss instead of waiting for it.
+    pub fn try_get(&self, k: &K) -> Option<V> {
+        let found = self
+            .try_lock_shard(self.get_shard(k))
+            .and_then(|mut shard| shard.cache.get(k).cloned());
+        self.count_lookup(found)
+    }
+
+    fn count_lookup(&self, found: Option<V>) -> Option<V> {
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (entries, bytes) = self.shards.iter().fold((0, 0), |(entries, bytes), shard| {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            (entries + shard.cache.len(), bytes + shard.current_bytes)
+        });
+        CacheStats {
+            entries,
+            bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn try_lock_shard(&self, idx: usize) -> Option<MutexGuard<'_, CacheShard<K, V>>> {
+        match self.shards[idx].try_lock() {
+            Ok(shard) => Some(shard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Changes the byte budget; shards over it shed entries on their next insert.
+    pub fn set_max_bytes(&self, max_total_bytes: usize) {
+        let shard_max_bytes = std::cmp::max(1, max_total_bytes / SHARDS);
+        for shard in &self.shards {
+            shard.lock().unwrap_or_else(|e| e.into_inner()).max_bytes = shard_max_bytes;
+        }
     }
 
     pub fn clear(&self) {
@@ -81,40 +239,697 @@ impl<K: std::hash::Hash + Eq, V: Clone> ShardedLruCache<K, V> {
     }
 }
 
+impl<K: std::hash::Hash + Eq, V: Clone + CacheWeight> ShardedLruCache<K, V> {
+    pub fn put(&self, k: K, v: V) {
+        let evicted = self.shards[self.get_shard(&k)]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(k, v);
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+    }
+}
+
+/// Running totals since startup, updated with relaxed atomics on the request path.
+#[derive(Default)]
+pub struct Metrics {
+    pub active_connections: AtomicUsize,
+    pub requests: AtomicU64,
+    pub server_errors: AtomicU64,
+    pub client_errors: AtomicU64,
+    pub not_found: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    /// Templates run, whether for a cache miss, an uncached page or a stream.
+    pub renders: AtomicU64,
+    /// Response bytes written to sockets, heads and chunk framing included.
+    pub bytes_sent: AtomicU64,
+    /// Responses by status class, 1xx through 5xx.
+    pub responses_by_class: [AtomicU64; 5],
+    /// Handler durations, counted into the first bucket of `DURATION_BUCKETS_US` they fit.
+    pub duration_buckets: [AtomicU64; DURATION_BUCKETS_US.len()],
+    pub duration_sum_us: AtomicU64,
+    pub duration_count: AtomicU64,
+}
+
+/// Upper bounds of the request duration histogram, in microseconds; slower requests only
+/// show in the `+Inf` bucket.
+pub const DURATION_BUCKETS_US: [u64; 12] = [
+    1_000, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000,
+    5_000_000, 10_000_000,
+];
+
+impl Metrics {
+    pub fn record_duration(&self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        self.duration_sum_us.fetch_add(us, Ordering::Relaxed);
+        self.duration_count.fetch_add(1, Ordering::Relaxed);
+        if let Some(i) = DURATION_BUCKETS_US.iter().position(|&le| us <= le) {
+            self.duration_buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The counters in the Prometheus text exposition format.
+    pub fn prometheus(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let requests = load(&self.requests);
+        let mut out = String::with_capacity(2048);
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (labels, value) in samples {
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+
+        metric(
+            "lumen_requests_total",
+            "counter",
+            "Requests answered.",
+            &[(String::new(), requests)],
+        );
+        let classes: Vec<(String, u64)> = self
+            .responses_by_class
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (format!("{{class=\"{}xx\"}}", i + 1), load(c)))
+            .collect();
+        metric(
+            "lumen_responses_total",
+            "counter",
+            "Responses by status class.",
+            &classes,
+        );
+        metric(
+            "lumen_active_connections",
+            "gauge",
+            "Open client connections.",
+            &[(
+                String::new(),
+                self.active_connections.load(Ordering::Relaxed) as u64,
+            )],
+        );
+        for (name, help, counter) in [
+            (
+                "lumen_cache_hits_total",
+                "Page cache hits.",
+                &self.cache_hits,
+            ),
+            (
+                "lumen_cache_misses_total",
+                "Page cache misses.",
+                &self.cache_misses,
+            ),
+            ("lumen_renders_total", "Templates rendered.", &self.renders),
+            (
+                "lumen_sent_bytes_total",
+                "Bytes written to clients.",
+                &self.bytes_sent,
+            ),
+        ] {
+            metric(name, "counter", help, &[(String::new(), load(counter))]);
+        }
+
+        let mut cumulative = 0;
+        let mut buckets: Vec<(String, u64)> = DURATION_BUCKETS_US
+            .iter()
+            .zip(&self.duration_buckets)
+            .map(|(le, count)| {
+                cumulative += load(count);
+                (
+                    format!("_bucket{{le=\"{}\"}}", *le as f64 / 1_000_000.0),
+                    cumulative,
+                )
+            })
+            .collect();
+        let count = load(&self.duration_count);
+        buckets.push(("_bucket{le=\"+Inf\"}".into(), count));
+        out.push_str(
+            "# HELP lumen_request_duration_seconds Time to produce a response.\n\
+             # TYPE lumen_request_duration_seconds histogram\n",
+        );
+        for (suffix, value) in buckets {
+            out.push_str(&format!(
+                "lumen_request_duration_seconds{} {}\n",
+                suffix, value
+            ));
+        }
+        out.push_str(&format!(
+            "lumen_request_duration_seconds_sum {}\nlumen_request_duration_seconds_count {}\n",
+            load(&self.duration_sum_us) as f64 / 1_000_000.0,
+            count
+        ));
+        out
+    }
+
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_response(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(class) = self
+            .responses_by_class
+            .get((status / 100).wrapping_sub(1) as usize)
+        {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        match status {
+            404 => {
+                self.client_errors.fetch_add(1, Ordering::Relaxed);
+                self.not_found.fetch_add(1, Ordering::Relaxed);
+            }
+            400..=499 => {
+                self.client_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            500.. => {
+                self.server_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `security.allow_ips` and `deny_ips` as network/mask pairs, checked for every connection.
+pub struct IpFilter {
+    allow: Vec<(u128, u128)>,
+    deny: Vec<(u128, u128)>,
+}
+
+impl IpFilter {
+    /// Entries that don't parse are left out; `Config::validate` rejects them at load.
+    pub fn new(security: &SecurityConfig) -> Self {
+        let parse = |list: &[String]| list.iter().filter_map(|s| parse_cidr(s)).collect();
+        Self {
+            allow: parse(&security.allow_ips),
+            deny: parse(&security.deny_ips),
+        }
+    }
+
+    /// A denied block wins over an allowed one; an empty allowlist allows everyone else.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let bits = ip_bits(ip);
+        let in_any = |list: &[(u128, u128)]| list.iter().any(|&(net, mask)| bits & mask == net);
+        !in_any(&self.deny) && (self.allow.is_empty() || in_any(&self.allow))
+    }
+}
+
+/// Counting semaphore bounding concurrent markdown renders; a limit of 0 means unbounded.
+pub struct RenderLimiter {
+    active: Mutex<usize>,
+    released: Condvar,
+    limit: usize,
+}
+
+pub struct RenderPermit<'a> {
+    limiter: &'a RenderLimiter,
+}
+
+/// A render slot that can move to another thread along with the render holding it.
+pub struct OwnedRenderPermit {
+    limiter: Arc<RenderLimiter>,
+}
+
+impl RenderLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            active: Mutex::new(0),
+            released: Condvar::new(),
+            limit,
+        }
+    }
+
+    /// Takes a render slot, waiting for one to free up.
+    pub fn acquire(&self) -> RenderPermit<'_> {
+        let active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let mut active = self
+            .released
+            .wait_while(active, |active| self.is_full(*active))
+            .unwrap_or_else(|e| e.into_inner());
+        *active += 1;
+        RenderPermit { limiter: self }
+    }
+
+    /// Takes a render slot if one frees up within `timeout`.
+    pub fn try_acquire_for(&self, timeout: Duration) -> Option<RenderPermit<'_>> {
+        let active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut active, result) = self
+            .released
+            .wait_timeout_while(active, timeout, |active| self.is_full(*active))
+            .unwrap_or_else(|e| e.into_inner());
+        if result.timed_out() && self.is_full(*active) {
+            return None;
+        }
+        *active += 1;
+        Some(RenderPermit { limiter: self })
+    }
+
+    /// Takes a render slot for another thread, waiting for one to free up.
+    pub fn acquire_owned(self: &Arc<Self>) -> OwnedRenderPermit {
+        std::mem::forget(self.acquire());
+        OwnedRenderPermit {
+            limiter: Arc::clone(self),
+        }
+    }
+
+    /// Takes a render slot for another thread if one is free right now.
+    pub fn try_acquire_owned(self: &Arc<Self>) -> Option<OwnedRenderPermit> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        if self.is_full(*active) {
+            return None;
+        }
+        *active += 1;
+        Some(OwnedRenderPermit {
+            limiter: Arc::clone(self),
+        })
+    }
+
+    fn is_full(&self, active: usize) -> bool {
+        self.limit != 0 && active >= self.limit
+    }
+
+    fn release(&self) {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        *active -= 1;
+        self.released.notify_one();
+    }
+}
+
+impl Drop for RenderPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+impl Drop for OwnedRenderPermit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// A page render other requests for the same file can wait on; see `serve_markdown`.
+pub type RenderFlight = Arc<OnceLock<Result<CacheEntry, u16>>>;
+
+pub struct ContentRoot {
+    pub dir: PathBuf,
+    pub canon: PathBuf,
+}
+
+/// A `paths.error_pages` file, loaded and ready to send.
+pub struct ErrorPage {
+    pub content_type: String,
+    pub body: Bytes,
+}
+
+/// One visible entry of a content directory, for `server.autoindex`.
+pub struct DirListing {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub mtime: SystemTime,
+}
+
 pub struct ServerState {
-    pub base_dir: PathBuf,
-    pub base_canon: PathBuf,
+    pub content_roots: Vec<ContentRoot>,
     pub page_cache: ShardedLruCache<PathBuf, CacheEntry>,
-    pub dir_cache: ShardedLruCache<PathBuf, (u64, minijinja::Value)>,
+    #[cfg(feature = "markdown")]
+    pub dir_cache: ShardedLruCache<(String, PathBuf), (u64, minijinja::Value)>,
+    /// `toc()` results keyed by a hash of the rendered content and the depth asked for.
+    #[cfg(feature = "markdown")]
+    pub toc_cache: ShardedLruCache<u64, minijinja::Value>,
+    #[cfg(feature = "markdown")]
     pub theme_state: RwLock<(u64, Arc<Environment<'static>>)>,
-    pub config: Config,
-    pub precomputed_headers: Arc<[u8]>,
+    config: ArcSwap<Config>,
+    /// Fingerprint of the running configuration, so pages rendered under another one are
+    /// not picked up from the disk cache.
+    pub config_hash: AtomicU64,
+    /// `[markdown]` as parser flags, so each render doesn't rebuild them.
+    #[cfg(feature = "markdown")]
+    markdown_options: AtomicU32,
+    pub precomputed_headers: ArcSwap<Vec<u8>>,
     pub is_running: Arc<AtomicBool>,
+    pub memory_pressure: AtomicBool,
+    pub request_id_seed: u32,
+    pub request_counter: AtomicU64,
+    pub date_header: ArcSwap<String>,
+    pub metrics: Arc<Metrics>,
+    pub ip_filter: ArcSwap<IpFilter>,
+    pub render_limiter: Arc<RenderLimiter>,
+    /// Threads feeding streamed responses, one per worker, so slow clients can't pile them up.
+    pub stream_limiter: Arc<RenderLimiter>,
+    pub inflight_renders: Mutex<HashMap<PathBuf, RenderFlight>>,
+    pub disk_cache: Option<DiskCache>,
+    pub access_log: Option<AccessLog>,
+    /// Loaded by `load_error_pages`; statuses without an entry use the built-in page.
+    pub error_pages: ArcSwap<HashMap<u16, ErrorPage>>,
+    /// `[mime]` keyed by lowercase extension without the dot, for `get_mime_type`.
+    pub mime_types: ArcSwap<HashMap<String, String>>,
+}
+
+/// The header lines every response carries, formatted once per configuration.
+fn precompute_headers(config: &Config) -> Vec<u8> {
+    let mut precomp = format!(
+        "Server: {}\r\nX-Content-Type-Options: {}\r\nX-Frame-Options: {}\r\nContent-Security-Policy: {}\r\n",
+        config.server.name,
+        config.security.x_content_type_options,
+        config.security.x_frame_options,
+        config.security.content_security_policy
+    );
+    if !config.security.cors_allow_origin.is_empty() {
+        precomp.push_str(&format!(
+            "Access-Control-Allow-Origin: {}\r\n",
+            config.security.cors_allow_origin
+        ));
+    }
+    if !config.server.alt_svc.is_empty() {
+        precomp.push_str(&format!("Alt-Svc: {}\r\n", config.server.alt_svc));
+    }
+    precomp.into_bytes()
+}
+
+#[cfg(feature = "markdown")]
+fn dir_cache_bytes(config: &Config) -> usize {
+    match config.performance.max_dir_cache_memory_mb {
+        0 => usize::MAX,
+        mb => mb * 1024 * 1024,
+    }
+}
+
+fn mime_overrides(config: &Config) -> HashMap<String, String> {
+    config
+        .mime
+        .iter()
+        .map(|(ext, mime)| (ext.trim_start_matches('.').to_lowercase(), mime.clone()))
+        .collect()
+}
+
+fn config_fingerprint(config: &Config) -> u64 {
+    content_hash(toml::to_string(config).unwrap_or_default().as_bytes())
 }
 
 impl ServerState {
-    pub fn cache_put(&self, path: PathBuf, entry: CacheEntry) {
-        let shard_idx = self.page_cache.get_shard(&path);
-        let size = entry.size_bytes();
+    pub fn new(config: Config) -> Self {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let content_dirs = if config.paths.content_dirs.is_empty() {
+            vec![config.paths.content_dir.clone()]
+        } else {
+            config.paths.content_dirs.clone()
+        };
+        let content_roots = content_dirs
+            .iter()
+            .map(|dir| {
+                let dir = cwd.join(dir);
+                let canon = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+                ContentRoot { dir, canon }
+            })
+            .collect();
 
-        let mut shard = self.page_cache.shards[shard_idx]
-            .lock()
-            .unwrap_or_else(|e| e.into_inner());
+        let precomputed_headers = ArcSwap::from_pointee(precompute_headers(&config));
+
+        let disk_cache = (!config.performance.disk_cache_dir.is_empty())
+            .then(|| {
+                let dir = cwd.join(&config.performance.disk_cache_dir);
+                DiskCache::new(&dir, config.performance.disk_cache_gzip)
+                    .map_err(|e| error!("Disk cache disabled, cannot use {}: {}", dir.display(), e))
+                    .ok()
+            })
+            .flatten();
 
-        if let Some(old_val) = shard.cache.peek(&path) {
-            shard.current_bytes = shard.current_bytes.saturating_sub(old_val.size_bytes());
+        let access_log = (!config.server.access_log.is_empty())
+            .then(|| {
+                let path = cwd.join(&config.server.access_log);
+                AccessLog::open(&path, config.server.access_log_format)
+                    .map_err(|e| {
+                        error!("Access log disabled, cannot open {}: {}", path.display(), e)
+                    })
+                    .ok()
+            })
+            .flatten();
+
+        let cache_mem_bytes = config.performance.max_cache_memory_mb * 1024 * 1024;
+        Self {
+            content_roots,
+            page_cache: ShardedLruCache::new(cache_mem_bytes, usize::MAX),
+            #[cfg(feature = "markdown")]
+            dir_cache: ShardedLruCache::new(dir_cache_bytes(&config), 10_000),
+            #[cfg(feature = "markdown")]
+            toc_cache: ShardedLruCache::new(dir_cache_bytes(&config), 10_000),
+            #[cfg(feature = "markdown")]
+            theme_state: RwLock::new((0, Arc::new(minijinja::Environment::new()))),
+            render_limiter: Arc::new(RenderLimiter::new(
+                config.performance.max_concurrent_renders,
+            )),
+            stream_limiter: Arc::new(RenderLimiter::new(config.server.threads.max(1))),
+            inflight_renders: Mutex::new(HashMap::new()),
+            disk_cache,
+            access_log,
+            error_pages: ArcSwap::from_pointee(HashMap::new()),
+            mime_types: ArcSwap::from_pointee(mime_overrides(&config)),
+            config_hash: AtomicU64::new(config_fingerprint(&config)),
+            #[cfg(feature = "markdown")]
+            markdown_options: AtomicU32::new(markdown_options(&config.markdown).bits()),
+            ip_filter: ArcSwap::from_pointee(IpFilter::new(&config.security)),
+            config: ArcSwap::from_pointee(config),
+            precomputed_headers,
+            is_running: Arc::new(AtomicBool::new(true)),
+            memory_pressure: AtomicBool::new(false),
+            request_id_seed: RandomState::new().hash_one(SystemTime::now()) as u32,
+            request_counter: AtomicU64::new(0),
+            date_header: ArcSwap::from_pointee(httpdate::fmt_http_date(SystemTime::now())),
+            metrics: Arc::default(),
         }
+    }
 
-        shard.current_bytes += size;
-        shard.cache.put(path, entry);
+    /// The configuration in effect; a reload swaps it for requests that start afterwards.
+    pub fn config(&self) -> Guard<Arc<Config>> {
+        self.config.load()
+    }
 
-        while (shard.current_bytes > shard.max_bytes || shard.cache.len() > shard.max_entries)
-            && !shard.cache.is_empty()
+    /// Swaps in a re-read configuration. Settings that are only applied at startup keep their
+    /// running values; their names are returned so the caller can say a restart is needed.
+    pub fn reload_config(&self, mut config: Config) -> Vec<&'static str> {
+        let kept = config.keep_startup_settings(&self.config());
+        self.precomputed_headers
+            .store(Arc::new(precompute_headers(&config)));
+        self.page_cache
+            .set_max_bytes(config.performance.max_cache_memory_mb * 1024 * 1024);
+        #[cfg(feature = "markdown")]
         {
-            if let Some((_, evicted)) = shard.cache.pop_lru() {
-                shard.current_bytes = shard.current_bytes.saturating_sub(evicted.size_bytes());
+            self.dir_cache.set_max_bytes(dir_cache_bytes(&config));
+            self.toc_cache.set_max_bytes(dir_cache_bytes(&config));
+        }
+        self.config_hash
+            .store(config_fingerprint(&config), Ordering::Relaxed);
+        #[cfg(feature = "markdown")]
+        self.markdown_options
+            .store(markdown_options(&config.markdown).bits(), Ordering::Relaxed);
+        self.ip_filter
+            .store(Arc::new(IpFilter::new(&config.security)));
+        self.mime_types.store(Arc::new(mime_overrides(&config)));
+        self.config.store(Arc::new(config));
+        kept
+    }
+
+    /// Reads the `paths.error_pages` files, rendering markdown ones through the current theme,
+    /// so it runs again whenever the theme or configuration changes. A page that fails to load
+    /// is left out, and its status falls back to the built-in page.
+    pub fn load_error_pages(&self) {
+        let config = self.config();
+        let mut pages = HashMap::new();
+        for (status, file) in &config.paths.error_pages {
+            let Ok(status) = status.parse::<u16>() else {
+                continue;
+            };
+            let path = Path::new(file);
+            let page = if path.extension().is_some_and(|ext| ext == "md") {
+                self.render_error_page(path)
+            } else {
+                fs::read(path)
+                    .map(|body| {
+                        let mime = get_mime_type(path, &self.mime_types.load());
+                        ErrorPage {
+                            content_type: if mime.starts_with("text/") && !mime.contains(';') {
+                                format!("{}; charset={}", mime, config.defaults.charset)
+                            } else {
+                                mime
+                            },
+                            body: Bytes::from(body),
+                        }
+                    })
+                    .map_err(|e| e.to_string())
+            };
+            match page {
+                Ok(page) => {
+                    pages.insert(status, page);
+                }
+                Err(e) => warn!(
+                    "Using the built-in {} page, {} failed to load: {}",
+                    status, file, e
+                ),
             }
         }
+        self.error_pages.store(Arc::new(pages));
+    }
+
+    #[cfg(feature = "markdown")]
+    fn render_error_page(&self, path: &Path) -> Result<ErrorPage, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let page =
+            crate::render::render_markdown(&content, path, self).map_err(|e| e.to_string())?;
+        Ok(ErrorPage {
+            content_type: page.content_type,
+            body: Bytes::from(page.body),
+        })
+    }
+
+    #[cfg(not(feature = "markdown"))]
+    fn render_error_page(&self, _path: &Path) -> Result<ErrorPage, String> {
+        Err("markdown pages need the markdown feature".into())
+    }
+
+    /// `path` (relative to the site root) as the URL clients see, under `server.base_path`.
+    pub fn public_path(&self, path: &str) -> String {
+        let config = self.config();
+        let base = config.server.base_path.trim_matches('/');
+        if base.is_empty() {
+            path.to_string()
+        } else {
+            format!("/{}/{}", base, path.trim_start_matches('/'))
+        }
+    }
+
+    #[cfg(feature = "markdown")]
+    pub fn markdown_options(&self) -> Options {
+        Options::from_bits_truncate(self.markdown_options.load(Ordering::Relaxed))
+    }
+
+    pub fn next_request_id(&self) -> String {
+        let n = self.request_counter.fetch_add(1, Ordering::Relaxed);
+        format!("{:08x}-{:x}", self.request_id_seed, n)
+    }
+
+    /// The `Date` header value, from the ticker-refreshed copy when `cache_date_header` is set.
+    pub fn http_date(&self) -> Arc<String> {
+        if self.config().performance.cache_date_header {
+            self.date_header.load_full()
+        } else {
+            Arc::new(httpdate::fmt_http_date(SystemTime::now()))
+        }
+    }
+
+    pub fn refresh_date_header(&self) {
+        self.date_header
+            .store(Arc::new(httpdate::fmt_http_date(SystemTime::now())));
+    }
+
+    pub fn cache_bytes(&self) -> usize {
+        self.page_cache
+            .shards
+            .iter()
+            .map(|s| s.lock().unwrap_or_else(|e| e.into_inner()).current_bytes)
+            .sum()
+    }
+
+    /// Resolves `rel` against each content root in order; the first root holding a file wins.
+    pub fn resolve_file(&self, rel: &str) -> Option<(PathBuf, fs::Metadata)> {
+        self.content_roots.iter().find_map(|root| {
+            let canon = secure_join(&root.dir, rel)?.canonicalize().ok()?;
+            if !canon.starts_with(&root.canon) {
+                return None;
+            }
+            let metadata = fs::metadata(&canon).ok()?;
+            metadata.is_file().then_some((canon, metadata))
+        })
+    }
+
+    pub fn is_content_dir(&self, rel: &str) -> bool {
+        self.content_roots.iter().any(|root| {
+            secure_join(&root.dir, rel)
+                .and_then(|p| fs::metadata(p).ok())
+                .is_some_and(|m| m.is_dir())
+        })
+    }
+
+    /// The non-hidden entries of content directory `rel` across the roots, the first root
+    /// holding a name winning as in `resolve_file`. Entries resolving outside their root are
+    /// left out; `None` when no root has the directory.
+    pub fn list_content_dir(&self, rel: &str) -> Option<Vec<DirListing>> {
+        let mut found = false;
+        let mut entries = BTreeMap::new();
+        for root in &self.content_roots {
+            let Some(dir) = secure_join(&root.dir, rel).and_then(|p| p.canonicalize().ok()) else {
+                continue;
+            };
+            if !dir.starts_with(&root.canon) || !dir.is_dir() {
+                continue;
+            }
+            let Ok(read) = fs::read_dir(&dir) else {
+                continue;
+            };
+            found = true;
+            for entry in read.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') || entries.contains_key(&name) {
+                    continue;
+                }
+                let Ok(canon) = entry.path().canonicalize() else {
+                    continue;
+                };
+                let Ok(metadata) = fs::metadata(&canon) else {
+                    continue;
+                };
+                if !canon.starts_with(&root.canon) {
+                    continue;
+                }
+                entries.insert(
+                    name.clone(),
+                    DirListing {
+                        name,
+                        is_dir: metadata.is_dir(),
+                        size: metadata.len(),
+                        mtime: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    },
+                );
+            }
+        }
+        found.then(|| entries.into_values().collect())
+    }
+
+    /// Page cache lookup; with `non_blocking_cache` a contended shard counts as a miss.
+    pub fn cache_get(&self, path: &PathBuf) -> Option<CacheEntry> {
+        if self.config().performance.non_blocking_cache {
+            self.page_cache.try_get(path)
+        } else {
+            self.page_cache.get(path)
+        }
+    }
+
+    /// With `non_blocking_cache` the insert is skipped when the shard is contended; the next
+    /// request simply renders again.
+    pub fn cache_put(&self, path: PathBuf, entry: CacheEntry) {
+        let shard_idx = self.page_cache.get_shard(&path);
+
+        let mut shard = if self.config().performance.non_blocking_cache {
+            match self.page_cache.try_lock_shard(shard_idx) {
+                Some(shard) => shard,
+                None => return,
+            }
+        } else {
+            self.page_cache.shards[shard_idx]
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+        };
+
+        let evicted = shard.insert(path, entry);
+        self.page_cache
+            .evictions
+            .fetch_add(evicted, Ordering::Relaxed);
     }
 
     pub fn add_cache_size(&self, path: &PathBuf, additional_bytes: usize) {
@@ -125,27 +940,277 @@ impl ServerState {
 
         if shard.cache.peek(path).is_some() {
             shard.current_bytes += additional_bytes;
+            let evicted = shard.evict_over_budget();
+            self.page_cache
+                .evictions
+                .fetch_add(evicted, Ordering::Relaxed);
+        }
+    }
 
-            while (shard.current_bytes > shard.max_bytes || shard.cache.len() > shard.max_entries)
-                && !shard.cache.is_empty()
-            {
-                if let Some((_, evicted)) = shard.cache.pop_lru() {
-                    shard.current_bytes = shard.current_bytes.saturating_sub(evicted.size_bytes());
-                }
-            }
+    #[cfg(feature = "markdown")]
+    pub fn dir_cache_put(&self, key: (String, PathBuf), hash: u64, val: minijinja::Value) {
+        self.dir_cache.put(key, (hash, val));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+
+    fn entry(body: &'static str) -> CacheEntry {
+        CacheEntry {
+            raw: Bytes::from_static(body.as_bytes()),
+            br: Arc::default(),
+            gz: Arc::default(),
+            df: Arc::default(),
+            content_type: "text/html".into(),
+            mtime: SystemTime::UNIX_EPOCH,
+            status: 200,
+            headers: Vec::new(),
+            expires_at: None,
         }
     }
 
-    pub fn dir_cache_put(&self, path: PathBuf, hash: u64, val: minijinja::Value) {
-        let shard_idx = self.dir_cache.get_shard(&path);
-        let mut shard = self.dir_cache.shards[shard_idx]
-            .lock()
-            .unwrap_or_else(|e| e.into_inner());
+    fn state(non_blocking_cache: bool) -> Arc<ServerState> {
+        let mut config = Config::default();
+        config.performance.non_blocking_cache = non_blocking_cache;
+        Arc::new(ServerState::new(config))
+    }
+
+    /// Runs `f` on another thread while this one holds `key`'s shard, returning what `f`
+    /// produced if it finished without the lock being released.
+    fn with_shard_held<T: Send + 'static>(
+        state: &Arc<ServerState>,
+        key: &PathBuf,
+        f: impl FnOnce(&ServerState) -> T + Send + 'static,
+    ) -> Option<T> {
+        let idx = state.page_cache.get_shard(key);
+        let _held = state.page_cache.shards[idx].lock().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let worker = Arc::clone(state);
+        thread::spawn(move || {
+            let _ = tx.send(f(&worker));
+        });
+        rx.recv_timeout(Duration::from_millis(200)).ok()
+    }
+
+    #[test]
+    fn non_blocking_cache_treats_a_held_shard_as_a_miss() {
+        let state = state(true);
+        let key = PathBuf::from("page.md");
+        state.cache_put(key.clone(), entry("cached"));
 
-        shard.cache.put(path, (hash, val));
+        let lookup = key.clone();
+        let got = with_shard_held(&state, &key, move |s| s.cache_get(&lookup).is_some());
+        assert_eq!(got, Some(false), "lookup blocked or hit a held shard");
+        assert!(state.cache_get(&key).is_some());
+    }
+
+    #[test]
+    fn non_blocking_cache_skips_inserts_into_a_held_shard() {
+        let state = state(true);
+        let key = PathBuf::from("page.md");
+        let insert = key.clone();
+        let done = with_shard_held(&state, &key, move |s| s.cache_put(insert, entry("new")));
+        assert!(done.is_some(), "insert blocked on a held shard");
+        assert!(state.cache_get(&key).is_none());
+    }
 
-        while shard.cache.len() > shard.max_entries && !shard.cache.is_empty() {
-            shard.cache.pop_lru();
+    #[test]
+    fn blocking_cache_waits_for_the_shard() {
+        let state = state(false);
+        let key = PathBuf::from("page.md");
+        state.cache_put(key.clone(), entry("cached"));
+        let lookup = key.clone();
+        assert!(with_shard_held(&state, &key, move |s| s.cache_get(&lookup)).is_none());
+    }
+
+    #[test]
+    fn render_limiter_caps_concurrent_holders() {
+        let limiter = Arc::new(RenderLimiter::new(3));
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let renders: Vec<_> = (0..12)
+            .map(|_| {
+                let (limiter, active, peak) =
+                    (Arc::clone(&limiter), Arc::clone(&active), Arc::clone(&peak));
+                thread::spawn(move || {
+                    let _permit = limiter.acquire();
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for render in renders {
+            render.join().unwrap();
         }
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn render_limiter_try_acquire_gives_up_when_full() {
+        let limiter = RenderLimiter::new(1);
+        let held = limiter.acquire();
+        assert!(limiter.try_acquire_for(Duration::from_millis(20)).is_none());
+        drop(held);
+        assert!(limiter.try_acquire_for(Duration::from_millis(20)).is_some());
+
+        let unbounded = RenderLimiter::new(0);
+        let _permits: Vec<_> = (0..100).map(|_| unbounded.acquire()).collect();
+    }
+
+    #[test]
+    fn owned_permits_share_the_limit_and_release_on_another_thread() {
+        let limiter = Arc::new(RenderLimiter::new(2));
+        let owned = limiter.acquire_owned();
+        let _borrowed = limiter.acquire();
+        assert!(limiter.try_acquire_owned().is_none());
+        assert!(limiter.try_acquire_for(Duration::from_millis(20)).is_none());
+
+        thread::spawn(move || drop(owned)).join().unwrap();
+        assert!(limiter.try_acquire_owned().is_some());
+    }
+
+    #[test]
+    fn ip_filter_denies_before_allowing() {
+        let filter = |allow: &[&str], deny: &[&str]| {
+            IpFilter::new(&SecurityConfig {
+                allow_ips: allow.iter().map(|s| s.to_string()).collect(),
+                deny_ips: deny.iter().map(|s| s.to_string()).collect(),
+                ..SecurityConfig::default()
+            })
+        };
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let open = filter(&[], &[]);
+        assert!(open.permits(ip("203.0.113.9")) && open.permits(ip("::1")));
+
+        let internal = filter(&["10.0.0.0/8", "fd00::/8"], &["10.0.0.13"]);
+        assert!(internal.permits(ip("10.1.2.3")));
+        assert!(internal.permits(ip("fd12::1")));
+        assert!(internal.permits(ip("::ffff:10.9.9.9")));
+        assert!(!internal.permits(ip("10.0.0.13")));
+        assert!(!internal.permits(ip("192.168.0.1")));
+        assert!(!internal.permits(ip("2001:db8::1")));
+
+        let blocklist = filter(&[], &["198.51.100.0/24"]);
+        assert!(!blocklist.permits(ip("198.51.100.200")));
+        assert!(blocklist.permits(ip("198.51.101.1")));
+    }
+
+    #[test]
+    fn prometheus_output_has_status_classes_and_a_cumulative_histogram() {
+        let metrics = Metrics::default();
+        for status in [200, 204, 304, 404, 503] {
+            metrics.record_response(status);
+        }
+        metrics.record_cache(true);
+        metrics.record_duration(Duration::from_micros(800));
+        metrics.record_duration(Duration::from_millis(20));
+        metrics.record_duration(Duration::from_secs(30));
+
+        let text = metrics.prometheus();
+        for line in [
+            "# TYPE lumen_requests_total counter",
+            "lumen_requests_total 5",
+            "lumen_responses_total{class=\"1xx\"} 0",
+            "lumen_responses_total{class=\"2xx\"} 2",
+            "lumen_responses_total{class=\"3xx\"} 1",
+            "lumen_responses_total{class=\"4xx\"} 1",
+            "lumen_responses_total{class=\"5xx\"} 1",
+            "lumen_cache_hits_total 1",
+            "lumen_cache_misses_total 0",
+            "# TYPE lumen_request_duration_seconds histogram",
+            "lumen_request_duration_seconds_bucket{le=\"0.001\"} 1",
+            "lumen_request_duration_seconds_bucket{le=\"0.01\"} 1",
+            "lumen_request_duration_seconds_bucket{le=\"0.025\"} 2",
+            "lumen_request_duration_seconds_bucket{le=\"10\"} 2",
+            "lumen_request_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "lumen_request_duration_seconds_sum 30.0208",
+            "lumen_request_duration_seconds_count 3",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{:?} missing from\n{}",
+                line,
+                text
+            );
+        }
+    }
+
+    #[derive(Clone)]
+    struct Weighs(usize);
+
+    impl CacheWeight for Weighs {
+        fn weight(&self) -> usize {
+            self.0
+        }
+    }
+
+    fn shard(max_bytes: usize, max_entries: usize) -> CacheShard<&'static str, Weighs> {
+        CacheShard {
+            cache: LruCache::unbounded_with_hasher(RandomState::new()),
+            current_bytes: 0,
+            max_bytes,
+            max_entries,
+        }
+    }
+
+    fn keys(shard: &CacheShard<&'static str, Weighs>) -> Vec<&'static str> {
+        let mut keys: Vec<_> = shard.cache.iter().map(|(k, _)| *k).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn shards_evict_the_least_recently_used_past_their_byte_budget() {
+        let mut shard = shard(100, 10);
+        shard.insert("a", Weighs(40));
+        shard.insert("b", Weighs(40));
+        shard.cache.get(&"a");
+        shard.insert("c", Weighs(40));
+        assert_eq!(keys(&shard), ["a", "c"]);
+        assert_eq!(shard.current_bytes, 80);
+
+        // replacing a value swaps its weight rather than adding to it
+        shard.insert("a", Weighs(10));
+        assert_eq!(shard.current_bytes, 50);
+
+        // a value over the whole budget doesn't stay, nor keep anything else
+        shard.insert("huge", Weighs(150));
+        assert!(shard.cache.is_empty());
+        assert_eq!(shard.current_bytes, 0);
+    }
+
+    #[test]
+    fn shards_hold_to_whichever_limit_binds_first() {
+        let mut shard = shard(usize::MAX, 2);
+        for key in ["a", "b", "c"] {
+            shard.insert(key, Weighs(1));
+        }
+        assert_eq!(keys(&shard), ["b", "c"]);
+        assert_eq!(shard.current_bytes, 2);
+
+        shard.max_bytes = 1;
+        shard.evict_over_budget();
+        assert_eq!(keys(&shard), ["c"]);
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn template_values_weigh_their_text_and_items() {
+        let text = minijinja::Value::from("hello");
+        assert_eq!(text.weight(), 32 + 5);
+        assert_eq!(minijinja::Value::from(7).weight(), 32);
+        let list = minijinja::Value::from(vec!["ab", "cde"]);
+        assert_eq!(list.weight(), 32 + (32 + 2) + (32 + 3));
+        let map = minijinja::Value::from_serialize(
+            [("k", "vvv")].into_iter().collect::<BTreeMap<_, _>>(),
+        );
+        assert_eq!(map.weight(), 32 + (32 + 1) + (32 + 3));
+        assert_eq!((0u64, text).weight(), 8 + 37);
     }
 }
@@ -0,0 +1,903 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    state::ServerState,
+    utils::{
+        absolute_url, content_hash, extract_headings, format_datetime, format_rfc3339,
+        markdown_to_html, parse_date, parse_datetime, secure_join, split_frontmatter,
+    },
+};
+
+pub struct DirPost {
+    pub url: String,
+    pub meta: BTreeMap<String, minijinja::Value>,
+    pub body: String,
+    pub mtime: SystemTime,
+}
+
+pub fn start_theme_watcher(state: Arc<ServerState>) {
+    thread::spawn(move || {
+        while state.is_running.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(2));
+            let _ = reload_theme(&state);
+        }
+    });
+}
+
+/// Rebuilds the template environment if the theme directory changed since the last build,
+/// returning the names of templates that failed to compile.
+pub fn reload_theme(state: &Arc<ServerState>) -> Vec<String> {
+    let mut max_mtime = SystemTime::UNIX_EPOCH;
+    let mut file_count = 0usize;
+
+    let theme_files = crate::utils::get_all_files(Path::new(&state.config().paths.theme_dir), 0);
+    for path in &theme_files {
+        if let Ok(meta) = fs::metadata(path) {
+            file_count += 1;
+            if let Ok(mt) = meta.modified()
+                && mt > max_mtime
+            {
+                max_mtime = mt;
+            }
+        }
+    }
+
+    // Full precision: the hash also versions the disk cache, where an edit landing in the
+    // same second as the previous one must still count as a change.
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    use std::hash::Hasher;
+    hasher.write(
+        &(max_mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos())
+        .to_ne_bytes(),
+    );
+    hasher.write(&file_count.to_ne_bytes());
+    let current_hash = hasher.finish();
+
+    let current_cache_hash = state
+        .theme_state
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .0;
+
+    if current_cache_hash == current_hash {
+        return Vec::new();
+    }
+
+    let (env, failed) = build_environment(state, theme_files);
+    let mut cache = state.theme_state.write().unwrap_or_else(|e| e.into_inner());
+    *cache = (current_hash, Arc::new(env));
+    drop(cache);
+    state.page_cache.clear();
+    state.dir_cache.clear();
+    state.load_error_pages();
+    failed
+}
+
+/// Rebuilds the template environment even though the theme is unchanged, for when the
+/// configuration its functions capture has been reloaded.
+pub fn rebuild_theme(state: &Arc<ServerState>) -> Vec<String> {
+    state
+        .theme_state
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .0 = 0;
+    reload_theme(state)
+}
+
+fn build_environment(
+    state: &Arc<ServerState>,
+    theme_files: Vec<PathBuf>,
+) -> (minijinja::Environment<'static>, Vec<String>) {
+    let mut env = minijinja::Environment::new();
+    let mut failed = Vec::new();
+
+    let env_state = Arc::clone(state);
+    env.add_function("list_dir", move |dir_path: String| {
+        list_dir(&env_state, &dir_path)
+    });
+    env.add_filter(
+        "dateformat",
+        |value: minijinja::Value, fmt: Option<String>| match value.as_str().and_then(parse_datetime)
+        {
+            Some(dt) => {
+                minijinja::Value::from(format_datetime(&dt, fmt.as_deref().unwrap_or("%Y-%m-%d")))
+            }
+            None => value,
+        },
+    );
+    let env_state = Arc::clone(state);
+    env.add_function(
+        "list_by_tag",
+        move |dir_path: String, tag: String, field: Option<String>| {
+            list_by_tag(
+                &env_state,
+                &dir_path,
+                &tag,
+                field.as_deref().unwrap_or("tags"),
+            )
+        },
+    );
+    let env_state = Arc::clone(state);
+    env.add_function(
+        "tag_counts",
+        move |dir_path: String, field: Option<String>| {
+            tag_counts(&env_state, &dir_path, field.as_deref().unwrap_or("tags"))
+        },
+    );
+    let env_state = Arc::clone(state);
+    env.add_function(
+        "paginate",
+        move |st: &minijinja::State,
+              dir_path: String,
+              page: Option<minijinja::Value>,
+              per_page: Option<usize>| {
+            let url = st.lookup("url").unwrap_or_default().to_string();
+            paginate(&env_state, &url, &dir_path, page, per_page)
+        },
+    );
+    let env_state = Arc::clone(state);
+    env.add_function("json_feed", move |dir_path: String| {
+        json_feed(&env_state, &dir_path)
+    });
+    let env_state = Arc::clone(state);
+    env.add_function("all_pages", move || all_pages(&env_state));
+    let env_state = Arc::clone(state);
+    env.add_function("nav_tree", move |root: Option<String>| {
+        nav_tree(&env_state, root.as_deref().unwrap_or(""))
+    });
+    let env_state = Arc::clone(state);
+    env.add_function("siblings", move |current_path: String, dir_path: String| {
+        siblings(&env_state, &current_path, &dir_path)
+    });
+
+    let env_state = Arc::clone(state);
+    env.add_function("toc", move |content: String, max_depth: Option<u8>| {
+        toc(&env_state, &content, max_depth)
+    });
+
+    env.add_function("set_header", crate::render::set_header);
+    let secure_cookies = state.config().server.base_url.starts_with("https://");
+    env.add_function(
+        "set_cookie",
+        move |st: &minijinja::State,
+              name: String,
+              value: String,
+              attrs: Option<minijinja::Value>| {
+            crate::render::set_cookie(st, secure_cookies, name, value, attrs)
+        },
+    );
+
+    let config = state.config();
+    let theme_dir_path = Path::new(&config.paths.theme_dir);
+    for path in theme_files {
+        let rel_path = path.strip_prefix(theme_dir_path).unwrap_or(&path);
+        let name = rel_path.to_string_lossy().replace('\\', "/");
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        if let Err(e) = env.add_template_owned(name.clone(), content.clone()) {
+            error!("Failed to compile template '{}': {}", name, e);
+            failed.push(name);
+            continue;
+        }
+        if name == "index.html" {
+            let _ = env.add_template_owned("index", content);
+        }
+    }
+    (env, failed)
+}
+
+/// Resolves `dir_path` under every content root and hashes the markdown files in it,
+/// so callers can check `dir_cache` before reading any file contents. Overlay roots
+/// shadow files of the same name in later roots.
+fn scan_dir(state: &ServerState, dir_path: &str) -> Option<(PathBuf, u64, Vec<fs::DirEntry>)> {
+    let target_dirs: Vec<PathBuf> = state
+        .content_roots
+        .iter()
+        .filter_map(|root| secure_join(&root.dir, dir_path))
+        .collect();
+    let cache_dir = target_dirs.first()?.clone();
+
+    let mut dir_hash = 0u64;
+    let mut file_entries = Vec::new();
+    let mut seen = HashSet::new();
+
+    for target_dir in &target_dirs {
+        let Ok(read_dir) = fs::read_dir(target_dir) else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            if entry.path().extension().is_some_and(|ext| ext == "md")
+                && seen.insert(entry.file_name())
+                && let Ok(meta) = entry.metadata()
+            {
+                dir_hash = (dir_hash.rotate_left(3) ^ mtime_nanos(&meta)).wrapping_add(meta.len());
+                file_entries.push(entry);
+            }
+        }
+    }
+
+    Some((cache_dir, dir_hash, file_entries))
+}
+
+/// Reads every scanned file and returns the posts newest-first, the order `list_dir` exposes.
+fn load_posts(
+    state: &ServerState,
+    dir_path: &str,
+    file_entries: Vec<fs::DirEntry>,
+) -> Vec<DirPost> {
+    let mut posts = Vec::with_capacity(file_entries.len());
+    for entry in file_entries {
+        let file_stem = entry
+            .path()
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let content = fs::read_to_string(entry.path()).unwrap_or_default();
+        let (meta, body) = split_frontmatter(&content, &state.config().markdown);
+        let url = state.public_path(&if file_stem == "index" {
+            format!("/{}/", dir_path)
+        } else {
+            format!("/{}/{}", dir_path, file_stem)
+        });
+        posts.push(DirPost {
+            url,
+            meta,
+            body: body.to_string(),
+            mtime: entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+
+    // dates that parse sort as dates, ahead of any that don't, which compare as text
+    posts.sort_by_cached_key(|post| {
+        let raw = post
+            .meta
+            .get("date")
+            .cloned()
+            .unwrap_or_default()
+            .to_string();
+        std::cmp::Reverse((parse_datetime(&raw), raw))
+    });
+    posts
+}
+
+fn cached_dir_value(
+    state: &ServerState,
+    kind: &str,
+    dir_path: &str,
+    build: impl FnOnce(Vec<DirPost>) -> minijinja::Value,
+) -> Option<minijinja::Value> {
+    let (target_dir, dir_hash, file_entries) = scan_dir(state, dir_path)?;
+    let key = (kind.to_string(), target_dir);
+
+    if let Some((cached_hash, cached_val)) = state.dir_cache.get(&key)
+        && cached_hash == dir_hash
+    {
+        return Some(cached_val);
+    }
+
+    let val = build(load_posts(state, dir_path, file_entries));
+    state.dir_cache_put(key, dir_hash, val.clone());
+    Some(val)
+}
+
+pub fn list_dir(state: &ServerState, dir_path: &str) -> minijinja::Value {
+    cached_dir_value(state, "list_dir", dir_path, |posts| {
+        let entries: Vec<minijinja::Value> = posts
+            .into_iter()
+            .map(|post| {
+                let mut meta = post.meta;
+                meta.insert("url".to_string(), minijinja::Value::from(post.url));
+                minijinja::Value::from(meta)
+            })
+            .collect();
+        minijinja::Value::from(entries)
+    })
+    .unwrap_or_else(|| minijinja::Value::from(Vec::<minijinja::Value>::new()))
+}
+
+/// The values of a post's `field`, which may be a list or a single string.
+fn post_tags(post: &minijinja::Value, field: &str) -> Vec<String> {
+    let Ok(value) = post.get_attr(field) else {
+        return Vec::new();
+    };
+    if let Some(tag) = value.as_str() {
+        return vec![tag.to_string()];
+    }
+    value
+        .try_iter()
+        .map(|tags| {
+            tags.filter_map(|t| t.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The `list_dir` posts whose `field` (`tags` by default, or e.g. `categories`) includes
+/// `tag`, ignoring ASCII case, in the same newest-first order. It filters the cached
+/// listing, so needs no cache of its own.
+pub fn list_by_tag(
+    state: &ServerState,
+    dir_path: &str,
+    tag: &str,
+    field: &str,
+) -> minijinja::Value {
+    let posts: Vec<minijinja::Value> = list_dir(state, dir_path)
+        .try_iter()
+        .map(|iter| {
+            iter.filter(|post| {
+                post_tags(post, field)
+                    .iter()
+                    .any(|t| t.trim().eq_ignore_ascii_case(tag.trim()))
+            })
+            .collect()
+        })
+        .unwrap_or_default();
+    minijinja::Value::from(posts)
+}
+
+#[derive(Serialize)]
+struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+/// Every value of `field` across the posts in `dir_path` with how many posts carry it, most
+/// used first, for a tag cloud. Tags differing only in case are counted together under the
+/// first spelling seen.
+pub fn tag_counts(state: &ServerState, dir_path: &str, field: &str) -> minijinja::Value {
+    let kind = format!("tag_counts:{}", field);
+    cached_dir_value(state, &kind, dir_path, |posts| {
+        let mut counts: Vec<TagCount> = Vec::new();
+        for post in posts {
+            let post = minijinja::Value::from(post.meta);
+            let mut seen = HashSet::new();
+            for tag in post_tags(&post, field) {
+                let tag = tag.trim();
+                if tag.is_empty() || !seen.insert(tag.to_ascii_lowercase()) {
+                    continue;
+                }
+                match counts.iter_mut().find(|c| c.tag.eq_ignore_ascii_case(tag)) {
+                    Some(c) => c.count += 1,
+                    None => counts.push(TagCount {
+                        tag: tag.to_string(),
+                        count: 1,
+                    }),
+                }
+            }
+        }
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        minijinja::Value::from_serialize(&counts)
+    })
+    .unwrap_or_else(|| minijinja::Value::from(Vec::<minijinja::Value>::new()))
+}
+
+#[derive(Serialize)]
+struct Pagination {
+    items: Vec<minijinja::Value>,
+    page: usize,
+    per_page: usize,
+    total_items: usize,
+    total_pages: usize,
+    has_prev: bool,
+    has_next: bool,
+    prev_url: Option<String>,
+    next_url: Option<String>,
+}
+
+/// One page of `list_dir(dir_path)`, `per_page` posts long (10 by default). `page` is 1-based
+/// and may be a number or a string such as `query.page`; anything out of range is clamped.
+/// The prev/next URLs link `page_url` with a `?page=` parameter, left off for the first page.
+pub fn paginate(
+    state: &ServerState,
+    page_url: &str,
+    dir_path: &str,
+    page: Option<minijinja::Value>,
+    per_page: Option<usize>,
+) -> minijinja::Value {
+    let posts: Vec<minijinja::Value> = list_dir(state, dir_path)
+        .try_iter()
+        .map(|iter| iter.collect())
+        .unwrap_or_default();
+    let per_page = per_page.unwrap_or(10).max(1);
+    let total_pages = posts.len().div_ceil(per_page).max(1);
+    let requested = page
+        .and_then(|p| {
+            p.as_i64()
+                .or_else(|| p.as_str().and_then(|s| s.trim().parse().ok()))
+        })
+        .unwrap_or(1);
+    let page = usize::try_from(requested)
+        .unwrap_or(1)
+        .clamp(1, total_pages);
+    let link = |n: usize| match n {
+        1 => page_url.to_string(),
+        n => format!("{}?page={}", page_url, n),
+    };
+    let pagination = Pagination {
+        items: posts
+            .iter()
+            .skip((page - 1) * per_page)
+            .take(per_page)
+            .cloned()
+            .collect(),
+        page,
+        per_page,
+        total_items: posts.len(),
+        total_pages,
+        has_prev: page > 1,
+        has_next: page < total_pages,
+        prev_url: (page > 1).then(|| link(page - 1)),
+        next_url: (page < total_pages).then(|| link(page + 1)),
+    };
+    minijinja::Value::from_serialize(&pagination)
+}
+
+/// The markdown files under `dir_path` in every content root, recursively and sorted by
+/// path, as `(path, path relative to dir_path, mtime)`. Overlay roots shadow later ones and
+/// hidden directories are skipped. The hash covers every file's name, mtime and size, so a
+/// cached result can be checked without reading any of them.
+fn scan_tree(state: &ServerState, dir_path: &str) -> (u64, Vec<(PathBuf, PathBuf, SystemTime)>) {
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    let mut tree_hash = 0u64;
+    for root in &state.content_roots {
+        let Some(dir) = secure_join(&root.canon, dir_path) else {
+            continue;
+        };
+        let mut paths = crate::utils::get_all_files(&dir, 0);
+        paths.sort();
+        for path in paths {
+            let Ok(rel) = path.strip_prefix(&dir) else {
+                continue;
+            };
+            let is_hidden = rel
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+            if is_hidden
+                || path.extension().is_none_or(|ext| ext != "md")
+                || !seen.insert(rel.to_path_buf())
+            {
+                continue;
+            }
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+            let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            tree_hash = (tree_hash.rotate_left(3)
+                ^ mtime_nanos(&meta)
+                ^ content_hash(rel.as_os_str().as_encoded_bytes()))
+            .wrapping_add(meta.len());
+            let rel = rel.to_path_buf();
+            files.push((path, rel, mtime));
+        }
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    (tree_hash, files)
+}
+
+/// A file's mtime for the listing hashes. Whole seconds would miss a same-size edit made
+/// within a second of the listing being cached.
+fn mtime_nanos(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// Every page in the site, for sitemaps: each page's frontmatter plus its `url`, `permalink`
+/// (the absolute URL), `lastmod` (the file's mtime as RFC 3339) and `mtime` (in Unix seconds),
+/// sorted by `url`. Pages marked `draft` or `noindex` and files under hidden directories are
+/// left out.
+pub fn all_pages(state: &ServerState) -> minijinja::Value {
+    let (site_hash, files) = scan_tree(state, "");
+    let key = ("all_pages".to_string(), PathBuf::new());
+    if let Some((cached_hash, cached_val)) = state.dir_cache.get(&key)
+        && cached_hash == site_hash
+    {
+        return cached_val;
+    }
+
+    let base_url = &state.config().server.base_url;
+    let mut pages = Vec::with_capacity(files.len());
+    for (path, _, mtime) in files {
+        let secs = mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let (mut meta, _) = split_frontmatter(&content, &state.config().markdown);
+        if ["draft", "noindex"]
+            .iter()
+            .any(|key| meta.get(*key).is_some_and(crate::render::frontmatter_bool))
+        {
+            continue;
+        }
+        let url = crate::render::page_path(state, &path);
+        meta.insert(
+            "permalink".to_string(),
+            minijinja::Value::from(absolute_url(
+                base_url,
+                &percent_encoding::utf8_percent_encode(&url, crate::http::PATH_ENCODE_SET)
+                    .to_string(),
+            )),
+        );
+        meta.insert("url".to_string(), minijinja::Value::from(url));
+        meta.insert(
+            "lastmod".to_string(),
+            minijinja::Value::from(format_rfc3339(mtime)),
+        );
+        meta.insert("mtime".to_string(), minijinja::Value::from(secs));
+        pages.push(meta);
+    }
+    pages.sort_by_cached_key(|meta| meta["url"].to_string());
+
+    let val = minijinja::Value::from_serialize(&pages);
+    state.dir_cache_put(key, site_hash, val.clone());
+    val
+}
+
+#[derive(Serialize)]
+struct NavPage {
+    title: String,
+    url: String,
+    weight: i64,
+}
+
+#[derive(Serialize)]
+struct NavSection {
+    name: String,
+    title: String,
+    url: String,
+    weight: i64,
+    pages: Vec<NavPage>,
+    sections: Vec<NavSection>,
+}
+
+impl NavSection {
+    fn new(name: &str, url: String) -> Self {
+        Self {
+            name: name.to_string(),
+            title: name.to_string(),
+            url,
+            weight: 0,
+            pages: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    fn sort(&mut self) {
+        self.pages
+            .sort_by(|a, b| (a.weight, &a.title).cmp(&(b.weight, &b.title)));
+        self.sections
+            .sort_by(|a, b| (a.weight, &a.title).cmp(&(b.weight, &b.title)));
+        for section in &mut self.sections {
+            section.sort();
+        }
+    }
+}
+
+/// The directory tree under `root` for site navigation: `{name, title, url, weight, pages,
+/// sections}` with pages as `{title, url, weight}`. A directory's `index.md` gives its
+/// section the title and weight instead of being listed as a page. Both lists are ordered by
+/// frontmatter `weight` (default 0), then title; pages marked `draft` are left out.
+pub fn nav_tree(state: &ServerState, root: &str) -> minijinja::Value {
+    let root = root.trim_matches('/');
+    let (tree_hash, files) = scan_tree(state, root);
+    let key = ("nav_tree".to_string(), PathBuf::from(root));
+    if let Some((cached_hash, cached_val)) = state.dir_cache.get(&key)
+        && cached_hash == tree_hash
+    {
+        return cached_val;
+    }
+
+    let dir_url = |dir: &str| {
+        state.public_path(&if dir.is_empty() {
+            "/".to_string()
+        } else {
+            format!("/{}/", dir)
+        })
+    };
+    let root_name = root.rsplit('/').next().unwrap_or_default();
+    let mut tree = NavSection::new(root_name, dir_url(root));
+    for (path, rel, _) in files {
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let (meta, _) = split_frontmatter(&content, &state.config().markdown);
+        if ["draft", "noindex"]
+            .iter()
+            .any(|key| meta.get(*key).is_some_and(crate::render::frontmatter_bool))
+        {
+            continue;
+        }
+        let stem = rel
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let title = meta
+            .get("title")
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_else(|| stem.clone());
+        let weight = meta
+            .get("weight")
+            .and_then(|v| {
+                i64::try_from(v.clone())
+                    .ok()
+                    .or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()))
+            })
+            .unwrap_or(0);
+
+        let mut section = &mut tree;
+        let mut dir = root.to_string();
+        for part in rel.parent().into_iter().flat_map(|p| p.components()) {
+            let name = part.as_os_str().to_string_lossy();
+            if !dir.is_empty() {
+                dir.push('/');
+            }
+            dir.push_str(&name);
+            let idx = match section.sections.iter().position(|s| s.name == name) {
+                Some(idx) => idx,
+                None => {
+                    section.sections.push(NavSection::new(&name, dir_url(&dir)));
+                    section.sections.len() - 1
+                }
+            };
+            section = &mut section.sections[idx];
+        }
+        if stem == "index" {
+            section.title = title;
+            section.weight = weight;
+        } else {
+            section.pages.push(NavPage {
+                title,
+                url: crate::render::page_path(state, &path),
+                weight,
+            });
+        }
+    }
+    tree.sort();
+
+    let val = minijinja::Value::from_serialize(&tree);
+    state.dir_cache_put(key, tree_hash, val.clone());
+    val
+}
+
+/// Renders the posts under `dir_path` as a JSON Feed 1.1 document.
+pub fn json_feed(state: &ServerState, dir_path: &str) -> minijinja::Value {
+    let base_url = &state.config().server.base_url;
+    cached_dir_value(state, "json_feed", dir_path, |posts| {
+        let items: Vec<serde_json::Value> = posts
+            .into_iter()
+            .map(|post| {
+                let url = absolute_url(base_url, &post.url);
+                let title = post
+                    .meta
+                    .get("title")
+                    .map(|v| v.to_string())
+                    .unwrap_or_default();
+                let date_published = post
+                    .meta
+                    .get("date")
+                    .and_then(|v| v.as_str().and_then(parse_date))
+                    .map(|(y, m, d)| format!("{:04}-{:02}-{:02}T00:00:00Z", y, m, d))
+                    .unwrap_or_else(|| format_rfc3339(post.mtime));
+                serde_json::json!({
+                    "id": url,
+                    "url": url,
+                    "title": title,
+                    "content_html": markdown_to_html(
+                        &post.body,
+                        state.markdown_options(),
+                        state.config().markdown.heading_ids,
+                    ),
+                    "date_published": date_published,
+                })
+            })
+            .collect();
+
+        let feed = serde_json::json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": state.config().server.name,
+            "home_page_url": absolute_url(base_url, &state.public_path("/")),
+            "items": items,
+        });
+        minijinja::Value::from_safe_string(feed.to_string())
+    })
+    .unwrap_or_else(|| minijinja::Value::from_safe_string("{}".into()))
+}
+
+/// Returns the `{prev, next}` neighbours of `current_path` in `list_dir` order, `none` at the ends.
+pub fn siblings(state: &ServerState, current_path: &str, dir_path: &str) -> minijinja::Value {
+    let listing = list_dir(state, dir_path);
+    let entries: Vec<minijinja::Value> = listing
+        .try_iter()
+        .map(|iter| iter.collect())
+        .unwrap_or_default();
+
+    let current = current_path.trim_end_matches('/');
+    let position = entries.iter().position(|entry| {
+        entry
+            .get_attr("url")
+            .ok()
+            .and_then(|url| url.as_str().map(|u| u.trim_end_matches('/') == current))
+            .unwrap_or(false)
+    });
+
+    let link = |idx: Option<usize>| match idx.and_then(|i| entries.get(i)) {
+        Some(entry) => minijinja::context! {
+            url => entry.get_attr("url").unwrap_or_default(),
+            title => entry.get_attr("title").unwrap_or_default(),
+        },
+        None => minijinja::Value::from(()),
+    };
+
+    match position {
+        Some(idx) => minijinja::context! {
+            prev => link(idx.checked_sub(1)),
+            next => link(idx.checked_add(1)),
+        },
+        None => minijinja::context! {
+            prev => minijinja::Value::from(()),
+            next => minijinja::Value::from(()),
+        },
+    }
+}
+
+#[derive(Serialize)]
+struct TocEntry {
+    level: u8,
+    text: String,
+    anchor: Option<String>,
+    children: Vec<TocEntry>,
+}
+
+/// Nests the headings of rendered page `content` into `{level, text, anchor, children}`
+/// entries, down to `max_depth` (default `markdown.toc_max_depth`). A heading that skips a
+/// level nests under the nearest shallower one; `anchor` is none for headings without an id.
+pub fn toc(state: &ServerState, content: &str, max_depth: Option<u8>) -> minijinja::Value {
+    let max_depth = max_depth.unwrap_or(state.config().markdown.toc_max_depth);
+    let key = content_hash(content.as_bytes()).wrapping_add(u64::from(max_depth));
+    if let Some(cached) = state.toc_cache.get(&key) {
+        return cached;
+    }
+
+    let mut entries = Vec::new();
+    for (level, text, anchor) in extract_headings(content) {
+        if level <= max_depth {
+            nest_toc_entry(
+                &mut entries,
+                TocEntry {
+                    level,
+                    text,
+                    anchor,
+                    children: Vec::new(),
+                },
+            );
+        }
+    }
+    let val = minijinja::Value::from_serialize(&entries);
+    state.toc_cache.put(key, val.clone());
+    val
+}
+
+fn nest_toc_entry(siblings: &mut Vec<TocEntry>, entry: TocEntry) {
+    match siblings.last_mut() {
+        Some(last) if last.level < entry.level => nest_toc_entry(&mut last.children, entry),
+        _ => siblings.push(entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// A state whose only content is `posts/`, holding `(stem, date)` pages.
+    fn state_with_posts(posts: &[(&str, &str)]) -> (tempfile::TempDir, ServerState) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("posts")).unwrap();
+        for (stem, date) in posts {
+            fs::write(
+                dir.path().join("posts").join(format!("{}.md", stem)),
+                format!(
+                    "---\ntitle: \"Post {}\"\ndate: \"{}\"\n---\n*{}* body",
+                    stem, date, stem
+                ),
+            )
+            .unwrap();
+        }
+        let mut config = Config::default();
+        config.paths.content_dir = dir.path().to_string_lossy().into_owned();
+        config.server.base_url = "https://example.com".into();
+        config.server.name = "Example".into();
+        (dir, ServerState::new(config))
+    }
+
+    fn link(value: &minijinja::Value, which: &str) -> Option<(String, String)> {
+        let link = value.get_attr(which).unwrap();
+        if link.is_none() {
+            return None;
+        }
+        Some((
+            link.get_attr("url").unwrap().to_string(),
+            link.get_attr("title").unwrap().to_string(),
+        ))
+    }
+
+    #[test]
+    fn json_feed_has_required_fields() {
+        let (_dir, state) = state_with_posts(&[("first", "2026-01-05"), ("second", "2026-02-10")]);
+        let feed: serde_json::Value =
+            serde_json::from_str(&json_feed(&state, "posts").to_string()).unwrap();
+
+        assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(feed["title"], "Example");
+        assert_eq!(feed["home_page_url"], "https://example.com/");
+        let items = feed["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+
+        let newest = &items[0];
+        assert_eq!(newest["id"], "https://example.com/posts/second");
+        assert_eq!(newest["url"], newest["id"]);
+        assert_eq!(newest["title"], "Post second");
+        assert_eq!(newest["date_published"], "2026-02-10T00:00:00Z");
+        assert_eq!(
+            newest["content_html"].as_str().unwrap().trim(),
+            "<p><em>second</em> body</p>"
+        );
+        assert_eq!(items[1]["date_published"], "2026-01-05T00:00:00Z");
+    }
+
+    #[test]
+    fn json_feed_of_missing_dir_is_empty_object() {
+        let (_dir, state) = state_with_posts(&[]);
+        assert_eq!(json_feed(&state, "../outside").to_string(), "{}");
+    }
+
+    #[test]
+    fn siblings_of_first_middle_and_last_posts() {
+        let (_dir, state) = state_with_posts(&[
+            ("a", "2026-01-01"),
+            ("b", "2026-02-01"),
+            ("c", "2026-03-01"),
+        ]);
+        let page = |stem: &str| ("/posts/".to_string() + stem, "Post ".to_string() + stem);
+
+        // list_dir order is newest first: c, b, a
+        let first = siblings(&state, "/posts/c", "posts");
+        assert_eq!(link(&first, "prev"), None);
+        assert_eq!(link(&first, "next"), Some(page("b")));
+
+        let middle = siblings(&state, "/posts/b", "posts");
+        assert_eq!(link(&middle, "prev"), Some(page("c")));
+        assert_eq!(link(&middle, "next"), Some(page("a")));
+
+        let last = siblings(&state, "/posts/a/", "posts");
+        assert_eq!(link(&last, "prev"), Some(page("b")));
+        assert_eq!(link(&last, "next"), None);
+    }
+
+    #[test]
+    fn siblings_of_unlisted_page_are_none() {
+        let (_dir, state) = state_with_posts(&[("a", "2026-01-01")]);
+        let value = siblings(&state, "/elsewhere", "posts");
+        assert_eq!(link(&value, "prev"), None);
+        assert_eq!(link(&value, "next"), None);
+    }
+}
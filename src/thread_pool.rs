@@ -1,28 +1,32 @@
-use crossbeam_deque::{Injector, Worker};
+use crossbeam_deque::{Injector, Stealer, Worker};
 use std::{
-    iter,
+    any::Any,
+    io, iter,
     panic::{AssertUnwindSafe, catch_unwind},
     sync::{
-        Arc, Condvar, Mutex,
+        Arc, Condvar, Mutex, RwLock,
         atomic::{AtomicUsize, Ordering},
     },
     thread,
+    time::Duration,
 };
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// Wake-up tokens for parked workers, capped at the number of live workers so a burst of
+/// jobs can't bank wake-ups for threads that don't exist.
 struct Parker {
     tokens: Mutex<usize>,
     condvar: Condvar,
-    max_tokens: usize,
+    max_tokens: AtomicUsize,
 }
 
 impl Parker {
-    fn new(max_tokens: usize) -> Self {
+    fn new() -> Self {
         Self {
             tokens: Mutex::new(0),
             condvar: Condvar::new(),
-            max_tokens,
+            max_tokens: AtomicUsize::new(0),
         }
     }
 
@@ -34,105 +38,386 @@ impl Parker {
         *tokens -= 1;
     }
 
+    /// Like `wait`, but gives up after `timeout`; returns whether a token was taken.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut tokens, _) = self
+            .condvar
+            .wait_timeout_while(tokens, timeout, |tokens| *tokens == 0)
+            .unwrap_or_else(|e| e.into_inner());
+        if *tokens == 0 {
+            return false;
+        }
+        *tokens -= 1;
+        true
+    }
+
     fn notify_one(&self) {
         let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
-        if *tokens < self.max_tokens {
+        if *tokens < self.max_tokens.load(Ordering::SeqCst) {
             *tokens += 1;
             self.condvar.notify_one();
         }
     }
+
+    fn set_max_tokens(&self, max: usize) {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        self.max_tokens.store(max, Ordering::SeqCst);
+        *tokens = (*tokens).min(max);
+    }
 }
 
-pub struct ThreadPool {
-    injector: Arc<Injector<Job>>,
-    parker: Arc<Parker>,
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+/// State the pool handle shares with its workers.
+struct Shared {
+    injector: Injector<Job>,
+    parker: Parker,
     pending: Arc<AtomicUsize>,
+    /// One slot per possible worker; a retired worker's slot is emptied and reused.
+    stealers: RwLock<Vec<Option<Stealer<Job>>>>,
+    live: AtomicUsize,
+    busy: AtomicUsize,
+    min: usize,
+    max: usize,
+    idle_timeout: Duration,
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
     queue_size: usize,
 }
 
 impl ThreadPool {
-    pub fn new(size: usize, queue_size: usize) -> Self {
-        let injector = Arc::new(Injector::<Job>::new());
-        let parker = Arc::new(Parker::new(size));
-        let pending = Arc::new(AtomicUsize::new(0));
-
-        let mut workers = Vec::with_capacity(size);
-        let mut stealers = Vec::with_capacity(size);
-
-        for _ in 0..size {
-            let worker = Worker::new_fifo();
-            stealers.push(worker.stealer());
-            workers.push(worker);
-        }
+    /// Starts `min` workers and lets the pool grow to `max` while jobs queue up behind busy
+    /// workers; those above `min` exit after `idle_timeout` without work. With `min == max`
+    /// the pool stays at a fixed size. Running short of threads is tolerated with a warning;
+    /// only failing to start any worker at all is an error. A `max` of 0 builds an inline pool
+    /// that runs every job on the calling thread.
+    pub fn new(
+        min: usize,
+        max: usize,
+        queue_size: usize,
+        idle_timeout: Duration,
+    ) -> io::Result<Self> {
+        let min = min.min(max);
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            parker: Parker::new(),
+            pending: Arc::new(AtomicUsize::new(0)),
+            stealers: RwLock::new(vec![None; max]),
+            live: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+            min,
+            max,
+            idle_timeout,
+        });
 
-        for worker in workers {
-            let injector = Arc::clone(&injector);
-            let parker = Arc::clone(&parker);
-            let stealers = stealers.clone();
-            let pending = Arc::clone(&pending);
-
-            thread::spawn(move || {
-                loop {
-                    let task = worker.pop().or_else(|| {
-                        iter::repeat_with(|| {
-                            injector.steal_batch_and_pop(&worker).or_else(|| {
-                                stealers
-                                    .iter()
-                                    .map(|s| s.steal())
-                                    .find(|s| !s.is_empty())
-                                    .unwrap_or(crossbeam_deque::Steal::Empty)
-                            })
-                        })
-                        .find(|s| !s.is_retry())
-                        .and_then(|s| s.success())
-                    });
-
-                    match task {
-                        Some(task) => {
-                            pending.fetch_sub(1, Ordering::SeqCst);
-                            if let Err(err) = catch_unwind(AssertUnwindSafe(|| {
-                                task();
-                            })) {
-                                tracing::error!("Worker thread panicked: {:?}", err);
-                            }
-                        }
-                        None => {
-                            let mut spun = false;
-                            for _ in 0..64 {
-                                if pending.load(Ordering::Relaxed) > 0 {
-                                    spun = true;
-                                    break;
-                                }
-                                std::hint::spin_loop();
-                            }
-                            if !spun {
-                                parker.wait();
-                            }
-                        }
-                    }
+        for _ in 0..min {
+            if let Err(e) = spawn_worker(&shared) {
+                tracing::warn!("Failed to spawn worker thread: {}", e);
+                if shared.live.load(Ordering::SeqCst) == 0 {
+                    return Err(e);
                 }
-            });
+            }
         }
 
-        Self {
-            injector,
-            parker,
-            pending,
-            queue_size,
+        let live = shared.live.load(Ordering::SeqCst);
+        if live < min {
+            tracing::warn!(
+                "Thread pool running with {} of {} configured workers",
+                live,
+                min
+            );
         }
+
+        Ok(Self { shared, queue_size })
+    }
+
+    /// Workers currently running.
+    pub fn workers(&self) -> usize {
+        self.shared.live.load(Ordering::SeqCst)
+    }
+
+    /// The most workers the pool will grow to.
+    pub fn max_workers(&self) -> usize {
+        self.shared.max
+    }
+
+    /// Shared count of queued jobs not yet picked up by a worker.
+    pub fn pending_jobs(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.shared.pending)
     }
 
     pub fn execute<F>(&self, f: F) -> Result<(), std::sync::mpsc::TrySendError<Job>>
     where
         F: FnOnce() + Send + 'static,
     {
-        if self.pending.load(Ordering::Relaxed) >= self.queue_size {
+        let shared = &self.shared;
+        if shared.max == 0 {
+            if let Err(err) = catch_unwind(AssertUnwindSafe(f)) {
+                tracing::error!("Inline job panicked: {}", panic_message(err.as_ref()));
+            }
+            return Ok(());
+        }
+
+        if shared.pending.load(Ordering::Relaxed) >= self.queue_size {
             return Err(std::sync::mpsc::TrySendError::Full(Box::new(f)));
         }
 
-        self.pending.fetch_add(1, Ordering::SeqCst);
-        self.injector.push(Box::new(f));
-        self.parker.notify_one();
+        let pending = shared.pending.fetch_add(1, Ordering::SeqCst) + 1;
+        shared.injector.push(Box::new(f));
+        // every worker is busy and this job would wait behind them
+        let live = shared.live.load(Ordering::SeqCst);
+        if live < shared.max
+            && shared.busy.load(Ordering::SeqCst) + pending > live
+            && let Err(e) = spawn_worker(shared)
+        {
+            tracing::warn!("Failed to grow thread pool past {} workers: {}", live, e);
+        }
+        shared.parker.notify_one();
         Ok(())
     }
 }
+
+#[cfg(test)]
+thread_local! {
+    /// How many more workers this thread may spawn before `spawn_worker` fails, so tests can
+    /// reach the paths for a system out of threads.
+    static SPAWNS_ALLOWED: std::cell::Cell<usize> = const { std::cell::Cell::new(usize::MAX) };
+}
+
+fn spawn_worker(shared: &Arc<Shared>) -> io::Result<()> {
+    #[cfg(test)]
+    if SPAWNS_ALLOWED.with(|left| left.replace(left.get().saturating_sub(1))) == 0 {
+        return Err(io::Error::other("thread limit reached"));
+    }
+    let worker = Worker::new_fifo();
+    let slot = {
+        let mut stealers = shared.stealers.write().unwrap_or_else(|e| e.into_inner());
+        let Some(slot) = stealers.iter().position(Option::is_none) else {
+            return Ok(());
+        };
+        stealers[slot] = Some(worker.stealer());
+        slot
+    };
+    let live = shared.live.fetch_add(1, Ordering::SeqCst) + 1;
+    shared.parker.set_max_tokens(live);
+
+    let builder = thread::Builder::new().name(format!("lumen-worker-{}", slot));
+    let worker_shared = Arc::clone(shared);
+    let spawned = builder.spawn(move || run_worker(&worker_shared, worker, slot));
+    if let Err(e) = spawned {
+        retire(shared, slot);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn run_worker(shared: &Shared, worker: Worker<Job>, slot: usize) {
+    let elastic = shared.min < shared.max;
+    loop {
+        let task = worker.pop().or_else(|| {
+            iter::repeat_with(|| {
+                shared.injector.steal_batch_and_pop(&worker).or_else(|| {
+                    shared
+                        .stealers
+                        .read()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .iter()
+                        .flatten()
+                        .map(|s| s.steal())
+                        .find(|s| !s.is_empty())
+                        .unwrap_or(crossbeam_deque::Steal::Empty)
+                })
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        });
+
+        match task {
+            Some(task) => {
+                shared.pending.fetch_sub(1, Ordering::SeqCst);
+                shared.busy.fetch_add(1, Ordering::SeqCst);
+                if let Err(err) = catch_unwind(AssertUnwindSafe(|| {
+                    task();
+                })) {
+                    // the default panic hook has already printed the backtrace
+                    // to stderr when RUST_BACKTRACE is set
+                    tracing::error!(
+                        "Worker thread '{}' panicked: {}",
+                        thread::current().name().unwrap_or("unnamed"),
+                        panic_message(err.as_ref())
+                    );
+                }
+                shared.busy.fetch_sub(1, Ordering::SeqCst);
+            }
+            None => {
+                let mut spun = false;
+                for _ in 0..64 {
+                    if shared.pending.load(Ordering::Relaxed) > 0 {
+                        spun = true;
+                        break;
+                    }
+                    std::hint::spin_loop();
+                }
+                if spun {
+                    continue;
+                }
+                if !elastic {
+                    shared.parker.wait();
+                } else if !shared.parker.wait_timeout(shared.idle_timeout) {
+                    // only leave with nothing queued, and never below `min`
+                    let left =
+                        shared
+                            .live
+                            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                                (live > shared.min && shared.pending.load(Ordering::SeqCst) == 0)
+                                    .then(|| live - 1)
+                            });
+                    if left.is_ok() {
+                        release_slot(shared, slot);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Undoes `spawn_worker`'s bookkeeping for a thread that never started.
+fn retire(shared: &Shared, slot: usize) {
+    shared.live.fetch_sub(1, Ordering::SeqCst);
+    release_slot(shared, slot);
+}
+
+/// Frees a departed worker's slot. Its deque is empty by then, so nothing is lost with it.
+fn release_slot(shared: &Shared, slot: usize) {
+    shared
+        .parker
+        .set_max_tokens(shared.live.load(Ordering::SeqCst));
+    shared.stealers.write().unwrap_or_else(|e| e.into_inner())[slot] = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    const IDLE: Duration = Duration::from_secs(30);
+
+    fn allow_spawns(n: usize) {
+        SPAWNS_ALLOWED.with(|left| left.set(n));
+    }
+
+    #[test]
+    fn fails_when_no_worker_starts() {
+        allow_spawns(0);
+        let err = ThreadPool::new(2, 2, 16, IDLE)
+            .err()
+            .expect("pool without workers");
+        assert_eq!(err.to_string(), "thread limit reached");
+    }
+
+    #[test]
+    fn reports_a_partial_pool_and_still_runs_jobs() {
+        allow_spawns(2);
+        let pool = ThreadPool::new(4, 4, 16, IDLE).unwrap();
+        assert_eq!(pool.workers(), 2);
+        assert_eq!(pool.max_workers(), 4);
+
+        let (tx, rx) = mpsc::channel();
+        for i in 0..8 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).unwrap();
+        }
+        let mut done: Vec<i32> = (0..8)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+        // growth past the failed spawns was refused too, without losing the jobs
+        assert_eq!(pool.workers(), 2);
+    }
+
+    #[test]
+    fn starts_every_configured_worker() {
+        let pool = ThreadPool::new(3, 3, 16, IDLE).unwrap();
+        assert_eq!(pool.workers(), 3);
+    }
+
+    fn wait_until(check: impl Fn() -> bool) -> bool {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::time::Instant::now() < deadline {
+            if check() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        check()
+    }
+
+    #[test]
+    fn grows_to_max_under_backlog_and_shrinks_back_to_min() {
+        let pool = ThreadPool::new(1, 4, 64, Duration::from_millis(50)).unwrap();
+        assert_eq!(pool.workers(), 1);
+
+        // jobs that hold their worker until released, more of them than `max`
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let (started_tx, started_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        for i in 0..6 {
+            let (release_rx, started_tx, done_tx) =
+                (Arc::clone(&release_rx), started_tx.clone(), done_tx.clone());
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                release_rx.lock().unwrap().recv().unwrap();
+                done_tx.send(i).unwrap();
+            })
+            .unwrap();
+        }
+        for _ in 0..4 {
+            started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(pool.workers(), 4);
+        assert!(started_rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        for _ in 0..6 {
+            release_tx.send(()).unwrap();
+        }
+        let mut done: Vec<i32> = (0..6)
+            .map(|_| done_rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        done.sort();
+        assert_eq!(done, (0..6).collect::<Vec<_>>());
+
+        assert!(
+            wait_until(|| pool.workers() == 1),
+            "{} workers",
+            pool.workers()
+        );
+        // the survivor still takes work, and the pool can grow again
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn a_fixed_pool_keeps_its_idle_workers() {
+        let pool = ThreadPool::new(2, 2, 16, Duration::from_millis(10)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(pool.workers(), 2);
+    }
+}
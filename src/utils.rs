@@ -1,8 +1,18 @@
-use pulldown_cmark::{Options, Parser, html};
-use std::collections::BTreeMap;
+use percent_encoding::percent_decode_str;
+#[cfg(feature = "markdown")]
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd, html};
+#[cfg(feature = "markdown")]
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+#[cfg(feature = "markdown")]
 use yaml_rust2::{Yaml, YamlLoader};
 
+#[cfg(feature = "markdown")]
+use crate::config::{HeadingIds, MarkdownConfig};
+
 pub fn secure_join(base: &Path, user_path: &str) -> Option<PathBuf> {
     let mut result = base.to_path_buf();
     for component in Path::new(user_path).components() {
@@ -55,6 +65,7 @@ pub fn escape_html(input: &str) -> String {
     String::from_utf8_lossy(&escaped).into_owned()
 }
 
+#[cfg(feature = "markdown")]
 fn yaml_to_minijinja(yaml: Yaml) -> minijinja::Value {
     match yaml {
         Yaml::String(s) => minijinja::Value::from(s),
@@ -81,69 +92,510 @@ fn yaml_to_minijinja(yaml: Yaml) -> minijinja::Value {
     }
 }
 
-pub fn split_frontmatter(content: &str) -> (BTreeMap<String, minijinja::Value>, &str) {
-    let mut meta = BTreeMap::new();
-    meta.insert("title".to_string(), minijinja::Value::from("Lumen Page"));
+#[cfg(feature = "markdown")]
+fn toml_to_minijinja(value: toml::Value) -> minijinja::Value {
+    match value {
+        toml::Value::String(s) => minijinja::Value::from(s),
+        toml::Value::Integer(i) => minijinja::Value::from(i),
+        toml::Value::Float(f) => minijinja::Value::from(f),
+        toml::Value::Boolean(b) => minijinja::Value::from(b),
+        // kept as written, the same string a quoted YAML date would give
+        toml::Value::Datetime(d) => minijinja::Value::from(d.to_string()),
+        toml::Value::Array(a) => {
+            let vec: Vec<_> = a.into_iter().map(toml_to_minijinja).collect();
+            minijinja::Value::from(vec)
+        }
+        toml::Value::Table(t) => {
+            let map: BTreeMap<_, _> = t
+                .into_iter()
+                .map(|(k, v)| (k, toml_to_minijinja(v)))
+                .collect();
+            minijinja::Value::from(map)
+        }
+    }
+}
+
+#[cfg(feature = "markdown")]
+fn json_to_minijinja(value: serde_json::Value) -> minijinja::Value {
+    match value {
+        serde_json::Value::String(s) => minijinja::Value::from(s),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => minijinja::Value::from(i),
+            None => minijinja::Value::from(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::Bool(b) => minijinja::Value::from(b),
+        serde_json::Value::Array(a) => {
+            let vec: Vec<_> = a.into_iter().map(json_to_minijinja).collect();
+            minijinja::Value::from(vec)
+        }
+        serde_json::Value::Object(o) => {
+            let map: BTreeMap<_, _> = o
+                .into_iter()
+                .map(|(k, v)| (k, json_to_minijinja(v)))
+                .collect();
+            minijinja::Value::from(map)
+        }
+        serde_json::Value::Null => minijinja::Value::from(()),
+    }
+}
 
+#[cfg(feature = "markdown")]
+#[derive(Clone, Copy, PartialEq)]
+enum FrontmatterFormat {
+    /// Fenced by `---`.
+    Yaml,
+    /// Fenced by `+++`.
+    Toml,
+    /// Fenced by `;;;`, or a bare leading object; only with `markdown.json_frontmatter`.
+    Json,
+}
+
+/// Splits a leading `---` (YAML), `+++` (TOML) or, when `json` is set, `;;;` or bare `{...}`
+/// (JSON) block from the body, returning the raw frontmatter text and its format if present.
+#[cfg(feature = "markdown")]
+fn frontmatter_block(content: &str, json: bool) -> (Option<(FrontmatterFormat, &str)>, &str) {
     let content = content.strip_prefix('\u{FEFF}').unwrap_or(content);
-    let mut body = content;
-    let bytes = content.as_bytes();
-
-    if bytes.starts_with(b"---\n") || bytes.starts_with(b"---\r\n") {
-        let after_start = if bytes[3] == b'\r' { 5 } else { 4 };
-        if let Some(end_idx) = content[after_start..].find("\n---") {
-            let fm_str = &content[after_start..after_start + end_idx];
-
-            if let Ok(mut docs) = YamlLoader::load_from_str(fm_str)
-                && !docs.is_empty()
-                && let Yaml::Hash(hash) = docs.remove(0)
-            {
-                for (k, v) in hash {
-                    if let Yaml::String(k_str) = k {
-                        meta.insert(k_str, yaml_to_minijinja(v));
-                    }
-                }
-            }
+    if json && content.starts_with('{') {
+        // the object ends wherever the parser stops; a page that only looks like it starts
+        // with one keeps its text
+        let mut values =
+            serde_json::Deserializer::from_str(content).into_iter::<serde_json::Value>();
+        if let Some(Ok(serde_json::Value::Object(_))) = values.next() {
+            let end = values.byte_offset();
+            return (
+                Some((FrontmatterFormat::Json, &content[..end])),
+                content[end..].trim_start(),
+            );
+        }
+    }
+    for (fence, format) in [
+        ("---", FrontmatterFormat::Yaml),
+        ("+++", FrontmatterFormat::Toml),
+        (";;;", FrontmatterFormat::Json),
+    ] {
+        if format == FrontmatterFormat::Json && !json {
+            continue;
+        }
+        let Some(after_fence) = content.strip_prefix(fence) else {
+            continue;
+        };
+        let Some(rest) = after_fence
+            .strip_prefix("\r\n")
+            .or_else(|| after_fence.strip_prefix('\n'))
+        else {
+            continue;
+        };
+        let closing = format!("\n{}", fence);
+        let Some(end_idx) = rest.find(&closing) else {
+            continue;
+        };
+        // with CRLF line endings the closing fence leaves a lone `\r`, which TOML rejects
+        let fm = rest[..end_idx]
+            .strip_suffix('\r')
+            .unwrap_or(&rest[..end_idx]);
+        let remainder = &rest[end_idx + closing.len()..];
+        let body = remainder
+            .strip_prefix("\r\n")
+            .or_else(|| remainder.strip_prefix('\n'))
+            .unwrap_or(remainder);
+        return (Some((format, fm)), body.trim_start());
+    }
+    (None, content.trim_start())
+}
 
-            let remainder = &content[after_start + end_idx..];
-            let rem_bytes = remainder.as_bytes();
+#[cfg(feature = "markdown")]
+fn parse_frontmatter(
+    format: FrontmatterFormat,
+    fm_str: &str,
+    meta: &mut BTreeMap<String, minijinja::Value>,
+) -> Result<(), String> {
+    match format {
+        FrontmatterFormat::Yaml => parse_yaml_frontmatter(fm_str, meta),
+        FrontmatterFormat::Toml => {
+            let table = toml::from_str::<toml::Table>(fm_str).map_err(|e| e.to_string())?;
+            for (k, v) in table {
+                meta.insert(k, toml_to_minijinja(v));
+            }
+            Ok(())
+        }
+        FrontmatterFormat::Json => {
+            let object = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(fm_str)
+                .map_err(|e| e.to_string())?;
+            for (k, v) in object {
+                meta.insert(k, json_to_minijinja(v));
+            }
+            Ok(())
+        }
+    }
+}
 
-            if rem_bytes.starts_with(b"\n---\r\n") {
-                body = &remainder[6..];
-            } else if rem_bytes.starts_with(b"\n---\n") {
-                body = &remainder[5..];
-            } else {
-                body = &remainder[4..];
+#[cfg(feature = "markdown")]
+fn parse_yaml_frontmatter(
+    fm_str: &str,
+    meta: &mut BTreeMap<String, minijinja::Value>,
+) -> Result<(), String> {
+    let mut docs = YamlLoader::load_from_str(fm_str).map_err(|e| e.to_string())?;
+    if docs.is_empty() {
+        return Ok(());
+    }
+    match docs.remove(0) {
+        Yaml::Hash(hash) => {
+            for (k, v) in hash {
+                if let Yaml::String(k_str) = k {
+                    meta.insert(k_str, yaml_to_minijinja(v));
+                }
             }
+            Ok(())
         }
+        Yaml::Null => Ok(()),
+        _ => Err("frontmatter is not a key/value mapping".into()),
+    }
+}
+
+#[cfg(feature = "markdown")]
+fn default_meta() -> BTreeMap<String, minijinja::Value> {
+    let mut meta = BTreeMap::new();
+    meta.insert("title".to_string(), minijinja::Value::from("Lumen Page"));
+    meta
+}
+
+/// Like `split_frontmatter`, but reports malformed frontmatter instead of ignoring it.
+#[cfg(feature = "markdown")]
+pub fn try_split_frontmatter<'a>(
+    content: &'a str,
+    config: &MarkdownConfig,
+) -> Result<(BTreeMap<String, minijinja::Value>, &'a str), String> {
+    let (fm, body) = frontmatter_block(content, config.json_frontmatter);
+    let mut meta = default_meta();
+    if let Some((format, fm_str)) = fm {
+        parse_frontmatter(format, fm_str, &mut meta)?;
     }
+    Ok((meta, body))
+}
 
-    (meta, body.trim_start())
+#[cfg(feature = "markdown")]
+pub fn split_frontmatter<'a>(
+    content: &'a str,
+    config: &MarkdownConfig,
+) -> (BTreeMap<String, minijinja::Value>, &'a str) {
+    let (fm, body) = frontmatter_block(content, config.json_frontmatter);
+    let mut meta = default_meta();
+    if let Some((format, fm_str)) = fm {
+        let mut parsed = default_meta();
+        if parse_frontmatter(format, fm_str, &mut parsed).is_ok() {
+            meta = parsed;
+        }
+    }
+    (meta, body)
 }
 
-pub fn markdown_to_html(body: &str) -> String {
+#[cfg(feature = "markdown")]
+pub fn markdown_options(config: &MarkdownConfig) -> Options {
     let mut options = Options::empty();
-    options.insert(
-        Options::ENABLE_TABLES
-            | Options::ENABLE_STRIKETHROUGH
-            | Options::ENABLE_TASKLISTS
-            | Options::ENABLE_SMART_PUNCTUATION,
-    );
+    for (enabled, flag) in [
+        (config.tables, Options::ENABLE_TABLES),
+        (config.footnotes, Options::ENABLE_FOOTNOTES),
+        (config.strikethrough, Options::ENABLE_STRIKETHROUGH),
+        (config.tasklists, Options::ENABLE_TASKLISTS),
+        (config.smart_punctuation, Options::ENABLE_SMART_PUNCTUATION),
+        (
+            config.heading_attributes,
+            Options::ENABLE_HEADING_ATTRIBUTES,
+        ),
+        (config.math, Options::ENABLE_MATH),
+        (config.gfm, Options::ENABLE_GFM),
+        (config.definition_list, Options::ENABLE_DEFINITION_LIST),
+        (config.superscript, Options::ENABLE_SUPERSCRIPT),
+        (config.subscript, Options::ENABLE_SUBSCRIPT),
+        (config.wikilinks, Options::ENABLE_WIKILINKS),
+    ] {
+        options.set(flag, enabled);
+    }
+    options
+}
 
+#[cfg(feature = "markdown")]
+pub fn markdown_to_html(body: &str, options: Options, heading_ids: HeadingIds) -> String {
     let parser = Parser::new_ext(body, options);
     let mut html_buf = String::with_capacity(body.len() * 2);
-    html::push_html(&mut html_buf, parser);
+    if heading_ids == HeadingIds::Off {
+        html::push_html(&mut html_buf, parser);
+    } else {
+        let events = with_heading_ids(parser, heading_ids == HeadingIds::Permalinks);
+        html::push_html(&mut html_buf, events.into_iter());
+    }
     html_buf
 }
 
-pub fn get_mime_type(path: &Path) -> String {
+/// Lowercases, turns whitespace runs into `-` and drops punctuation, so the same heading
+/// text always yields the same id.
+#[cfg(feature = "markdown")]
+fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() || c == '-' || c == '_' {
+            slug.extend(c.to_lowercase());
+        } else if c.is_whitespace() && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Sets an id on every heading that lacks one, suffixing `-1`, `-2`... when a slug is already
+/// taken in the document. Explicit `{#id}` attributes are kept and reserved first.
+#[cfg(feature = "markdown")]
+fn with_heading_ids<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    permalinks: bool,
+) -> Vec<Event<'a>> {
+    let events: Vec<Event<'a>> = events.collect();
+    let mut used: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(events.len());
+    let mut heading: Option<(usize, String)> = None;
+    for event in events {
+        match &event {
+            Event::Start(Tag::Heading { .. }) => heading = Some((out.len(), String::new())),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading_text)) = heading.as_mut() {
+                    heading_text.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((start, text)) = heading.take()
+                    && let Event::Start(Tag::Heading { id, .. }) = &mut out[start]
+                {
+                    let anchor = match id {
+                        Some(id) => id.to_string(),
+                        None => {
+                            let slug = unique_slug(&text, &mut used);
+                            *id = Some(slug.clone().into());
+                            slug
+                        }
+                    };
+                    if permalinks {
+                        out.push(Event::InlineHtml(
+                            format!(
+                                "<a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                                escape_html(&anchor)
+                            )
+                            .into(),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        out.push(event);
+    }
+    out
+}
+
+/// The `(level, text, id)` of each `<h1>`..`<h6>` in rendered HTML, in document order. Tags
+/// inside a heading are dropped, along with any `heading_ids` permalink.
+#[cfg(feature = "markdown")]
+pub fn extract_headings(html: &str) -> Vec<(u8, String, Option<String>)> {
+    let mut headings = Vec::new();
+    let mut rest = html;
+    while let Some(pos) = rest.find("<h") {
+        rest = &rest[pos + 2..];
+        let Some(level) = rest
+            .bytes()
+            .next()
+            .filter(|b| (b'1'..=b'6').contains(b))
+            .map(|b| b - b'0')
+        else {
+            continue;
+        };
+        let tag = &rest[1..];
+        if !tag.starts_with(['>', ' ']) {
+            continue;
+        }
+        let Some(open_end) = tag.find('>') else {
+            break;
+        };
+        let close = format!("</h{}>", level);
+        let inner_start = open_end + 1;
+        let Some(inner_len) = tag[inner_start..].find(&close) else {
+            break;
+        };
+        let id = attribute(&tag[..open_end], "id").map(unescape_html);
+        let text = heading_text(&tag[inner_start..inner_start + inner_len]);
+        headings.push((level, text, id));
+        rest = &tag[inner_start + inner_len + close.len()..];
+    }
+    headings
+}
+
+#[cfg(feature = "markdown")]
+fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!(" {}=\"", name);
+    let start = attrs.find(&needle)? + needle.len();
+    let len = attrs[start..].find('"')?;
+    Some(&attrs[start..start + len])
+}
+
+#[cfg(feature = "markdown")]
+fn heading_text(inner: &str) -> String {
+    let mut text = String::with_capacity(inner.len());
+    let mut rest = inner;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+        if rest.starts_with("<a class=\"anchor\"")
+            && let Some(end) = rest.find("</a>")
+        {
+            rest = &rest[end + 4..];
+            continue;
+        }
+        match rest.find('>') {
+            Some(end) => rest = &rest[end + 1..],
+            None => rest = "",
+        }
+    }
+    text.push_str(rest);
+    unescape_html(text.trim())
+}
+
+/// Reverses `escape_html` and the escaping the markdown renderer applies.
+#[cfg(feature = "markdown")]
+fn unescape_html(input: &str) -> String {
+    input
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(feature = "markdown")]
+fn unique_slug(text: &str, used: &mut HashSet<String>) -> String {
+    let base = slugify(text);
+    let mut slug = base.clone();
+    let mut n = 0;
+    while !used.insert(slug.clone()) {
+        n += 1;
+        slug = format!("{}-{}", base, n);
+    }
+    slug
+}
+
+/// Parses `Accept-Encoding` into lowercase `(coding, q)` pairs, highest q first. Entries with
+/// `q=0` are kept so an explicit refusal can be told apart from an omission.
+pub fn parse_accept_encoding(header: &str) -> Vec<(String, f32)> {
+    let mut codings: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q=").or(p.trim().strip_prefix("Q=")))
+                .find_map(|v| v.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some((coding, q))
+        })
+        .collect();
+    codings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    codings
+}
+
+/// The q-value the client gives `coding`: its own entry, else `*`, else 1 for `identity`
+/// (which is acceptable unless refused) and 0 for anything else.
+pub fn encoding_quality(accepted: &[(String, f32)], coding: &str) -> f32 {
+    accepted
+        .iter()
+        .find(|(c, _)| c == coding)
+        .or_else(|| accepted.iter().find(|(c, _)| c == "*"))
+        .map(|(_, q)| *q)
+        .unwrap_or(if coding == "identity" { 1.0 } else { 0.0 })
+}
+
+/// Whether an `Accept` header ranks `text/plain` above `text/html`, counting `text/*` and
+/// `*/*` for types it doesn't name.
+pub fn prefers_plain_text(accept: &str) -> bool {
+    let types = parse_accept_encoding(accept);
+    let quality = |media: &str| {
+        [media, "text/*", "*/*"]
+            .iter()
+            .find_map(|name| types.iter().find(|(t, _)| t == name).map(|(_, q)| *q))
+            .unwrap_or(0.0)
+    };
+    let plain = quality("text/plain");
+    plain > 0.0 && plain > quality("text/html")
+}
+
+/// Parses a `Cookie` header into name/value pairs, unquoting values. The first occurrence
+/// of a name wins, matching how browsers order more specific cookies first.
+pub fn parse_cookies(header: &str) -> BTreeMap<String, String> {
+    let mut cookies = BTreeMap::new();
+    for pair in header.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        cookies
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+    cookies
+}
+
+/// Parses a query string into decoded name/value pairs, `+` read as a space. A bare `name`
+/// gets an empty value, and the first occurrence of a name wins.
+pub fn parse_query(query: &str) -> BTreeMap<String, String> {
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+    let mut params = BTreeMap::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params.entry(decode(name)).or_insert_with(|| decode(value));
+    }
+    params
+}
+
+/// `overrides` maps lowercase extensions (without the dot) to types taken over `mime_guess`.
+pub fn get_mime_type(path: &Path, overrides: &HashMap<String, String>) -> String {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    if let Some(mime) = ext.and_then(|ext| overrides.get(&ext)) {
+        return mime.clone();
+    }
     mime_guess::from_path(path)
         .first_or_octet_stream()
         .to_string()
 }
 
 pub fn is_compressible(mime: &str) -> bool {
-    let m = mime.to_lowercase();
+    let m = mime.split(';').next().unwrap_or(mime).trim().to_lowercase();
     m.starts_with("text/")
         || m.ends_with("xml")
         || m.ends_with("json")
@@ -151,3 +603,892 @@ pub fn is_compressible(mime: &str) -> bool {
         || m.contains("svg+xml")
         || m.contains("font")
 }
+
+/// Resident set size of this process, read from procfs.
+#[cfg(target_os = "linux")]
+pub fn resident_memory_bytes() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system setting
+    let page_size = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+    Some(pages * page_size)
+}
+
+/// Elsewhere there's no portable figure for current usage (`getrusage` only has the peak,
+/// which never falls back under a limit), so the monitor goes by the caches instead.
+#[cfg(not(target_os = "linux"))]
+pub fn resident_memory_bytes() -> Option<usize> {
+    None
+}
+
+/// FNV-1a, stable across restarts so content-derived ETags survive a redeploy.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn absolute_url(base_url: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Parses the date of a frontmatter date.
+pub fn parse_date(raw: &str) -> Option<(i64, u32, u32)> {
+    parse_datetime(raw).map(|dt| (dt.year, dt.month, dt.day))
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// A date and time as written in frontmatter; any UTC offset is dropped, not applied.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    fn days_since_epoch(&self) -> i64 {
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = i64::from((self.month + 9) % 12);
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// 0 for Monday through 6 for Sunday.
+    fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday
+        (self.days_since_epoch() + 3).rem_euclid(7) as usize
+    }
+}
+
+/// Parses the common ways of writing a date: `2026-03-01`, `2026-3-1`, `2026/03/01`,
+/// `1 March 2026` and `March 1, 2026` (month names may be abbreviated), optionally followed
+/// by a `HH:MM[:SS]` time after `T` or a space. Text after the date that isn't a time is
+/// ignored.
+pub fn parse_datetime(raw: &str) -> Option<DateTime> {
+    let raw = raw.trim();
+    let month_number = |name: &str| {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        (name.len() >= 3)
+            .then(|| {
+                MONTH_NAMES
+                    .iter()
+                    .position(|m| m.to_ascii_lowercase().starts_with(&name))
+            })
+            .flatten()
+            .map(|i| i as u32 + 1)
+    };
+
+    let (date, time) = match raw.split_once(['T', ' ']) {
+        Some((date, time)) if date.contains(['-', '/']) => (date, Some(time)),
+        _ if raw.contains(['-', '/']) => (raw, None),
+        // `1 March 2026` or `March 1, 2026`, possibly with a time after the year
+        _ => {
+            let words: Vec<&str> = raw
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|w| !w.is_empty())
+                .collect();
+            let (day, month, year) = match words.get(..3)? {
+                [d, m, y] if d.parse::<u32>().is_ok() => (*d, month_number(m)?, *y),
+                [m, d, y] => (*d, month_number(m)?, *y),
+                _ => return None,
+            };
+            let mut dt = DateTime {
+                year: year.parse().ok()?,
+                month,
+                day: day.parse().ok()?,
+                hour: 0,
+                minute: 0,
+                second: 0,
+            };
+            if let Some(time) = words.get(3).and_then(|t| parse_time(t)) {
+                (dt.hour, dt.minute, dt.second) = time;
+            }
+            return valid_datetime(dt);
+        }
+    };
+    let mut parts = date.splitn(3, ['-', '/']);
+    let mut dt = DateTime {
+        year: parts.next()?.parse().ok()?,
+        month: parts.next()?.parse().ok()?,
+        day: parts.next()?.parse().ok()?,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    if let Some(time) = time.and_then(parse_time) {
+        (dt.hour, dt.minute, dt.second) = time;
+    }
+    valid_datetime(dt)
+}
+
+/// `HH:MM` or `HH:MM:SS`, ignoring fractional seconds and any `Z` or `+HH:MM` offset.
+fn parse_time(raw: &str) -> Option<(u32, u32, u32)> {
+    let raw = raw.trim();
+    let end = raw
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(raw.len());
+    let mut parts = raw[..end].split(':');
+    let hour = parts.next()?.parse().ok()?;
+    let minute = parts.next()?.parse().ok()?;
+    let second = parts.next().map_or(Some(0), |s| s.parse().ok())?;
+    Some((hour, minute, second))
+}
+
+fn valid_datetime(dt: DateTime) -> Option<DateTime> {
+    let valid = (1..=12).contains(&dt.month)
+        && (1..=31).contains(&dt.day)
+        && dt.hour < 24
+        && dt.minute < 60
+        && dt.second < 61;
+    valid.then_some(dt)
+}
+
+/// Formats `dt` with strftime-style `%Y %y %m %-m %d %-d %e %B %b %A %a %H %I %p %M %S %j %%`
+/// conversions; anything else is copied through.
+pub fn format_datetime(dt: &DateTime, fmt: &str) -> String {
+    let month = MONTH_NAMES[(dt.month - 1) as usize];
+    let weekday = WEEKDAY_NAMES[dt.weekday()];
+    let mut out = String::with_capacity(fmt.len() + 16);
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = chars.next();
+        let unpadded = spec == Some('-');
+        if unpadded {
+            spec = chars.next();
+        }
+        let num = |n: u32| {
+            if unpadded {
+                n.to_string()
+            } else {
+                format!("{:02}", n)
+            }
+        };
+        match spec {
+            Some('Y') => out.push_str(&dt.year.to_string()),
+            Some('y') => out.push_str(&format!("{:02}", dt.year.rem_euclid(100))),
+            Some('m') => out.push_str(&num(dt.month)),
+            Some('d') => out.push_str(&num(dt.day)),
+            Some('e') => out.push_str(&format!("{:>2}", dt.day)),
+            Some('B') => out.push_str(month),
+            Some('b') => out.push_str(&month[..3]),
+            Some('A') => out.push_str(weekday),
+            Some('a') => out.push_str(&weekday[..3]),
+            Some('H') => out.push_str(&num(dt.hour)),
+            Some('I') => out.push_str(&num((dt.hour + 11) % 12 + 1)),
+            Some('p') => out.push_str(if dt.hour < 12 { "AM" } else { "PM" }),
+            Some('M') => out.push_str(&num(dt.minute)),
+            Some('S') => out.push_str(&num(dt.second)),
+            Some('j') => {
+                let jan1 = DateTime {
+                    month: 1,
+                    day: 1,
+                    ..*dt
+                };
+                let doy = (dt.days_since_epoch() - jan1.days_since_epoch() + 1) as u32;
+                out.push_str(&if unpadded {
+                    doy.to_string()
+                } else {
+                    format!("{:03}", doy)
+                });
+            }
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                if unpadded {
+                    out.push('-');
+                }
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // days-since-epoch to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Decodes standard (padded or unpadded) base64, as used by `Authorization: Basic`.
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for b in input.bytes() {
+        let v = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// Standard padded base64, as the WebSocket handshake's `Sec-WebSocket-Accept` uses.
+pub fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// SHA-1 (FIPS 180-4). Only for the WebSocket handshake, which is defined over it; it is no
+/// longer fit for anything that needs collision resistance.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in msg.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (state, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// SHA-256 (FIPS 180-4), for comparing credentials without keeping them in plaintext.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in msg.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *state = state.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 32];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// Equality whose running time doesn't depend on where the inputs first differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parses `addr/prefix`, or a bare address as a single host, into a network and mask over
+/// the IPv6 space, with IPv4 mapped into `::ffff:0:0/96`.
+pub fn parse_cidr(s: &str) -> Option<(u128, u128)> {
+    let (addr, prefix) = match s.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (s.trim(), None),
+    };
+    let addr: IpAddr = addr.parse().ok()?;
+    let width = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p.parse::<u32>().ok().filter(|p| *p <= width)?,
+        None => width,
+    } + (128 - width);
+    let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+    Some((ip_bits(addr) & mask, mask))
+}
+
+/// An address as the 128 bits `parse_cidr` matches against.
+pub fn ip_bits(ip: IpAddr) -> u128 {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accept_encoding_orders_by_quality() {
+        let table: &[(&str, &[(&str, f32)])] = &[
+            ("", &[]),
+            ("gzip", &[("gzip", 1.0)]),
+            ("gzip, br", &[("gzip", 1.0), ("br", 1.0)]),
+            ("gzip;q=0.5, br", &[("br", 1.0), ("gzip", 0.5)]),
+            ("GZIP;Q=0.3, Br;q=0.8", &[("br", 0.8), ("gzip", 0.3)]),
+            ("gzip;q=0, identity", &[("identity", 1.0), ("gzip", 0.0)]),
+            ("*;q=0.1, deflate ; q=0.9", &[("deflate", 0.9), ("*", 0.1)]),
+            ("br;q=7, gzip;q=-1", &[("br", 1.0), ("gzip", 0.0)]),
+            ("br;q=nope", &[("br", 1.0)]),
+            (" , gzip ,", &[("gzip", 1.0)]),
+        ];
+        for (header, expected) in table {
+            let expected: Vec<(String, f32)> =
+                expected.iter().map(|&(c, q)| (c.to_string(), q)).collect();
+            assert_eq!(parse_accept_encoding(header), expected, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn encoding_quality_applies_refusals_wildcards_and_identity() {
+        let table = [
+            ("gzip", "gzip", 1.0),
+            ("gzip", "br", 0.0),
+            ("gzip;q=0", "gzip", 0.0),
+            ("gzip", "identity", 1.0),
+            ("identity;q=0", "identity", 0.0),
+            ("*", "br", 1.0),
+            ("*;q=0.4", "deflate", 0.4),
+            ("*;q=0", "identity", 0.0),
+            ("*, br;q=0", "br", 0.0),
+            ("gzip;q=0.2, *", "gzip", 0.2),
+            ("Identity;q=0.5", "identity", 0.5),
+            ("", "identity", 1.0),
+            ("", "gzip", 0.0),
+        ];
+        for (header, coding, q) in table {
+            assert_eq!(
+                encoding_quality(&parse_accept_encoding(header), coding),
+                q,
+                "{:?} for {}",
+                header,
+                coding
+            );
+        }
+    }
+
+    #[test]
+    fn parse_cookies_splits_unquotes_and_keeps_the_first() {
+        let cookies = parse_cookies(r#"flag=beta; theme="dark" ;session=a=b; flag=old; =x; junk"#);
+        let expected: BTreeMap<String, String> =
+            [("flag", "beta"), ("theme", "dark"), ("session", "a=b")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        assert_eq!(cookies, expected);
+        assert!(parse_cookies("").is_empty());
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn default_markdown_options_keep_the_original_extensions() {
+        let defaults = markdown_options(&MarkdownConfig::default());
+        assert_eq!(
+            defaults,
+            Options::ENABLE_TABLES
+                | Options::ENABLE_STRIKETHROUGH
+                | Options::ENABLE_TASKLISTS
+                | Options::ENABLE_SMART_PUNCTUATION
+        );
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn markdown_flags_switch_their_extensions() {
+        let source = "Text[^1] and ~~gone~~.\n\n[^1]: The note.\n";
+        let render = |config: MarkdownConfig| {
+            markdown_to_html(source, markdown_options(&config), HeadingIds::Off)
+        };
+
+        let default = render(MarkdownConfig::default());
+        assert!(default.contains("<del>gone</del>"), "{}", default);
+        assert!(!default.contains("footnote-definition"), "{}", default);
+
+        let footnotes = render(MarkdownConfig {
+            footnotes: true,
+            strikethrough: false,
+            ..Default::default()
+        });
+        assert!(footnotes.contains("footnote-definition"), "{}", footnotes);
+        assert!(!footnotes.contains("<del>"), "{}", footnotes);
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn slugify_lowercases_and_drops_punctuation() {
+        for (text, slug) in [
+            ("Getting Started", "getting-started"),
+            ("  What's new in 2.0?  ", "whats-new-in-20"),
+            ("snake_case and kebab-case", "snake_case-and-kebab-case"),
+            ("Ünïcödé Héadings", "ünïcödé-héadings"),
+            ("!!!", "section"),
+        ] {
+            assert_eq!(slugify(text), slug, "{:?}", text);
+        }
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn heading_ids_are_unique_stable_and_respect_explicit_ids() {
+        let source = "# Intro\n\n## Setup\n\n## Setup\n\n## Setup-1 {#setup-1}\n\n### `Setup`\n";
+        let options = Options::ENABLE_HEADING_ATTRIBUTES;
+        let html = markdown_to_html(source, options, HeadingIds::Ids);
+        let ids: Vec<_> = extract_headings(&html)
+            .into_iter()
+            .map(|(_, _, id)| id.unwrap())
+            .collect();
+        // the explicit id is reserved before the duplicates are numbered around it
+        assert_eq!(ids, ["intro", "setup", "setup-2", "setup-1", "setup-3"]);
+        assert_eq!(markdown_to_html(source, options, HeadingIds::Ids), html);
+        assert!(!html.contains("class=\"anchor\""));
+
+        let linked = markdown_to_html("## Setup\n", options, HeadingIds::Permalinks);
+        assert!(
+            linked.contains("<a class=\"anchor\" href=\"#setup\""),
+            "{}",
+            linked
+        );
+        let plain = markdown_to_html("## Setup\n", options, HeadingIds::Off);
+        assert_eq!(plain, "<h2>Setup</h2>\n");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn extract_headings_reads_levels_text_and_ids() {
+        let html = concat!(
+            "<h1>Plain</h1><p>text</p><hr>",
+            "<h2 id=\"code\">The <code>main</code> fn</h2>",
+            "<h3 id=\"q&amp;a\">Q &amp; A<a class=\"anchor\" href=\"#q&amp;a\">#</a></h3>",
+            "<header>not a heading</header><h7>nor this</h7>",
+        );
+        assert_eq!(
+            extract_headings(html),
+            [
+                (1, "Plain".to_string(), None),
+                (2, "The main fn".to_string(), Some("code".to_string())),
+                (3, "Q & A".to_string(), Some("q&a".to_string())),
+            ]
+        );
+        assert!(extract_headings("<h2>never closed").is_empty());
+    }
+
+    #[test]
+    fn prefers_plain_text_needs_plain_ranked_above_html() {
+        for (accept, expected) in [
+            ("text/plain", true),
+            ("text/plain, text/html;q=0.5", true),
+            ("text/plain;q=0.5, */*;q=0.1", true),
+            ("text/html, text/plain", false),
+            ("text/html;q=0.9, text/plain;q=0.9", false),
+            ("text/*", false),
+            ("*/*", false),
+            ("text/plain;q=0", false),
+            ("", false),
+        ] {
+            assert_eq!(prefers_plain_text(accept), expected, "{:?}", accept);
+        }
+    }
+
+    #[test]
+    fn parse_cidr_masks_v4_and_v6_blocks() {
+        let v4 = |s: &str| ip_bits(s.parse().unwrap());
+        let (net, mask) = parse_cidr("10.1.2.3/8").unwrap();
+        assert_eq!(net, v4("10.0.0.0"));
+        assert_eq!(v4("10.255.0.1") & mask, net);
+        assert_ne!(v4("11.0.0.1") & mask, net);
+
+        let (net, mask) = parse_cidr(" 192.168.1.7 ").unwrap();
+        assert_eq!((net, mask), (v4("192.168.1.7"), u128::MAX));
+
+        let (net, mask) = parse_cidr("2001:db8::/32").unwrap();
+        assert_eq!(v4("2001:db8:ffff::1") & mask, net);
+        assert_ne!(v4("2001:db9::1") & mask, net);
+
+        // /0 covers its own family only
+        let (net, mask) = parse_cidr("0.0.0.0/0").unwrap();
+        assert_eq!(v4("203.0.113.9") & mask, net);
+        assert_ne!(v4("2001:db8::1") & mask, net);
+        assert_eq!(parse_cidr("::/0"), Some((0, 0)));
+
+        for bad in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "10.0.0.0/x",
+            "example.com",
+            "",
+        ] {
+            assert_eq!(parse_cidr(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn ip_bits_treats_v4_mapped_v6_as_v4() {
+        assert_eq!(
+            ip_bits("::ffff:127.0.0.1".parse().unwrap()),
+            ip_bits("127.0.0.1".parse().unwrap())
+        );
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn toml_frontmatter_gives_the_same_values_as_yaml() {
+        let config = MarkdownConfig::default();
+        let toml = "+++\ntitle = \"Hello\"\nweight = 3\ndraft = false\n\
+                    tags = [\"a\", \"b\"]\ndate = 2024-05-01\n[author]\nname = \"Ann\"\n+++\n\n# Body\n";
+        let yaml = "---\ntitle: Hello\nweight: 3\ndraft: false\n\
+                    tags: [a, b]\ndate: \"2024-05-01\"\nauthor:\n  name: Ann\n---\n\n# Body\n";
+        let (from_toml, body) = split_frontmatter(toml, &config);
+        assert_eq!(body, "# Body\n");
+        assert_eq!(from_toml, split_frontmatter(yaml, &config).0);
+        assert_eq!(from_toml["date"].as_str(), Some("2024-05-01"));
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn toml_frontmatter_accepts_crlf_fences() {
+        let config = MarkdownConfig::default();
+        let (meta, body) = split_frontmatter("+++\r\ntitle = \"Win\"\r\n+++\r\nText", &config);
+        assert_eq!(meta["title"].as_str(), Some("Win"));
+        assert_eq!(body, "Text");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn malformed_toml_frontmatter_falls_back_or_reports() {
+        let config = MarkdownConfig::default();
+        let page = "+++\ntitle = unquoted\n+++\nText";
+        let (meta, body) = split_frontmatter(page, &config);
+        assert_eq!(meta["title"].as_str(), Some("Lumen Page"));
+        assert_eq!(body, "Text");
+        assert!(try_split_frontmatter(page, &config).is_err());
+
+        // an unclosed fence is no frontmatter at all
+        let (meta, body) = split_frontmatter("+++\ntitle = \"x\"\n", &config);
+        assert_eq!(meta["title"].as_str(), Some("Lumen Page"));
+        assert!(body.starts_with("+++"));
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn json_frontmatter_is_read_only_when_enabled() {
+        let mut config = MarkdownConfig::default();
+        let bare = "{\"title\": \"Hi\", \"weight\": 2, \"tags\": [\"a\"]}\n\n# Body";
+        let fenced = ";;;\n{\"title\": \"Fenced\"}\n;;;\nText";
+
+        let (meta, body) = split_frontmatter(bare, &config);
+        assert_eq!(meta["title"].as_str(), Some("Lumen Page"));
+        assert_eq!(body, bare);
+        assert_eq!(split_frontmatter(fenced, &config).1, fenced);
+
+        config.json_frontmatter = true;
+        let (meta, body) = split_frontmatter(bare, &config);
+        assert_eq!(meta["title"].as_str(), Some("Hi"));
+        assert_eq!(i64::try_from(meta["weight"].clone()).ok(), Some(2));
+        assert_eq!(meta["tags"].len(), Some(1));
+        assert_eq!(body, "# Body");
+
+        let (meta, body) = split_frontmatter(fenced, &config);
+        assert_eq!(meta["title"].as_str(), Some("Fenced"));
+        assert_eq!(body, "Text");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn text_that_only_looks_like_json_stays_in_the_body() {
+        let config = MarkdownConfig {
+            json_frontmatter: true,
+            ..MarkdownConfig::default()
+        };
+        for page in ["{not json} here", "[1, 2]", "{\"unterminated\": "] {
+            let (meta, body) = split_frontmatter(page, &config);
+            assert_eq!(meta["title"].as_str(), Some("Lumen Page"), "{:?}", page);
+            assert_eq!(body, page);
+        }
+        let page = ";;;\n{\"title\": oops}\n;;;\nText";
+        assert!(try_split_frontmatter(page, &config).is_err());
+    }
+
+    #[test]
+    fn parse_query_decodes_pairs_and_keeps_the_first_value() {
+        let params = parse_query("page=2&q=a+b%26c&flag&page=3&&%C3%A9=%E2%9C%93");
+        assert_eq!(
+            params.into_iter().collect::<Vec<_>>(),
+            [
+                ("flag".to_string(), String::new()),
+                ("page".to_string(), "2".to_string()),
+                ("q".to_string(), "a b&c".to_string()),
+                ("é".to_string(), "✓".to_string()),
+            ]
+        );
+        assert!(parse_query("").is_empty());
+    }
+
+    #[test]
+    fn parse_datetime_reads_the_common_date_spellings() {
+        let day = |year, month, day| DateTime {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        for raw in [
+            "2026-03-01",
+            "2026-3-1",
+            "2026/03/01",
+            " 1 March 2026 ",
+            "1 Mar 2026",
+            "March 1, 2026",
+            "mar. 1 2026",
+        ] {
+            assert_eq!(parse_datetime(raw), Some(day(2026, 3, 1)), "{:?}", raw);
+        }
+        let with_time = DateTime {
+            hour: 14,
+            minute: 5,
+            second: 9,
+            ..day(2026, 3, 1)
+        };
+        for raw in [
+            "2026-03-01T14:05:09Z",
+            "2026-03-01 14:05:09.250+02:00",
+            "March 1, 2026 14:05:09",
+        ] {
+            assert_eq!(parse_datetime(raw), Some(with_time), "{:?}", raw);
+        }
+        assert_eq!(
+            parse_datetime("2026-03-01T14:05").map(|dt| (dt.hour, dt.minute, dt.second)),
+            Some((14, 5, 0))
+        );
+        for raw in [
+            "",
+            "soon",
+            "2026-13-01",
+            "2026-02-32",
+            "Smarch 1, 2026",
+            "2026-03",
+        ] {
+            assert_eq!(parse_datetime(raw), None, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn format_datetime_expands_strftime_conversions() {
+        let dt = parse_datetime("2026-03-01T14:05:09").unwrap();
+        assert_eq!(
+            format_datetime(&dt, "%A %a %B %b %d %-d %e %m %-m %Y %y"),
+            "Sunday Sun March Mar 01 1  1 03 3 2026 26"
+        );
+        assert_eq!(
+            format_datetime(&dt, "%H:%M:%S %I %p day %j, 100%% %q"),
+            "14:05:09 02 PM day 060, 100% %q"
+        );
+        let midnight = parse_datetime("2024-12-31").unwrap();
+        assert_eq!(format_datetime(&midnight, "%I%p %j %a"), "12AM 366 Tue");
+    }
+
+    #[test]
+    fn get_mime_type_prefers_overrides_by_lowercase_extension() {
+        let overrides: HashMap<String, String> = [
+            (
+                "webmanifest".to_string(),
+                "application/manifest+json".to_string(),
+            ),
+            ("txt".to_string(), "text/plain; charset=utf-8".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            get_mime_type(Path::new("site.WebManifest"), &overrides),
+            "application/manifest+json"
+        );
+        assert_eq!(
+            get_mime_type(Path::new("a/notes.txt"), &overrides),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            get_mime_type(Path::new("style.css"), &overrides),
+            "text/css"
+        );
+        assert_eq!(
+            get_mime_type(Path::new("blob.unknownext"), &overrides),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            get_mime_type(Path::new("README"), &HashMap::new()),
+            "application/octet-stream"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resident_memory_counts_whole_pages_of_the_host_size() {
+        let used = resident_memory_bytes().unwrap();
+        // SAFETY: sysconf only reads a system setting
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        assert!(used >= page_size);
+        assert_eq!(used % page_size, 0);
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{TestServer, wait_for};
+use lumen::config::AccessLogFormat;
+
+fn logged_lines(server: &TestServer) -> Vec<String> {
+    std::fs::read_to_string(server.dir.path().join("access.log"))
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn responses_are_written_to_the_access_log_in_common_format() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.access_log = root.join("access.log").to_string_lossy().into_owned();
+    });
+    assert_eq!(server.get("/hello.txt").status, 200);
+    assert_eq!(server.get("/missing").status, 404);
+
+    assert!(wait_for(|| logged_lines(&server).len() == 2));
+    let lines = logged_lines(&server);
+    assert!(lines[0].starts_with("127.0.0.1 - - ["), "{}", lines[0]);
+    assert!(
+        lines[0].contains("] \"GET /hello.txt HTTP/1.1\" 200 5 "),
+        "{}",
+        lines[0]
+    );
+    assert!(
+        lines[1].contains("\"GET /missing HTTP/1.1\" 404 "),
+        "{}",
+        lines[1]
+    );
+}
+
+#[test]
+fn the_json_format_writes_one_object_per_response() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.access_log = root.join("access.log").to_string_lossy().into_owned();
+        config.server.access_log_format = AccessLogFormat::Json;
+    });
+    let res = server.request("HEAD /hello.txt HTTP/1.0\r\n\r\n");
+    assert_eq!(res.status, 200);
+
+    assert!(wait_for(|| logged_lines(&server).len() == 1));
+    let entry: serde_json::Value = serde_json::from_str(&logged_lines(&server)[0]).unwrap();
+    assert_eq!(entry["ip"], "127.0.0.1");
+    assert_eq!(entry["method"], "HEAD");
+    assert_eq!(entry["path"], "/hello.txt");
+    assert_eq!(entry["protocol"], "HTTP/1.0");
+    assert_eq!(entry["status"], 200);
+    assert!(entry["duration_us"].is_u64());
+}
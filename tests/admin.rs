@@ -0,0 +1,57 @@
+mod common;
+
+use common::TestServer;
+use lumen::utils::base64_encode;
+
+fn admin_server() -> TestServer {
+    TestServer::start_with(|_, config| {
+        config.server.admin_path = "/admin/cache".into();
+        config.server.admin_credentials = vec!["ops:pager".into()];
+    })
+}
+
+fn post(server: &TestServer, query: &str) -> common::Response {
+    server.request(&format!(
+        "POST /admin/cache?{} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        query,
+        base64_encode(b"ops:pager")
+    ))
+}
+
+#[test]
+fn the_purge_action_is_read_like_any_query_parameter() {
+    let server = admin_server();
+    for query in [
+        "action=purge",
+        "action=%70urge",
+        "a%63tion=purge",
+        "x=1&action=purge",
+    ] {
+        let res = post(&server, query);
+        assert_eq!(res.status, 200, "{}", query);
+        assert!(res.text().contains("\"purged\":true"), "{}", query);
+    }
+}
+
+#[test]
+fn other_actions_and_keys_are_refused() {
+    let server = admin_server();
+    for query in [
+        "action=flush",
+        "actionx=purge",
+        "xaction=purge",
+        "action",
+        "action=purge+",
+    ] {
+        assert_eq!(post(&server, query).status, 400, "{}", query);
+    }
+}
+
+#[test]
+fn the_endpoint_needs_the_admin_credentials() {
+    let server = admin_server();
+    let res = server.request(
+        "POST /admin/cache?action=purge HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(res.status, 401);
+}
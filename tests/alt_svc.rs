@@ -0,0 +1,25 @@
+mod common;
+
+use common::TestServer;
+
+const ALT_SVC: &str = "h3=\":443\"; ma=86400, h2=\"alt.example:8443\"";
+
+#[test]
+fn the_configured_alt_svc_is_sent_on_every_response() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.alt_svc = ALT_SVC.into();
+    });
+    for path in ["/hello.txt", "/missing"] {
+        let res = server.get(path);
+        assert_eq!(res.header_values("alt-svc"), [ALT_SVC], "{}", path);
+    }
+}
+
+#[test]
+fn no_alt_svc_is_sent_by_default() {
+    let server = TestServer::start_with(|root, _| {
+        common::write_file(root, "content/hello.txt", "hello");
+    });
+    assert_eq!(server.get("/hello.txt").header("alt-svc"), None);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{TestServer, write_file};
+
+fn listing_server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(root, "content/files/b.txt", "bee");
+        write_file(root, "content/files/a 100%.txt", "a");
+        write_file(root, "content/files/<x>.txt", "x");
+        write_file(root, "content/files/sub/c.txt", "c");
+        write_file(root, "content/files/.secret", "hidden");
+        write_file(root, "content/files/.private/d.txt", "hidden");
+        write_file(root, "content/files/page.md", "---\ntitle: Page\n---\n");
+        write_file(root, "content/indexed/index.html", "own index");
+        write_file(root, "outside.txt", "outside");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            root.join("outside.txt"),
+            root.join("content/files/escape.txt"),
+        )
+        .unwrap();
+        config.server.autoindex = true;
+    })
+}
+
+/// The `(href, name)` of each link in a listing, in order.
+fn links(html: &str) -> Vec<(String, String)> {
+    html.split("<a href=\"")
+        .skip(1)
+        .map(|rest| {
+            let (href, rest) = rest.split_once("\">").unwrap();
+            let (name, _) = rest.split_once("</a>").unwrap();
+            (href.to_string(), name.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn a_directory_without_an_index_is_listed() {
+    let server = listing_server();
+    let res = server.get("/files/");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("content-type"), Some("text/html; charset=utf-8"));
+    let html = res.text();
+    assert!(html.contains("<title>Index of /files/</title>"), "{}", html);
+
+    let mut expected = vec![
+        ("/", "../"),
+        ("/files/sub/", "sub/"),
+        ("/files/%3Cx%3E.txt", "&lt;x&gt;.txt"),
+        ("/files/a%20100%25.txt", "a 100%.txt"),
+        ("/files/b.txt", "b.txt"),
+    ];
+    if cfg!(feature = "markdown") {
+        expected.push(("/files/page", "page"));
+    }
+    let expected: Vec<(String, String)> = expected
+        .into_iter()
+        .map(|(href, name)| (href.to_string(), name.to_string()))
+        .collect();
+    // hidden entries and the symlink out of the content root are left out
+    assert_eq!(links(&html), expected);
+    assert!(html.contains("<td>3</td>"), "{}", html);
+
+    // the links resolve
+    assert_eq!(server.get("/files/a%20100%25.txt").text(), "a");
+    let sub = server.get("/files/sub/").text();
+    assert_eq!(links(&sub)[0], ("/files/".to_string(), "../".to_string()));
+}
+
+#[test]
+fn an_index_file_is_served_instead_of_a_listing() {
+    let server = listing_server();
+    assert_eq!(server.get("/indexed/").text(), "own index");
+}
+
+#[test]
+fn directories_are_not_listed_by_default() {
+    let server = TestServer::start_with(|root, _| {
+        write_file(root, "content/files/b.txt", "bee");
+    });
+    assert_eq!(server.get("/files/").status, 404);
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn a_theme_autoindex_template_replaces_the_built_in_markup() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "content/files/b.txt", "bee");
+        write_file(root, "content/files/sub/c.txt", "c");
+        write_file(
+            root,
+            "themes/default/autoindex.html",
+            "{{ path|safe }} up={{ parent|safe }}{% for e in entries %} {{ e.name|safe }}:{% if e.is_dir %}dir{% else %}{{ e.size }}{% endif %}{% endfor %}",
+        );
+        config.server.autoindex = true;
+    });
+    assert_eq!(
+        server.get("/files/").text(),
+        "/files/ up=/ sub/:dir b.txt:3"
+    );
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::TestServer;
+
+fn docs_server() -> TestServer {
+    TestServer::start_with(|_, config| {
+        config.server.base_path = "/docs/".into();
+        config.seo.sitemap_url = "/sitemap.xml".into();
+        config.seo.disallow = vec!["/drafts/".into()];
+        config.seo.canonical_link_header = true;
+    })
+}
+
+#[test]
+fn generated_links_carry_the_base_path() {
+    let server = docs_server();
+    let home = server.get("/docs/");
+    assert_eq!(home.status, 200);
+    // the theme autoescapes `/` in attribute values
+    let body = home.text().replace("&#x2f;", "/");
+    assert!(
+        body.contains("href=\"/docs/posts/hello-world\""),
+        "{}",
+        body
+    );
+
+    let post = server.get("/docs/posts/hello-world");
+    assert_eq!(
+        post.header("link"),
+        Some("<http://127.0.0.1:8080/docs/posts/hello-world>; rel=\"canonical\"")
+    );
+
+    let robots = server.get("/docs/robots.txt").text();
+    assert!(robots.contains("Disallow: /docs/drafts/"), "{}", robots);
+    assert!(
+        robots.contains("Sitemap: http://127.0.0.1:8080/docs/sitemap.xml"),
+        "{}",
+        robots
+    );
+}
+
+#[test]
+fn requests_resolve_with_or_without_the_base_path() {
+    let server = docs_server();
+    let prefixed = server.get("/docs/posts/hello-world");
+    assert_eq!(prefixed.status, 200);
+    // a proxy that strips the prefix forwards the bare path
+    let stripped = server.get("/posts/hello-world");
+    assert_eq!(stripped.status, 200);
+    assert_eq!(stripped.body, prefixed.body);
+    // only a whole segment counts as the prefix
+    assert_eq!(server.get("/docsposts/hello-world").status, 404);
+}
+
+#[test]
+fn redirects_stay_under_the_base_path() {
+    let server = docs_server();
+    let bare = server.get("/docs");
+    assert_eq!(bare.status, 301);
+    assert_eq!(bare.header("location"), Some("/docs/"));
+
+    let index = server.get("/docs/posts/index");
+    assert_eq!(index.status, 301);
+    assert_eq!(index.header("location"), Some("/docs/posts/"));
+}
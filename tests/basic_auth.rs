@@ -0,0 +1,112 @@
+mod common;
+
+use common::TestServer;
+use lumen::utils::{base64_encode, sha256};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        common::write_file(root, "content/admin/secret.txt", "secret");
+        common::write_file(root, "content/admin/ops/runbook.txt", "runbook");
+        common::write_file(root, "content/public.txt", "public");
+        config.security.basic_auth = [
+            (
+                "/admin".to_string(),
+                vec![
+                    "alice:wonderland".to_string(),
+                    format!("bob:sha256:{}", hex(&sha256(b"builder"))),
+                ],
+            ),
+            ("/admin/ops/".to_string(), vec!["ops:pager".to_string()]),
+        ]
+        .into_iter()
+        .collect();
+    })
+}
+
+fn get_as(server: &TestServer, path: &str, credentials: Option<&str>) -> common::Response {
+    let auth = credentials
+        .map(|c| format!("Authorization: Basic {}\r\n", base64_encode(c.as_bytes())))
+        .unwrap_or_default();
+    server.request(&format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+        path, auth
+    ))
+}
+
+#[test]
+fn protected_paths_challenge_without_valid_credentials() {
+    let server = server();
+    for credentials in [
+        None,
+        Some("alice:wrong"),
+        Some("mallory:wonderland"),
+        Some("alice"),
+    ] {
+        let res = get_as(&server, "/admin/secret.txt", credentials);
+        assert_eq!(res.status, 401, "{:?}", credentials);
+        assert_eq!(res.reason, "Unauthorized");
+        assert!(
+            res.header("www-authenticate")
+                .is_some_and(|v| v.starts_with("Basic realm=")),
+            "{:?}",
+            res.headers
+        );
+        assert!(!res.text().contains("secret"));
+    }
+}
+
+#[test]
+fn plaintext_and_hashed_passwords_both_log_in() {
+    let server = server();
+    for credentials in ["alice:wonderland", "bob:builder"] {
+        let res = get_as(&server, "/admin/secret.txt", Some(credentials));
+        assert_eq!(res.status, 200, "{}", credentials);
+        assert_eq!(res.text(), "secret");
+    }
+    // the hash itself is not a password
+    let digest = format!("bob:sha256:{}", hex(&sha256(b"builder")));
+    assert_eq!(
+        get_as(&server, "/admin/secret.txt", Some(&digest)).status,
+        401
+    );
+}
+
+#[test]
+fn the_longest_matching_prefix_decides() {
+    let server = server();
+    let runbook = "/admin/ops/runbook.txt";
+    assert_eq!(get_as(&server, runbook, Some("ops:pager")).status, 200);
+    assert_eq!(
+        get_as(&server, runbook, Some("alice:wonderland")).status,
+        401
+    );
+    assert_eq!(
+        get_as(&server, "/admin/secret.txt", Some("ops:pager")).status,
+        401
+    );
+    assert_eq!(get_as(&server, "/public.txt", None).status, 200);
+}
+
+#[test]
+fn path_tricks_do_not_bypass_the_check() {
+    let server = server();
+    for path in [
+        "/%61dmin/secret.txt",
+        "//admin//secret.txt",
+        "/admin/./secret.txt",
+        "/public/../admin/secret.txt",
+    ] {
+        let res = get_as(&server, path, None);
+        assert!(
+            res.status == 401 || res.status == 403,
+            "{} got {}",
+            path,
+            res.status
+        );
+        assert!(!res.text().contains("secret"), "{}", path);
+    }
+}
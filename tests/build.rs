@@ -0,0 +1,82 @@
+#![cfg(feature = "markdown")]
+
+use std::{fs, path::Path};
+
+use lumen::{build::build_site, cli::scaffold_workspace, config::load_config};
+
+mod common;
+
+use common::write_file;
+
+/// Scaffolds a workspace, lets `setup` add content, and builds it into `dist/` inside it.
+fn build(setup: impl FnOnce(&Path)) -> (tempfile::TempDir, lumen::build::BuildReport) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    scaffold_workspace(root.to_str().unwrap()).unwrap();
+    setup(root);
+    let mut config = load_config(root.join("lumen.toml").to_str().unwrap()).unwrap();
+    config.paths.content_dir = root.join("content").to_string_lossy().into_owned();
+    config.paths.theme_dir = root.join("themes/default").to_string_lossy().into_owned();
+    let report = build_site(config, &root.join("content/dist")).unwrap();
+    (dir, report)
+}
+
+fn read(root: &Path, rel: &str) -> String {
+    fs::read_to_string(root.join("content/dist").join(rel))
+        .unwrap_or_else(|e| panic!("{}: {}", rel, e))
+}
+
+#[test]
+fn pages_are_laid_out_by_url_and_assets_copied() {
+    let (dir, report) = build(|root| {
+        write_file(
+            root,
+            "content/docs/index.md",
+            "---\ntitle: Docs\n---\nStart here.",
+        );
+        write_file(root, "content/css/site.css", "body{}");
+        write_file(root, "content/.well-known/security.txt", "Contact: x");
+        write_file(
+            root,
+            "content/.drafts/secret.md",
+            "---\ntitle: Secret\n---\n",
+        );
+    });
+    let root = dir.path();
+    assert!(report.failed.is_empty());
+    assert_eq!((report.pages, report.assets), (3, 2));
+
+    assert!(read(root, "index.html").contains("Welcome to Lumen"));
+    assert!(read(root, "posts/hello-world/index.html").contains("Hello World"));
+    assert!(read(root, "docs/index.html").contains("Start here."));
+    assert_eq!(read(root, "css/site.css"), "body{}");
+    assert_eq!(read(root, ".well-known/security.txt"), "Contact: x");
+    assert!(!root.join("content/dist/.drafts").exists());
+}
+
+#[test]
+fn failed_pages_are_reported_with_their_paths() {
+    let (dir, report) = build(|root| {
+        write_file(
+            root,
+            "content/broken.md",
+            "---\ntitle: Broken\ntemplate: missing.html\n---\n",
+        );
+    });
+    assert_eq!(report.failed.len(), 1);
+    assert!(report.failed[0].0.ends_with("broken.md"));
+    assert!(!dir.path().join("content/dist/broken").exists());
+    assert_eq!(report.pages, 2);
+}
+
+#[test]
+fn a_rebuild_does_not_read_back_its_own_output() {
+    let (dir, _) = build(|_| {});
+    let root = dir.path();
+    let mut config = load_config(root.join("lumen.toml").to_str().unwrap()).unwrap();
+    config.paths.content_dir = root.join("content").to_string_lossy().into_owned();
+    config.paths.theme_dir = root.join("themes/default").to_string_lossy().into_owned();
+    let again = build_site(config, &root.join("content/dist")).unwrap();
+    assert_eq!((again.pages, again.assets), (2, 0));
+    assert!(!root.join("content/dist/dist").exists());
+}
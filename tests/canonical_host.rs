@@ -0,0 +1,62 @@
+mod common;
+
+use common::TestServer;
+
+fn server(canonical_host: &str, base_url: &str) -> TestServer {
+    let (canonical_host, base_url) = (canonical_host.to_string(), base_url.to_string());
+    TestServer::start_with(|root, config| {
+        common::write_file(root, "content/docs/page.txt", "page");
+        config.redirects.canonical_host = canonical_host;
+        config.server.base_url = base_url;
+    })
+}
+
+fn get_on(server: &TestServer, host: &str, target: &str) -> common::Response {
+    server.request(&format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        target, host
+    ))
+}
+
+#[test]
+fn www_redirects_to_the_apex_keeping_path_and_query() {
+    let server = server("example.com", "https://example.com");
+    let res = get_on(&server, "www.example.com", "/docs/page.txt?lang=en&v=2");
+    assert_eq!(res.status, 301);
+    assert_eq!(
+        res.header("location"),
+        Some("https://example.com/docs/page.txt?lang=en&v=2")
+    );
+
+    let res = get_on(&server, "example.com", "/docs/page.txt?lang=en");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "page");
+}
+
+#[test]
+fn apex_redirects_to_www() {
+    let server = server("www.example.com", "http://www.example.com");
+    let res = get_on(&server, "example.com:8080", "/docs/page.txt?q=%20x");
+    assert_eq!(res.status, 301);
+    assert_eq!(
+        res.header("location"),
+        Some("http://www.example.com/docs/page.txt?q=%20x")
+    );
+
+    // a port-less canonical host accepts any port, and case doesn't matter
+    let res = get_on(&server, "WWW.Example.com:8080", "/docs/page.txt");
+    assert_eq!(res.status, 200);
+}
+
+#[test]
+fn requests_without_a_host_or_with_redirects_off_are_served() {
+    let server = server("example.com", "https://example.com");
+    let res = server.request("GET /docs/page.txt HTTP/1.0\r\n\r\n");
+    assert_eq!(res.status, 200);
+
+    let server = self::server("", "https://example.com");
+    assert_eq!(
+        get_on(&server, "www.example.com", "/docs/page.txt").status,
+        200
+    );
+}
@@ -0,0 +1,387 @@
+//! Starts Lumen on an ephemeral port against a scaffolded workspace in a temp dir, and talks to
+//! it over raw sockets so tests see exactly the bytes a client would.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use lumen::{
+    cli::scaffold_workspace,
+    config::{Config, load_config},
+    server::{ConfigLoader, Server, ShutdownHandle},
+    state::Metrics,
+};
+use tempfile::TempDir;
+use tracing_subscriber::fmt::MakeWriter;
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub dir: TempDir,
+    pub metrics: Arc<Metrics>,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// A server for the workspace `lumen init` creates.
+    pub fn start() -> Self {
+        Self::start_with(|_, _| {})
+    }
+
+    /// Scaffolds a workspace, then lets `setup` add files and adjust the config before binding.
+    /// Paths in the config are made absolute first, so tests can run in parallel.
+    pub fn start_with(setup: impl FnOnce(&Path, &mut Config)) -> Self {
+        Self::start_custom(setup, |server| server)
+    }
+
+    /// Like `start_with`, with a last chance to adjust the bound `Server` before it runs.
+    pub fn start_custom(
+        setup: impl FnOnce(&Path, &mut Config),
+        customize: impl FnOnce(Server) -> Server,
+    ) -> Self {
+        Self::launch(setup, customize, false)
+    }
+
+    /// Like `start_with`, but writes the adjusted config back to `lumen.toml` and reloads from
+    /// that file on SIGHUP, so a test can `edit_config` and then signal a reload.
+    pub fn start_reloadable(setup: impl FnOnce(&Path, &mut Config)) -> Self {
+        Self::launch(setup, Server::reload_on_sighup, true)
+    }
+
+    fn launch(
+        setup: impl FnOnce(&Path, &mut Config),
+        customize: impl FnOnce(Server) -> Server,
+        from_file: bool,
+    ) -> Self {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let root = dir.path();
+        scaffold_workspace(root.to_str().expect("utf-8 temp dir")).expect("scaffold workspace");
+
+        let config_path = root.join("lumen.toml").to_string_lossy().into_owned();
+        let mut config = load_config(&config_path).unwrap();
+        config.server.port = 0;
+        config.server.threads = 4;
+        config.paths.content_dir = root.join("content").to_string_lossy().into_owned();
+        config.paths.theme_dir = root.join("themes/default").to_string_lossy().into_owned();
+        setup(root, &mut config);
+
+        let reload: ConfigLoader = if from_file {
+            save_config(root, &config);
+            Box::new(move || load_config(&config_path))
+        } else {
+            let config = config.clone();
+            Box::new(move || Ok(config.clone()))
+        };
+        let server = Server::bind(config, reload).expect("bind");
+        let server = customize(server);
+        let addr = server.local_addr();
+        let metrics = server.metrics();
+        let shutdown = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Self {
+            addr,
+            dir,
+            metrics,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream),
+        }
+    }
+
+    /// Sends `raw` on a fresh connection and reads one response.
+    pub fn request(&self, raw: &str) -> Response {
+        let mut client = self.connect();
+        client.send(raw);
+        client.response(raw.starts_with("HEAD "))
+    }
+
+    /// A plain `GET` that closes the connection afterwards.
+    pub fn get(&self, path: &str) -> Response {
+        self.request(&format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ))
+    }
+
+    /// Rewrites `lumen.toml` with `edit` applied; a reloadable server picks it up on reload.
+    pub fn edit_config(&self, edit: impl FnOnce(&mut Config)) {
+        let path = self.dir.path().join("lumen.toml");
+        let mut config = load_config(path.to_str().unwrap()).unwrap();
+        edit(&mut config);
+        save_config(self.dir.path(), &config);
+    }
+
+    /// Writes (or overwrites) a file in the workspace, creating its directories.
+    pub fn write(&self, rel: &str, contents: &str) {
+        write_file(self.dir.path(), rel, contents);
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Polls `check` for up to five seconds; counters are bumped just after the bytes they count
+/// go out, so a client can see a response before its numbers land.
+pub fn wait_for(mut check: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    check()
+}
+
+/// Installs a global JSON subscriber, with the current span on each event, on first use and
+/// returns the events it has logged so far. Every thread logs to it, so a test binary that
+/// checks logs should keep them to one test or expect the others' events too.
+pub fn logged_events() -> Vec<serde_json::Value> {
+    static OUT: OnceLock<Arc<Mutex<Vec<u8>>>> = OnceLock::new();
+    let out = OUT.get_or_init(|| {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(Captured(Arc::clone(&out)))
+            .init();
+        out
+    });
+    let text = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[derive(Clone)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Captured {
+    type Writer = Captured;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+fn save_config(root: &Path, config: &Config) {
+    let text = toml::to_string_pretty(config).expect("serialize config");
+    fs::write(root.join("lumen.toml"), text).unwrap();
+}
+
+pub fn write_file(root: &Path, rel: &str, contents: &str) {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+/// One connection, buffered so pipelined responses can be read back one at a time.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    pub fn send(&mut self, raw: &str) {
+        self.send_bytes(raw.as_bytes());
+    }
+
+    pub fn send_bytes(&mut self, raw: &[u8]) {
+        self.reader.get_mut().write_all(raw).expect("write request");
+    }
+
+    /// Reads the next response; `head` says it answers a HEAD request, so has no body.
+    pub fn response(&mut self, head: bool) -> Response {
+        let mut res = self.response_head();
+        if head || res.status < 200 || res.status == 204 || res.status == 304 {
+            return res;
+        }
+        if res
+            .header("transfer-encoding")
+            .is_some_and(|te| te.eq_ignore_ascii_case("chunked"))
+        {
+            res.chunked = true;
+            res.body = self.read_chunked();
+        } else if let Some(len) = res.header("content-length") {
+            let mut body = vec![0; len.parse().expect("numeric Content-Length")];
+            self.reader.read_exact(&mut body).expect("read body");
+            res.body = body;
+        } else {
+            self.reader.read_to_end(&mut res.body).expect("read body");
+        }
+        res
+    }
+
+    /// Reads the status line and headers of the next response, leaving its body unread.
+    pub fn response_head(&mut self) -> Response {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).expect("read status line");
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let version = parts.next().unwrap_or_default().to_string();
+        let status = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(|| panic!("malformed status line {:?}", line));
+        let reason = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = Vec::new();
+        let mut head_len = line.len();
+        loop {
+            line.clear();
+            self.reader.read_line(&mut line).expect("read header");
+            head_len += line.len();
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').expect("header without a colon");
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        Response {
+            version,
+            status,
+            reason,
+            headers,
+            body: Vec::new(),
+            chunked: false,
+            head_len,
+        }
+    }
+
+    /// Reads exactly `len` bytes.
+    pub fn read_exact(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        self.reader.read_exact(&mut buf).expect("read bytes");
+        buf
+    }
+
+    /// Reads until the server closes the connection.
+    pub fn read_to_close(&mut self) -> Vec<u8> {
+        let mut rest = Vec::new();
+        self.reader.read_to_end(&mut rest).expect("read to close");
+        rest
+    }
+
+    fn read_chunked(&mut self) -> Vec<u8> {
+        let mut body = Vec::new();
+        loop {
+            let mut size = String::new();
+            self.reader.read_line(&mut size).expect("read chunk size");
+            let size = usize::from_str_radix(size.trim(), 16).expect("hex chunk size");
+            let mut chunk = vec![0; size + 2];
+            self.reader.read_exact(&mut chunk).expect("read chunk");
+            assert_eq!(&chunk[size..], b"\r\n", "chunk not terminated by CRLF");
+            if size == 0 {
+                return body;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    /// Whether the server has closed its end, waiting briefly for it to do so.
+    pub fn is_closed(&mut self) -> bool {
+        self.closed_within(Duration::from_secs(3))
+    }
+
+    /// Whether the server closes its end within `limit`; a reset counts, a read timing out
+    /// does not.
+    pub fn closed_within(&mut self, limit: Duration) -> bool {
+        self.reader.get_ref().set_read_timeout(Some(limit)).unwrap();
+        let mut buf = [0; 1];
+        match self.reader.read(&mut buf) {
+            Ok(n) => n == 0,
+            Err(e) => !is_timeout(&e),
+        }
+    }
+
+    /// Whether the connection is still open and quiet: a short read times out rather than
+    /// seeing EOF or data.
+    pub fn is_open(&mut self) -> bool {
+        self.reader
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0; 1];
+        matches!(self.reader.read(&mut buf), Err(e) if is_timeout(&e))
+    }
+
+    /// Stops sending; the server still answers what it already received.
+    pub fn finish(&mut self) {
+        let _ = self.reader.get_ref().shutdown(Shutdown::Write);
+    }
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The body arrived with `Transfer-Encoding: chunked`, already decoded.
+    pub chunked: bool,
+    /// Bytes in the status line and headers, blank line included.
+    pub head_len: usize,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use std::io::Read;
+
+use common::{Response, TestServer};
+use lumen::config::{Algorithm, Config};
+
+/// Repetitive enough to compress well, and well over the default `min_size`.
+fn stylesheet() -> String {
+    "body { margin: 0; padding: 0; }\n".repeat(128)
+}
+
+fn compression_server(configure: impl FnOnce(&mut Config)) -> TestServer {
+    TestServer::start_with(|root, config| {
+        common::write_file(root, "content/app.css", &stylesheet());
+        common::write_file(root, "content/tiny.css", "a{}\n");
+        configure(config);
+    })
+}
+
+fn fetch(server: &TestServer, path: &str, accept_encoding: &str) -> Response {
+    server.request(&format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\nConnection: close\r\n\r\n",
+        path, accept_encoding
+    ))
+}
+
+fn decoded(res: &Response) -> String {
+    let mut out = String::new();
+    match res.header("content-encoding") {
+        Some("gzip") => flate2::read::GzDecoder::new(&res.body[..])
+            .read_to_string(&mut out)
+            .unwrap(),
+        Some("deflate") => flate2::read::ZlibDecoder::new(&res.body[..])
+            .read_to_string(&mut out)
+            .unwrap(),
+        Some("br") => brotli::Decompressor::new(&res.body[..], 4096)
+            .read_to_string(&mut out)
+            .unwrap(),
+        _ => return res.text(),
+    };
+    out
+}
+
+#[test]
+fn picks_the_algorithm_for_each_accept_encoding() {
+    let server = compression_server(|_| {});
+    for (accept, expected) in [
+        ("br, gzip, deflate", Some("br")),
+        ("gzip, deflate", Some("gzip")),
+        ("deflate", Some("deflate")),
+        ("gzip;q=1, br;q=0.5", Some("gzip")),
+        ("br;q=0, *", Some("gzip")),
+        ("zstd", None),
+        ("identity", None),
+    ] {
+        let res = fetch(&server, "/app.css", accept);
+        assert_eq!(res.status, 200, "{}", accept);
+        assert_eq!(res.header("content-encoding"), expected, "{}", accept);
+        assert_eq!(decoded(&res), stylesheet(), "{}", accept);
+    }
+}
+
+#[test]
+fn server_order_breaks_ties_and_disabled_algorithms_are_skipped() {
+    let server = compression_server(|c| {
+        c.performance.compression.algorithms = vec![Algorithm::Gzip, Algorithm::Brotli];
+    });
+    let res = fetch(&server, "/app.css", "br, gzip");
+    assert_eq!(res.header("content-encoding"), Some("gzip"));
+    let res = fetch(&server, "/app.css", "deflate");
+    assert_eq!(res.header("content-encoding"), None);
+    assert_eq!(res.text(), stylesheet());
+}
+
+#[test]
+fn bodies_under_min_size_go_out_uncompressed() {
+    let server = compression_server(|c| c.performance.compression.min_size = 64);
+    let small = fetch(&server, "/tiny.css", "gzip");
+    assert_eq!(small.header("content-encoding"), None);
+    assert_eq!(small.text(), "a{}\n");
+    assert_eq!(
+        fetch(&server, "/app.css", "gzip").header("content-encoding"),
+        Some("gzip")
+    );
+
+    let server = compression_server(|c| c.performance.compression.min_size = 1 << 20);
+    assert_eq!(
+        fetch(&server, "/app.css", "gzip").header("content-encoding"),
+        None
+    );
+}
+
+#[test]
+fn configured_level_is_used() {
+    let size_at = |level| {
+        let server = compression_server(|c| c.performance.compression.gzip_level = level);
+        let res = fetch(&server, "/app.css", "gzip");
+        assert_eq!(decoded(&res), stylesheet());
+        res.body.len()
+    };
+    // level 0 stores the data, so the "compressed" body outgrows the original
+    assert!(size_at(0) > stylesheet().len());
+    assert!(size_at(9) < stylesheet().len() / 10);
+}
+
+#[test]
+fn refusing_identity_without_a_usable_encoding_is_406() {
+    let server = compression_server(|_| {});
+    let res = fetch(&server, "/tiny.css", "gzip, identity;q=0");
+    assert_eq!(res.status, 406);
+    assert_eq!(res.reason, "Not Acceptable");
+    let res = fetch(&server, "/app.css", "zstd, *;q=0");
+    assert_eq!(res.status, 406);
+    // the same refusal is fine when the body can be compressed
+    let res = fetch(&server, "/app.css", "gzip, identity;q=0");
+    assert_eq!(res.header("content-encoding"), Some("gzip"));
+}
+
+fn gzip(data: &str) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(data.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn set_mtime(path: &std::path::Path, secs_ago: u64) {
+    let when = std::time::SystemTime::now() - std::time::Duration::from_secs(secs_ago);
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(when)
+        .unwrap();
+}
+
+#[test]
+fn fresh_precompressed_sibling_is_served_as_is() {
+    let server = compression_server(|_| {});
+    let sidecar = gzip(&format!("{}/* prebuilt */\n", stylesheet()));
+    std::fs::write(server.dir.path().join("content/app.css.gz"), &sidecar).unwrap();
+
+    let res = fetch(&server, "/app.css", "gzip");
+    assert_eq!(res.header("content-encoding"), Some("gzip"));
+    assert_eq!(res.body, sidecar);
+    assert!(res.header_values("vary").contains(&"Accept-Encoding"));
+
+    // a client without gzip still gets the original
+    assert_eq!(fetch(&server, "/app.css", "identity").text(), stylesheet());
+}
+
+#[test]
+fn stale_precompressed_sibling_is_ignored() {
+    let server = compression_server(|_| {});
+    let root = server.dir.path();
+    std::fs::write(root.join("content/app.css.gz"), gzip("/* old build */")).unwrap();
+    set_mtime(&root.join("content/app.css.gz"), 3600);
+
+    let res = fetch(&server, "/app.css", "gzip");
+    assert_eq!(res.header("content-encoding"), Some("gzip"));
+    assert_eq!(decoded(&res), stylesheet());
+}
+
+#[test]
+fn head_and_304_carry_no_body() {
+    let server = compression_server(|_| {});
+    let get = fetch(&server, "/app.css", "gzip");
+    let head = server.request(
+        "HEAD /app.css HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(head.status, 200);
+    assert!(head.body.is_empty());
+    assert_eq!(head.header("content-encoding"), Some("gzip"));
+    assert_eq!(head.header("content-length"), get.header("content-length"));
+
+    let not_modified = server.request(&format!(
+        "GET /app.css HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nIf-None-Match: {}\r\nConnection: close\r\n\r\n",
+        get.header("etag").unwrap()
+    ));
+    assert_eq!(not_modified.status, 304);
+    assert!(not_modified.body.is_empty());
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn rendered_pages_are_compressed_from_one_cached_render() {
+    use std::sync::atomic::Ordering;
+    let server = compression_server(|c| {
+        common::write_file(
+            std::path::Path::new(&c.paths.content_dir),
+            "long.md",
+            &"A paragraph that repeats.\n\n".repeat(100),
+        );
+    });
+    let plain = fetch(&server, "/long", "identity");
+    for accept in ["gzip", "br", "deflate", "gzip"] {
+        let res = fetch(&server, "/long", accept);
+        assert_eq!(res.header("content-encoding"), Some(accept));
+        assert_eq!(decoded(&res), plain.text());
+    }
+    assert_eq!(server.metrics.renders.load(Ordering::Relaxed), 1);
+}
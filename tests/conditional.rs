@@ -0,0 +1,104 @@
+mod common;
+
+use common::{Response, TestServer};
+
+const ASSET: &str = "0123456789abcdefghij";
+
+fn asset_server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        common::write_file(root, "content/asset.bin", ASSET);
+        config.performance.enable_compression = false;
+    })
+}
+
+fn get_with(server: &TestServer, path: &str, headers: &str) -> Response {
+    server.request(&format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+        path, headers
+    ))
+}
+
+#[test]
+fn static_files_get_a_quoted_strong_etag() {
+    let server = asset_server();
+    let res = server.get("/asset.bin");
+    let etag = res.header("etag").unwrap();
+    assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+    assert!(!etag.starts_with("W/"));
+    assert_eq!(server.get("/asset.bin").header("etag"), Some(etag));
+}
+
+#[test]
+fn if_none_match_uses_weak_comparison() {
+    let server = asset_server();
+    let etag = server.get("/asset.bin").header("etag").unwrap().to_string();
+    for inm in [
+        etag.clone(),
+        format!("W/{}", etag),
+        format!("\"other\", {}", etag),
+        "*".to_string(),
+    ] {
+        let res = get_with(
+            &server,
+            "/asset.bin",
+            &format!("If-None-Match: {}\r\n", inm),
+        );
+        assert_eq!(res.status, 304, "{}", inm);
+        assert!(res.body.is_empty());
+        assert_eq!(res.header("etag"), Some(etag.as_str()));
+    }
+    let res = get_with(&server, "/asset.bin", "If-None-Match: \"other\"\r\n");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), ASSET);
+}
+
+#[test]
+fn if_range_with_a_current_etag_gets_the_range() {
+    let server = asset_server();
+    let etag = server.get("/asset.bin").header("etag").unwrap().to_string();
+    let res = get_with(
+        &server,
+        "/asset.bin",
+        &format!("Range: bytes=0-3\r\nIf-Range: {}\r\n", etag),
+    );
+    assert_eq!(res.status, 206);
+    assert_eq!(res.text(), "0123");
+}
+
+#[test]
+fn stale_if_range_falls_back_to_the_full_body() {
+    let server = asset_server();
+    for if_range in ["\"0-0\"", "W/\"0-0\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+        let res = get_with(
+            &server,
+            "/asset.bin",
+            &format!("Range: bytes=0-3\r\nIf-Range: {}\r\n", if_range),
+        );
+        assert_eq!(res.status, 200, "{}", if_range);
+        assert_eq!(res.text(), ASSET);
+    }
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn rendered_pages_revalidate_too() {
+    let server = asset_server();
+    let page = server.get("/posts/hello-world");
+    let etag = page.header("etag").unwrap();
+    let res = get_with(
+        &server,
+        "/posts/hello-world",
+        &format!("If-None-Match: {}\r\n", etag),
+    );
+    assert_eq!(res.status, 304);
+    assert!(res.body.is_empty());
+    let res = get_with(
+        &server,
+        "/posts/hello-world",
+        &format!(
+            "If-Modified-Since: {}\r\n",
+            page.header("last-modified").unwrap()
+        ),
+    );
+    assert_eq!(res.status, 304);
+}
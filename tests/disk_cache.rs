@@ -0,0 +1,114 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use std::{path::Path, sync::atomic::Ordering};
+
+use common::{TestServer, write_file};
+use lumen::utils::base64_encode;
+use tempfile::TempDir;
+
+/// A server over a site and disk cache that outlive it, as across a restart. The config
+/// feeds the cache version, so every path in it has to stay put too.
+fn server_over(shared: &Path) -> TestServer {
+    let shared = shared.to_path_buf();
+    TestServer::start_with(move |_, config| {
+        config.paths.content_dir = shared.join("content").to_string_lossy().into_owned();
+        config.paths.theme_dir = shared.join("theme").to_string_lossy().into_owned();
+        config.performance.disk_cache_dir = shared.join("cache").to_string_lossy().into_owned();
+        config.server.admin_path = "/admin/cache".into();
+        config.server.admin_credentials = vec!["ops:pager".into()];
+    })
+}
+
+fn shared_site() -> TempDir {
+    let shared = tempfile::tempdir().unwrap();
+    write_file(
+        shared.path(),
+        "content/page.md",
+        "---\ntitle: Page\n---\nFirst\n",
+    );
+    write_file(
+        shared.path(),
+        "theme/index.html",
+        "<h1>{{ title }}</h1>{{ content|safe }}",
+    );
+    shared
+}
+
+fn renders(server: &TestServer) -> u64 {
+    server.metrics.renders.load(Ordering::Relaxed)
+}
+
+#[test]
+fn rendered_pages_survive_a_restart() {
+    let shared = shared_site();
+    let first = server_over(shared.path());
+    let before = first.get("/page");
+    assert_eq!(before.status, 200);
+    assert_eq!(renders(&first), 1);
+    drop(first);
+
+    let second = server_over(shared.path());
+    let after = second.get("/page");
+    assert_eq!(after.status, 200);
+    assert_eq!(after.body, before.body);
+    assert_eq!(renders(&second), 0);
+}
+
+#[test]
+fn an_edited_page_misses_after_a_restart() {
+    let shared = shared_site();
+    drop(server_over(shared.path()).get("/page"));
+
+    write_file(
+        shared.path(),
+        "content/page.md",
+        "---\ntitle: Page\n---\nSecond\n",
+    );
+    let second = server_over(shared.path());
+    assert!(second.get("/page").text().contains("Second"));
+    assert_eq!(renders(&second), 1);
+}
+
+#[test]
+fn a_theme_change_misses_after_a_restart() {
+    let shared = shared_site();
+    drop(server_over(shared.path()).get("/page"));
+
+    write_file(shared.path(), "theme/index.html", "NEW THEME {{ title }}");
+    let second = server_over(shared.path());
+    assert_eq!(second.get("/page").text(), "NEW THEME Page");
+    assert_eq!(renders(&second), 1);
+}
+
+fn purge(server: &TestServer) {
+    let res = server.request(&format!(
+        "POST /admin/cache?action=purge HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        base64_encode(b"ops:pager")
+    ));
+    assert_eq!(res.status, 200);
+}
+
+#[test]
+fn a_purge_rerenders_rather_than_reading_the_disk_cache() {
+    let shared = shared_site();
+    let server = server_over(shared.path());
+    server.get("/page");
+    purge(&server);
+    assert_eq!(server.get("/page").status, 200);
+    assert_eq!(renders(&server), 2);
+}
+
+#[test]
+fn a_purge_holds_across_a_restart() {
+    let shared = shared_site();
+    let first = server_over(shared.path());
+    first.get("/page");
+    purge(&first);
+    drop(first);
+
+    let second = server_over(shared.path());
+    assert_eq!(second.get("/page").status, 200);
+    assert_eq!(renders(&second), 1);
+}
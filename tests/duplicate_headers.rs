@@ -0,0 +1,96 @@
+mod common;
+
+use common::TestServer;
+
+fn server() -> TestServer {
+    TestServer::start_with(|root, _| common::write_file(root, "content/hello.txt", "hello"))
+}
+
+/// Sends a GET for `/hello.txt` with `extra` header lines on a fresh connection.
+fn get_with(server: &TestServer, extra: &str) -> (common::Response, common::Client) {
+    let mut client = server.connect();
+    client.send(&format!("GET /hello.txt HTTP/1.1\r\n{}\r\n", extra));
+    let res = client.response(false);
+    (res, client)
+}
+
+fn assert_rejected(server: &TestServer, extra: &str) {
+    let (res, mut client) = get_with(server, extra);
+    assert_eq!(res.status, 400, "{:?}", extra);
+    assert_eq!(res.header("connection"), Some("close"), "{:?}", extra);
+    assert!(client.is_closed(), "{:?}", extra);
+}
+
+#[test]
+fn duplicate_host_is_a_bad_request() {
+    let server = server();
+    assert_rejected(&server, "Host: a.example\r\nHost: b.example\r\n");
+    // even identical copies leave which virtual host was meant ambiguous
+    assert_rejected(&server, "Host: a.example\r\nHost: a.example\r\n");
+}
+
+#[test]
+fn conflicting_content_length_is_a_bad_request() {
+    let server = server();
+    assert_rejected(
+        &server,
+        "Host: localhost\r\nContent-Length: 3\r\nContent-Length: 5\r\n",
+    );
+    assert_rejected(
+        &server,
+        "Host: localhost\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n",
+    );
+    assert_rejected(&server, "Host: localhost\r\nContent-Length: three\r\n");
+}
+
+#[test]
+fn transfer_encoded_bodies_are_a_bad_request() {
+    let server = server();
+    for coding in ["chunked", "gzip, chunked", "identity"] {
+        let (res, mut client) = get_with(
+            &server,
+            &format!(
+                "Host: localhost\r\nTransfer-Encoding: {}\r\n\r\n\
+                 2c\r\nGET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n0\r\n\r\n",
+                coding
+            ),
+        );
+        assert_eq!(res.status, 400, "{}", coding);
+        assert_eq!(res.header("connection"), Some("close"), "{}", coding);
+        // the body never gets read as a second request
+        assert!(client.is_closed(), "{}", coding);
+    }
+}
+
+#[test]
+fn repeated_identical_single_valued_headers_are_accepted() {
+    let server = server();
+    let (res, mut client) = get_with(
+        &server,
+        "Host: localhost\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc",
+    );
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "hello");
+    assert!(client.is_open());
+}
+
+#[test]
+fn conflicting_ranges_are_a_bad_request() {
+    let server = server();
+    assert_rejected(
+        &server,
+        "Host: localhost\r\nRange: bytes=0-1\r\nRange: bytes=2-3\r\n",
+    );
+}
+
+#[test]
+fn close_in_any_connection_header_wins() {
+    let server = server();
+    let (res, mut client) = get_with(
+        &server,
+        "Host: localhost\r\nConnection: keep-alive\r\nConnection: close\r\n",
+    );
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("connection"), Some("close"));
+    assert!(client.is_closed());
+}
@@ -0,0 +1,43 @@
+//! Kept to its own binary: it installs the global subscriber to read the events back.
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::{TestServer, logged_events, wait_for};
+use lumen::config::LogLevel;
+
+fn status_events(status: u16) -> Vec<serde_json::Value> {
+    logged_events()
+        .into_iter()
+        .filter(|e| {
+            e["fields"]["message"]
+                .as_str()
+                .is_some_and(|m| m.ends_with(&format!("-> {}", status)))
+        })
+        .collect()
+}
+
+#[test]
+fn not_found_is_counted_and_logged_at_the_configured_level() {
+    logged_events();
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.client_error_log_level = LogLevel::Warn;
+    });
+    assert_eq!(server.get("/missing-page").status, 404);
+    assert_eq!(server.get("/hello.txt").status, 200);
+
+    assert!(wait_for(
+        || server.metrics.requests.load(Ordering::Relaxed) == 2
+    ));
+    assert_eq!(server.metrics.not_found.load(Ordering::Relaxed), 1);
+    assert_eq!(server.metrics.client_errors.load(Ordering::Relaxed), 1);
+    assert_eq!(server.metrics.server_errors.load(Ordering::Relaxed), 0);
+
+    assert!(wait_for(|| !status_events(404).is_empty()));
+    let events = status_events(404);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["level"], "WARN");
+    assert_eq!(events[0]["fields"]["message"], "GET /missing-page -> 404");
+    assert!(status_events(200).is_empty());
+}
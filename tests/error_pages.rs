@@ -0,0 +1,67 @@
+mod common;
+
+use common::{TestServer, write_file};
+
+fn page(root: &std::path::Path, name: &str) -> String {
+    root.join(name).to_string_lossy().into_owned()
+}
+
+#[test]
+fn a_configured_file_is_served_as_the_404_page() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "errors/404.html", "<h1>Nothing here</h1>");
+        config.paths.error_pages = [("404".to_string(), page(root, "errors/404.html"))]
+            .into_iter()
+            .collect();
+    });
+    let res = server.get("/missing");
+    assert_eq!(res.status, 404);
+    assert_eq!(res.header("content-type"), Some("text/html; charset=utf-8"));
+    assert_eq!(res.text(), "<h1>Nothing here</h1>");
+
+    let res =
+        server.request("HEAD /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(res.status, 404);
+    assert_eq!(res.header("content-length"), Some("21"));
+}
+
+#[test]
+fn a_missing_error_page_falls_back_to_the_built_in_one() {
+    let server = TestServer::start_with(|root, config| {
+        config.paths.fallback_404 = "built-in".into();
+        config.paths.error_pages = [("404".to_string(), page(root, "errors/none.html"))]
+            .into_iter()
+            .collect();
+    });
+    let res = server.get("/missing");
+    assert_eq!(res.status, 404);
+    assert_eq!(res.text(), "built-in");
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn a_markdown_error_page_is_rendered_through_the_theme() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(
+            root,
+            "themes/default/error.html",
+            "<main>{{ title }}: {{ content|safe }}</main>",
+        );
+        write_file(
+            root,
+            "errors/403.md",
+            "---\ntitle: Forbidden\ntemplate: error.html\n---\nNot *yours*.",
+        );
+        write_file(root, "content/secret.md", "---\ntitle: Secret\n---\n");
+        config.paths.error_pages = [("403".to_string(), page(root, "errors/403.md"))]
+            .into_iter()
+            .collect();
+    });
+    let res = server.get("/secret.md");
+    assert_eq!(res.status, 403);
+    assert!(res.header("content-type").unwrap().starts_with("text/html"));
+    assert_eq!(
+        res.text().trim(),
+        "<main>Forbidden: <p>Not <em>yours</em>.</p>\n</main>"
+    );
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::TestServer;
+
+/// Fetches `path`, then again with the ETag it came back with.
+fn revalidate(server: &TestServer, path: &str) -> (common::Response, common::Response) {
+    let first = server.get(path);
+    let etag = first
+        .header("etag")
+        .expect("generated response has an ETag");
+    let second = server.request(&format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\nConnection: close\r\n\r\n",
+        path, etag
+    ));
+    (first, second)
+}
+
+#[test]
+fn robots_txt_answers_304_for_its_own_etag() {
+    let server = TestServer::start_with(|_, config| {
+        config.seo.disallow = vec!["/drafts/".into()];
+        config.performance.generated_max_age = 120;
+    });
+    let (first, second) = revalidate(&server, "/robots.txt");
+    assert_eq!(first.status, 200);
+    assert!(first.text().contains("Disallow: /drafts/"));
+    assert_eq!(first.header("cache-control"), Some("max-age=120"));
+    assert_eq!(second.status, 304);
+    assert!(second.body.is_empty());
+    assert_eq!(second.header("etag"), first.header("etag"));
+}
+
+#[test]
+fn autoindex_answers_304_until_the_listing_changes() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/files/a.bin", "a");
+        config.server.autoindex = true;
+    });
+    let (first, second) = revalidate(&server, "/files/");
+    assert_eq!(first.status, 200);
+    assert!(first.text().contains("a.bin"));
+    assert_eq!(second.status, 304);
+
+    server.write("content/files/b.bin", "b");
+    let stale = server.request(&format!(
+        "GET /files/ HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\nConnection: close\r\n\r\n",
+        first.header("etag").unwrap()
+    ));
+    assert_eq!(stale.status, 200);
+    assert!(stale.text().contains("b.bin"));
+    assert_ne!(stale.header("etag"), first.header("etag"));
+}
+
+#[test]
+fn a_different_etag_gets_the_full_body() {
+    let server = TestServer::start_with(|_, config| config.seo.disallow = vec!["/x/".into()]);
+    let res = server.request(
+        "GET /robots.txt HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"0\"\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(res.status, 200);
+    assert!(res.text().starts_with("User-agent: *"));
+}
+
+#[test]
+fn robots_txt_is_built_from_the_seo_settings() {
+    let server = TestServer::start_with(|_, config| {
+        config.seo.sitemap_url = "/sitemap.xml".into();
+        config.seo.disallow = vec!["/drafts/".into(), "/tmp/".into()];
+        config.seo.user_agent = [
+            ("GPTBot".to_string(), vec!["/".to_string()]),
+            ("Googlebot".to_string(), vec![]),
+        ]
+        .into_iter()
+        .collect();
+    });
+    let res = server.get("/robots.txt");
+    assert_eq!(res.status, 200);
+    assert!(
+        res.header("content-type")
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    assert_eq!(
+        res.text(),
+        "User-agent: *\nDisallow: /drafts/\nDisallow: /tmp/\n\n\
+         User-agent: GPTBot\nDisallow: /\n\n\
+         User-agent: Googlebot\nDisallow:\n\n\
+         Sitemap: http://127.0.0.1:8080/sitemap.xml\n"
+    );
+}
+
+#[test]
+fn a_robots_txt_file_wins_over_the_generated_one() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/robots.txt", "User-agent: *\nDisallow: /\n");
+        config.seo.disallow = vec!["/drafts/".into()];
+    });
+    assert_eq!(
+        server.get("/robots.txt").text(),
+        "User-agent: *\nDisallow: /\n"
+    );
+}
+
+#[test]
+fn robots_txt_is_not_generated_without_settings() {
+    let server = TestServer::start();
+    assert_eq!(server.get("/robots.txt").status, 404);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::TestServer;
+
+fn head_server() -> TestServer {
+    TestServer::start_with(|root, _| {
+        common::write_file(root, "content/style.css", &"p { color: red; }\n".repeat(40));
+        common::write_file(root, "content/docs/index.md", "---\ntitle: Docs\n---\n");
+    })
+}
+
+#[test]
+fn head_matches_get_without_a_body() {
+    let server = head_server();
+    let mut paths = vec!["/style.css", "/missing", "/no/such/dir/"];
+    if cfg!(feature = "markdown") {
+        paths.extend(["/posts/hello-world", "/docs", "/"]);
+    }
+    for path in paths {
+        let get = server.get(path);
+        let mut client = server.connect();
+        client.send(&format!(
+            "HEAD {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ));
+        let head = client.response(true);
+        assert_eq!(head.status, get.status, "{}", path);
+        for name in ["content-type", "content-length", "location", "etag"] {
+            assert_eq!(head.header(name), get.header(name), "{} {}", path, name);
+        }
+        // nothing follows the headers
+        assert!(client.read_to_close().is_empty(), "{}", path);
+    }
+}
+
+#[test]
+fn head_on_a_kept_alive_connection_leaves_it_framed() {
+    let server = head_server();
+    let mut client = server.connect();
+    client.send("HEAD /style.css HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    client.send("GET /style.css HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let head = client.response(true);
+    let get = client.response(false);
+    assert_eq!(head.status, 200);
+    assert_eq!(get.status, 200);
+    assert_eq!(get.text(), "p { color: red; }\n".repeat(40));
+}
+
+fn head_with_range(server: &TestServer, range: &str) -> common::Response {
+    server.request(&format!(
+        "HEAD /style.css HTTP/1.1\r\nHost: localhost\r\nRange: {}\r\nConnection: close\r\n\r\n",
+        range
+    ))
+}
+
+#[test]
+fn head_with_a_satisfiable_range_reports_the_partial_response() {
+    let server = head_server();
+    let len = "p { color: red; }\n".len() * 40;
+    for (range, content_range, clen) in [
+        ("bytes=0-9", format!("bytes 0-9/{}", len), 10),
+        (
+            "bytes=-5",
+            format!("bytes {}-{}/{}", len - 5, len - 1, len),
+            5,
+        ),
+        (
+            "bytes=700-",
+            format!("bytes 700-{}/{}", len - 1, len),
+            len - 700,
+        ),
+    ] {
+        let res = head_with_range(&server, range);
+        assert_eq!(res.status, 206, "{}", range);
+        assert_eq!(res.header("content-range"), Some(content_range.as_str()));
+        assert_eq!(
+            res.header("content-length"),
+            Some(clen.to_string().as_str())
+        );
+        assert!(res.header("etag").is_some());
+        assert!(res.body.is_empty());
+    }
+}
+
+#[test]
+fn head_with_an_unsatisfiable_range_is_416() {
+    let server = head_server();
+    let len = "p { color: red; }\n".len() * 40;
+    let res = head_with_range(&server, &format!("bytes={}-", len + 10));
+    assert_eq!(res.status, 416);
+    assert_eq!(
+        res.header("content-range"),
+        Some(format!("bytes */{}", len).as_str())
+    );
+    assert!(res.body.is_empty());
+}
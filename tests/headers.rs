@@ -0,0 +1,41 @@
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use common::TestServer;
+
+fn date_of(res: &common::Response) -> SystemTime {
+    httpdate::parse_http_date(res.header("date").expect("Date header")).unwrap()
+}
+
+fn within_two_seconds(a: SystemTime, b: SystemTime) -> bool {
+    let gap = a
+        .duration_since(b)
+        .or_else(|_| b.duration_since(a))
+        .unwrap();
+    gap <= Duration::from_secs(2)
+}
+
+#[test]
+fn cached_date_header_advances_each_second() {
+    let server = TestServer::start_with(|_, c| c.performance.cache_date_header = true);
+    let first = date_of(&server.get("/robots.txt"));
+    assert!(within_two_seconds(first, SystemTime::now()));
+
+    thread::sleep(Duration::from_millis(1100));
+    let second = date_of(&server.get("/robots.txt"));
+    assert!(second > first, "{:?} not after {:?}", second, first);
+    assert!(within_two_seconds(second, SystemTime::now()));
+}
+
+#[test]
+fn uncached_date_header_is_current() {
+    let server = TestServer::start_with(|_, c| c.performance.cache_date_header = false);
+    assert!(within_two_seconds(
+        date_of(&server.get("/robots.txt")),
+        SystemTime::now()
+    ));
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::{TestServer, write_file};
+
+fn server() -> TestServer {
+    TestServer::start_with(|root, _| {
+        write_file(
+            root,
+            "content/.well-known/acme-challenge/token123",
+            "token123.thumbprint",
+        );
+        write_file(root, "content/.git/config", "[core]");
+        write_file(root, "content/docs/.env", "SECRET=1");
+    })
+}
+
+#[test]
+fn acme_http_01_challenges_are_served() {
+    let server = server();
+    let res = server.get("/.well-known/acme-challenge/token123");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "token123.thumbprint");
+}
+
+#[test]
+fn other_dot_segments_are_forbidden() {
+    let server = server();
+    assert_eq!(server.get("/.git/config").status, 403);
+    assert_eq!(server.get("/docs/.env").status, 403);
+    assert_eq!(server.get("/.well-known/../.git/config").status, 403);
+}
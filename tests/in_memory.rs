@@ -0,0 +1,68 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use std::{net::Ipv4Addr, sync::Arc};
+
+use common::TestServer;
+use lumen::{
+    http::{HttpRequest, ResponseBody, process_http_request, write_head},
+    state::ServerState,
+    theme::reload_theme,
+};
+
+fn get(path: &str) -> HttpRequest {
+    HttpRequest {
+        method: "GET".into(),
+        path: path.into(),
+        accept_encoding: String::new(),
+        accept: String::new(),
+        host: "localhost".into(),
+        authorization: None,
+        range: None,
+        if_none_match: None,
+        if_modified_since: None,
+        if_range: None,
+        user_agent: String::new(),
+        cookie: String::new(),
+        keep_alive: true,
+        peer_ip: Ipv4Addr::LOCALHOST.into(),
+        request_id: "in-memory".into(),
+        http_minor: 1,
+        stream_notify: None,
+    }
+}
+
+#[test]
+fn renders_into_a_vec_the_bytes_the_socket_gets() {
+    let mut config = None;
+    let server = TestServer::start_with(|_, c| config = Some(c.clone()));
+    let state = Arc::new(ServerState::new(config.unwrap()));
+    assert!(reload_theme(&state).is_empty());
+
+    let res = process_http_request(get("/posts/hello-world"), Arc::clone(&state));
+    let mut out = Vec::new();
+    write_head(
+        &mut out,
+        &res,
+        1,
+        &state.http_date(),
+        &state.precomputed_headers.load(),
+    )
+    .unwrap();
+    let Some(ResponseBody::Bytes(body)) = &res.body else {
+        panic!("page body not rendered in memory");
+    };
+    let head = String::from_utf8(out).unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.ends_with("\r\n\r\n"), "{}", head);
+    assert!(
+        head.contains(&format!("Content-Length: {}\r\n", body.len())),
+        "{}",
+        head
+    );
+
+    let over_socket = server.get("/posts/hello-world");
+    assert_eq!(over_socket.status, 200);
+    assert_eq!(&over_socket.body[..], &body[..]);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn serves_end_to_end_without_worker_threads() {
+    let big = "x".repeat(200 * 1024);
+    let server = TestServer::start_with(|root, config| {
+        config.server.threads = 0;
+        common::write_file(root, "content/big.txt", &big);
+    });
+
+    let mut client = server.connect();
+    client.send("GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.status, 200);
+    assert_eq!(res.body.len(), big.len());
+
+    // the connection stays usable for a second request
+    client.send("GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(client.response(false).status, 404);
+    assert!(client.is_closed());
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn renders_pages_inline() {
+    let server = TestServer::start_with(|_, config| config.server.threads = 0);
+    let res = server.get("/");
+    assert_eq!(res.status, 200);
+    assert!(res.text().contains("Welcome to Lumen"));
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn a_stalled_stream_does_not_hold_up_other_renders() {
+    let server = TestServer::start_with(|root, config| {
+        // written a line at a time, and far more than the socket buffers take, so the
+        // stream stalls on its reader while holding the only render slot
+        common::write_file(
+            root,
+            "themes/default/big.html",
+            &format!(
+                "{{% for i in range(1000) %}}{{% for j in range(256) %}}{}\n{{% endfor %}}{{% endfor %}}",
+                "x".repeat(255)
+            ),
+        );
+        common::write_file(root, "content/big.md", "---\ntemplate: big.html\n---\n");
+        config.server.threads = 0;
+        config.performance.max_concurrent_renders = 1;
+        config.performance.enable_caching = false;
+        config.performance.stream_threshold_bytes = 1024;
+    });
+
+    let mut stalled = server.connect();
+    stalled.send("GET /big HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(stalled.response_head().status, 200);
+
+    let res = server.get("/");
+    assert_eq!(res.status, 200);
+    assert!(res.text().contains("Welcome to Lumen"));
+
+    // and the stalled stream still finishes once read
+    let rest = stalled.read_to_close();
+    assert!(rest.len() > 1000 * 256 * 256);
+    assert!(rest.ends_with(b"0\r\n\r\n"));
+}
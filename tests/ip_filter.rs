@@ -0,0 +1,37 @@
+mod common;
+
+use common::TestServer;
+
+fn server(allow: &[&str], deny: &[&str]) -> TestServer {
+    let allow: Vec<String> = allow.iter().map(|s| s.to_string()).collect();
+    let deny: Vec<String> = deny.iter().map(|s| s.to_string()).collect();
+    TestServer::start_with(move |root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.security.allow_ips = allow;
+        config.security.deny_ips = deny;
+    })
+}
+
+#[test]
+fn a_denied_client_gets_403_before_its_request_is_read() {
+    let server = server(&[], &["127.0.0.0/8"]);
+    let mut client = server.connect();
+    let res = client.response_head();
+    assert_eq!(res.status, 403);
+    assert_eq!(res.header("connection"), Some("close"));
+    assert!(client.read_to_close().is_empty());
+}
+
+#[test]
+fn a_client_outside_the_allowlist_is_refused() {
+    let server = server(&["10.0.0.0/8", "fd00::/8"], &[]);
+    assert_eq!(server.get("/hello.txt").status, 403);
+}
+
+#[test]
+fn allowed_and_unlisted_clients_are_served() {
+    let allowed = server(&["127.0.0.1/32"], &["10.0.0.0/8"]);
+    assert_eq!(allowed.get("/hello.txt").text(), "hello");
+    let unlisted = server(&[], &["192.0.2.0/24"]);
+    assert_eq!(unlisted.get("/hello.txt").text(), "hello");
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{Client, TestServer};
+
+fn subscribe(server: &TestServer) -> Client {
+    let mut client = server.connect();
+    client.send(
+        "GET /__lumen/live-reload HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+    );
+    let res = client.response_head();
+    assert_eq!(res.status, 101);
+    assert_eq!(
+        res.header("sec-websocket-accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+    client
+}
+
+#[test]
+fn a_file_change_sends_one_reload_to_subscribers() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.live_reload = true;
+    });
+    let mut client = subscribe(&server);
+
+    // a burst of writes settles into a single message
+    server.write("content/hello.txt", "hello again");
+    server.write("content/new.txt", "new");
+    assert_eq!(client.read_exact(8), b"\x81\x06reload");
+    assert!(client.is_open());
+}
+
+#[test]
+fn theme_changes_reload_too() {
+    let server = TestServer::start_with(|_, config| config.server.live_reload = true);
+    let mut client = subscribe(&server);
+    server.write("themes/default/extra.html", "{{ title }}");
+    assert_eq!(client.read_exact(8), b"\x81\x06reload");
+}
+
+#[test]
+fn the_endpoint_is_an_ordinary_path_when_live_reload_is_off() {
+    let server = TestServer::start();
+    let res = server.request(
+        "GET /__lumen/live-reload HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade, close\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+    );
+    assert_eq!(res.status, 404);
+}
+
+#[test]
+fn serving_files_does_not_count_as_a_change() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.live_reload = true;
+    });
+    let mut client = subscribe(&server);
+    assert_eq!(server.get("/hello.txt").text(), "hello");
+    server.get("/");
+    std::thread::sleep(std::time::Duration::from_millis(400));
+    assert!(client.is_open());
+}
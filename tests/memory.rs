@@ -0,0 +1,27 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{TestServer, wait_for};
+
+static USED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+fn stub_sampler() -> Option<usize> {
+    Some(USED_BYTES.load(Ordering::Relaxed))
+}
+
+#[test]
+fn sheds_requests_while_over_max_memory() {
+    USED_BYTES.store(64 * 1024 * 1024, Ordering::Relaxed);
+    let server = TestServer::start_custom(
+        |_, config| config.performance.max_memory_mb = 32,
+        |server| server.with_memory_sampler(stub_sampler),
+    );
+
+    assert!(wait_for(|| server.get("/robots.txt").status == 503));
+    let res = server.get("/robots.txt");
+    assert!(res.header("x-request-id").is_some());
+
+    USED_BYTES.store(16 * 1024 * 1024, Ordering::Relaxed);
+    assert!(wait_for(|| server.get("/robots.txt").status != 503));
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::TestServer;
+
+#[test]
+fn post_with_a_body_gets_an_intact_405() {
+    let server = TestServer::start();
+    let body = vec![b'x'; 256 * 1024];
+    let mut client = server.connect();
+    client.send(&format!(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    ));
+    // the head is answered straight away; the body keeps arriving after the 405 is out
+    thread::sleep(Duration::from_millis(50));
+    for part in body.chunks(16 * 1024) {
+        client.send_bytes(part);
+    }
+    let res = client.response(false);
+    assert_eq!(res.status, 405);
+    assert_eq!(res.text(), "Method Not Allowed");
+    assert_eq!(res.header("connection"), Some("close"));
+    assert!(res.header("allow").is_some_and(|a| a.contains("GET")));
+    assert!(client.is_closed());
+}
+
+#[test]
+fn pipelined_request_after_a_405_is_not_answered() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.send(
+        "PUT / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+         GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(client.response(false).status, 405);
+    assert!(client.is_closed());
+}
+
+#[test]
+fn cors_preflight_gets_a_204_with_the_configured_headers() {
+    let server = TestServer::start_with(|_, config| {
+        config.security.cors_allow_origin = "https://app.example".into();
+        config.security.cors_allow_methods = "GET, HEAD".into();
+        config.security.cors_allow_headers = "X-Requested-With".into();
+    });
+    let mut client = server.connect();
+    client.send(
+        "OPTIONS /no/such/file.json HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example\r\n\
+         Access-Control-Request-Method: GET\r\n\r\n",
+    );
+    let res = client.response(false);
+    assert_eq!(res.status, 204);
+    assert_eq!(res.header("allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(
+        res.header("access-control-allow-origin"),
+        Some("https://app.example")
+    );
+    assert_eq!(
+        res.header("access-control-allow-methods"),
+        Some("GET, HEAD")
+    );
+    assert_eq!(
+        res.header("access-control-allow-headers"),
+        Some("X-Requested-With")
+    );
+    assert_eq!(res.header("content-length"), None);
+
+    // no body was sent, so the next request reads cleanly
+    client.send("GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert!(client.response(false).status > 0);
+}
+
+#[test]
+fn options_without_cors_only_lists_the_methods() {
+    let server = TestServer::start();
+    let res = server.request("OPTIONS / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(res.status, 204);
+    assert_eq!(res.header("allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(res.header("access-control-allow-methods"), None);
+    assert_eq!(res.header("access-control-allow-origin"), None);
+}
+
+#[test]
+fn options_asterisk_describes_the_server_without_resolving_a_path() {
+    let server = TestServer::start_with(|root, config| {
+        // a file literally named `*` must not be what answers
+        common::write_file(root, "content/*", "not this");
+        config.security.cors_allow_origin = "*".into();
+    });
+    let mut client = server.connect();
+    client.send("OPTIONS * HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.status, 204);
+    assert_eq!(res.header("allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(res.header("content-length"), None);
+    // not a preflight: there is no resource for CORS methods to apply to
+    assert_eq!(res.header("access-control-allow-methods"), None);
+    assert!(res.body.is_empty());
+
+    // the connection stays usable, and `*` is no target for anything but OPTIONS
+    client.send("GET * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.status, 400);
+    assert!(!res.text().contains("not this"));
+}
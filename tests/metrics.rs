@@ -0,0 +1,72 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::TestServer;
+
+fn server(allow_ips: &[&str]) -> TestServer {
+    let allow_ips: Vec<String> = allow_ips.iter().map(|s| s.to_string()).collect();
+    TestServer::start_with(move |root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.metrics_path = "/metrics".into();
+        config.server.metrics_allow_ips = allow_ips;
+    })
+}
+
+#[test]
+fn metrics_are_served_in_prometheus_text_format() {
+    let server = server(&["127.0.0.1"]);
+    assert_eq!(server.get("/hello.txt").status, 200);
+    assert_eq!(server.get("/missing").status, 404);
+    assert!(common::wait_for(|| server
+        .metrics
+        .requests
+        .load(Ordering::Relaxed)
+        == 2));
+
+    let res = server.get("/metrics");
+    assert_eq!(res.status, 200);
+    assert_eq!(
+        res.header("content-type"),
+        Some("text/plain; version=0.0.4; charset=utf-8")
+    );
+    assert_eq!(res.header("cache-control"), Some("no-store"));
+    let text = res.text();
+    assert!(
+        text.contains("lumen_responses_total{class=\"2xx\"} 1\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("lumen_responses_total{class=\"4xx\"} 1\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("# TYPE lumen_active_connections gauge\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("lumen_request_duration_seconds_count 2\n"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn metrics_refuse_other_methods_and_other_clients() {
+    let local = server(&["127.0.0.1"]);
+    let res = local.request("POST /metrics HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    assert_eq!(res.status, 405);
+    assert_eq!(res.header("allow"), Some("GET"));
+
+    let remote_only = server(&["10.0.0.0/8"]);
+    assert_eq!(remote_only.get("/metrics").status, 403);
+}
+
+#[test]
+fn metrics_are_off_without_a_path() {
+    let server = TestServer::start();
+    assert_eq!(server.get("/metrics").status, 404);
+}
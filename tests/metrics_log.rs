@@ -0,0 +1,55 @@
+//! Kept to its own binary: it installs the global subscriber to read the events back.
+mod common;
+
+use common::{TestServer, logged_events, wait_for};
+
+fn metrics_events() -> Vec<serde_json::Value> {
+    logged_events()
+        .into_iter()
+        .filter(|e| e["target"] == "lumen::metrics")
+        .collect()
+}
+
+fn total(events: &[serde_json::Value], field: &str) -> u64 {
+    events
+        .iter()
+        .map(|e| e["fields"][field].as_u64().unwrap_or(0))
+        .sum()
+}
+
+#[test]
+fn periodic_event_carries_the_counters() {
+    logged_events();
+    let server = TestServer::start_with(|_, c| c.server.metrics_interval_secs = 1);
+    for _ in 0..2 {
+        assert_eq!(server.get("/no-such-page").status, 404);
+    }
+
+    // the counts are per interval, so the two requests may be split across events
+    assert!(wait_for(|| total(&metrics_events(), "not_found") >= 2));
+    let events = metrics_events();
+    assert_eq!(total(&events, "requests"), 2);
+    assert_eq!(total(&events, "server_errors"), 0);
+    let fields = &events[0]["fields"];
+    for field in [
+        "active_connections",
+        "queue_depth",
+        "requests",
+        "server_errors",
+        "client_errors",
+        "not_found",
+        "renders",
+        "bytes_sent",
+        "cache_hit_ratio",
+        "cache_bytes",
+    ] {
+        assert!(
+            !fields[field].is_null(),
+            "{} missing from {}",
+            field,
+            fields
+        );
+    }
+    assert_eq!(fields["message"], "metrics");
+    assert_eq!(events[0]["level"], "INFO");
+}
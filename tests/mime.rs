@@ -0,0 +1,35 @@
+mod common;
+
+use common::{TestServer, write_file};
+
+#[test]
+fn mime_overrides_set_the_content_type_of_static_files() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "content/site.webmanifest", "{}");
+        write_file(root, "content/notes.TXT", "notes");
+        write_file(root, "content/style.css", "a{}");
+        config.mime = [
+            ("webmanifest", "application/manifest+json"),
+            (".txt", "text/plain; charset=iso-8859-1"),
+        ]
+        .into_iter()
+        .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+        .collect();
+    });
+    assert_eq!(
+        server.get("/site.webmanifest").header("content-type"),
+        Some("application/manifest+json")
+    );
+    assert_eq!(
+        server.get("/notes.TXT").header("content-type"),
+        Some("text/plain; charset=iso-8859-1")
+    );
+    // extensions without an override keep the built-in guess
+    assert!(
+        server
+            .get("/style.css")
+            .header("content-type")
+            .unwrap()
+            .starts_with("text/css")
+    );
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::{TestServer, write_file};
+
+/// `overlay/` listed ahead of the scaffolded `content/`.
+fn overlay_server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        config.paths.content_dirs = vec![
+            root.join("overlay").to_string_lossy().into_owned(),
+            config.paths.content_dir.clone(),
+        ];
+        write_file(
+            root,
+            "content/about.md",
+            "---\ntitle: Base about\n---\nbase",
+        );
+        write_file(
+            root,
+            "content/only-base.md",
+            "---\ntitle: Base only\n---\nbase",
+        );
+        write_file(
+            root,
+            "overlay/about.md",
+            "---\ntitle: Overlay about\n---\noverlay",
+        );
+        write_file(root, "content/style.css", "base {}");
+        write_file(root, "overlay/style.css", "overlay {}");
+        write_file(
+            root,
+            "content/posts/shared.md",
+            "---\ntitle: Base shared\ndate: \"2026-01-01\"\n---\n",
+        );
+        write_file(
+            root,
+            "overlay/posts/shared.md",
+            "---\ntitle: Overlay shared\ndate: \"2026-01-01\"\n---\n",
+        );
+        write_file(
+            root,
+            "overlay/posts/extra.md",
+            "---\ntitle: Overlay extra\ndate: \"2026-04-01\"\n---\n",
+        );
+        write_file(
+            root,
+            "themes/default/titles.html",
+            "{% for p in list_dir(\"posts\") %}{{ p.title }};{% endfor %}",
+        );
+        write_file(
+            root,
+            "overlay/titles.md",
+            "---\ntemplate: titles.html\n---\n",
+        );
+    })
+}
+
+#[test]
+fn overlay_wins_over_base() {
+    let server = overlay_server();
+    let res = server.get("/about");
+    assert_eq!(res.status, 200);
+    assert!(res.text().contains("Overlay about"), "{}", res.text());
+    assert_eq!(server.get("/style.css").text(), "overlay {}");
+}
+
+#[test]
+fn base_fills_gaps_in_overlay() {
+    let server = overlay_server();
+    let res = server.get("/only-base");
+    assert_eq!(res.status, 200);
+    assert!(res.text().contains("Base only"), "{}", res.text());
+    // the home page only exists in the scaffolded base
+    assert_eq!(server.get("/").status, 200);
+}
+
+#[test]
+fn listings_merge_roots_with_the_first_root_winning() {
+    let server = overlay_server();
+    assert_eq!(
+        server.get("/titles").text(),
+        "Overlay extra;Hello World;Overlay shared;"
+    );
+}
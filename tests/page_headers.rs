@@ -0,0 +1,130 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::{TestServer, write_file};
+
+/// Serves `content/page.md` with `frontmatter` through a theme template made of `template`.
+fn page_server(frontmatter: &str, template: &str) -> TestServer {
+    let (frontmatter, template) = (frontmatter.to_string(), template.to_string());
+    TestServer::start_with(move |root, _| {
+        write_file(root, "themes/default/custom.html", &template);
+        write_file(
+            root,
+            "content/page.md",
+            &format!(
+                "---\ntitle: Page\ntemplate: custom.html\n{}---\nBody\n",
+                frontmatter
+            ),
+        );
+    })
+}
+
+#[test]
+fn frontmatter_and_set_header_add_headers() {
+    let server = page_server(
+        "headers:\n  X-Frontmatter: from yaml\n  Refresh: \"30\"\n",
+        "{{ set_header('X-Template', 'from ' ~ title) }}<p>{{ title }}</p>",
+    );
+    let res = server.get("/page");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("x-frontmatter"), Some("from yaml"));
+    assert_eq!(res.header("refresh"), Some("30"));
+    assert_eq!(res.header("x-template"), Some("from Page"));
+    assert_eq!(res.text(), "<p>Page</p>");
+}
+
+#[test]
+fn frontmatter_header_injection_is_dropped() {
+    let server = page_server(
+        "headers:\n  X-Bad: \"a\\r\\nX-Injected: 1\"\n  Content-Length: \"0\"\n  X-Good: kept\n",
+        "<p>{{ title }}</p>",
+    );
+    let res = server.get("/page");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("x-good"), Some("kept"));
+    assert_eq!(res.header("x-bad"), None);
+    assert_eq!(res.header("x-injected"), None);
+    assert_eq!(res.text(), "<p>Page</p>");
+}
+
+#[test]
+fn set_header_injection_fails_the_render() {
+    for call in [
+        "set_header('X-Evil', 'a\\r\\nX-Injected: 1')",
+        "set_header('X-Evil\\r\\nX-Injected', '1')",
+        "set_header('Transfer-Encoding', 'chunked')",
+    ] {
+        let server = page_server("", &format!("{{{{ {} }}}}<p>{{{{ title }}}}</p>", call));
+        let res = server.get("/page");
+        assert_eq!(res.status, 500, "{}", call);
+        assert_eq!(res.header("x-injected"), None, "{}", call);
+        assert_eq!(res.header("x-evil"), None, "{}", call);
+    }
+}
+
+const FLAG_TEMPLATE: &str =
+    "{% if cookies.flag == 'beta' %}beta{% else %}stable{% endif %} {{ cookies.other }}";
+
+fn get_with_cookie(server: &TestServer, cookie: &str) -> String {
+    server
+        .request(&format!(
+            "GET /page HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nConnection: close\r\n\r\n",
+            cookie
+        ))
+        .text()
+}
+
+#[test]
+fn uncached_pages_branch_on_any_cookie() {
+    let server = page_server("cache: false\n", FLAG_TEMPLATE);
+    assert_eq!(get_with_cookie(&server, "other=1; flag=\"beta\""), "beta 1");
+    assert_eq!(get_with_cookie(&server, "flag=alpha; other=2"), "stable 2");
+}
+
+#[test]
+fn cached_pages_only_see_keyed_cookies() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "themes/default/custom.html", FLAG_TEMPLATE);
+        write_file(
+            root,
+            "content/page.md",
+            "---\ntitle: Page\ntemplate: custom.html\n---\nBody\n",
+        );
+        config.performance.cache_key_cookies = vec!["flag".into()];
+    });
+    // each flag value gets its own cache slot; the unkeyed cookie never leaks into one
+    assert_eq!(get_with_cookie(&server, "flag=beta; other=secret"), "beta ");
+    assert_eq!(get_with_cookie(&server, "flag=alpha; other=2"), "stable ");
+    assert_eq!(get_with_cookie(&server, "flag=beta"), "beta ");
+    let res = server.get("/page");
+    assert!(res.header_values("vary").contains(&"Cookie"));
+}
+
+#[test]
+fn set_cookie_emits_set_cookie_headers() {
+    let server = page_server(
+        "cache: false\n",
+        "{{ set_cookie('theme', 'dark') }}{{ set_cookie('lang', 'en', {'max_age': 60, 'same_site': 'strict'}) }}ok",
+    );
+    let res = server.get("/page");
+    assert_eq!(res.status, 200);
+    assert_eq!(
+        res.header_values("set-cookie"),
+        [
+            "theme=dark; Path=/; HttpOnly; SameSite=Lax",
+            "lang=en; Path=/; Max-Age=60; HttpOnly; SameSite=Strict"
+        ]
+    );
+    assert_eq!(res.text(), "ok");
+}
+
+#[test]
+fn set_cookie_injection_fails_the_render() {
+    let server = page_server("cache: false\n", "{{ set_cookie('theme', cookies.v) }}ok");
+    let res = server.request(
+        "GET /page HTTP/1.1\r\nHost: localhost\r\nCookie: v=\"a;Domain=evil\"\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(res.status, 500);
+    assert!(res.header_values("set-cookie").is_empty());
+}
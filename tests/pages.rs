@@ -0,0 +1,436 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::{TestServer, write_file};
+
+/// A theme template that prints one context value, so tests can read it back as the body.
+fn show(root: &std::path::Path, name: &str, expr: &str) {
+    write_file(root, &format!("themes/default/{}", name), expr);
+}
+
+#[test]
+fn canonical_url_defaults_to_the_page_url() {
+    let server = TestServer::start_with(|root, config| {
+        config.server.base_url = "https://example.com".into();
+        config.seo.canonical_link_header = true;
+        show(root, "canonical.html", "{{ canonical_url|safe }}");
+        write_file(
+            root,
+            "content/about.md",
+            "---\ntemplate: canonical.html\n---\n",
+        );
+        write_file(
+            root,
+            "content/docs/index.md",
+            "---\ntemplate: canonical.html\n---\n",
+        );
+    });
+
+    let res = server.get("/about");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "https://example.com/about");
+    assert_eq!(
+        res.header("link"),
+        Some("<https://example.com/about>; rel=\"canonical\"")
+    );
+
+    let res = server.get("/docs/");
+    assert_eq!(res.text(), "https://example.com/docs/");
+}
+
+#[test]
+fn canonical_frontmatter_overrides_the_derived_url() {
+    let server = TestServer::start_with(|root, config| {
+        config.server.base_url = "https://example.com".into();
+        config.seo.canonical_link_header = true;
+        show(root, "canonical.html", "{{ canonical_url|safe }}");
+        write_file(
+            root,
+            "content/syndicated.md",
+            "---\ntemplate: canonical.html\ncanonical: https://origin.example/post\n---\n",
+        );
+        write_file(
+            root,
+            "content/moved.md",
+            "---\ntemplate: canonical.html\ncanonical: /elsewhere\n---\n",
+        );
+    });
+
+    let res = server.get("/syndicated");
+    assert_eq!(res.text(), "https://origin.example/post");
+    assert_eq!(
+        res.header("link"),
+        Some("<https://origin.example/post>; rel=\"canonical\"")
+    );
+    assert_eq!(server.get("/moved").text(), "https://example.com/elsewhere");
+}
+
+#[test]
+fn canonical_link_header_is_off_by_default() {
+    let server = TestServer::start();
+    assert_eq!(server.get("/").header("link"), None);
+}
+
+#[test]
+fn max_age_frontmatter_sets_cache_control() {
+    let server = TestServer::start_with(|root, _| {
+        write_file(
+            root,
+            "content/long.md",
+            "---\nmax_age: 3600\n---\nlong-lived",
+        );
+        write_file(root, "content/never.md", "---\nmax_age: 0\n---\nvolatile");
+    });
+
+    let res = server.get("/long");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("cache-control"), Some("max-age=3600"));
+    assert!(res.text().contains("long-lived"));
+    assert_eq!(
+        server.get("/never").header("cache-control"),
+        Some("no-store")
+    );
+    assert_eq!(
+        server.get("/posts/hello-world").header("cache-control"),
+        None
+    );
+}
+
+#[test]
+fn status_frontmatter_overrides_the_status_line() {
+    let server = TestServer::start_with(|root, _| {
+        write_file(
+            root,
+            "content/gone.md",
+            "---\ntitle: Gone for good\nstatus: 410\n---\nThis page was removed.",
+        );
+        write_file(root, "content/no-content.md", "---\nstatus: 204\n---\n");
+        write_file(root, "content/blocked.md", "---\nstatus: 451\n---\n");
+    });
+
+    let res = server.get("/gone");
+    assert_eq!(res.status, 410);
+    assert_eq!(res.reason, "Gone");
+    let body = res.text();
+    assert!(body.contains("<h1>Gone for good</h1>"), "{}", body);
+    assert!(body.contains("This page was removed."), "{}", body);
+
+    let res = server.get("/blocked");
+    assert_eq!(res.status, 451);
+    assert_eq!(res.reason, "Unavailable For Legal Reasons");
+
+    // a status that can't carry the rendered body is ignored
+    assert_eq!(server.get("/no-content").status, 200);
+}
+
+#[test]
+fn noindex_pages_get_x_robots_tag_and_stay_out_of_the_sitemap() {
+    let server = TestServer::start_with(|root, _| {
+        write_file(
+            root,
+            "content/thanks.md",
+            "---\nnoindex: true\n---\nThanks!",
+        );
+        write_file(
+            root,
+            "content/notes.md",
+            "---\nnoindex: true\nnofollow: true\n---\n",
+        );
+        write_file(
+            root,
+            "themes/default/sitemap.html",
+            "{% for p in all_pages() %}{{ p.url|safe }};{% endfor %}",
+        );
+        write_file(
+            root,
+            "content/sitemap.md",
+            "---\ntemplate: sitemap.html\n---\n",
+        );
+    });
+
+    let res = server.get("/thanks");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("x-robots-tag"), Some("noindex"));
+    assert_eq!(
+        server.get("/notes").header("x-robots-tag"),
+        Some("noindex, nofollow")
+    );
+    assert_eq!(server.get("/").header("x-robots-tag"), None);
+
+    let sitemap = server.get("/sitemap").text();
+    assert!(sitemap.contains("/posts/hello-world;"), "{}", sitemap);
+    assert!(!sitemap.contains("/thanks"), "{}", sitemap);
+    assert!(!sitemap.contains("/notes"), "{}", sitemap);
+}
+
+#[test]
+fn default_language_and_charset_apply_unless_frontmatter_overrides() {
+    let server = TestServer::start_with(|root, config| {
+        config.defaults.language = "de".into();
+        config.defaults.charset = "iso-8859-1".into();
+        write_file(
+            root,
+            "content/english.md",
+            "---\nlanguage: en\ncharset: utf-8\n---\nHello",
+        );
+    });
+
+    let res = server.get("/posts/hello-world");
+    assert_eq!(res.header("content-language"), Some("de"));
+    assert_eq!(
+        res.header("content-type"),
+        Some("text/html; charset=iso-8859-1")
+    );
+
+    let res = server.get("/english");
+    assert_eq!(res.header("content-language"), Some("en"));
+    assert_eq!(res.header("content-type"), Some("text/html; charset=utf-8"));
+}
+
+#[test]
+fn no_content_language_without_a_default() {
+    let server = TestServer::start();
+    let res = server.get("/posts/hello-world");
+    assert_eq!(res.header("content-language"), None);
+    assert_eq!(res.header("content-type"), Some("text/html; charset=utf-8"));
+}
+
+#[test]
+fn markdown_section_switches_parser_extensions() {
+    let page = "---\ntitle: Notes\n---\nClaim[^src].\n\n[^src]: A source.\n";
+    let plain = TestServer::start_with(|root, _| write_file(root, "content/notes.md", page));
+    let body = plain.get("/notes").text();
+    assert!(!body.contains("footnote-definition"), "{}", body);
+
+    let footnotes = TestServer::start_with(|root, config| {
+        write_file(root, "content/notes.md", page);
+        config.markdown.footnotes = true;
+    });
+    let body = footnotes.get("/notes").text();
+    assert!(body.contains("footnote-definition"), "{}", body);
+}
+
+#[test]
+fn heading_ids_setting_anchors_page_headings() {
+    let page = "---\ntitle: Guide\n---\n## First steps\n\n## First steps\n";
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "content/guide.md", page);
+        config.markdown.heading_ids = lumen::config::HeadingIds::Permalinks;
+    });
+    let body = server.get("/guide").text();
+    assert!(body.contains("<h2 id=\"first-steps\">"), "{}", body);
+    assert!(body.contains("<h2 id=\"first-steps-1\">"), "{}", body);
+    assert!(body.contains("href=\"#first-steps-1\""), "{}", body);
+}
+
+#[test]
+fn toc_nests_headings_and_respects_the_depth() {
+    let page = "---\ntitle: Manual\ntemplate: toc.html\n---\n# Manual\n\n### Skipped level\n\n## Usage\n\n#### Deep detail\n";
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "content/manual.md", page);
+        show(
+            root,
+            "toc.html",
+            "{% macro list(entries) %}{% for e in entries %}{{ e.level }}:{{ e.text }}#{{ e.anchor }}({{ list(e.children) }}){% endfor %}{% endmacro %}\
+             default={{ list(toc(content)) }}\ndeep={{ list(toc(content, 4)) }}",
+        );
+        config.markdown.heading_ids = lumen::config::HeadingIds::Ids;
+    });
+    let body = server.get("/manual").text();
+    let mut lines = body.lines();
+    // a skipped level nests under the nearest shallower heading, and h4 is past the default
+    assert_eq!(
+        lines.next(),
+        Some("default=1:Manual#manual(3:Skipped level#skipped-level()2:Usage#usage())")
+    );
+    assert_eq!(
+        lines.next(),
+        Some(
+            "deep=1:Manual#manual(3:Skipped level#skipped-level()2:Usage#usage(4:Deep detail#deep-detail()))"
+        )
+    );
+}
+
+#[test]
+fn nav_tree_nests_sections_in_weight_order() {
+    let server = TestServer::start_with(|root, config| {
+        // re-render every time, so the second fetch goes through nav_tree's own cache
+        config.performance.enable_caching = false;
+        show(
+            root,
+            "nav.html",
+            "{% macro show(s) %}[{{ s.title }} {{ s.url|safe }} \
+             {% for p in s.pages %}{{ p.title }}={{ p.url|safe }} {% endfor %}\
+             {% for c in s.sections %}{{ show(c) }}{% endfor %}]{% endmacro %}\
+             {{ show(nav_tree(\"docs\")) }}",
+        );
+        write_file(root, "content/nav.md", "---\ntemplate: nav.html\n---\n");
+        write_file(root, "content/docs/index.md", "---\ntitle: Docs\n---\n");
+        write_file(
+            root,
+            "content/docs/intro.md",
+            "---\ntitle: Intro\nweight: 1\n---\n",
+        );
+        write_file(root, "content/docs/setup.md", "---\ntitle: Setup\n---\n");
+        write_file(
+            root,
+            "content/docs/wip.md",
+            "---\ntitle: WIP\ndraft: true\n---\n",
+        );
+        write_file(
+            root,
+            "content/docs/.notes/todo.md",
+            "---\ntitle: Todo\n---\n",
+        );
+        write_file(root, "content/docs/api/x.md", "---\ntitle: X\n---\n");
+        write_file(
+            root,
+            "content/docs/guide/index.md",
+            "---\ntitle: Guide\nweight: \"2\"\n---\n",
+        );
+        write_file(root, "content/docs/guide/b.md", "---\ntitle: Beta\n---\n");
+        write_file(root, "content/docs/guide/a.md", "---\ntitle: Alpha\n---\n");
+    });
+
+    assert_eq!(
+        server.get("/nav").text(),
+        "[Docs /docs/ Setup=/docs/setup Intro=/docs/intro \
+         [api /docs/api/ X=/docs/api/x ]\
+         [Guide /docs/guide/ Alpha=/docs/guide/a Beta=/docs/guide/b ]]"
+    );
+
+    // the cached tree follows the content
+    server.write("content/docs/api/y.md", "---\ntitle: Y\nweight: -1\n---\n");
+    let nav = server.get("/nav").text();
+    assert!(
+        nav.contains("[api /docs/api/ Y=/docs/api/y X=/docs/api/x ]"),
+        "{}",
+        nav
+    );
+}
+
+fn blog_server() -> TestServer {
+    TestServer::start_with(|root, _| {
+        show(
+            root,
+            "blog.html",
+            "{% set p = paginate(\"notes\", query.page, 2) %}\
+             {{ p.page }}/{{ p.total_pages }} of {{ p.total_items }}:\
+             {% for post in p.items %} {{ post.title }}{% endfor %} \
+             prev={{ (p.prev_url or \"none\")|safe }} next={{ (p.next_url or \"none\")|safe }}",
+        );
+        write_file(root, "content/blog.md", "---\ntemplate: blog.html\n---\n");
+        for day in 1..=5 {
+            write_file(
+                root,
+                &format!("content/notes/p{}.md", day),
+                &format!("---\ntitle: P{}\ndate: 2024-01-0{}\n---\n", day, day),
+            );
+        }
+    })
+}
+
+#[test]
+fn paginate_splits_a_listing_by_the_page_query_parameter() {
+    let server = blog_server();
+    assert_eq!(
+        server.get("/blog").text(),
+        "1/3 of 5: P5 P4 prev=none next=/blog?page=2"
+    );
+    assert_eq!(
+        server.get("/blog?page=2").text(),
+        "2/3 of 5: P3 P2 prev=/blog next=/blog?page=3"
+    );
+    assert_eq!(
+        server.get("/blog?page=3").text(),
+        "3/3 of 5: P1 prev=/blog?page=2 next=none"
+    );
+}
+
+#[test]
+fn paginate_clamps_pages_out_of_range() {
+    let server = blog_server();
+    assert!(
+        server
+            .get("/blog?page=99")
+            .text()
+            .starts_with("3/3 of 5: P1 ")
+    );
+    assert!(
+        server
+            .get("/blog?page=0")
+            .text()
+            .starts_with("1/3 of 5: P5 P4 ")
+    );
+    assert!(
+        server
+            .get("/blog?page=x")
+            .text()
+            .starts_with("1/3 of 5: P5 P4 ")
+    );
+}
+
+#[test]
+fn list_by_tag_and_tag_counts_ignore_case() {
+    let server = TestServer::start_with(|root, _| {
+        show(
+            root,
+            "tags.html",
+            "{% for post in list_by_tag(\"notes\", \"Rust\") %}{{ post.title }} {% endfor %}|\
+             {% for post in list_by_tag(\"notes\", \"intro\", \"categories\") %}{{ post.title }} {% endfor %}|\
+             {% for t in tag_counts(\"notes\") %}{{ t.tag }}={{ t.count }} {% endfor %}",
+        );
+        write_file(root, "content/tags.md", "---\ntemplate: tags.html\n---\n");
+        write_file(
+            root,
+            "content/notes/a.md",
+            "---\ntitle: A\ndate: 2024-01-01\ntags: [rust, web]\ncategories: intro\n---\n",
+        );
+        write_file(
+            root,
+            "content/notes/b.md",
+            "---\ntitle: B\ndate: 2024-01-02\ntags: [Rust, RUST, cli]\n---\n",
+        );
+        write_file(
+            root,
+            "content/notes/c.md",
+            "---\ntitle: C\ndate: 2024-01-03\ntags: web\n---\n",
+        );
+    });
+    // counted under the spelling of the newest post, and once per post
+    assert_eq!(server.get("/tags").text(), "B A |A |Rust=2 web=2 cli=1 ");
+}
+
+#[test]
+fn list_dir_sorts_by_parsed_date_and_dateformat_formats_it() {
+    let server = TestServer::start_with(|root, _| {
+        show(
+            root,
+            "dates.html",
+            "{% for post in list_dir(\"notes\") %}{{ post.title }}@{{ post.date|dateformat(\"%b %-d, %Y\") }};{% endfor %}",
+        );
+        write_file(root, "content/dates.md", "---\ntemplate: dates.html\n---\n");
+        for (name, date) in [
+            ("a", "2024-02-10"),
+            ("b", "March 5, 2024"),
+            ("c", "2024/1/20 09:30"),
+            ("d", "someday"),
+        ] {
+            write_file(
+                root,
+                &format!("content/notes/{}.md", name),
+                &format!(
+                    "---\ntitle: {}\ndate: \"{}\"\n---\n",
+                    name.to_uppercase(),
+                    date
+                ),
+            );
+        }
+    });
+    // newest first whatever the spelling; a date that doesn't parse goes last, as written
+    assert_eq!(
+        server.get("/dates").text(),
+        "B@Mar 5, 2024;A@Feb 10, 2024;C@Jan 20, 2024;D@someday;"
+    );
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::{TestServer, write_file};
+
+const PAGE: &str = "---\ntitle: Notes\n---\n# Notes\n\nSome *emphasis* here.\n";
+
+fn server(enabled: bool) -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(root, "content/notes.md", PAGE);
+        config.seo.plaintext_alternates = enabled;
+    })
+}
+
+fn get_accepting(server: &TestServer, path: &str, accept: &str) -> common::Response {
+    server.request(&format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+        path, accept
+    ))
+}
+
+#[test]
+fn txt_alternate_serves_the_source_without_frontmatter() {
+    let server = server(true);
+    let res = server.get("/notes.txt");
+    assert_eq!(res.status, 200);
+    assert_eq!(
+        res.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(res.text(), "# Notes\n\nSome *emphasis* here.\n");
+    assert_eq!(res.header("vary"), None);
+}
+
+#[test]
+fn accept_text_plain_negotiates_the_source() {
+    let server = server(true);
+    let plain = get_accepting(&server, "/notes", "text/plain, text/html;q=0.5");
+    assert_eq!(plain.status, 200);
+    assert!(
+        plain
+            .header("content-type")
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    assert!(plain.text().starts_with("# Notes"));
+    assert!(plain.header_values("vary").contains(&"Accept"));
+
+    let html = get_accepting(&server, "/notes", "text/html,*/*;q=0.8");
+    assert!(
+        html.header("content-type")
+            .unwrap()
+            .starts_with("text/html")
+    );
+    assert!(html.text().contains("<em>emphasis</em>"));
+    assert!(html.header_values("vary").contains(&"Accept"));
+}
+
+#[test]
+fn raw_md_stays_forbidden() {
+    let server = server(true);
+    assert_eq!(server.get("/notes.md").status, 403);
+}
+
+#[test]
+fn alternates_are_off_by_default() {
+    let server = server(false);
+    assert_eq!(server.get("/notes.txt").status, 404);
+    let res = get_accepting(&server, "/notes", "text/plain");
+    assert!(res.header("content-type").unwrap().starts_with("text/html"));
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use common::TestServer;
+
+fn assert_version_rejected(server: &TestServer, raw: &[u8]) {
+    let mut client = server.connect();
+    client.send_bytes(raw);
+    let res = client.response(false);
+    assert_eq!(res.status, 505, "{:?}", String::from_utf8_lossy(raw));
+    assert_eq!(res.reason, "HTTP Version Not Supported");
+    assert_eq!(res.text(), "HTTP Version Not Supported");
+    assert_eq!(res.header("connection"), Some("close"));
+    assert!(client.is_closed());
+}
+
+#[test]
+fn http09_simple_request_gets_505() {
+    let server = TestServer::start();
+    assert_version_rejected(&server, b"GET /\r\n");
+    assert_version_rejected(&server, b"GET /index.html\n");
+}
+
+#[test]
+fn h2c_preface_gets_505() {
+    let server = TestServer::start();
+    assert_version_rejected(&server, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+}
+
+#[test]
+fn bogus_versions_get_505() {
+    let server = TestServer::start();
+    assert_version_rejected(&server, b"GET / HTTP/2.0\r\nHost: localhost\r\n\r\n");
+    assert_version_rejected(&server, b"GET / HTTP/1.7\r\nHost: localhost\r\n\r\n");
+}
+
+#[test]
+fn a_505_for_head_has_no_body() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.send("HEAD / HTTP/2.0\r\nHost: localhost\r\n\r\n");
+    let res = client.response(true);
+    assert_eq!(res.status, 505);
+    assert_eq!(
+        res.header("content-length"),
+        Some("HTTP Version Not Supported".len().to_string().as_str())
+    );
+    assert!(client.read_to_close().is_empty());
+}
+
+#[test]
+fn http10_and_http11_are_served() {
+    let server = TestServer::start();
+    let res = server.request("GET /robots.txt HTTP/1.0\r\n\r\n");
+    assert_eq!(res.version, "HTTP/1.0");
+    assert_ne!(res.status, 505);
+    let res = server.get("/robots.txt");
+    assert_eq!(res.version, "HTTP/1.1");
+    assert_ne!(res.status, 505);
+}
+
+const INVALID_PATHS: [&str; 5] = ["/%FF%FE", "/%E2%82", "/%C0%AF", "/%ZZ", "/trailing%4"];
+
+#[test]
+fn invalid_percent_encoding_is_a_bad_request() {
+    let server = TestServer::start();
+    for path in INVALID_PATHS {
+        let res = server.get(path);
+        assert_eq!(res.status, 400, "{}", path);
+        assert_eq!(res.header("connection"), Some("close"), "{}", path);
+    }
+}
+
+#[test]
+fn valid_escapes_still_resolve() {
+    let server = TestServer::start_with(|root, _| {
+        common::write_file(root, "content/caf\u{e9} menu.txt", "menu");
+    });
+    let res = server.get("/caf%C3%A9%20menu.txt");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "menu");
+}
+
+#[test]
+fn lenient_decoding_falls_back_to_the_raw_path() {
+    let server = TestServer::start_with(|_, config| {
+        config.security.strict_path_decoding = false;
+    });
+    for path in INVALID_PATHS {
+        assert_eq!(server.get(path).status, 404, "{}", path);
+    }
+}
+
+#[test]
+fn http10_closes_unless_asked_to_keep_alive() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.send("GET /favicon.ico HTTP/1.0\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.version, "HTTP/1.0");
+    assert_eq!(res.header("connection"), Some("close"));
+    assert!(client.is_closed());
+
+    let mut client = server.connect();
+    client.send("GET /favicon.ico HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.version, "HTTP/1.0");
+    assert_eq!(res.header("connection"), Some("keep-alive"));
+    client.send("GET /favicon.ico HTTP/1.0\r\n\r\n");
+    assert_eq!(client.response(false).version, "HTTP/1.0");
+    assert!(client.is_closed());
+}
+
+#[test]
+fn http11_keeps_alive_unless_asked_to_close() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.send("GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.version, "HTTP/1.1");
+    assert_eq!(res.header("connection"), Some("keep-alive"));
+    assert!(client.is_open());
+    client.send("GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(client.response(false).header("connection"), Some("close"));
+    assert!(client.is_closed());
+}
+
+#[test]
+fn a_client_half_closing_mid_connection_still_gets_its_answer() {
+    let server = TestServer::start_with(|root, _| {
+        common::write_file(root, "content/hello.txt", "hello");
+    });
+    let mut client = server.connect();
+    client.send(&"GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(2));
+    client.send("GET /hello.txt HTTP/1.1\r\nHost: loc");
+    client.finish();
+    for _ in 0..2 {
+        let res = client.response(false);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "hello");
+    }
+    // the truncated third request is dropped along with the connection
+    assert!(client.is_closed());
+}
+
+#[test]
+fn a_client_vanishing_mid_request_frees_its_connection() {
+    use std::sync::atomic::Ordering;
+
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.send("GET /hello.txt HTTP/1.1\r\nHost: loc");
+    assert!(common::wait_for(|| {
+        server.metrics.active_connections.load(Ordering::Relaxed) == 1
+    }));
+    drop(client);
+    assert!(common::wait_for(|| {
+        server.metrics.active_connections.load(Ordering::Relaxed) == 0
+    }));
+    assert_eq!(server.metrics.server_errors.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn a_tls_client_hello_on_the_http_port_is_refused() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    // the start of a TLS 1.2 ClientHello record
+    client.send_bytes(&[
+        0x16, 0x03, 0x01, 0x00, 0xa5, 0x01, 0x00, 0x00, 0xa1, 0x03, 0x03, 0x00, 0x01, 0x02,
+    ]);
+    // an HTTP answer would be garbage to a TLS client, so it is simply disconnected
+    assert!(client.read_to_close().is_empty());
+}
+
+#[test]
+fn pipelining_past_max_keepalive_requests_closes_the_connection() {
+    let server = TestServer::start_with(|root, config| {
+        common::write_file(root, "content/hello.txt", "hello");
+        config.server.max_keepalive_requests = 3;
+    });
+    let mut client = server.connect();
+    client.send(&"GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(10));
+    for n in 1..=3 {
+        let res = client.response(false);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "hello");
+        let close = res.header("connection") == Some("close");
+        assert_eq!(close, n == 3, "response {}", n);
+    }
+    // the seven requests behind the cap are dropped unanswered
+    assert!(client.read_to_close().is_empty());
+}
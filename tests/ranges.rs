@@ -0,0 +1,57 @@
+mod common;
+
+use common::TestServer;
+
+const LEN: usize = 300_000;
+
+fn payload() -> Vec<u8> {
+    (0..LEN).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn pipelined_range_requests_keep_the_connection_in_step() {
+    let server = TestServer::start_with(|root, _| {
+        std::fs::write(root.join("content/blob.bin"), payload()).unwrap();
+    });
+    let mut client = server.connect();
+    client.send(concat!(
+        "GET /blob.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes=1000-100999\r\n\r\n",
+        "GET /blob.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-9,20-29\r\n\r\n",
+        "HEAD /blob.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-99\r\n\r\n",
+        "GET /blob.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    ));
+    let payload = payload();
+
+    let partial = client.response(false);
+    assert_eq!(partial.status, 206);
+    assert_eq!(
+        partial.header("content-range"),
+        Some(format!("bytes 1000-100999/{}", LEN).as_str())
+    );
+    assert_eq!(partial.header("content-length"), Some("100000"));
+    assert_eq!(partial.body, &payload[1000..101_000]);
+
+    // several ranges are answered with the whole file rather than multipart
+    let multi = client.response(false);
+    assert_eq!(multi.status, 200);
+    assert_eq!(multi.header("content-range"), None);
+    assert_eq!(
+        multi.header("content-length"),
+        Some(LEN.to_string().as_str())
+    );
+    assert_eq!(multi.body, payload);
+
+    let head = client.response(true);
+    assert_eq!(head.status, 206);
+    assert_eq!(
+        head.header("content-range"),
+        Some(format!("bytes 0-99/{}", LEN).as_str())
+    );
+    assert_eq!(head.header("content-length"), Some("100"));
+
+    // had the HEAD sent a body, this status line would be read from its bytes
+    let full = client.response(false);
+    assert_eq!(full.status, 200);
+    assert_eq!(full.body, payload);
+    assert!(client.read_to_close().is_empty());
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{TestServer, write_file};
+use lumen::config::{Config, RedirectRule};
+
+fn rule(from: &str, to: &str, status: u16) -> RedirectRule {
+    RedirectRule {
+        from: from.into(),
+        to: to.into(),
+        status,
+    }
+}
+
+fn with_rules(config: &mut Config) {
+    config.redirect = vec![
+        rule("/old", "/new", 301),
+        rule("/blog/*", "/posts/", 308),
+        rule("/chat", "https://chat.example/room?ref=site", 302),
+        rule("/hello.txt", "/elsewhere", 307),
+        rule("/old", "/never", 302),
+    ];
+}
+
+#[test]
+fn an_exact_rule_redirects_with_its_status() {
+    let server = TestServer::start_with(|_, config| with_rules(config));
+    let res = server.get("/old");
+    assert_eq!(res.status, 301);
+    assert_eq!(res.header("location"), Some("/new"));
+    assert!(
+        res.text().starts_with("301 Moved Permanently: "),
+        "{}",
+        res.text()
+    );
+    // only the first matching rule applies, and only to the exact path
+    assert_eq!(server.get("/old/").status, 404);
+}
+
+#[test]
+fn a_prefix_rule_appends_the_rest_of_the_path_and_keeps_the_query() {
+    let server = TestServer::start_with(|_, config| with_rules(config));
+    let res = server.get("/blog/2024/hello%20world?page=2");
+    assert_eq!(res.status, 308);
+    assert_eq!(
+        res.header("location"),
+        Some("/posts/2024/hello%20world?page=2")
+    );
+}
+
+#[test]
+fn an_external_target_keeps_its_own_query() {
+    let server = TestServer::start_with(|_, config| with_rules(config));
+    let res = server.get("/chat?utm=x");
+    assert_eq!(res.status, 302);
+    assert_eq!(
+        res.header("location"),
+        Some("https://chat.example/room?ref=site")
+    );
+}
+
+#[test]
+fn rules_are_checked_before_the_filesystem() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(root, "content/hello.txt", "hello");
+        with_rules(config);
+    });
+    let res = server.get("/hello.txt");
+    assert_eq!(res.status, 307);
+    assert_eq!(res.header("location"), Some("/elsewhere"));
+}
+
+#[test]
+fn site_targets_get_the_base_path() {
+    let server = TestServer::start_with(|_, config| {
+        config.server.base_path = "/docs".into();
+        with_rules(config);
+    });
+    let res = server.get("/docs/old");
+    assert_eq!(res.status, 301);
+    assert_eq!(res.header("location"), Some("/docs/new"));
+}
@@ -0,0 +1,70 @@
+//! Kept to its own binary: SIGHUP reaches every server in the process, and the warnings are read
+//! back from the global subscriber.
+#![cfg(unix)]
+
+mod common;
+
+use common::{TestServer, logged_events, wait_for};
+
+fn sighup() {
+    // SAFETY: raise only delivers a signal to this process, whose handler the server installed
+    unsafe {
+        libc::raise(libc::SIGHUP);
+    }
+}
+
+fn warnings() -> Vec<String> {
+    logged_events()
+        .into_iter()
+        .filter(|e| e["level"] == "WARN")
+        .filter_map(|e| e["fields"]["message"].as_str().map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn sighup_swaps_the_config_without_dropping_connections() {
+    logged_events();
+    let server = TestServer::start_reloadable(|root, _| {
+        common::write_file(root, "content/hello.txt", "hello");
+    });
+    let mut client = server.connect();
+    client.send("GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let before = client.response(false);
+    assert_eq!(before.header("x-frame-options"), Some("DENY"));
+
+    server.edit_config(|config| {
+        config.security.x_frame_options = "SAMEORIGIN".into();
+        config.server.port = 1;
+    });
+    sighup();
+    assert!(wait_for(|| {
+        server.get("/hello.txt").header("x-frame-options") == Some("SAMEORIGIN")
+    }));
+    assert!(
+        warnings()
+            .iter()
+            .any(|w| w.starts_with("server.port changed"))
+    );
+
+    // the connection opened before the reload is still served, under the new config
+    client.send("GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let after = client.response(false);
+    assert_eq!(after.status, 200);
+    assert_eq!(after.header("x-frame-options"), Some("SAMEORIGIN"));
+
+    // a file that no longer parses leaves the running config in place
+    server.write("lumen.toml", "[server\nport = ");
+    sighup();
+    assert!(wait_for(|| {
+        logged_events().iter().any(|e| {
+            e["level"] == "ERROR"
+                && e["fields"]["message"]
+                    .as_str()
+                    .is_some_and(|m| m.starts_with("Keeping the running configuration"))
+        })
+    }));
+    client.send("GET /hello.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let kept = client.response(false);
+    assert_eq!(kept.status, 200);
+    assert_eq!(kept.header("x-frame-options"), Some("SAMEORIGIN"));
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use std::{sync::atomic::Ordering, thread};
+
+use common::{TestServer, write_file};
+
+#[test]
+fn capped_renders_still_answer_every_concurrent_miss() {
+    let server = TestServer::start_with(|root, config| {
+        for i in 0..8 {
+            write_file(
+                root,
+                &format!("content/page{}.md", i),
+                &format!(
+                    "---\ntitle: Page {}\n---\n{}",
+                    i,
+                    "Some text.\n\n".repeat(200)
+                ),
+            );
+        }
+        config.performance.max_concurrent_renders = 1;
+    });
+    thread::scope(|s| {
+        let server = &server;
+        let requests: Vec<_> = (0..8)
+            .map(|i| s.spawn(move || server.get(&format!("/page{}", i))))
+            .collect();
+        for (i, request) in requests.into_iter().enumerate() {
+            let res = request.join().unwrap();
+            assert_eq!(res.status, 200);
+            assert!(res.text().contains(&format!("Page {}", i)));
+        }
+    });
+    assert_eq!(server.metrics.renders.load(Ordering::Relaxed), 8);
+}
+
+#[test]
+fn simultaneous_misses_on_one_page_render_once() {
+    const CLIENTS: usize = 16;
+    let server = TestServer::start_with(|root, _| {
+        // big enough that the first render is still running when the others arrive
+        let body = "A paragraph with *some* `inline` markup and a [link](/x).\n\n".repeat(20_000);
+        write_file(
+            root,
+            "content/big.md",
+            &format!("---\ntitle: Big\n---\n{}", body),
+        );
+    });
+    let request = "GET /big HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let mut clients: Vec<_> = (0..CLIENTS).map(|_| server.connect()).collect();
+    // every request is on the wire before any response is read
+    for client in &mut clients {
+        client.send(request);
+    }
+    let bodies: Vec<_> = clients
+        .iter_mut()
+        .map(|client| {
+            let res = client.response(false);
+            assert_eq!(res.status, 200);
+            res.body
+        })
+        .collect();
+    assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+    assert_eq!(server.metrics.renders.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn a_render_past_max_rendered_bytes_is_a_clean_500() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(
+            root,
+            "themes/default/runaway.html",
+            "{% for i in range(1000000) %}runaway output {% endfor %}",
+        );
+        write_file(
+            root,
+            "content/runaway.md",
+            "---\ntitle: Runaway\ntemplate: runaway.html\n---\nBody.",
+        );
+        config.performance.max_rendered_bytes = 64 * 1024;
+    });
+    let mut client = server.connect();
+    client.send("GET /runaway HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let res = client.response(false);
+    assert_eq!(res.status, 500);
+    assert!(res.body.len() < 64 * 1024);
+    assert!(!res.text().contains("runaway output"));
+
+    // the failed render leaves the connection and the rest of the site working
+    client.send("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(client.response(false).status, 200);
+}
+
+#[test]
+fn a_cache_ttl_renders_the_page_again_once_it_lapses() {
+    let server = TestServer::start_with(|root, config| {
+        write_file(
+            root,
+            "content/live.md",
+            "---\ntitle: Live\ncache_ttl: 1\n---\n",
+        );
+        write_file(root, "content/still.md", "---\ntitle: Still\n---\n");
+        config.performance.disk_cache_dir = root.join("disk-cache").to_string_lossy().into_owned();
+    });
+    let renders = || server.metrics.renders.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        assert_eq!(server.get("/live").status, 200);
+        assert_eq!(server.get("/still").status, 200);
+    }
+    assert_eq!(renders(), 2);
+
+    thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(server.get("/live").status, 200);
+    assert_eq!(server.get("/still").status, 200);
+    // only the page with a TTL, which the disk cache doesn't hold either
+    assert_eq!(renders(), 3);
+    assert_eq!(server.get("/live").status, 200);
+    assert_eq!(renders(), 3);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{TestServer, logged_events, wait_for};
+use lumen::config::LogLevel;
+
+#[test]
+fn every_response_gets_a_fresh_request_id() {
+    let server = TestServer::start();
+    let a = server.get("/");
+    let b = server.get("/missing");
+    let (a, b) = (a.header("x-request-id"), b.header("x-request-id"));
+    assert!(a.is_some_and(|id| !id.is_empty()));
+    assert!(b.is_some_and(|id| !id.is_empty()));
+    assert_ne!(a, b);
+}
+
+#[test]
+fn incoming_request_id_is_echoed_only_when_trusted_and_valid() {
+    let raw = |id: &str| {
+        format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: {}\r\nConnection: close\r\n\r\n",
+            id
+        )
+    };
+
+    let untrusted = TestServer::start();
+    let res = untrusted.request(&raw("from-proxy-1"));
+    assert_ne!(res.header("x-request-id"), Some("from-proxy-1"));
+
+    let trusted = TestServer::start_with(|_, config| config.server.trust_request_id = true);
+    let res = trusted.request(&raw("from-proxy-1"));
+    assert_eq!(res.header("x-request-id"), Some("from-proxy-1"));
+    let res = trusted.request(&raw("bad id<script>"));
+    let id = res.header("x-request-id").unwrap();
+    assert!(!id.contains('<'), "{}", id);
+}
+
+#[test]
+fn logs_while_serving_carry_the_request_span() {
+    logged_events();
+    let server = TestServer::start_with(|_, config| {
+        config.server.trust_request_id = true;
+        config.server.client_error_log_level = LogLevel::Info;
+    });
+    let res = server.request(
+        "GET /no-such-page HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: trace-me-42\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(res.status, 404);
+
+    let logged = || {
+        logged_events().into_iter().find(|event| {
+            event["fields"]["message"]
+                .as_str()
+                .is_some_and(|m| m.contains("/no-such-page"))
+        })
+    };
+    assert!(wait_for(|| logged().is_some()));
+    let span = &logged().unwrap()["span"];
+    assert_eq!(span["name"], "request");
+    assert_eq!(span["id"], "trace-me-42");
+    assert_eq!(span["ip"], "127.0.0.1");
+}
@@ -0,0 +1,274 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::{TestServer, write_file};
+use lumen::config::{Config, DirPrecedence, DirRedirect, TrailingSlash};
+
+/// `guide` is both `guide.md` and a `guide/` directory with an index; `blog/` only a
+/// directory; `solo` only a file.
+fn routing_server(configure: impl FnOnce(&mut Config)) -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(root, "content/guide.md", "---\ntitle: Guide file\n---\n");
+        write_file(
+            root,
+            "content/guide/index.md",
+            "---\ntitle: Guide index\n---\n",
+        );
+        write_file(
+            root,
+            "content/blog/index.md",
+            "---\ntitle: Blog index\n---\n",
+        );
+        write_file(root, "content/solo.md", "---\ntitle: Solo file\n---\n");
+        configure(config);
+    })
+}
+
+fn title_of(server: &TestServer, path: &str) -> (u16, String) {
+    let res = server.get(path);
+    let body = res.text();
+    let title = body
+        .split("<title>")
+        .nth(1)
+        .and_then(|rest| rest.split("</title>").next())
+        .unwrap_or_default()
+        .to_string();
+    (res.status, title)
+}
+
+#[test]
+fn directory_index_beats_sibling_file_with_a_slash() {
+    for precedence in [DirPrecedence::File, DirPrecedence::Directory] {
+        let server = routing_server(|c| c.paths.dir_precedence = precedence);
+        assert_eq!(title_of(&server, "/guide/"), (200, "Guide index".into()));
+    }
+}
+
+#[test]
+fn file_precedence_serves_the_file_without_a_slash() {
+    let server = routing_server(|c| c.paths.dir_precedence = DirPrecedence::File);
+    assert_eq!(title_of(&server, "/guide"), (200, "Guide file".into()));
+}
+
+#[test]
+fn directory_precedence_redirects_without_a_slash() {
+    let server = routing_server(|c| c.paths.dir_precedence = DirPrecedence::Directory);
+    let res = server.get("/guide");
+    assert_eq!(res.status, 301);
+    assert_eq!(res.header("location"), Some("/guide/"));
+}
+
+#[test]
+fn directory_without_a_file_follows_dir_redirect() {
+    let server = routing_server(|c| c.paths.dir_redirect = DirRedirect::Redirect);
+    let res = server.get("/blog?page=2");
+    assert_eq!(res.status, 301);
+    assert_eq!(res.header("location"), Some("/blog/?page=2"));
+
+    let server = routing_server(|c| c.paths.dir_redirect = DirRedirect::NotFound);
+    assert_eq!(server.get("/blog").status, 404);
+    assert_eq!(title_of(&server, "/blog/"), (200, "Blog index".into()));
+
+    let server = routing_server(|c| c.paths.dir_redirect = DirRedirect::Index);
+    assert_eq!(title_of(&server, "/blog"), (200, "Blog index".into()));
+}
+
+#[test]
+fn file_without_a_directory_is_not_found_with_a_slash() {
+    let server = routing_server(|_| {});
+    assert_eq!(title_of(&server, "/solo"), (200, "Solo file".into()));
+    assert_eq!(server.get("/solo/").status, 404);
+}
+
+#[test]
+fn index_urls_redirect_to_the_directory() {
+    let server = routing_server(|_| {});
+    for (path, location) in [
+        ("/index", "/"),
+        ("/index.html", "/"),
+        ("/index?ref=nav", "/?ref=nav"),
+        ("/guide/index", "/guide/"),
+    ] {
+        let res = server.get(path);
+        assert_eq!(res.status, 301, "{}", path);
+        assert_eq!(res.header("location"), Some(location), "{}", path);
+    }
+
+    let res = server.get("/");
+    assert_eq!(res.status, 200);
+    assert!(res.text().contains("Welcome to Lumen"));
+}
+
+#[test]
+fn index_urls_are_served_when_redirect_index_is_off() {
+    let server = routing_server(|c| c.seo.redirect_index = false);
+    let res = server.get("/index");
+    assert_eq!(res.status, 200);
+    assert!(res.text().contains("Welcome to Lumen"));
+}
+
+#[test]
+fn dir_redirect_applies_to_directories_without_pages() {
+    let assets = |mode| {
+        routing_server(|c| {
+            c.paths.dir_redirect = mode;
+            c.server.autoindex = true;
+            write_file(
+                std::path::Path::new(&c.paths.content_dir),
+                "assets/app.css",
+                "p {}",
+            );
+        })
+    };
+    let server = assets(DirRedirect::Redirect);
+    let res = server.get("/assets");
+    assert_eq!(res.status, 301);
+    assert_eq!(res.header("location"), Some("/assets/"));
+    assert_eq!(server.get("/assets/").status, 200);
+
+    let server = assets(DirRedirect::NotFound);
+    assert_eq!(server.get("/assets").status, 404);
+    assert_eq!(server.get("/assets/app.css").status, 200);
+}
+
+#[test]
+fn redirect_is_the_default() {
+    let server = routing_server(|_| {});
+    assert_eq!(server.get("/blog").status, 301);
+}
+
+/// `both/` has an `index.md` and a prebuilt `index.html`.
+fn index_server(index_files: &[&str]) -> TestServer {
+    let index_files = index_files.iter().map(|f| f.to_string()).collect();
+    TestServer::start_with(|root, config| {
+        write_file(
+            root,
+            "content/both/index.md",
+            "---\ntitle: Markdown index\n---\n",
+        );
+        write_file(
+            root,
+            "content/both/index.html",
+            "<title>Static index</title>",
+        );
+        config.paths.index_files = index_files;
+    })
+}
+
+#[test]
+fn markdown_index_wins_by_default() {
+    let server = index_server(&["index.md", "index.html"]);
+    assert_eq!(
+        title_of(&server, "/both/"),
+        (200, "Markdown index".to_string())
+    );
+}
+
+#[test]
+fn listing_index_html_first_serves_the_static_override() {
+    let server = index_server(&["index.html", "index.md"]);
+    let res = server.get("/both/");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "<title>Static index</title>");
+}
+
+#[test]
+fn index_files_missing_from_the_list_are_not_served() {
+    let server = index_server(&["index.html"]);
+    assert_eq!(server.get("/both/").text(), "<title>Static index</title>");
+    let server = index_server(&["default.md"]);
+    assert_eq!(server.get("/both/").status, 404);
+}
+
+fn html_server(extensionless_html: bool) -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(root, "content/about.html", "<title>About (static)</title>");
+        write_file(root, "content/both.html", "<title>Both (static)</title>");
+        write_file(
+            root,
+            "content/both.md",
+            "---\ntitle: Both (markdown)\n---\n",
+        );
+        config.paths.extensionless_html = extensionless_html;
+    })
+}
+
+#[test]
+fn extensionless_html_serves_clean_urls_for_static_pages() {
+    let server = html_server(true);
+    let res = server.get("/about");
+    assert_eq!(res.status, 200);
+    assert!(res.header("content-type").unwrap().starts_with("text/html"));
+    assert_eq!(res.text(), "<title>About (static)</title>");
+    assert_eq!(server.get("/about.html").status, 200);
+    // a page of the same name still wins, and its source stays hidden
+    assert_eq!(
+        title_of(&server, "/both"),
+        (200, "Both (markdown)".to_string())
+    );
+    assert_eq!(server.get("/both.md").status, 403);
+    assert_eq!(server.get("/missing").status, 404);
+}
+
+#[test]
+fn extensionless_html_is_off_by_default() {
+    let server = html_server(false);
+    assert_eq!(server.get("/about").status, 404);
+    assert_eq!(server.get("/about.html").status, 200);
+}
+
+fn redirect_of(server: &TestServer, path: &str) -> (u16, Option<String>) {
+    let res = server.get(path);
+    (res.status, res.header("location").map(str::to_string))
+}
+
+#[test]
+fn always_redirect_makes_the_slashed_url_canonical() {
+    let server = routing_server(|config| {
+        config.server.trailing_slash = TrailingSlash::AlwaysRedirect;
+    });
+    assert_eq!(
+        redirect_of(&server, "/solo?x=1"),
+        (301, Some("/solo/?x=1".into()))
+    );
+    assert_eq!(title_of(&server, "/solo/"), (200, "Solo file".into()));
+    assert_eq!(redirect_of(&server, "/blog"), (301, Some("/blog/".into())));
+    assert_eq!(title_of(&server, "/blog/"), (200, "Blog index".into()));
+    // a directory index still wins over the page of the same name
+    assert_eq!(title_of(&server, "/guide/"), (200, "Guide index".into()));
+    assert_eq!(server.get("/missing").status, 404);
+}
+
+#[test]
+fn never_redirect_makes_the_bare_url_canonical() {
+    let server = routing_server(|config| {
+        config.server.trailing_slash = TrailingSlash::NeverRedirect;
+        config.seo.redirect_index = true;
+    });
+    assert_eq!(
+        redirect_of(&server, "/solo/?x=1"),
+        (301, Some("/solo?x=1".into()))
+    );
+    assert_eq!(title_of(&server, "/solo"), (200, "Solo file".into()));
+    assert_eq!(redirect_of(&server, "/blog/"), (301, Some("/blog".into())));
+    assert_eq!(title_of(&server, "/blog"), (200, "Blog index".into()));
+    // straight to the bare path, without a hop through `/blog/`
+    assert_eq!(
+        redirect_of(&server, "/blog/index"),
+        (301, Some("/blog".into()))
+    );
+    // the site root keeps its slash
+    assert_eq!(server.get("/").status, 200);
+    assert_eq!(server.get("/missing/").status, 404);
+}
+
+#[test]
+fn keep_serves_both_spellings_as_they_resolve() {
+    let server = routing_server(|config| {
+        config.server.trailing_slash = TrailingSlash::Keep;
+    });
+    assert_eq!(title_of(&server, "/solo"), (200, "Solo file".into()));
+    assert_eq!(server.get("/solo/").status, 404);
+    assert_eq!(title_of(&server, "/blog/"), (200, "Blog index".into()));
+}
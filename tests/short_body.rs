@@ -0,0 +1,104 @@
+mod common;
+
+use std::fs;
+
+use common::TestServer;
+
+/// Bigger than the 10 MiB the server reads into memory, so it streams from the open file,
+/// and than the socket buffers, so most of it is still unread when the file changes.
+const BIG: usize = 24 * 1024 * 1024;
+
+fn big_file_server() -> TestServer {
+    big_file_server_with(true)
+}
+
+fn big_file_server_with(verify_content_length: bool) -> TestServer {
+    TestServer::start_with(|root, config| {
+        common::write_file(root, "content/big.bin", &"x".repeat(BIG));
+        config.performance.enable_compression = false;
+        config.server.verify_content_length = verify_content_length;
+    })
+}
+
+const REQUEST: &str = "GET /big.bin HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+#[test]
+fn a_body_cut_short_closes_the_connection() {
+    for verify in [true, false] {
+        body_cut_short_closes_the_connection(big_file_server_with(verify));
+    }
+}
+
+fn body_cut_short_closes_the_connection(server: TestServer) {
+    let mut client = server.connect();
+    client.send(REQUEST);
+    let head = client.response_head();
+    assert_eq!(
+        head.header("content-length"),
+        Some(BIG.to_string().as_str())
+    );
+    client.read_exact(1024);
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(server.dir.path().join("content/big.bin"))
+        .unwrap();
+    file.set_len(4096).unwrap();
+
+    // the server can't make up the missing bytes, so it must not carry on as if the next
+    // response could follow them
+    let rest = client.read_to_close();
+    assert!(
+        1024 + rest.len() < BIG,
+        "got the full body from a truncated file"
+    );
+}
+
+#[test]
+fn a_file_growing_mid_send_is_sent_at_its_declared_length() {
+    let server = big_file_server();
+    let mut client = server.connect();
+    client.send(REQUEST);
+    let head = client.response_head();
+    assert_eq!(
+        head.header("content-length"),
+        Some(BIG.to_string().as_str())
+    );
+    client.read_exact(1024);
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(server.dir.path().join("content/big.bin"))
+        .unwrap();
+    std::io::Write::write_all(&mut file, &[b'y'; 65536]).unwrap();
+
+    let rest = client.read_exact(BIG - 1024);
+    assert!(
+        rest.iter().all(|&b| b == b'x'),
+        "appended bytes leaked into the body"
+    );
+    // framing held, so the connection is still good for the next request
+    client.send("GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert!(client.response(false).status > 0);
+}
+
+#[test]
+fn a_file_truncated_mid_send_leaves_no_stale_bytes_for_the_next_request() {
+    let server = big_file_server();
+    let mut client = server.connect();
+    client.send(REQUEST);
+    client.send("GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    client.response_head();
+    client.read_exact(1024);
+    fs::OpenOptions::new()
+        .write(true)
+        .open(server.dir.path().join("content/big.bin"))
+        .unwrap()
+        .set_len(0)
+        .unwrap();
+
+    // the pipelined request is dropped with the connection rather than answered into the
+    // middle of the short body
+    let rest = client.read_to_close();
+    assert!(!String::from_utf8_lossy(&rest).contains("HTTP/1.1"));
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use std::{
+    fs::File,
+    time::{Duration, SystemTime},
+};
+
+use common::{TestServer, write_file};
+
+fn sitemap_server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(
+            root,
+            "content/sitemap.xml.md",
+            "---\ntemplate: sitemap.xml\ncontent_type: application/xml\n---\n",
+        );
+        write_file(
+            root,
+            "themes/default/sitemap.xml",
+            "{% for p in all_pages() %}<url><loc>{{ p.permalink }}</loc><lastmod>{{ p.lastmod }}</lastmod><title>{{ p.title }}</title></url>\n{% endfor %}",
+        );
+        write_file(
+            root,
+            "content/docs/guide/setup.md",
+            "---\ntitle: Setup\n---\n",
+        );
+        write_file(
+            root,
+            "content/wip.md",
+            "---\ntitle: WIP\ndraft: true\n---\n",
+        );
+        write_file(
+            root,
+            "content/.private/notes.md",
+            "---\ntitle: Notes\n---\n",
+        );
+        config.performance.enable_caching = false;
+    })
+}
+
+/// The sitemap with `/` unescaped; the theme's autoescaping writes it as `&#x2f;`.
+fn sitemap(server: &TestServer) -> String {
+    server.get("/sitemap.xml").text().replace("&#x2f;", "/")
+}
+
+fn locs(body: &str) -> Vec<&str> {
+    body.lines()
+        .filter_map(|l| l.split("<loc>").nth(1)?.split("</loc>").next())
+        .collect()
+}
+
+#[test]
+fn sitemap_lists_every_public_page_recursively() {
+    let server = sitemap_server();
+    let res = server.get("/sitemap.xml");
+    assert_eq!(res.status, 200);
+    assert!(
+        res.header("content-type")
+            .unwrap()
+            .starts_with("application/xml")
+    );
+    let body = res.text().replace("&#x2f;", "/");
+    assert_eq!(
+        locs(&body),
+        [
+            "http://127.0.0.1:8080/",
+            "http://127.0.0.1:8080/docs/guide/setup",
+            "http://127.0.0.1:8080/posts/hello-world",
+            "http://127.0.0.1:8080/sitemap.xml",
+        ]
+    );
+}
+
+#[test]
+fn lastmod_and_listing_follow_the_file() {
+    let server = sitemap_server();
+    let setup = server.dir.path().join("content/docs/guide/setup.md");
+    let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    File::options()
+        .write(true)
+        .open(&setup)
+        .unwrap()
+        .set_modified(when)
+        .unwrap();
+    let body = sitemap(&server);
+    let line = body
+        .lines()
+        .find(|l| l.contains("/docs/guide/setup"))
+        .unwrap();
+    assert!(
+        line.contains("<lastmod>2023-11-14T22:13:20Z</lastmod>"),
+        "{}",
+        line
+    );
+
+    // an edit within the same second, keeping the size, is still picked up
+    std::fs::write(&setup, "---\ntitle: Setap\n---\n").unwrap();
+    File::options()
+        .write(true)
+        .open(&setup)
+        .unwrap()
+        .set_modified(when + Duration::from_millis(1))
+        .unwrap();
+    let body = sitemap(&server);
+    let line = body
+        .lines()
+        .find(|l| l.contains("/docs/guide/setup"))
+        .unwrap();
+    assert!(line.contains("<title>Setap</title>"), "{}", line);
+}
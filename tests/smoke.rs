@@ -0,0 +1,21 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn get_index_renders_home_page() {
+    let server = TestServer::start();
+    let res = server.get("/");
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("content-type"), Some("text/html; charset=utf-8"));
+    let body = res.text();
+    assert!(body.contains("<h1>Welcome to Lumen</h1>"), "{}", body);
+    assert!(body.contains("Server is running successfully!"), "{}", body);
+    assert!(
+        body.contains("Hello World"),
+        "recent posts missing: {}",
+        body
+    );
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use common::{TestServer, write_file};
+use lumen::{
+    cli::scaffold_workspace,
+    config::{Config, load_config},
+    server::Server,
+};
+
+const BROKEN: &str = "<h1>{{ title </h1>{% for %}";
+
+/// The scaffolded workspace's config with a broken `broken.html` added to its theme.
+fn broken_theme_config(root: &std::path::Path) -> Config {
+    scaffold_workspace(root.to_str().unwrap()).unwrap();
+    write_file(root, "themes/default/broken.html", BROKEN);
+    let mut config = load_config(root.join("lumen.toml").to_str().unwrap()).unwrap();
+    config.server.port = 0;
+    config.server.threads = 1;
+    config.paths.content_dir = root.join("content").to_string_lossy().into_owned();
+    config.paths.theme_dir = root.join("themes/default").to_string_lossy().into_owned();
+    config
+}
+
+#[test]
+fn strict_mode_refuses_to_start_with_a_broken_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = broken_theme_config(dir.path());
+    config.server.strict = true;
+    let reload = config.clone();
+    let err = match Server::bind(config, Box::new(move || Ok(reload.clone()))) {
+        Ok(_) => panic!("started with a broken template"),
+        Err(e) => e,
+    };
+    assert!(err.contains("broken.html"), "{}", err);
+    assert!(!err.contains("index.html"), "{}", err);
+}
+
+#[test]
+fn broken_template_only_fails_its_own_pages_outside_strict_mode() {
+    let server = TestServer::start_with(|root, _| {
+        write_file(root, "themes/default/broken.html", BROKEN);
+        write_file(
+            root,
+            "content/uses-broken.md",
+            "---\ntemplate: broken.html\n---\n",
+        );
+    });
+    assert_eq!(server.get("/").status, 200);
+    assert_eq!(server.get("/uses-broken").status, 500);
+}
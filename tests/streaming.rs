@@ -0,0 +1,82 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use std::thread;
+
+use common::{TestServer, write_file};
+
+/// A server that streams anything rendering past 1 KiB, with a page well past that.
+fn streaming_server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(
+            root,
+            "content/big.md",
+            &format!(
+                "---\ntitle: Big\n---\n{}",
+                "A paragraph of text.\n\n".repeat(2000)
+            ),
+        );
+        config.performance.enable_caching = false;
+        config.performance.stream_threshold_bytes = 1024;
+    })
+}
+
+#[test]
+fn large_renders_stream_chunked_and_keep_the_connection() {
+    let server = streaming_server();
+    let mut client = server.connect();
+    client.send("GET /big HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let first = client.response(false);
+    assert_eq!(first.status, 200);
+    assert!(first.chunked);
+    assert_eq!(first.header("content-length"), None);
+    assert!(first.text().trim_end().ends_with("</html>"));
+    assert_eq!(first.text().matches("A paragraph of text.").count(), 2000);
+
+    // the terminating chunk leaves the connection ready for the next request
+    client.send("GET /big HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let second = client.response(false);
+    assert_eq!(second.status, 200);
+    assert!(second.chunked);
+    assert_eq!(second.body, first.body);
+    assert!(client.is_closed());
+}
+
+#[test]
+fn http_1_0_clients_get_a_content_length_instead() {
+    let server = streaming_server();
+    let res = server.request("GET /big HTTP/1.0\r\nHost: localhost\r\n\r\n");
+    assert_eq!(res.status, 200);
+    assert!(!res.chunked);
+    assert_eq!(res.header("transfer-encoding"), None);
+    let len: usize = res.header("content-length").unwrap().parse().unwrap();
+    assert_eq!(len, res.body.len());
+    assert_eq!(res.text().matches("A paragraph of text.").count(), 2000);
+}
+
+#[test]
+fn small_renders_are_not_chunked() {
+    let server = streaming_server();
+    let res = server.get("/");
+    assert_eq!(res.status, 200);
+    assert!(!res.chunked);
+    assert!(res.header("content-length").is_some());
+}
+
+#[test]
+fn more_concurrent_streams_than_workers_all_complete() {
+    let server = streaming_server();
+    let expected = server.get("/big").body;
+    thread::scope(|s| {
+        let server = &server;
+        let requests: Vec<_> = (0..16)
+            .map(|_| s.spawn(move || server.get("/big")))
+            .collect();
+        for request in requests {
+            let res = request.join().unwrap();
+            assert_eq!(res.status, 200);
+            assert_eq!(res.body, expected);
+        }
+    });
+}
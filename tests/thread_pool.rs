@@ -0,0 +1,40 @@
+mod common;
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use common::{logged_events, wait_for};
+use lumen::thread_pool::ThreadPool;
+
+const IDLE: Duration = Duration::from_secs(30);
+
+#[test]
+fn workers_are_named_and_survive_a_panicking_job() {
+    logged_events();
+    let pool = ThreadPool::new(1, 1, 16, IDLE).unwrap();
+    pool.execute(|| panic!("job exploded")).unwrap();
+
+    let panic_logged = || {
+        logged_events().into_iter().find(|event| {
+            event["fields"]["message"]
+                .as_str()
+                .is_some_and(|m| m.contains("job exploded"))
+        })
+    };
+    assert!(wait_for(|| panic_logged().is_some()));
+    let event = panic_logged().unwrap();
+    assert_eq!(event["level"], "ERROR");
+    assert_eq!(
+        event["fields"]["message"],
+        "Worker thread 'lumen-worker-0' panicked: job exploded"
+    );
+
+    // the same worker carries on with the next job
+    let (tx, rx) = mpsc::channel();
+    pool.execute(move || {
+        tx.send(thread::current().name().map(String::from)).unwrap();
+    })
+    .unwrap();
+    let name = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(name.as_deref(), Some("lumen-worker-0"));
+    assert_eq!(pool.workers(), 1);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::TestServer;
+
+const REQUEST: &str = "GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+fn timeouts(read: u64, keep_alive: u64, lifetime: u64) -> TestServer {
+    TestServer::start_with(|_, config| {
+        config.server.timeout_secs = read;
+        config.server.keep_alive_timeout_secs = keep_alive;
+        config.server.max_connection_lifetime_secs = lifetime;
+    })
+}
+
+#[test]
+fn read_timeout_closes_a_stalled_request() {
+    let server = timeouts(1, 30, 60);
+    let mut stalled = server.connect();
+    stalled.send("GET / HTTP/1.1\r\nHost: local");
+    let mut silent = server.connect();
+
+    let started = Instant::now();
+    assert!(stalled.closed_within(Duration::from_secs(5)));
+    assert!(silent.closed_within(Duration::from_secs(5)));
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
+
+#[test]
+fn keep_alive_timeout_closes_an_idle_connection_but_not_a_slow_request() {
+    let server = timeouts(30, 1, 60);
+    let mut slow = server.connect();
+    slow.send("GET / HTTP/1.1\r\n");
+
+    let mut idle = server.connect();
+    idle.send(REQUEST);
+    idle.response(false);
+    assert!(idle.closed_within(Duration::from_secs(5)));
+
+    // the slow request is held to the read timeout, and can still finish
+    assert!(slow.is_open());
+    slow.send("Host: localhost\r\n\r\n");
+    assert!(slow.response(false).status > 0);
+}
+
+#[test]
+fn lifetime_cap_closes_a_connection_the_other_timeouts_would_keep() {
+    let server = timeouts(30, 30, 1);
+    let mut client = server.connect();
+    let started = Instant::now();
+    for _ in 0..2 {
+        client.send(REQUEST);
+        client.response(false);
+    }
+    assert!(client.closed_within(Duration::from_secs(5)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "markdown")]
+
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::{TestServer, write_file};
+use lumen::config::TemplateVariant;
+
+fn variant_server() -> TestServer {
+    TestServer::start_with(|root, config| {
+        write_file(root, "themes/default/print.html", "PRINT {{ title }}");
+        write_file(root, "themes/default/amp.html", "AMP {{ title }}");
+        write_file(root, "themes/default/slim.html", "SLIM {{ title }}");
+        write_file(root, "content/page.md", "---\ntitle: Page\n---\nBody\n");
+        write_file(
+            root,
+            "content/custom.md",
+            "---\ntitle: Custom\nvariants:\n  print: slim.html\n---\nBody\n",
+        );
+        config.variants = vec![
+            TemplateVariant {
+                name: "print".into(),
+                query: "print=1".into(),
+                template: "print.html".into(),
+                ..Default::default()
+            },
+            TemplateVariant {
+                name: "amp".into(),
+                user_agent: "AmpBot".into(),
+                template: "amp.html".into(),
+                ..Default::default()
+            },
+            TemplateVariant {
+                name: "missing".into(),
+                query: "missing".into(),
+                template: "no-such-template.html".into(),
+                ..Default::default()
+            },
+        ];
+    })
+}
+
+fn get_as(server: &TestServer, path: &str, user_agent: &str) -> String {
+    server
+        .request(&format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nConnection: close\r\n\r\n",
+            path, user_agent
+        ))
+        .text()
+}
+
+#[test]
+fn query_and_user_agent_pick_the_template() {
+    let server = variant_server();
+    assert!(get_as(&server, "/page", "curl").contains("<h1>Page</h1>"));
+    assert_eq!(get_as(&server, "/page?print=1", "curl"), "PRINT Page");
+    assert_eq!(
+        get_as(&server, "/page", "Mozilla/5.0 (ampbot/2.1)"),
+        "AMP Page"
+    );
+    // a different value for the flag is not the variant
+    assert!(get_as(&server, "/page?print=2", "curl").contains("<h1>Page</h1>"));
+}
+
+#[test]
+fn variants_are_cached_apart() {
+    let server = variant_server();
+    for _ in 0..2 {
+        assert!(get_as(&server, "/page", "curl").contains("<h1>Page</h1>"));
+        assert_eq!(get_as(&server, "/page?print=1", "curl"), "PRINT Page");
+        assert_eq!(get_as(&server, "/page", "AmpBot"), "AMP Page");
+    }
+    let m = &server.metrics;
+    assert_eq!(m.renders.load(Ordering::Relaxed), 3);
+    assert_eq!(m.cache_hits.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn frontmatter_overrides_and_missing_templates_fall_back() {
+    let server = variant_server();
+    assert_eq!(get_as(&server, "/custom?print=1", "curl"), "SLIM Custom");
+    assert!(get_as(&server, "/page?missing", "curl").contains("<h1>Page</h1>"));
+}
+
+#[test]
+fn user_agent_variants_add_vary() {
+    let server = variant_server();
+    let res = server.get("/page");
+    assert!(res.header_values("vary").contains(&"User-Agent"));
+}